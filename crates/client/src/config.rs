@@ -8,10 +8,29 @@ use serde::{Deserialize, Serialize};
 use engine::filesystem::DIRS;
 use rendering::GraphicsSettings;
 
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Config {
     pub graphics: GraphicsSettings,
     pub log_level: String,
+    /// Write structured engine events to telemetry.jsonl
+    pub telemetry: bool,
+    /// Keep rendering while the window is unfocused,
+    /// otherwise rendering stops and the simulation only updates a few times a second
+    pub background_rendering: bool,
+    /// Simulation steps per second, frames are rendered as fast as possible in between
+    pub tick_rate: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            graphics: Default::default(),
+            log_level: Default::default(),
+            telemetry: false,
+            background_rendering: false,
+            tick_rate: 60,
+        }
+    }
 }
 
 pub static CONFIG: Lazy<RwLock<Config>> = Lazy::new(|| RwLock::new(Config::new()));
@@ -27,6 +46,12 @@ impl Config {
             .expect("Failed to load settings")
     }
 
+    /// False on first launch, before any settings file was written
+    pub fn exists() -> bool {
+        let cfg = DIRS.project.config_dir();
+        cfg.join("engine_settings.toml").exists() || cfg.join("engine_settings.yaml").exists()
+    }
+
     pub fn save(&self) {
         let cfg = DIRS.project.config_dir().join("engine_settings.yaml");
         match serde_yaml::to_string(self) {
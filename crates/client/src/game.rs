@@ -1,81 +1,161 @@
-use std::path::PathBuf;
+use std::path::Path;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use log::info;
-use nalgebra::{Isometry3, Point3, UnitQuaternion, Vector3};
+use log::{debug, error, info};
+use nalgebra::{Isometry3, Point3, Vector3};
+use uom::si::angle::degree;
+use uom::si::f32::Angle;
 use uom::si::f64::Time;
 use uom::si::time::second;
-use winit::event::{Event, WindowEvent};
+use winit::event::{ElementState, Event, MouseButton, VirtualKeyCode, WindowEvent};
 use winit::event_loop::ControlFlow;
-use winit::window::Window;
+use winit::monitor::VideoMode;
+use winit::window::{Fullscreen, Window};
 
-use engine::ecs::{IntoIter, UniqueView, View, ViewMut, World};
-use rendering::{Camera, Material, Mesh, RenderingEngine};
+use engine::ecs::{
+    EntityId, IntoIter, IntoWithId, Schedule, Stage, View, ViewMut, World,
+};
+use engine::filesystem::DIRS;
+use engine::transform::{rotate, Transform};
+use rendering::animation::advance_animations;
+use rendering::{Camera, RenderingEngine};
 
 use crate::game::input::InputManager;
-use crate::CONFIG;
+use crate::game::scene::{load_scene, AssetCache};
+use crate::{fullscreen_mode, CONFIG};
 
 pub mod input;
+mod scene;
+
+/// Time between simulation steps while paused in the background
+const BACKGROUND_TICK: Duration = Duration::from_millis(250);
+/// Time between logging the rendering engine's frame stats
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+/// World units per second the camera moves while a movement key is held
+const CAMERA_SPEED: f32 = 4.;
+/// Factor the camera speed is multiplied by while control is held
+const FAST_CAMERA_FACTOR: f32 = 4.;
+/// Scene loaded into the world at startup
+const SCENE_PATH: &str = "./scene.yaml";
+/// File in the data directory the world is saved to and loaded from with the quicksave actions
+const QUICKSAVE_FILE: &str = "quicksave.yaml";
+/// Most simulation steps run before a frame, if it falls further behind the simulation slows down
+const MAX_STEPS_PER_FRAME: u32 = 8;
+/// Degrees the zoom actions change the vertical field of view by
+const FOV_STEP: f32 = 5.;
+/// Narrowest and widest vertical field of view in degrees the zoom actions allow
+const FOV_RANGE: (f32, f32) = (30., 120.);
 
 pub struct Game<R: RenderingEngine> {
     world: World,
+    schedule: Schedule,
     camera: Camera,
+    previous_view: Isometry3<f32>,
     rendering_engine: Box<R>,
     time: Instant,
+    /// Fixed time every simulation step advances by
+    step: Duration,
+    /// Real time not yet simulated, less than a step after every frame
+    accumulator: Duration,
+    /// When the frame stats were last logged
+    stats_logged: Instant,
     window: Window,
     visible: bool,
+    focused: bool,
+    background_rendering: bool,
     input_manager: InputManager,
 }
 
 impl<R: RenderingEngine> Game<R> {
     pub fn new(mut rendering_engine: Box<R>, window: Window) -> Self {
+        let background_rendering = CONFIG.read().background_rendering;
+        let step = Duration::from_secs(1) / CONFIG.read().tick_rate.max(1);
         let cfg = &CONFIG.read().graphics;
-        let mut camera = Camera::new(cfg.resolution[0], cfg.resolution[1], cfg.fov);
-        let path = PathBuf::from("./model.obj");
-        let mesh = rendering_engine.load_model(&path).unwrap();
-        let material = rendering_engine.load_material().unwrap();
+        let mut camera = Camera::new(cfg.resolution[0], cfg.resolution[1], cfg.fov)
+            .with_orthographic_depth(cfg.orthographic_depth)
+            .with_perspective_depth(cfg.perspective_depth)
+            .with_coordinate_system(cfg.coordinate_system);
         let mut world = World::new();
-        let mut iso = Isometry3::<f32>::default();
-        iso.translation.x += 2.;
-        iso.translation.z += -6.;
-        let mut iso2 = iso;
-        iso2.translation.x -= 4.;
-        let eye = Point3::new(0.0, 0.0, 0.0);
-        let up = Vector3::new(0., 1., 0.);
-        let mut target = iso.translation.vector;
-        target.x = 0.;
-        let target = Point3::from(target);
-        camera.view = Isometry3::look_at_rh(&eye, &target, &up);
-        let _entity = world.add_entity((mesh.clone(), material.clone(), iso));
-        let _ = world.add_entity((mesh, material, iso2));
+        load_scene(Path::new(SCENE_PATH), &mut world, rendering_engine.as_mut());
+        camera.set_position(Point3::origin());
+        camera.look_at(Point3::new(0., 0., -6.));
+        let mut schedule = Schedule::new();
+        schedule
+            .add_system(Stage::Update, |world| world.run(store_previous_transforms))
+            .add_system(Stage::Update, |world| world.run(rotate))
+            .add_system(Stage::Update, |world| world.run(advance_animations));
         Game {
             world,
+            schedule,
+            previous_view: camera.view,
             camera,
             rendering_engine,
             time: Instant::now(),
+            step,
+            accumulator: Duration::ZERO,
+            stats_logged: Instant::now(),
             window,
             visible: true,
+            focused: true,
+            background_rendering,
             input_manager: InputManager::new().expect("Failed to create input manager"),
         }
     }
 
     pub fn main_loop(&mut self, event: Event<()>, control_flow: &mut ControlFlow) {
-        *control_flow = ControlFlow::Poll;
+        *control_flow = if self.paused() {
+            ControlFlow::WaitUntil(self.time + BACKGROUND_TICK)
+        } else {
+            ControlFlow::Poll
+        };
         match event {
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 window_id,
             } if self.window.id() == window_id => *control_flow = ControlFlow::Exit,
 
+            // minimized windows are resized to zero, the camera and swapchain keep their size
+            // and the engine skips frames until the window is restored
             Event::WindowEvent {
                 event: WindowEvent::Resized(size),
                 window_id,
-            } if self.window.id() == window_id => {
-                self.camera = Camera::new(size.width, size.height, CONFIG.read().graphics.fov);
+            } if self.window.id() == window_id && size.width > 0 && size.height > 0 => {
+                self.camera.set_aspect(size.width, size.height);
                 self.rendering_engine.resize(size.width, size.height);
             }
 
+            Event::WindowEvent {
+                event: WindowEvent::Focused(focused),
+                window_id,
+            } if self.window.id() == window_id => {
+                self.focused = focused;
+                if self.paused() {
+                    info!("Window lost focus, pausing rendering");
+                }
+            }
+
+            // left clicks pick the entity under the cursor
+            Event::WindowEvent {
+                event:
+                    event @ WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        button: MouseButton::Left,
+                        ..
+                    },
+                window_id,
+            } if self.window.id() == window_id => {
+                self.input_manager.handle_window_input(&event);
+                match self.entity_under_cursor() {
+                    Some(entity) => info!("Picked entity {entity:?}"),
+                    None => debug!("Nothing to pick under the cursor"),
+                }
+            }
+
+            Event::WindowEvent { event, window_id } if self.window.id() == window_id => {
+                self.input_manager.handle_window_input(&event);
+            }
+
             Event::DeviceEvent { event, device_id } if self.visible => {
                 self.input_manager.handle_input(event, device_id);
             }
@@ -84,11 +164,16 @@ impl<R: RenderingEngine> Game<R> {
             Event::Resumed => self.visible = true,
 
             Event::MainEventsCleared => {
-                if self.visible {
-                    let now = Instant::now();
-                    let delta = Time::new::<second>((now - self.time).as_secs_f64());
-                    self.tick(delta);
-                    self.input_manager.clear_events();
+                let now = Instant::now();
+                let paused = self.paused();
+                self.input_manager.poll_gamepads();
+                if self.visible && (!paused || now >= self.time + BACKGROUND_TICK) {
+                    if paused {
+                        self.update(Time::new::<second>((now - self.time).as_secs_f64()));
+                        self.input_manager.clear_events();
+                    } else {
+                        self.tick(now - self.time);
+                    }
                     self.time = now;
                 }
             }
@@ -103,35 +188,258 @@ impl<R: RenderingEngine> Game<R> {
         }
     }
 
-    fn tick(&mut self, delta: Time) {
-        self.world.add_unique(delta).unwrap();
-        self.world.run(rotate).unwrap();
+    /// Whether rendering is skipped because the window is in the background
+    fn paused(&self) -> bool {
+        !self.focused && !self.background_rendering
+    }
+
+    /// Runs the simulation steps that fit in the time since the last frame and renders
+    /// between the last two of them
+    fn tick(&mut self, elapsed: Duration) {
+        let (steps, accumulator) = fixed_steps(self.accumulator + elapsed, self.step);
+        self.accumulator = accumulator;
+        for _ in 0..steps {
+            self.update(Time::new::<second>(self.step.as_secs_f64()));
+            // input is only seen by the step after it, frames without a step keep it
+            self.input_manager.clear_events();
+        }
+        self.draw(self.accumulator.as_secs_f32() / self.step.as_secs_f32());
+        if self.time >= self.stats_logged + STATS_INTERVAL {
+            let stats = self.rendering_engine.frame_stats();
+            debug!(
+                "Frame cpu time {:.2} ms, {:.2} ms on average",
+                stats.cpu_ms, stats.average_cpu_ms
+            );
+            self.stats_logged = self.time;
+        }
+    }
+
+    /// Advances the simulation by one step, remembering the state it started from
+    fn update(&mut self, delta: Time) {
+        self.previous_view = self.camera.view;
+        self.move_camera(delta);
+        if self.input_manager.action_pressed("fullscreen") {
+            self.toggle_fullscreen();
+        }
+        let zoom = f32::from(u8::from(self.input_manager.action_pressed("zoom_out")))
+            - f32::from(u8::from(self.input_manager.action_pressed("zoom_in")));
+        if zoom != 0. {
+            let fov = CONFIG.read().graphics.fov.get::<degree>() + zoom * FOV_STEP;
+            self.set_fov(Angle::new::<degree>(fov.clamp(FOV_RANGE.0, FOV_RANGE.1)));
+        }
+        let quicksave = DIRS.project.data_dir().join(QUICKSAVE_FILE);
+        if self.input_manager.action_pressed("quicksave") {
+            match self.save_world(&quicksave) {
+                Ok(()) => info!("Saved the world to {quicksave:?}"),
+                Err(e) => error!("Failed to save the world: {e}"),
+            }
+        }
+        if self.input_manager.action_pressed("quickload") {
+            if let Err(e) = self.load_world(&quicksave) {
+                error!("Failed to load the world: {e}");
+            }
+        }
+        self.schedule
+            .run(&self.world, delta)
+            .expect("Simulation step failed");
+    }
+
+    /// Moves the camera relative to the direction it looks in while movement keys are held
+    fn move_camera(&mut self, delta: Time) {
+        let input = &self.input_manager;
+        let axis = |positive, negative| {
+            f32::from(u8::from(input.action_active(positive)))
+                - f32::from(u8::from(input.action_active(negative)))
+        };
+        let direction = self.camera.forward() * axis("forward", "back")
+            + self.camera.right() * axis("right", "left")
+            + self.camera.coordinate_system.up() * axis("up", "down");
+        if direction != Vector3::zeros() {
+            let fast = input.is_pressed(VirtualKeyCode::LControl);
+            let speed = if fast {
+                CAMERA_SPEED * FAST_CAMERA_FACTOR
+            } else {
+                CAMERA_SPEED
+            };
+            let distance = speed * delta.get::<second>() as f32;
+            self.camera.translate(direction.normalize() * distance);
+        }
+    }
 
-        self.rendering_engine.begin_rendering(&self.camera);
+    /// Renders the world interpolated between the previous and current simulation step
+    ///
+    /// # Arguments
+    ///
+    /// * `alpha`: how far between the previous (0) and current (1) step to render
+    fn draw(&mut self, alpha: f32) {
+        let mut camera = self.camera.clone();
+        camera.view = self.previous_view.lerp_slerp(&self.camera.view, alpha);
+        self.rendering_engine.begin_rendering(&camera);
 
         self.world
             .run(
-                |mesh: View<Arc<Mesh>>,
-                 material: View<Arc<Material>>,
-                 transform: View<Isometry3<f32>>| {
-                    for (mesh, material, transform) in (&mesh, &material, &transform).iter() {
-                        self.rendering_engine
-                            .render(mesh, material, transform.to_homogeneous());
+                |mesh: View<Arc<R::Mesh>>,
+                 material: View<Arc<R::Material>>,
+                 transform: View<Transform>,
+                 previous: View<PreviousTransform>| {
+                    for (entity, (mesh, material, transform, previous)) in
+                        (&mesh, &material, &transform, &previous).iter().with_id()
+                    {
+                        let transform = previous.0.interpolate(transform, alpha);
+                        self.rendering_engine.render_with_id(
+                            mesh,
+                            material,
+                            transform.to_matrix(),
+                            entity.inner(),
+                        );
                     }
                 },
             )
             .expect("Rendering failed");
 
         self.rendering_engine.end_rendering();
-        self.world.remove_unique::<Time>().unwrap();
+    }
+
+    /// Leaves fullscreen, or enters the largest video mode of the window's monitor
+    fn toggle_fullscreen(&mut self) {
+        if self.window.fullscreen().is_some() {
+            self.set_video_mode(None);
+            return;
+        }
+        let largest = self.available_video_modes().into_iter().max_by_key(|mode| {
+            let size = mode.size();
+            (size.width * size.height, mode.refresh_rate(), mode.bit_depth())
+        });
+        match largest {
+            Some(mode) => self.set_video_mode(Some(mode)),
+            None => info!("The window's monitor has no fullscreen modes"),
+        }
+    }
+
+    /// Exclusive fullscreen modes of the monitor the window is on
+    fn available_video_modes(&self) -> Vec<VideoMode> {
+        self.window
+            .current_monitor()
+            .map_or_else(Vec::new, |monitor| monitor.video_modes().collect())
+    }
+
+    /// Switches to exclusive fullscreen with `mode`, or back to a window if it is None,
+    /// and stores the choice in the config.
+    ///
+    /// The swapchain is recreated once the window reports its new size
+    fn set_video_mode(&mut self, mode: Option<VideoMode>) {
+        {
+            let mut cfg = CONFIG.write();
+            cfg.graphics.fullscreen = mode.as_ref().map(fullscreen_mode);
+            cfg.save();
+        }
+        self.window.set_fullscreen(mode.map(Fullscreen::Exclusive));
+    }
+
+    /// Changes the vertical field of view of the camera and stores it in the config
+    pub fn set_fov(&mut self, fov: Angle) {
+        {
+            let mut cfg = CONFIG.write();
+            cfg.graphics.fov = fov;
+            cfg.save();
+        }
+        self.camera.set_fov(fov);
+    }
+
+    /// Saves the transforms of the world's entities and where their assets were loaded from
+    fn save_world(&self, path: &Path) -> anyhow::Result<()> {
+        engine::ecs::save_world(&self.world, path)
+    }
+
+    /// Replaces the world with the entities saved at `path` by [save_world](Game::save_world),
+    /// loading their meshes and materials again. Entities whose assets fail to load are dropped
+    fn load_world(&mut self, path: &Path) -> anyhow::Result<()> {
+        // frames in flight may still draw the current entities
+        self.rendering_engine.wait();
+        self.world.clear();
+        let mut assets = AssetCache::new();
+        let rendering_engine = self.rendering_engine.as_mut();
+        let count = engine::ecs::load_world(path, &mut self.world, |world, entity, saved| {
+            match assets.resolve(&saved.source, rendering_engine) {
+                Some((mesh, material)) => {
+                    let previous = PreviousTransform(saved.transform);
+                    world.add_component(entity, (mesh, material, previous));
+                }
+                None => {
+                    world.delete_entity(entity);
+                }
+            }
+        })?;
+        info!("Loaded {count} entities from {path:?}");
+        Ok(())
+    }
+
+    /// Entity drawn under the cursor's current position in the last rendered frame,
+    /// see [pick_entity](Game::pick_entity)
+    fn entity_under_cursor(&self) -> Option<EntityId> {
+        self.pick_entity(self.input_manager.cursor()?)
+    }
+
+    /// Returns the entity drawn under the cursor in the last rendered frame,
+    /// None if the cursor is over empty space or outside of the window
+    ///
+    /// # Arguments
+    ///
+    /// * `cursor`: physical position of the cursor relative to the top left corner of the window
+    fn pick_entity(&self, cursor: (f64, f64)) -> Option<EntityId> {
+        let (x, y) = cursor;
+        if x < 0. || y < 0. {
+            return None;
+        }
+        self.rendering_engine
+            .pick(x as u32, y as u32)
+            .and_then(EntityId::from_inner)
+    }
+}
+
+/// Number of `step`s to simulate for the `accumulated` real time and the time left over.
+///
+/// At most [MAX_STEPS_PER_FRAME] are run, the rest of the backlog is dropped
+/// so slow steps don't make every following frame run even more of them
+fn fixed_steps(accumulated: Duration, step: Duration) -> (u32, Duration) {
+    let behind = accumulated.as_nanos() / step.as_nanos();
+    if behind > u128::from(MAX_STEPS_PER_FRAME) {
+        let skipped = accumulated - step * MAX_STEPS_PER_FRAME;
+        debug!("Simulation fell behind, skipping {skipped:?}");
+        (MAX_STEPS_PER_FRAME, Duration::ZERO)
+    } else {
+        let steps = behind as u32;
+        (steps, accumulated - step * steps)
+    }
+}
+
+/// Transform of an entity at the end of the previous simulation step
+#[derive(Debug, Copy, Clone)]
+struct PreviousTransform(Transform);
+
+fn store_previous_transforms(
+    transforms: View<Transform>,
+    mut previous: ViewMut<PreviousTransform>,
+) {
+    for (transform, mut previous) in (&transforms, &mut previous).iter() {
+        previous.0 = *transform;
     }
 }
 
-fn rotate(mut iso: ViewMut<Isometry3<f32>>, time: UniqueView<Time>) {
-    for mut transform in (&mut iso).iter() {
-        let (r, p, y) = transform.rotation.euler_angles();
-        let q = UnitQuaternion::from_euler_angles(r, p + 1., y);
-        let r = transform.rotation;
-        transform.rotation = r.slerp(&q, time.value as f32 / 60.);
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::game::{fixed_steps, MAX_STEPS_PER_FRAME};
+
+    #[test]
+    fn time_is_simulated_in_fixed_steps() {
+        let step = Duration::from_millis(10);
+        let millis = Duration::from_millis;
+        assert_eq!(fixed_steps(millis(4), step), (0, millis(4)));
+        assert_eq!(fixed_steps(millis(25), step), (2, millis(5)));
+        // a long hitch only runs the most steps per frame and the rest is skipped
+        let (steps, left) = fixed_steps(Duration::from_secs(2), step);
+        assert_eq!((steps, left), (MAX_STEPS_PER_FRAME, Duration::ZERO));
     }
 }
@@ -1,15 +1,33 @@
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
+use std::path::{Path, PathBuf};
+
 use anyhow::Result;
 use engine::filesystem::DIRS;
-use log::{info, trace};
+use gilrs::{Axis, Button, EventType, Gilrs};
+use log::{error, info, trace, warn};
 use multimap::{MultiMap, multimap};
 use serde::{Deserialize, Serialize};
-use winit::event::{AxisId, ButtonId, DeviceEvent, DeviceId, ElementState, VirtualKeyCode};
+use winit::event::{
+    AxisId, ButtonId, DeviceEvent, DeviceId, ElementState, MouseButton, MouseScrollDelta,
+    VirtualKeyCode, WindowEvent,
+};
+
+/// Pixels of a touchpad's scroll that count as one line of a mouse wheel
+const PIXELS_PER_LINE: f64 = 20.;
+/// Dead zone of gamepad axis bindings that don't set their own
+const DEFAULT_DEAD_ZONE: f64 = 0.15;
 
 #[derive(Debug)]
 pub struct InputManager {
     input_bindings: MultiMap<String, InputBinding>,
+    /// Where the bindings are loaded from and [saved](InputManager::save) to
+    bindings_path: PathBuf,
+    /// Events since the last [clear_events](InputManager::clear_events)
     input_events: AHashMap<InputAction, InputValue>,
+    /// Keys and buttons currently held down, kept across steps
+    held: AHashSet<InputAction>,
+    /// None if gamepads aren't supported on this platform
+    gamepads: Option<Gilrs>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Hash, Copy, Clone, Eq, PartialEq)]
@@ -17,16 +35,29 @@ pub enum InputAction {
     Axis(AxisId),
     Button(ButtonId),
     Key(VirtualKeyCode),
+    /// Cursor position inside the window, kept until the cursor moves or leaves the window
+    Cursor,
+    MouseButton(MouseButton),
+    /// Horizontal and vertical scrolling in lines
+    Scroll,
+    /// Button of any connected gamepad
+    GamepadButton(Button),
+    /// Position of a stick or trigger of any connected gamepad from -1 to 1,
+    /// kept until it moves
+    GamepadAxis(Axis),
 }
 
 #[derive(Debug)]
 enum InputValue {
     Axis(f64),
     Button(ElementState),
+    /// Physical pixels from the top left corner of the window
+    Position(f64, f64),
+    Scroll(f64, f64),
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
-enum InputBinding {
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum InputBinding {
     Axis {
         id: AxisId,
         scale: f64,
@@ -38,23 +69,125 @@ enum InputBinding {
     Key {
         id: VirtualKeyCode,
         state: ElementState,
-    }
+    },
+    MouseButton {
+        id: MouseButton,
+        state: ElementState,
+    },
+    GamepadButton {
+        id: Button,
+        state: ElementState,
+    },
+    /// Active while the axis is pushed past the dead zone in the direction of `scale`
+    GamepadAxis {
+        id: Axis,
+        scale: f64,
+        #[serde(default = "default_dead_zone")]
+        dead_zone: f64,
+    },
+}
+
+fn default_dead_zone() -> f64 {
+    DEFAULT_DEAD_ZONE
 }
 
 impl InputManager {
     pub fn new() -> Result<Self> {
-        let cfg = DIRS.project.config_dir();
-        let bindings = if let Ok(file) = std::fs::read_to_string(cfg.join("keybindings.yaml")) {
-            serde_yaml::from_str(file.as_str())?
-        } else {
-            default_bindings()
+        let path = DIRS.project.config_dir().join("keybindings.yaml");
+        let mut manager = InputManager {
+            input_bindings: load_bindings(&path)?,
+            bindings_path: path,
+            input_events: Default::default(),
+            held: Default::default(),
+            gamepads: Gilrs::new()
+                .map_err(|e| warn!("Gamepads are not supported: {e}"))
+                .ok(),
         };
-        trace!("Bindings:\n{}", serde_yaml::to_string(&bindings).unwrap());
+        // written out so there is a file to edit the defaults in,
+        // including the ones of actions added since it was written
+        if manager.bind_missing_defaults() || !manager.bindings_path.exists() {
+            manager.save();
+        }
+        trace!(
+            "Bindings:\n{}",
+            serde_yaml::to_string(&manager.input_bindings).unwrap()
+        );
+        Ok(manager)
+    }
 
-        Ok(InputManager {
-            input_bindings: bindings,
-            input_events: Default::default(),
-        })
+    /// Replaces the bindings of `action` with `binding`, call [save](Self::save) to keep them
+    pub fn rebind(&mut self, action: &str, binding: InputBinding) {
+        self.input_bindings.remove(action);
+        self.input_bindings.insert(action.into(), binding);
+    }
+
+    /// Replaces the bindings of `action` with all of `bindings`, unbinding it if there are none
+    pub fn rebind_all(&mut self, action: &str, bindings: Vec<InputBinding>) {
+        let mut bindings = bindings.into_iter();
+        match bindings.next() {
+            Some(first) => {
+                self.rebind(action, first);
+                self.input_bindings.insert_many(action.into(), bindings);
+            }
+            None => {
+                self.input_bindings.remove(action);
+            }
+        }
+    }
+
+    /// Binds the actions that have no bindings to their defaults, returns whether any were
+    fn bind_missing_defaults(&mut self) -> bool {
+        let mut bound = false;
+        for (action, bindings) in default_bindings() {
+            if !self.input_bindings.contains_key(&action) {
+                info!("Binding {action} to its default");
+                self.rebind_all(&action, bindings);
+                bound = true;
+            }
+        }
+        bound
+    }
+
+    /// Writes the bindings to `keybindings.yaml` in the config directory
+    pub fn save(&self) {
+        if let Err(e) = save_bindings(&self.bindings_path, &self.input_bindings) {
+            error!("Error writing keybindings: {e}");
+        }
+    }
+
+    /// Records the events of all gamepads since the last poll, called every frame
+    pub(super) fn poll_gamepads(&mut self) {
+        while let Some(event) = self.gamepads.as_mut().and_then(Gilrs::next_event) {
+            match event.event {
+                EventType::Connected => {
+                    if let Some(gamepads) = &self.gamepads {
+                        let name = gamepads.gamepad(event.id).name();
+                        info!("Gamepad {} connected: {name}", event.id);
+                    }
+                }
+                EventType::Disconnected => {
+                    info!("Gamepad {} disconnected", event.id);
+                    // whatever it held is let go
+                    self.held
+                        .retain(|action| !matches!(action, InputAction::GamepadButton(_)));
+                    self.input_events
+                        .retain(|action, _| !matches!(action, InputAction::GamepadAxis(_)));
+                }
+                EventType::ButtonPressed(button, _) => {
+                    self.record_button(InputAction::GamepadButton(button), ElementState::Pressed);
+                }
+                EventType::ButtonReleased(button, _) => {
+                    self.record_button(InputAction::GamepadButton(button), ElementState::Released);
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    self.input_events.insert(
+                        InputAction::GamepadAxis(axis),
+                        InputValue::Axis(value as f64),
+                    );
+                }
+                _ => {}
+            }
+        }
     }
 
     pub(super) fn handle_input(&mut self, event: DeviceEvent, device_id: DeviceId) {
@@ -66,34 +199,455 @@ impl InputManager {
                 info!("Device {device_id:?} disconnected");
             }
             DeviceEvent::Motion { axis, value } => {
+                let total = self.axis_value(axis) + value;
                 self.input_events
-                    .insert(InputAction::Axis(axis), InputValue::Axis(value));
+                    .insert(InputAction::Axis(axis), InputValue::Axis(total));
             }
             DeviceEvent::Button { button, state } => {
-                self.input_events
-                    .insert(InputAction::Button(button), InputValue::Button(state));
+                self.record_button(InputAction::Button(button), state);
             }
             DeviceEvent::Key(input) => {
                 if let Some(code) = input.virtual_keycode {
-                    self.input_events
-                        .insert(InputAction::Key(code), InputValue::Button(input.state));
+                    self.record_button(InputAction::Key(code), input.state);
                 }
             }
             _ => {}
         }
     }
 
+    /// Updates the held keys and buttons and records the press or release as an event,
+    /// repeated presses of a held key are not recorded again
+    fn record_button(&mut self, action: InputAction, state: ElementState) {
+        let changed = match state {
+            ElementState::Pressed => self.held.insert(action),
+            ElementState::Released => self.held.remove(&action),
+        };
+        if changed {
+            self.input_events.insert(action, InputValue::Button(state));
+        }
+    }
+
+    /// Whether the key or button is held down
+    pub fn is_down(&self, action: InputAction) -> bool {
+        self.held.contains(&action)
+    }
+
+    /// Whether the key or button went down since the events were last cleared
+    pub fn just_pressed(&self, action: InputAction) -> bool {
+        matches!(
+            self.input_events.get(&action),
+            Some(InputValue::Button(ElementState::Pressed))
+        )
+    }
+
+    /// Whether the key or button was let go since the events were last cleared
+    pub fn just_released(&self, action: InputAction) -> bool {
+        matches!(
+            self.input_events.get(&action),
+            Some(InputValue::Button(ElementState::Released))
+        )
+    }
+
+    /// Whether `key` is held down
+    pub fn is_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.is_down(InputAction::Key(key))
+    }
+
+    /// Position of the gamepad axis from -1 to 1, without a dead zone
+    pub fn gamepad_axis(&self, axis: Axis) -> f64 {
+        match self.input_events.get(&InputAction::GamepadAxis(axis)) {
+            Some(InputValue::Axis(value)) => *value,
+            _ => 0.,
+        }
+    }
+
+    /// Sum of the raw motion along the axis since the events were last cleared
+    pub fn axis_value(&self, id: AxisId) -> f64 {
+        match self.input_events.get(&InputAction::Axis(id)) {
+            Some(InputValue::Axis(value)) => *value,
+            _ => 0.,
+        }
+    }
+
+    /// Whether any input bound to `action` is active: keys and buttons bound while pressed
+    /// are held down, ones bound to their release were just released,
+    /// and bound axes moved since the events were last cleared
+    pub fn action_active(&self, action: &str) -> bool {
+        let bindings = self.input_bindings.get_vec(action);
+        bindings.map_or(false, |bindings| {
+            bindings.iter().any(|binding| self.matches(binding))
+        })
+    }
+
+    /// Whether a key or button bound to `action` went down since the events were last cleared,
+    /// for actions that should only happen once per press
+    pub fn action_pressed(&self, action: &str) -> bool {
+        let bindings = self.input_bindings.get_vec(action);
+        bindings.map_or(false, |bindings| {
+            bindings.iter().any(|binding| {
+                let action = match *binding {
+                    InputBinding::Button { id, .. } => InputAction::Button(id),
+                    InputBinding::Key { id, .. } => InputAction::Key(id),
+                    InputBinding::MouseButton { id, .. } => InputAction::MouseButton(id),
+                    InputBinding::GamepadButton { id, .. } => InputAction::GamepadButton(id),
+                    InputBinding::Axis { .. } | InputBinding::GamepadAxis { .. } => return false,
+                };
+                self.just_pressed(action)
+            })
+        })
+    }
+
+    fn matches(&self, binding: &InputBinding) -> bool {
+        let (action, bound_state) = match *binding {
+            InputBinding::Axis { id, scale } => {
+                let value = self.input_events.get(&InputAction::Axis(id));
+                return matches!(value, Some(InputValue::Axis(value)) if value * scale != 0.);
+            }
+            InputBinding::Button { id, state } => (InputAction::Button(id), state),
+            InputBinding::Key { id, state } => (InputAction::Key(id), state),
+            InputBinding::MouseButton { id, state } => (InputAction::MouseButton(id), state),
+            InputBinding::GamepadButton { id, state } => (InputAction::GamepadButton(id), state),
+            InputBinding::GamepadAxis {
+                id,
+                scale,
+                dead_zone,
+            } => return apply_dead_zone(self.gamepad_axis(id), dead_zone) * scale > 0.,
+        };
+        match bound_state {
+            ElementState::Pressed => self.is_down(action),
+            ElementState::Released => self.just_released(action),
+        }
+    }
+
+    /// Records the cursor, mouse buttons and scrolling over the window,
+    /// raw mouse motion is received by [handle_input](InputManager::handle_input)
+    pub(super) fn handle_window_input(&mut self, event: &WindowEvent) {
+        match *event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.input_events.insert(
+                    InputAction::Cursor,
+                    InputValue::Position(position.x, position.y),
+                );
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.input_events.remove(&InputAction::Cursor);
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.record_button(InputAction::MouseButton(button), state);
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (x, y) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (x as f64, y as f64),
+                    MouseScrollDelta::PixelDelta(position) => {
+                        (position.x / PIXELS_PER_LINE, position.y / PIXELS_PER_LINE)
+                    }
+                };
+                let (total_x, total_y) = self.scroll();
+                self.input_events.insert(
+                    InputAction::Scroll,
+                    InputValue::Scroll(total_x + x, total_y + y),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Position of the cursor in physical pixels from the top left corner of the window,
+    /// None if it is outside of the window
+    pub(super) fn cursor(&self) -> Option<(f64, f64)> {
+        match self.input_events.get(&InputAction::Cursor) {
+            Some(InputValue::Position(x, y)) => Some((*x, *y)),
+            _ => None,
+        }
+    }
+
+    /// Lines scrolled horizontally and vertically since the events were last cleared
+    pub(super) fn scroll(&self) -> (f64, f64) {
+        match self.input_events.get(&InputAction::Scroll) {
+            Some(InputValue::Scroll(x, y)) => (*x, *y),
+            _ => (0., 0.),
+        }
+    }
+
+    /// Forgets the events of the last step,
+    /// held keys and buttons and the cursor position stay until they change
     pub(super) fn clear_events(&mut self) {
-        self.input_events.clear();
+        self.input_events.retain(|action, _| {
+            matches!(action, InputAction::Cursor | InputAction::GamepadAxis(_))
+        });
+    }
+}
+
+
+/// Bindings saved at `path`, or the defaults if there are none
+fn load_bindings(path: &Path) -> Result<MultiMap<String, InputBinding>> {
+    if let Ok(file) = std::fs::read_to_string(path) {
+        Ok(serde_yaml::from_str(file.as_str())?)
+    } else {
+        Ok(default_bindings())
     }
 }
 
+fn save_bindings(path: &Path, bindings: &MultiMap<String, InputBinding>) -> Result<()> {
+    std::fs::write(path, serde_yaml::to_string(bindings)?)?;
+    Ok(())
+}
+
+/// Zero inside the dead zone around the center of an axis,
+/// the rest of the range is stretched so that values still start at zero
+fn apply_dead_zone(value: f64, dead_zone: f64) -> f64 {
+    let dead_zone = dead_zone.clamp(0., 0.99);
+    if value.abs() <= dead_zone {
+        0.
+    } else {
+        value.signum() * (value.abs() - dead_zone) / (1. - dead_zone)
+    }
+}
 
 fn default_bindings() -> MultiMap<String, InputBinding> {
     multimap! {
         "forward".into() => InputBinding::Key {
             id: VirtualKeyCode::W,
             state: ElementState::Pressed
+        },
+        "back".into() => InputBinding::Key {
+            id: VirtualKeyCode::S,
+            state: ElementState::Pressed
+        },
+        "left".into() => InputBinding::Key {
+            id: VirtualKeyCode::A,
+            state: ElementState::Pressed
+        },
+        "right".into() => InputBinding::Key {
+            id: VirtualKeyCode::D,
+            state: ElementState::Pressed
+        },
+        "up".into() => InputBinding::Key {
+            id: VirtualKeyCode::Space,
+            state: ElementState::Pressed
+        },
+        "down".into() => InputBinding::Key {
+            id: VirtualKeyCode::LShift,
+            state: ElementState::Pressed
+        },
+        "fullscreen".into() => InputBinding::Key {
+            id: VirtualKeyCode::F11,
+            state: ElementState::Pressed
+        },
+        "quicksave".into() => InputBinding::Key {
+            id: VirtualKeyCode::F5,
+            state: ElementState::Pressed
+        },
+        "quickload".into() => InputBinding::Key {
+            id: VirtualKeyCode::F9,
+            state: ElementState::Pressed
+        },
+        "zoom_in".into() => InputBinding::Key {
+            id: VirtualKeyCode::Equals,
+            state: ElementState::Pressed
+        },
+        "zoom_out".into() => InputBinding::Key {
+            id: VirtualKeyCode::Minus,
+            state: ElementState::Pressed
+        },
+        // mouse motion, axis 0 is horizontal and 1 vertical
+        "look_x".into() => InputBinding::Axis { id: 0, scale: 1. },
+        "look_y".into() => InputBinding::Axis { id: 1, scale: 1. }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use gilrs::{Axis, Button};
+    use multimap::{multimap, MultiMap};
+    use winit::dpi::PhysicalPosition;
+    use winit::event::{
+        DeviceEvent, DeviceId, ElementState, KeyboardInput, MouseButton, MouseScrollDelta,
+        TouchPhase, VirtualKeyCode, WindowEvent,
+    };
+
+    use crate::game::input::{
+        apply_dead_zone, default_bindings, load_bindings, InputAction, InputBinding, InputManager,
+    };
+
+    fn manager() -> InputManager {
+        InputManager {
+            input_bindings: default_bindings(),
+            bindings_path: std::env::temp_dir().join("dragonfire-keybindings-test.yaml"),
+            input_events: Default::default(),
+            held: Default::default(),
+            gamepads: None,
+        }
+    }
+
+    #[allow(deprecated)]
+    fn key(code: VirtualKeyCode, state: ElementState) -> DeviceEvent {
+        DeviceEvent::Key(KeyboardInput {
+            scancode: 0,
+            state,
+            virtual_keycode: Some(code),
+            modifiers: Default::default(),
+        })
+    }
+
+    #[test]
+    fn actions_resolve_through_bindings() {
+        let mut input = manager();
+        let device_id = unsafe { DeviceId::dummy() };
+        input.handle_input(key(VirtualKeyCode::W, ElementState::Pressed), device_id);
+        assert!(input.action_active("forward"));
+        assert!(!input.action_active("back"));
+        assert!(!input.action_active("unbound"));
+        assert!(input.action_pressed("forward"));
+        // held keys stay active over following steps, but are only pressed once
+        input.clear_events();
+        assert!(input.is_pressed(VirtualKeyCode::W));
+        assert!(input.action_active("forward"));
+        assert!(!input.action_pressed("forward"));
+        input.handle_input(key(VirtualKeyCode::W, ElementState::Released), device_id);
+        assert!(!input.action_active("forward"));
+        input.clear_events();
+        assert!(!input.is_pressed(VirtualKeyCode::W));
+    }
+
+    #[test]
+    fn movement_is_bound_by_default() {
+        let bindings = default_bindings();
+        for action in ["forward", "back", "left", "right", "up", "down"] {
+            let keys = bindings.get_vec(action).map(Vec::as_slice);
+            assert!(
+                matches!(keys, Some([InputBinding::Key { .. }])),
+                "{action} is not bound to a key"
+            );
+        }
+        assert!(bindings.contains_key("look_x") && bindings.contains_key("look_y"));
+        for action in ["fullscreen", "quicksave", "quickload"] {
+            assert!(bindings.contains_key(action), "{action} is not bound");
+        }
+    }
+
+    #[test]
+    fn bindings_serialization() {
+        let bindings: MultiMap<String, InputBinding> = multimap! {
+            "look".into() => InputBinding::Axis { id: 0, scale: -1.5 },
+            "look".into() => InputBinding::GamepadAxis {
+                id: Axis::RightStickX,
+                scale: 1.,
+                dead_zone: 0.25
+            },
+            "use".into() => InputBinding::Button {
+                id: 1,
+                state: ElementState::Released
+            },
+            "use".into() => InputBinding::MouseButton {
+                id: MouseButton::Right,
+                state: ElementState::Pressed
+            },
+            "use".into() => InputBinding::GamepadButton {
+                id: Button::South,
+                state: ElementState::Pressed
+            },
+            "jump".into() => InputBinding::Key {
+                id: VirtualKeyCode::Space,
+                state: ElementState::Pressed
+            }
+        };
+        let string = serde_yaml::to_string(&bindings).expect("Failed to serialize bindings");
+        let result: MultiMap<String, InputBinding> =
+            serde_yaml::from_str(&string).expect("Failed to deserialize bindings");
+        assert_eq!(result, bindings);
+    }
+
+    #[test]
+    fn rebound_keys_are_saved() {
+        let mut input = manager();
+        let up = InputBinding::Key {
+            id: VirtualKeyCode::Up,
+            state: ElementState::Pressed,
+        };
+        input.rebind("forward", up.clone());
+        input.save();
+        let bindings = load_bindings(&input.bindings_path).expect("Failed to load bindings");
+        std::fs::remove_file(&input.bindings_path).unwrap();
+        assert_eq!(bindings.get_vec("forward"), Some(&vec![up]));
+        assert_eq!(bindings.get_vec("back"), default_bindings().get_vec("back"));
+    }
+
+    #[test]
+    fn rebind_all_replaces_every_binding() {
+        let mut input = manager();
+        let key = |id| InputBinding::Key {
+            id,
+            state: ElementState::Pressed,
+        };
+        let bindings = vec![key(VirtualKeyCode::Up), key(VirtualKeyCode::K)];
+        input.rebind_all("forward", bindings.clone());
+        assert_eq!(input.input_bindings.get_vec("forward"), Some(&bindings));
+        input.rebind_all("forward", Vec::new());
+        assert!(!input.input_bindings.contains_key("forward"));
+    }
+
+    #[test]
+    fn missing_actions_get_default_bindings() {
+        let mut input = manager();
+        assert!(!input.bind_missing_defaults());
+        input.input_bindings.remove("fullscreen");
+        input.input_bindings.remove("forward");
+        assert!(input.bind_missing_defaults());
+        assert_eq!(input.input_bindings, default_bindings());
+    }
+
+    #[test]
+    fn dead_zone_is_cut_from_axes() {
+        assert_eq!(apply_dead_zone(0.1, 0.2), 0.);
+        assert_eq!(apply_dead_zone(-0.2, 0.2), 0.);
+        assert!((apply_dead_zone(0.6, 0.2) - 0.5).abs() < 1e-9);
+        assert_eq!(apply_dead_zone(-1., 0.2), -1.);
+        assert_eq!(apply_dead_zone(0.05, 0.), 0.05);
+    }
+
+    #[test]
+    fn held_keys_outlive_cleared_events() {
+        let mut input = manager();
+        let device_id = unsafe { DeviceId::dummy() };
+        let space = InputAction::Key(VirtualKeyCode::Space);
+        let event = |state| key(VirtualKeyCode::Space, state);
+        input.handle_input(event(ElementState::Pressed), device_id);
+        assert!(input.just_pressed(space) && input.is_down(space));
+        input.clear_events();
+        // the key repeating while held is not a new press
+        input.handle_input(event(ElementState::Pressed), device_id);
+        assert!(!input.just_pressed(space) && input.is_down(space));
+        input.clear_events();
+        input.handle_input(event(ElementState::Released), device_id);
+        assert!(input.just_released(space) && !input.is_down(space));
+        input.clear_events();
+        assert!(!input.just_released(space) && !input.is_down(space));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn cursor_outlives_cleared_events() {
+        let mut input = manager();
+        let device_id = unsafe { DeviceId::dummy() };
+        input.handle_window_input(&WindowEvent::CursorMoved {
+            device_id,
+            position: PhysicalPosition::new(10., 20.),
+            modifiers: Default::default(),
+        });
+        for _ in 0..2 {
+            input.handle_window_input(&WindowEvent::MouseWheel {
+                device_id,
+                delta: MouseScrollDelta::LineDelta(0., 1.5),
+                phase: TouchPhase::Moved,
+                modifiers: Default::default(),
+            });
         }
+        assert_eq!(input.scroll(), (0., 3.));
+        input.clear_events();
+        assert_eq!(input.cursor(), Some((10., 20.)));
+        assert_eq!(input.scroll(), (0., 0.));
+        input.handle_window_input(&WindowEvent::CursorLeft { device_id });
+        assert_eq!(input.cursor(), None);
     }
 }
@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ahash::AHashMap;
+use log::{error, info, warn};
+use nalgebra::{UnitQuaternion, Vector3};
+use serde::Deserialize;
+
+use engine::ecs::{AssetSource, World};
+use engine::transform::Transform;
+use rendering::RenderingEngine;
+
+use crate::game::PreviousTransform;
+
+/// Entity of a scene file, meshes and materials shared by several entities are loaded once
+#[derive(Debug, Deserialize)]
+pub(super) struct SceneEntity {
+    #[serde(flatten)]
+    source: AssetSource,
+    #[serde(default)]
+    translation: [f32; 3],
+    /// Roll, pitch and yaw in degrees
+    #[serde(default)]
+    rotation: [f32; 3],
+    #[serde(default = "unit_scale")]
+    scale: [f32; 3],
+}
+
+fn unit_scale() -> [f32; 3] {
+    [1.; 3]
+}
+
+impl SceneEntity {
+    fn transform(&self) -> Transform {
+        let [roll, pitch, yaw] = self.rotation.map(f32::to_radians);
+        Transform::new(
+            Vector3::from(self.translation),
+            UnitQuaternion::from_euler_angles(roll, pitch, yaw),
+            Vector3::from(self.scale),
+        )
+    }
+}
+
+/// Adds the entities of the scene file at `path` to the world,
+/// the world stays empty if the file is missing or invalid
+pub(super) fn load_scene<R: RenderingEngine>(path: &Path, world: &mut World, engine: &mut R) {
+    let file = match std::fs::read_to_string(path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("No scene loaded from {path:?}: {e}");
+            return;
+        }
+    };
+    match serde_yaml::from_str::<Vec<SceneEntity>>(&file) {
+        Ok(entities) => {
+            let total = entities.len();
+            let count = spawn_scene(&entities, world, engine);
+            info!("Loaded {count} of {total} entities from {path:?}");
+        }
+        Err(e) => error!("Invalid scene {path:?}: {e}"),
+    }
+}
+
+/// Meshes and materials already loaded for the entities of a world
+pub(super) struct AssetCache<R: RenderingEngine> {
+    // failed loads are remembered too so they are only reported once
+    meshes: AHashMap<PathBuf, Option<Arc<R::Mesh>>>,
+    materials: AHashMap<String, Option<Arc<R::Material>>>,
+}
+
+impl<R: RenderingEngine> AssetCache<R> {
+    pub(super) fn new() -> Self {
+        AssetCache {
+            meshes: AHashMap::new(),
+            materials: AHashMap::new(),
+        }
+    }
+
+    /// Mesh and material of `source`, loaded on first use.
+    /// None if either of them failed to load
+    pub(super) fn resolve(
+        &mut self,
+        source: &AssetSource,
+        engine: &mut R,
+    ) -> Option<(Arc<R::Mesh>, Arc<R::Material>)> {
+        let mesh = self
+            .meshes
+            .entry(source.model.clone())
+            .or_insert_with(|| {
+                engine
+                    .load_model(&source.model)
+                    .map_err(|e| error!("Failed to load model {:?}: {e}", source.model))
+                    .ok()
+            })
+            .clone();
+        let material = self
+            .materials
+            .entry(source.material.clone())
+            .or_insert_with(|| {
+                engine
+                    .load_material(&source.material)
+                    .map_err(|e| error!("Failed to load material {}: {e}", source.material))
+                    .ok()
+            })
+            .clone();
+        Some((mesh?, material?))
+    }
+}
+
+/// Adds the entities to the world and returns how many of them were added,
+/// entities whose model or material fails to load are skipped
+fn spawn_scene<R: RenderingEngine>(
+    entities: &[SceneEntity],
+    world: &mut World,
+    engine: &mut R,
+) -> usize {
+    let mut assets = AssetCache::new();
+    let mut count = 0;
+    for entity in entities {
+        if let Some((mesh, material)) = assets.resolve(&entity.source, engine) {
+            let transform = entity.transform();
+            world.add_entity((
+                mesh,
+                material,
+                entity.source.clone(),
+                transform,
+                PreviousTransform(transform),
+            ));
+            count += 1;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use engine::ecs::{IntoIter, View, World};
+    use rendering::null::{NullEngine, NullMesh};
+
+    use crate::game::scene::{spawn_scene, SceneEntity};
+
+    #[test]
+    fn repeated_models_are_loaded_once() {
+        let entities: Vec<SceneEntity> = serde_yaml::from_str(
+            "
+            - model: ./model.obj
+              material: base
+              translation: [2, 0, -6]
+            - model: ./model.obj
+              material: base
+              rotation: [0, 90, 0]
+              scale: [2, 2, 2]
+            ",
+        )
+        .expect("Failed to parse scene");
+        assert_eq!(entities[0].scale, [1.; 3]);
+        let mut world = World::new();
+        let mut engine = NullEngine::new();
+        assert_eq!(spawn_scene(&entities, &mut world, &mut engine), 2);
+        assert_eq!(engine.calls().load_model, 1);
+        assert_eq!(engine.calls().load_material, 1);
+        let meshes = world
+            .run(|meshes: View<Arc<NullMesh>>| (&meshes).iter().cloned().collect::<Vec<_>>())
+            .unwrap();
+        assert_eq!(meshes.len(), 2);
+        assert!(Arc::ptr_eq(&meshes[0], &meshes[1]));
+    }
+}
@@ -1,15 +1,21 @@
 use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 
 use fern::colors::{Color, ColoredLevelConfig};
-use log::{info, LevelFilter};
+use log::{error, info, LevelFilter};
+use serde::Serialize;
 use winit::dpi::LogicalSize;
 use winit::event_loop::EventLoop;
-use winit::window::{Window, WindowBuilder};
+use winit::monitor::{MonitorHandle, VideoMode};
+use winit::window::{Fullscreen, Window, WindowBuilder};
 
 use engine::filesystem::DIRS;
-use rendering::create_rendering_engine;
+use engine::telemetry::{self, TelemetryEvent};
+use rendering::null::NullEngine;
+use rendering::{create_rendering_engine, Backend, FullscreenMode, RenderingEngine};
 
-use crate::config::CONFIG;
+use crate::config::{Config, CONFIG};
 use crate::game::Game;
 
 mod config;
@@ -17,10 +23,57 @@ mod game;
 
 pub fn start() -> ! {
     init_logging().expect("Failed to initialize logging");
+    if CONFIG.read().telemetry {
+        if let Err(e) = init_telemetry() {
+            error!("Failed to create telemetry file: {e}");
+        }
+    }
     info!("Starting");
     let event_loop = EventLoop::new();
     let window = create_window(&event_loop).expect("Failed to create window");
-    let rendering_engine = create_rendering_engine(&window, &CONFIG.read().graphics);
+    let backend = CONFIG.read().graphics.backend;
+    match backend {
+        Backend::Vulkan => {
+            let first_launch = !Config::exists();
+            let mut rendering_engine = create_rendering_engine(&window, &CONFIG.read().graphics);
+            if first_launch {
+                // pick settings for the gpu once and keep them for later launches,
+                // the engine is recreated if they differ from the defaults it was created with
+                let gpu = rendering_engine.gpu_info();
+                let monitor = window.current_monitor().map(|monitor| {
+                    let size = monitor.size().to_logical::<u32>(monitor.scale_factor());
+                    [size.width, size.height]
+                });
+                let mut cfg = CONFIG.write();
+                let settings = cfg.graphics.clone().with_preset(&gpu, monitor);
+                info!(
+                    "Configuring graphics for {:?} tier gpu {}",
+                    gpu.tier(),
+                    gpu.name
+                );
+                if settings != cfg.graphics {
+                    let [width, height] = settings.resolution;
+                    window.set_inner_size(LogicalSize { width, height });
+                    drop(rendering_engine);
+                    rendering_engine = create_rendering_engine(&window, &settings);
+                }
+                cfg.graphics = settings;
+                cfg.save();
+            }
+            run(event_loop, window, rendering_engine)
+        }
+        Backend::Null => {
+            info!("Using null rendering backend");
+            run(event_loop, window, Box::new(NullEngine::new()))
+        }
+    }
+}
+
+fn run<R: RenderingEngine + 'static>(
+    event_loop: EventLoop<()>,
+    window: Window,
+    rendering_engine: Box<R>,
+) -> ! {
     let mut game = Game::new(rendering_engine, window);
     info!("Initialization finished");
 
@@ -29,16 +82,45 @@ pub fn start() -> ! {
 
 fn create_window<T>(events: &EventLoop<T>) -> Result<Window, Box<dyn Error>> {
     let settings = &CONFIG.read().graphics;
+    let fullscreen = settings
+        .fullscreen
+        .and_then(|mode| find_video_mode(&events.primary_monitor()?, &mode));
     Ok(WindowBuilder::new()
         .with_inner_size(LogicalSize {
             width: settings.resolution[0],
             height: settings.resolution[1],
         })
+        .with_fullscreen(fullscreen.map(Fullscreen::Exclusive))
         .with_title(std::option_env!("APP_NAME").unwrap_or("dragonfire engine"))
         .build(events)?)
     // todo more window options
 }
 
+/// Describes a video mode in the form it is stored in the config
+pub(crate) fn fullscreen_mode(mode: &VideoMode) -> FullscreenMode {
+    FullscreenMode {
+        resolution: [mode.size().width, mode.size().height],
+        refresh_rate: mode.refresh_rate(),
+        bit_depth: mode.bit_depth(),
+    }
+}
+
+/// The monitor's video mode closest to `mode`,
+/// a matching resolution is preferred over the refresh rate and bit depth
+pub(crate) fn find_video_mode(monitor: &MonitorHandle, mode: &FullscreenMode) -> Option<VideoMode> {
+    monitor
+        .video_modes()
+        .min_by_key(|candidate| mode_difference(mode, &fullscreen_mode(candidate)))
+}
+
+fn mode_difference(a: &FullscreenMode, b: &FullscreenMode) -> (u32, u16, u16) {
+    (
+        a.resolution[0].abs_diff(b.resolution[0]) + a.resolution[1].abs_diff(b.resolution[1]),
+        a.refresh_rate.abs_diff(b.refresh_rate),
+        a.bit_depth.abs_diff(b.bit_depth),
+    )
+}
+
 fn init_logging() -> Result<(), fern::InitError> {
     let cfg = CONFIG.read();
     let level = match cfg.log_level.as_str() {
@@ -78,3 +160,59 @@ fn init_logging() -> Result<(), fern::InitError> {
     }
     Ok(())
 }
+
+/// Line of the telemetry file
+#[derive(Serialize)]
+struct TelemetryLine<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: &'a TelemetryEvent,
+}
+
+/// Spawns a thread writing every telemetry event as a line of json
+fn init_telemetry() -> std::io::Result<()> {
+    let path = DIRS.project.data_local_dir().join("telemetry.jsonl");
+    let mut file = BufWriter::new(File::options().create(true).append(true).open(&path)?);
+    let events = telemetry::subscribe();
+    std::thread::Builder::new()
+        .name("Telemetry".into())
+        .spawn(move || {
+            for event in events {
+                let line = TelemetryLine {
+                    time: chrono::Local::now().to_rfc3339(),
+                    event: &event,
+                };
+                let result = serde_json::to_writer(&mut file, &line)
+                    .map_err(std::io::Error::from)
+                    .and_then(|_| writeln!(file))
+                    .and_then(|_| file.flush());
+                if let Err(e) = result {
+                    error!("Error writing telemetry: {e}");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rendering::FullscreenMode;
+
+    use crate::mode_difference;
+
+    #[test]
+    fn closest_mode_prefers_resolution() {
+        let mode = |width, refresh_rate| FullscreenMode {
+            resolution: [width, 1080],
+            refresh_rate,
+            bit_depth: 32,
+        };
+        let wanted = mode(1920, 144);
+        let candidates = [mode(2560, 144), mode(1920, 60), mode(1920, 120)];
+        let closest = candidates
+            .iter()
+            .min_by_key(|candidate| mode_difference(&wanted, candidate));
+        assert_eq!(closest, Some(&mode(1920, 120)));
+        assert_eq!(mode_difference(&wanted, &wanted), (0, 0, 0));
+    }
+}
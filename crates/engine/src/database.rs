@@ -0,0 +1,29 @@
+use std::path::PathBuf;
+
+use rusqlite::{Connection, OpenFlags};
+
+use crate::filesystem::DIRS;
+
+/// File name of the asset database in the asset directory,
+/// its material tables are created by the rendering crate's `materials::database::init`
+pub const DATABASE_FILE: &str = "materials.db";
+/// Environment variable with the path of a database used instead of the one in the asset directory
+pub const DATABASE_PATH_VAR: &str = "DRAGONFIRE_ASSET_DATABASE";
+
+thread_local! {
+    /// Read only connection of the current thread to the asset database,
+    /// opened the first time the thread uses it.
+    ///
+    /// Holds the error instead if the database couldn't be opened, e.g. because it doesn't exist
+    pub static CONN: rusqlite::Result<Connection> = Connection::open_with_flags(
+        database_path(),
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    );
+}
+
+/// Path of the asset database,
+/// [DATABASE_FILE] in the asset directory unless [DATABASE_PATH_VAR] is set
+pub fn database_path() -> PathBuf {
+    std::env::var_os(DATABASE_PATH_VAR)
+        .map_or_else(|| DIRS.asset.join(DATABASE_FILE), PathBuf::from)
+}
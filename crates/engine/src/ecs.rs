@@ -0,0 +1,7 @@
+pub use shipyard::*;
+
+pub use persistence::{load_world, save_world, AssetSource, SavedEntity};
+pub use schedule::{Schedule, Stage};
+
+mod persistence;
+mod schedule;
@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use shipyard::{EntityId, IntoIter, View, World};
+
+use crate::transform::Transform;
+
+/// Where the assets of an entity were loaded from, saved in place of their handles
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AssetSource {
+    pub model: PathBuf,
+    pub material: String,
+}
+
+/// Components of an entity written by [save_world]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedEntity {
+    pub transform: Transform,
+    pub source: AssetSource,
+}
+
+/// Writes every entity with both a [Transform] and an [AssetSource] to `path`,
+/// their other components are not saved
+pub fn save_world(world: &World, path: &Path) -> Result<()> {
+    let entities = saved_entities(world)?;
+    std::fs::write(path, serde_yaml::to_string(&entities)?)?;
+    Ok(())
+}
+
+/// Adds the entities saved at `path` to the world and returns how many there were.
+///
+/// Asset handles can't be saved, so every entity only gets its [Transform] and [AssetSource]
+/// and `resolve` is called to load its assets again and add them to it
+pub fn load_world(
+    path: &Path,
+    world: &mut World,
+    mut resolve: impl FnMut(&mut World, EntityId, &SavedEntity),
+) -> Result<usize> {
+    let entities: Vec<SavedEntity> = serde_yaml::from_str(&std::fs::read_to_string(path)?)?;
+    for saved in &entities {
+        let entity = world.add_entity((saved.transform, saved.source.clone()));
+        resolve(world, entity, saved);
+    }
+    Ok(entities.len())
+}
+
+fn saved_entities(world: &World) -> Result<Vec<SavedEntity>> {
+    world
+        .run(|transforms: View<Transform>, sources: View<AssetSource>| {
+            (&transforms, &sources)
+                .iter()
+                .map(|(transform, source)| SavedEntity {
+                    transform: *transform,
+                    source: source.clone(),
+                })
+                .collect()
+        })
+        .map_err(|e| anyhow!("Failed to read the world: {e:?}"))
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{UnitQuaternion, Vector3};
+    use shipyard::World;
+
+    use crate::ecs::persistence::{
+        load_world, save_world, saved_entities, AssetSource, SavedEntity,
+    };
+    use crate::transform::Transform;
+
+    #[test]
+    fn saved_world_is_restored() {
+        let source = |material: &str| AssetSource {
+            model: "./model.obj".into(),
+            material: material.into(),
+        };
+        let first = Transform::new(
+            Vector3::new(2., 0., -6.),
+            UnitQuaternion::from_euler_angles(0.1, 0.2, 0.3),
+            Vector3::new(1., 2., 3.),
+        );
+        let second = Transform::default();
+        let mut world = World::new();
+        world.add_entity((first, source("base")));
+        world.add_entity((second, source("metal")));
+        // without a source there is nothing to load it from again
+        world.add_entity((Transform::default(),));
+        let path = std::env::temp_dir().join("dragonfire-world-test.yaml");
+        save_world(&world, &path).expect("Failed to save world");
+
+        let mut loaded = World::new();
+        let mut resolved = Vec::new();
+        let count = load_world(&path, &mut loaded, |_, _, saved| {
+            resolved.push(saved.clone())
+        })
+        .expect("Failed to load world");
+        std::fs::remove_file(&path).unwrap();
+        let expected = vec![
+            SavedEntity {
+                transform: first,
+                source: source("base"),
+            },
+            SavedEntity {
+                transform: second,
+                source: source("metal"),
+            },
+        ];
+        assert_eq!(count, 2);
+        assert_eq!(resolved, expected);
+        assert_eq!(saved_entities(&loaded).unwrap(), expected);
+    }
+}
@@ -0,0 +1,65 @@
+use anyhow::{anyhow, Result};
+use shipyard::{error, World};
+use uom::si::f64::Time;
+
+type System = Box<dyn Fn(&World) -> Result<(), error::Run> + Send + Sync>;
+
+/// Stages of a [Schedule], run in the order they are declared in
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Stage {
+    Input,
+    Update,
+    RenderExtract,
+}
+
+const STAGE_COUNT: usize = 3;
+
+/// Ordered list of systems that are run once per simulation step.
+///
+/// The step's delta [Time] is available to every system as a unique
+/// and is removed from the world again once all systems have run
+#[derive(Default)]
+pub struct Schedule {
+    stages: [Vec<System>; STAGE_COUNT],
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a system, systems in the same stage run in the order they were added
+    ///
+    /// # Arguments
+    ///
+    /// * `stage`: stage to run the system in
+    /// * `system`: function running the system on the world, usually `|world| world.run(system)`
+    pub fn add_system(
+        &mut self,
+        stage: Stage,
+        system: impl Fn(&World) -> Result<(), error::Run> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.stages[stage as usize].push(Box::new(system));
+        self
+    }
+
+    /// Runs every stage of the schedule.
+    ///
+    /// # Errors
+    /// Returns the first error of a system, the remaining systems are skipped
+    pub fn run(&self, world: &World, delta: Time) -> Result<()> {
+        world
+            .add_unique(delta)
+            .map_err(|e| anyhow!("Failed to add delta time: {e:?}"))?;
+        let result = self
+            .stages
+            .iter()
+            .flatten()
+            .try_for_each(|system| system(world))
+            .map_err(|e| anyhow!("System failed: {e:?}"));
+        world
+            .remove_unique::<Time>()
+            .map_err(|e| anyhow!("Failed to remove delta time: {e:?}"))?;
+        result
+    }
+}
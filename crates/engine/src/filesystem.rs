@@ -22,6 +22,11 @@ impl Directories {
         let exe_dir = std::env::current_exe()
             .map(|it| it.parent().unwrap().to_path_buf())
             .unwrap_or_else(|_| std::env::current_dir().expect("Could not get current dir"));
+        // test binaries are placed in the deps directory next to the regular executables
+        let exe_dir = match exe_dir.parent() {
+            Some(parent) if exe_dir.ends_with("deps") => parent.to_path_buf(),
+            _ => exe_dir,
+        };
         let asset = exe_dir.join("asset");
         std::fs::create_dir_all(project.config_dir()).unwrap();
         std::fs::create_dir_all(project.data_dir()).unwrap();
@@ -1,3 +1,5 @@
-pub use shipyard as ecs;
-
-pub mod filesystem;
\ No newline at end of file
+pub mod database;
+pub mod ecs;
+pub mod filesystem;
+pub mod telemetry;
+pub mod transform;
@@ -0,0 +1,71 @@
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+/// Machine readable event for diagnostics tooling, kept separate from the text log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TelemetryEvent {
+    EngineInit {
+        gpu: String,
+    },
+    SwapchainRecreated {
+        width: u32,
+        height: u32,
+    },
+    MeshLoaded {
+        path: String,
+        vertices: usize,
+    },
+    MaterialLoaded {
+        vertex_shader: String,
+        fragment_shader: String,
+    },
+    /// An asset failed to load, whether or not a placeholder replaced it
+    AssetFailed {
+        path: String,
+        error: String,
+    },
+    DeviceLost,
+}
+
+/// Sender of the current subscription, None while nobody listens
+static SENDER: Mutex<Option<Sender<TelemetryEvent>>> = parking_lot::const_mutex(None);
+
+/// Sends an event to the telemetry channel.
+///
+/// Events are dropped while nobody is subscribed, so they can't pile up in the channel
+pub fn emit(event: TelemetryEvent) {
+    let mut sender = SENDER.lock();
+    if let Some(channel) = sender.as_ref() {
+        // fails once every receiver of the subscription was dropped
+        if channel.send(event).is_err() {
+            *sender = None;
+        }
+    }
+}
+
+/// Returns a receiver of all events emitted from now on.
+///
+/// Every event is delivered to only one receiver, so there should be a single consumer.
+/// Subscribing again ends the previous subscription
+pub fn subscribe() -> Receiver<TelemetryEvent> {
+    let (sender, receiver) = unbounded();
+    *SENDER.lock() = Some(sender);
+    receiver
+}
+
+#[cfg(test)]
+mod test {
+    use crate::telemetry::{emit, subscribe, TelemetryEvent, SENDER};
+
+    #[test]
+    fn subscriber_receives_events() {
+        let receiver = subscribe();
+        emit(TelemetryEvent::DeviceLost);
+        assert_eq!(receiver.try_recv(), Ok(TelemetryEvent::DeviceLost));
+        drop(receiver);
+        emit(TelemetryEvent::DeviceLost);
+        assert!(SENDER.lock().is_none());
+    }
+}
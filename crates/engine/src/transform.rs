@@ -0,0 +1,97 @@
+use nalgebra::{Isometry3, Matrix4, UnitQuaternion, Vector3};
+use serde::{Deserialize, Serialize};
+use shipyard::{IntoIter, UniqueView, ViewMut};
+use uom::si::f64::Time;
+
+/// Position, orientation and (possibly non uniform) scale of an entity
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: UnitQuaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Transform {
+    pub fn new(
+        translation: Vector3<f32>,
+        rotation: UnitQuaternion<f32>,
+        scale: Vector3<f32>,
+    ) -> Self {
+        Transform {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    /// Composes the transform into a model matrix, scaling first, then rotating and translating
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+
+    /// Linearly interpolates translation and scale and spherically interpolates the rotation
+    ///
+    /// # Arguments
+    ///
+    /// * `other`: transform reached at `t` = 1
+    /// * `t`: interpolation factor between 0 and 1
+    pub fn interpolate(&self, other: &Transform, t: f32) -> Transform {
+        Transform {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: self.rotation.slerp(&other.rotation, t),
+            scale: self.scale.lerp(&other.scale, t),
+        }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: Vector3::zeros(),
+            rotation: UnitQuaternion::identity(),
+            scale: Vector3::repeat(1.),
+        }
+    }
+}
+
+impl From<Isometry3<f32>> for Transform {
+    fn from(iso: Isometry3<f32>) -> Self {
+        Transform {
+            translation: iso.translation.vector,
+            rotation: iso.rotation,
+            ..Default::default()
+        }
+    }
+}
+
+/// Keeps turning every transform around its pitch axis, scaled by the step's delta [Time]
+pub fn rotate(mut transforms: ViewMut<Transform>, time: UniqueView<Time>) {
+    for mut transform in (&mut transforms).iter() {
+        let (r, p, y) = transform.rotation.euler_angles();
+        let q = UnitQuaternion::from_euler_angles(r, p + 1., y);
+        let r = transform.rotation;
+        transform.rotation = r.slerp(&q, time.value as f32 / 60.);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{Point3, UnitQuaternion, Vector3};
+
+    use crate::transform::Transform;
+
+    #[test]
+    fn matrix_scales_before_rotating() {
+        let transform = Transform::new(
+            Vector3::new(0., 0., -5.),
+            UnitQuaternion::from_axis_angle(&Vector3::z_axis(), std::f32::consts::FRAC_PI_2),
+            Vector3::new(2., 1., 1.),
+        );
+        let point = transform
+            .to_matrix()
+            .transform_point(&Point3::new(1., 0., 0.));
+        assert!((point - Point3::new(0., 2., -5.)).norm() < 1e-5);
+    }
+}
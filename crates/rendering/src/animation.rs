@@ -0,0 +1,386 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use engine::ecs::{IntoIter, UniqueView, ViewMut};
+use engine::transform::Transform;
+use gltf::animation::util::ReadOutputs;
+use log::{info, warn};
+use nalgebra::{Matrix4, Quaternion, UnitQuaternion, Vector3};
+use uom::si::f64::Time;
+use uom::si::time::second;
+
+/// Joint hierarchy of a skinned glTF model.
+///
+/// Poses are given as the local transform of every node of the file, indexed by node index,
+/// since animations may also move nodes that are not joints of the skin
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skin {
+    /// Parent of every node, None for root nodes
+    parents: Vec<Option<usize>>,
+    /// Node indices ordered so that every parent comes before its children
+    order: Vec<usize>,
+    rest_pose: Vec<Transform>,
+    /// Node index of every joint, in the order the vertices' joint indices refer to
+    joints: Vec<usize>,
+    inverse_bind_matrices: Vec<Matrix4<f32>>,
+}
+
+impl Skin {
+    /// Local transforms of all nodes as stored in the file, before any animation is applied
+    #[inline]
+    pub fn rest_pose(&self) -> &[Transform] {
+        &self.rest_pose
+    }
+
+    #[inline]
+    pub fn joint_count(&self) -> usize {
+        self.joints.len()
+    }
+
+    /// Computes the bone matrices a [Skeleton](crate::Skeleton) is drawn with from a pose,
+    /// bone matrices transform vertices from the bind pose into the posed model space
+    ///
+    /// # Arguments
+    ///
+    /// * `pose`: local transforms of every node, usually the rest pose sampled by an [Animation]
+    pub fn bone_matrices(&self, pose: &[Transform]) -> Vec<Matrix4<f32>> {
+        let mut globals = vec![Matrix4::identity(); pose.len()];
+        for &node in &self.order {
+            let parent = self.parents[node].map_or_else(Matrix4::identity, |it| globals[it]);
+            globals[node] = parent * pose[node].to_matrix();
+        }
+        self.joints
+            .iter()
+            .zip(&self.inverse_bind_matrices)
+            .map(|(joint, inverse_bind)| globals[*joint] * inverse_bind)
+            .collect()
+    }
+}
+
+/// How values between two keyframes are computed
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    /// Keeps the value of the previous keyframe until the next one is reached
+    Step,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Keyframes {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<UnitQuaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+/// Keyframes animating one property of one node
+#[derive(Debug, Clone, PartialEq)]
+struct Channel {
+    node: usize,
+    times: Vec<f32>,
+    interpolation: Interpolation,
+    keyframes: Keyframes,
+}
+
+/// Animation clip of the translation, rotation and scale keyframes of a model's nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    pub name: Option<String>,
+    channels: Vec<Channel>,
+    /// Time of the last keyframe in seconds
+    duration: f32,
+}
+
+impl Animation {
+    #[inline]
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    /// Overwrites the animated properties of the nodes in `pose` with their values at `time`.
+    ///
+    /// Times before the first or after the last keyframe of a channel use the first or last value
+    ///
+    /// # Arguments
+    ///
+    /// * `time`: time since the start of the animation in seconds
+    /// * `pose`: local transforms of every node, properties that are not animated are kept
+    pub fn sample(&self, time: f32, pose: &mut [Transform]) {
+        for channel in &self.channels {
+            let transform = match pose.get_mut(channel.node) {
+                Some(transform) => transform,
+                None => continue,
+            };
+            let (previous, next, t) = keyframe(&channel.times, time);
+            let t = match channel.interpolation {
+                Interpolation::Linear => t,
+                Interpolation::Step => 0.,
+            };
+            match &channel.keyframes {
+                Keyframes::Translation(values) => {
+                    transform.translation = values[previous].lerp(&values[next], t)
+                }
+                Keyframes::Rotation(values) => {
+                    transform.rotation = values[previous].slerp(&values[next], t)
+                }
+                Keyframes::Scale(values) => {
+                    transform.scale = values[previous].lerp(&values[next], t)
+                }
+            }
+        }
+    }
+}
+
+/// Finds the keyframes surrounding `time` and how far between them it is
+fn keyframe(times: &[f32], time: f32) -> (usize, usize, f32) {
+    let next = times.partition_point(|it| *it <= time);
+    if next == 0 {
+        (0, 0, 0.)
+    } else if next == times.len() {
+        (next - 1, next - 1, 0.)
+    } else {
+        let previous = next - 1;
+        let t = (time - times[previous]) / (times[next] - times[previous]);
+        (previous, next, t)
+    }
+}
+
+/// Loads the skins and animations of a glTF file.
+///
+/// Cubic spline channels are played back with linear interpolation between their keyframes,
+/// morph target weights are ignored
+pub fn load_animations(path: &Path) -> Result<(Vec<Skin>, Vec<Animation>)> {
+    let (document, buffers, _) = gltf::import(path)?;
+    let nodes: Vec<_> = document.nodes().collect();
+
+    let mut parents = vec![None; nodes.len()];
+    for node in &nodes {
+        for child in node.children() {
+            parents[child.index()] = Some(node.index());
+        }
+    }
+    let mut order = Vec::with_capacity(nodes.len());
+    let mut queue: VecDeque<_> = (0..nodes.len())
+        .filter(|it| parents[*it].is_none())
+        .collect();
+    while let Some(node) = queue.pop_front() {
+        order.push(node);
+        queue.extend(nodes[node].children().map(|it| it.index()));
+    }
+    let rest_pose: Vec<_> = nodes
+        .iter()
+        .map(|node| {
+            let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+            Transform::new(
+                translation.into(),
+                UnitQuaternion::new_normalize(Quaternion::new(w, x, y, z)),
+                scale.into(),
+            )
+        })
+        .collect();
+
+    let skins = document
+        .skins()
+        .map(|skin| {
+            let joints: Vec<_> = skin.joints().map(|it| it.index()).collect();
+            let inverse_bind_matrices = match skin
+                .reader(|buffer| Some(&buffers[buffer.index()]))
+                .read_inverse_bind_matrices()
+            {
+                Some(matrices) => matrices.map(Matrix4::from).collect(),
+                None => vec![Matrix4::identity(); joints.len()],
+            };
+            Skin {
+                parents: parents.clone(),
+                order: order.clone(),
+                rest_pose: rest_pose.clone(),
+                joints,
+                inverse_bind_matrices,
+            }
+        })
+        .collect();
+
+    let mut animations = Vec::new();
+    for animation in document.animations() {
+        let mut channels = Vec::new();
+        for channel in animation.channels() {
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let times: Vec<f32> = reader
+                .read_inputs()
+                .ok_or_else(|| anyhow!("Animation channel has no keyframe times"))?
+                .collect();
+            let (interpolation, cubic) = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Step => (Interpolation::Step, false),
+                gltf::animation::Interpolation::Linear => (Interpolation::Linear, false),
+                gltf::animation::Interpolation::CubicSpline => {
+                    warn!("Cubic spline interpolation is not supported, using linear instead");
+                    (Interpolation::Linear, true)
+                }
+            };
+            let keyframes = match reader.read_outputs() {
+                Some(ReadOutputs::Translations(values)) => {
+                    Keyframes::Translation(spline_values(values.map(Vector3::from), cubic))
+                }
+                Some(ReadOutputs::Rotations(values)) => {
+                    let values = values.into_f32().map(|[x, y, z, w]| {
+                        UnitQuaternion::new_normalize(Quaternion::new(w, x, y, z))
+                    });
+                    Keyframes::Rotation(spline_values(values, cubic))
+                }
+                Some(ReadOutputs::Scales(values)) => {
+                    Keyframes::Scale(spline_values(values.map(Vector3::from), cubic))
+                }
+                Some(ReadOutputs::MorphTargetWeights(_)) => continue,
+                None => return Err(anyhow!("Animation channel has no keyframe values")),
+            };
+            let len = match &keyframes {
+                Keyframes::Translation(values) | Keyframes::Scale(values) => values.len(),
+                Keyframes::Rotation(values) => values.len(),
+            };
+            if times.is_empty() || len != times.len() {
+                return Err(anyhow!("Animation channel has mismatched keyframes"));
+            }
+            channels.push(Channel {
+                node: channel.target().node().index(),
+                times,
+                interpolation,
+                keyframes,
+            });
+        }
+        let duration = channels
+            .iter()
+            .filter_map(|it| it.times.last().copied())
+            .fold(0f32, f32::max);
+        animations.push(Animation {
+            name: animation.name().map(String::from),
+            channels,
+            duration,
+        });
+    }
+    info!("Loaded {} animations from {path:?}", animations.len());
+    Ok((skins, animations))
+}
+
+/// Drops the in and out tangents of cubic spline keyframes, keeping only their values
+fn spline_values<T>(values: impl Iterator<Item = T>, cubic: bool) -> Vec<T> {
+    if cubic {
+        values.skip(1).step_by(3).collect()
+    } else {
+        values.collect()
+    }
+}
+
+/// Ecs component playing an animation on a skin
+#[derive(Debug, Clone)]
+pub struct AnimationPlayer {
+    pub skin: Arc<Skin>,
+    pub animation: Arc<Animation>,
+    /// Time since the start of the animation in seconds
+    pub time: f32,
+    /// Restart the animation after it ends instead of holding the last pose
+    pub looping: bool,
+    pose: Vec<Transform>,
+}
+
+impl AnimationPlayer {
+    pub fn new(skin: Arc<Skin>, animation: Arc<Animation>) -> Self {
+        let mut pose = skin.rest_pose().to_vec();
+        animation.sample(0., &mut pose);
+        AnimationPlayer {
+            skin,
+            animation,
+            time: 0.,
+            looping: true,
+            pose,
+        }
+    }
+
+    /// Moves the animation forward by `delta` seconds and samples the new pose
+    pub fn advance(&mut self, delta: f32) {
+        let duration = self.animation.duration();
+        self.time += delta;
+        self.time = if self.looping && duration > 0. {
+            self.time.rem_euclid(duration)
+        } else {
+            self.time.min(duration)
+        };
+        self.pose.clear();
+        self.pose.extend_from_slice(self.skin.rest_pose());
+        self.animation.sample(self.time, &mut self.pose);
+    }
+
+    /// Bone matrices of the current pose, passed to the engine's `update_bones` each frame
+    pub fn bone_matrices(&self) -> Vec<Matrix4<f32>> {
+        self.skin.bone_matrices(&self.pose)
+    }
+}
+
+/// Advances every animation player by the step's delta time
+pub fn advance_animations(mut players: ViewMut<AnimationPlayer>, delta: UniqueView<Time>) {
+    let delta = delta.get::<second>() as f32;
+    for mut player in (&mut players).iter() {
+        player.advance(delta);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use engine::transform::Transform;
+    use nalgebra::{Matrix4, UnitQuaternion, Vector3};
+
+    use crate::animation::{Animation, Channel, Interpolation, Keyframes, Skin};
+
+    fn animation(interpolation: Interpolation) -> Animation {
+        Animation {
+            name: None,
+            channels: vec![Channel {
+                node: 0,
+                times: vec![1., 2.],
+                interpolation,
+                keyframes: Keyframes::Translation(vec![Vector3::zeros(), Vector3::new(2., 0., 0.)]),
+            }],
+            duration: 2.,
+        }
+    }
+
+    #[test]
+    fn samples_keyframes() {
+        let mut pose = [Transform::default()];
+        let linear = animation(Interpolation::Linear);
+        linear.sample(1.5, &mut pose);
+        assert_eq!(pose[0].translation, Vector3::new(1., 0., 0.));
+        linear.sample(0., &mut pose);
+        assert_eq!(pose[0].translation, Vector3::zeros());
+        linear.sample(3., &mut pose);
+        assert_eq!(pose[0].translation, Vector3::new(2., 0., 0.));
+
+        animation(Interpolation::Step).sample(1.5, &mut pose);
+        assert_eq!(pose[0].translation, Vector3::zeros());
+        assert_eq!(pose[0].rotation, UnitQuaternion::identity());
+    }
+
+    #[test]
+    fn bone_matrices_follow_hierarchy() {
+        let child = Transform::new(
+            Vector3::new(0., 1., 0.),
+            UnitQuaternion::identity(),
+            Vector3::repeat(1.),
+        );
+        let skin = Skin {
+            parents: vec![Some(1), None],
+            order: vec![1, 0],
+            rest_pose: vec![child, Transform::default()],
+            joints: vec![0],
+            inverse_bind_matrices: vec![Matrix4::new_translation(&Vector3::new(0., -1., 0.))],
+        };
+        let mut pose = skin.rest_pose().to_vec();
+        assert_eq!(skin.bone_matrices(&pose), vec![Matrix4::identity()]);
+
+        pose[1].translation.x = 3.;
+        assert_eq!(
+            skin.bone_matrices(&pose),
+            vec![Matrix4::new_translation(&Vector3::new(3., 0., 0.))]
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+
+/// Plane of the points whose dot product with `normal` plus `distance` is zero,
+/// points on the side the normal faces have a positive signed distance
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Plane {
+    /// Unit length normal
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+}
+
+/// Axis aligned bounding box, empty if any component of `min` is greater than `max`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+/// Planes bounding the volume visible through a projection, their normals face inwards
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far plane
+    pub planes: [Plane; 6],
+}
+
+/// Overlap test between two shapes, touching shapes intersect
+pub trait Intersects<T> {
+    fn intersects(&self, other: &T) -> bool;
+}
+
+impl Plane {
+    /// Plane with the given normal passing through `point`
+    ///
+    /// # Panics
+    /// if `normal` has zero length
+    pub fn new(normal: Vector3<f32>, point: &Point3<f32>) -> Self {
+        let normal = normal.normalize();
+        Plane {
+            normal,
+            distance: -normal.dot(&point.coords),
+        }
+    }
+
+    /// Plane of the points `p` where `coefficients · (p, 1)` is zero,
+    /// scaled so that the normal has unit length
+    pub fn from_coefficients(coefficients: Vector4<f32>) -> Self {
+        let length = coefficients.xyz().norm();
+        Plane {
+            normal: coefficients.xyz() / length,
+            distance: coefficients.w / length,
+        }
+    }
+
+    #[inline]
+    pub fn signed_distance(&self, point: &Point3<f32>) -> f32 {
+        self.normal.dot(&point.coords) + self.distance
+    }
+}
+
+impl Aabb {
+    /// The empty box, which grows to exactly the first point added to it
+    pub const EMPTY: Aabb = Aabb {
+        min: Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+        max: Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+    };
+
+    /// Smallest box containing all points, [EMPTY](Aabb::EMPTY) if there are none
+    pub fn from_points<'a>(points: impl IntoIterator<Item = &'a Point3<f32>>) -> Self {
+        points.into_iter().fold(Aabb::EMPTY, |aabb, point| Aabb {
+            min: aabb.min.inf(point),
+            max: aabb.max.sup(point),
+        })
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.min
+            .iter()
+            .zip(self.max.iter())
+            .any(|(min, max)| min > max)
+    }
+
+    #[inline]
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    /// Distance from the center to the faces along each axis
+    #[inline]
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) / 2.
+    }
+
+    pub fn contains(&self, point: &Point3<f32>) -> bool {
+        (0..3).all(|axis| point[axis] >= self.min[axis] && point[axis] <= self.max[axis])
+    }
+
+    /// Smallest axis aligned box containing this box after it was transformed by `matrix`
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        let corners = (0..8)
+            .map(|corner| {
+                let pick = |axis: usize| {
+                    if corner & (1 << axis) == 0 {
+                        self.min[axis]
+                    } else {
+                        self.max[axis]
+                    }
+                };
+                matrix.transform_point(&Point3::new(pick(0), pick(1), pick(2)))
+            })
+            .collect::<Vec<_>>();
+        Aabb::from_points(&corners)
+    }
+
+    /// Corner furthest along `direction`
+    fn support(&self, direction: &Vector3<f32>) -> Point3<f32> {
+        Point3::from(Vector3::from_fn(|axis, _| {
+            if direction[axis] >= 0. {
+                self.max[axis]
+            } else {
+                self.min[axis]
+            }
+        }))
+    }
+}
+
+impl BoundingSphere {
+    /// Sphere through the corners of a box
+    pub fn from_aabb(aabb: &Aabb) -> Self {
+        BoundingSphere {
+            center: aabb.center(),
+            radius: aabb.half_extents().norm(),
+        }
+    }
+}
+
+/// Extracts the frustum of a projection or combined model view projection matrix.
+///
+/// Clip space is expected to follow the OpenGL convention used by nalgebra's projections,
+/// `-w <= x, y, z <= w` for visible points. Planes are in the space the matrix transforms from,
+/// so the frustum of a model view projection matrix can be tested against model space bounds
+pub fn frustum_from_matrix(matrix: &Matrix4<f32>) -> Frustum {
+    let row = |index: usize| matrix.row(index).transpose();
+    let w = row(3);
+    let plane = Plane::from_coefficients;
+    Frustum {
+        planes: [
+            plane(w + row(0)),
+            plane(w - row(0)),
+            plane(w + row(1)),
+            plane(w - row(1)),
+            plane(w + row(2)),
+            plane(w - row(2)),
+        ],
+    }
+}
+
+impl Intersects<Point3<f32>> for Frustum {
+    fn intersects(&self, point: &Point3<f32>) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(point) >= 0.)
+    }
+}
+
+impl Intersects<BoundingSphere> for Frustum {
+    /// Conservative, spheres near the frustum's edges outside of it may still intersect
+    fn intersects(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(&sphere.center) >= -sphere.radius)
+    }
+}
+
+impl Intersects<Aabb> for Frustum {
+    /// Conservative, boxes near the frustum's edges outside of it may still intersect
+    fn intersects(&self, aabb: &Aabb) -> bool {
+        !aabb.is_empty()
+            && self
+                .planes
+                .iter()
+                .all(|plane| plane.signed_distance(&aabb.support(&plane.normal)) >= 0.)
+    }
+}
+
+impl Intersects<BoundingSphere> for Plane {
+    fn intersects(&self, sphere: &BoundingSphere) -> bool {
+        self.signed_distance(&sphere.center).abs() <= sphere.radius
+    }
+}
+
+impl Intersects<Aabb> for Plane {
+    fn intersects(&self, aabb: &Aabb) -> bool {
+        !aabb.is_empty()
+            && self.signed_distance(&aabb.support(&self.normal)) >= 0.
+            && self.signed_distance(&aabb.support(&-self.normal)) <= 0.
+    }
+}
+
+impl Intersects<Aabb> for Aabb {
+    fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|axis| self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis])
+    }
+}
+
+impl Intersects<BoundingSphere> for BoundingSphere {
+    fn intersects(&self, other: &BoundingSphere) -> bool {
+        nalgebra::distance(&self.center, &other.center) <= self.radius + other.radius
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::{Isometry3, Matrix4, Perspective3, Point3, Vector3};
+
+    use crate::geometry::{frustum_from_matrix, Aabb, BoundingSphere, Frustum, Intersects, Plane};
+
+    /// Camera at the origin looking down negative z with a 90 degree field of view
+    fn frustum() -> Frustum {
+        let projection = Perspective3::new(1., std::f32::consts::FRAC_PI_2, 0.1, 100.);
+        frustum_from_matrix(&projection.to_homogeneous())
+    }
+
+    fn cube(center: Point3<f32>, half_size: f32) -> Aabb {
+        Aabb {
+            min: center - Vector3::repeat(half_size),
+            max: center + Vector3::repeat(half_size),
+        }
+    }
+
+    #[test]
+    fn points_inside_and_outside_frustum() {
+        let frustum = frustum();
+        assert!(frustum.intersects(&Point3::new(0., 0., -10.)));
+        assert!(frustum.intersects(&Point3::new(9., -9., -10.)));
+        assert!(!frustum.intersects(&Point3::new(11., 0., -10.)));
+        assert!(!frustum.intersects(&Point3::new(0., 0., 10.)));
+        assert!(!frustum.intersects(&Point3::new(0., 0., -0.05)));
+        assert!(!frustum.intersects(&Point3::new(0., 0., -101.)));
+    }
+
+    #[test]
+    fn sphere_straddling_plane() {
+        let plane = Plane::new(Vector3::new(0., 2., 0.), &Point3::new(0., 1., 0.));
+        assert_eq!(plane.signed_distance(&Point3::new(5., 3., 0.)), 2.);
+        let sphere = |y| BoundingSphere {
+            center: Point3::new(0., y, 0.),
+            radius: 1.,
+        };
+        assert!(plane.intersects(&sphere(1.5)));
+        assert!(plane.intersects(&sphere(0.5)));
+        assert!(!plane.intersects(&sphere(2.5)));
+        assert!(!plane.intersects(&sphere(-0.5)));
+
+        let frustum = frustum();
+        assert!(frustum.intersects(&BoundingSphere {
+            center: Point3::new(10.5, 0., -10.),
+            radius: 1.,
+        }));
+        assert!(!frustum.intersects(&BoundingSphere {
+            center: Point3::new(0., 0., 2.),
+            radius: 1.,
+        }));
+    }
+
+    #[test]
+    fn aabb_outside_frustum() {
+        let frustum = frustum();
+        assert!(frustum.intersects(&cube(Point3::new(0., 0., -10.), 1.)));
+        assert!(frustum.intersects(&cube(Point3::new(10.5, 0., -10.), 1.)));
+        assert!(!frustum.intersects(&cube(Point3::new(0., 0., 5.), 1.)));
+        assert!(!frustum.intersects(&cube(Point3::new(20., 0., -10.), 1.)));
+        assert!(!frustum.intersects(&cube(Point3::new(0., 0., -200.), 1.)));
+        assert!(!frustum.intersects(&Aabb::EMPTY));
+
+        let plane = Plane::new(Vector3::x(), &Point3::origin());
+        assert!(plane.intersects(&cube(Point3::new(0.5, 0., 0.), 1.)));
+        assert!(!plane.intersects(&cube(Point3::new(2., 0., 0.), 1.)));
+    }
+
+    #[test]
+    fn model_view_projection_frustum_is_in_model_space() {
+        let view =
+            Isometry3::look_at_rh(&Point3::new(0., 0., 10.), &Point3::origin(), &Vector3::y());
+        let projection = Perspective3::new(1., std::f32::consts::FRAC_PI_2, 0.1, 100.);
+        let model = Matrix4::new_translation(&Vector3::new(50., 0., 0.));
+        let frustum =
+            frustum_from_matrix(&(projection.to_homogeneous() * view.to_homogeneous() * model));
+        assert!(!frustum.intersects(&cube(Point3::origin(), 1.)));
+        assert!(frustum.intersects(&cube(Point3::new(-50., 0., 0.), 1.)));
+
+        let moved = cube(Point3::origin(), 1.).transformed(&model);
+        assert_eq!(moved, cube(Point3::new(50., 0., 0.), 1.));
+        assert!(moved.intersects(&cube(Point3::new(51.5, 0., 0.), 1.)));
+        assert!(!moved.intersects(&cube(Point3::new(53., 0., 0.), 1.)));
+    }
+}
@@ -4,7 +4,7 @@ use std::error::Error;
 use std::path::Path;
 use std::sync::Arc;
 
-use nalgebra::{Isometry3, Matrix4, Orthographic3, Perspective3};
+use nalgebra::{Isometry3, Matrix4, Orthographic3, Perspective3, Point3, UnitQuaternion, Vector3};
 use raw_window_handle::HasRawWindowHandle;
 use serde::{Deserialize, Serialize};
 use uom::si::angle::degree;
@@ -12,62 +12,762 @@ use uom::si::f32::Angle;
 
 #[cfg(feature = "vulkan")]
 mod vulkan {
+    pub(crate) mod dds;
     pub mod engine;
     pub(super) mod material;
     pub(super) mod mesh;
+    pub(crate) mod sampler;
     pub(crate) mod texture;
 }
 
+pub mod animation;
+pub mod geometry;
+pub mod materials;
+pub mod null;
+
+#[cfg(feature = "vulkan")]
+pub type Engine = vulkan::engine::Engine;
 #[cfg(feature = "vulkan")]
 pub type Material = vulkan::material::Material;
 #[cfg(feature = "vulkan")]
 pub type Mesh = vulkan::mesh::Mesh;
+#[cfg(feature = "vulkan")]
+pub type Vertex = vulkan::mesh::Vertex;
+#[cfg(feature = "vulkan")]
+pub type SkinVertex = vulkan::mesh::SkinVertex;
+#[cfg(feature = "vulkan")]
+pub type DrawBatch = vulkan::engine::batch::DrawBatch;
+#[cfg(feature = "vulkan")]
+pub type DynamicVertexBuffer = vulkan::engine::dynamic::DynamicVertexBuffer;
+#[cfg(feature = "vulkan")]
+pub type Sampler = vulkan::sampler::Sampler;
+#[cfg(feature = "vulkan")]
+pub type Texture = vulkan::texture::Texture;
+#[cfg(feature = "vulkan")]
+pub type Skeleton = vulkan::engine::skinning::Skeleton;
+#[cfg(feature = "vulkan")]
+pub type StorageBuffer = vulkan::engine::alloc::StorageBuffer;
+#[cfg(feature = "vulkan")]
+pub type ComputePipeline = vulkan::engine::compute::ComputePipeline;
+#[cfg(feature = "vulkan")]
+pub type Upload<T> = vulkan::engine::upload::Upload<T>;
+#[cfg(feature = "vulkan")]
+pub use vulkan::mesh::merge_meshes;
 
 pub trait RenderingEngine {
+    /// Meshes and materials are stored as ecs components and shared with render threads,
+    /// so they must be thread safe even if the engine itself is not
+    type Mesh: Send + Sync + 'static;
+    type Material: Send + Sync + 'static;
+
     fn begin_rendering(&mut self, camera: &Camera);
-    fn render(&mut self, mesh: &Arc<Mesh>, material: &Arc<Material>, transform: Matrix4<f32>);
+    fn render(
+        &mut self,
+        mesh: &Arc<Self::Mesh>,
+        material: &Arc<Self::Material>,
+        transform: Matrix4<f32>,
+    );
+    /// Renders a mesh like [render](RenderingEngine::render),
+    /// tagging the pixels it covers with `id` so [pick](RenderingEngine::pick) can find it
+    fn render_with_id(
+        &mut self,
+        mesh: &Arc<Self::Mesh>,
+        material: &Arc<Self::Material>,
+        transform: Matrix4<f32>,
+        id: u64,
+    ) {
+        let _ = id;
+        self.render(mesh, material, transform);
+    }
+    /// Returns the id of the draw covering the pixel at `x`, `y` in the last rendered frame,
+    /// counted in physical pixels from the top left corner.
+    ///
+    /// Returns None over pixels without a tagged draw,
+    /// or if the engine does not support picking
+    fn pick(&self, x: u32, y: u32) -> Option<u64> {
+        let _ = (x, y);
+        None
+    }
     fn end_rendering(&mut self);
+    /// Saves the next frame that is rendered to `path` as a png,
+    /// once the gpu finished it and without blocking the render loop.
+    ///
+    /// Failures are logged, engines that can't read back frames ignore the request
+    fn capture_screenshot(&mut self, path: &Path) {
+        let _ = path;
+    }
+    /// Switches between presenting with vsync and unsynchronized presentation,
+    /// engines that don't present ignore it
+    fn set_vsync(&mut self, vsync: bool) {
+        let _ = vsync;
+    }
+    /// Draws a cubemap behind all geometry, replacing the previous skybox.
+    ///
+    /// `faces` are png, jpeg, bmp or tga images of the +x, -x, +y, -y, +z and -z faces,
+    /// squares of the same size. Engines that don't draw ignore it
+    fn set_skybox(&mut self, faces: [&Path; 6]) -> Result<(), Box<dyn Error>> {
+        let _ = faces;
+        Ok(())
+    }
+    /// Cpu time of the last frames, zero for engines that don't measure it
+    fn frame_stats(&self) -> FrameStats {
+        FrameStats::default()
+    }
+    /// Usage of the gpu's memory heaps, empty for engines without gpu memory
+    fn memory_report(&self) -> MemoryReport {
+        MemoryReport::default()
+    }
     fn resize(&mut self, width: u32, height: u32);
-    fn load_model(&mut self, path: &Path) -> Result<Arc<Mesh>, Box<dyn Error>>;
-    fn load_material(&mut self) -> Result<Arc<Material>, Box<dyn Error>>;
+    fn load_model(&mut self, path: &Path) -> Result<Arc<Self::Mesh>, Box<dyn Error>> {
+        self.load_model_with_options(path, &ModelOptions::default())
+    }
+    fn load_model_with_options(
+        &mut self,
+        path: &Path,
+        options: &ModelOptions,
+    ) -> Result<Arc<Self::Mesh>, Box<dyn Error>>;
+    /// Loads several models in order.
+    ///
+    /// `progress` is called with the number of loaded models and the total after each model
+    /// finishes loading, always on the calling thread so it is safe to redraw a loading screen from it
+    fn load_models_with_progress(
+        &mut self,
+        paths: &[&Path],
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<Vec<Arc<Self::Mesh>>, Box<dyn Error>> {
+        let mut meshes = Vec::with_capacity(paths.len());
+        for path in paths {
+            meshes.push(self.load_model(path)?);
+            progress(meshes.len(), paths.len());
+        }
+        Ok(meshes)
+    }
+    /// Loads the material `name` from the asset database,
+    /// materials loaded while an earlier load of the same name is alive are shared
+    fn load_material(&mut self, name: &str) -> Result<Arc<Self::Material>, Box<dyn Error>>;
     fn wait(&self);
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct GraphicsSettings {
+    pub backend: Backend,
     pub resolution: [u32; 2],
     pub fov: Angle,
     pub vsync: bool,
+    /// Width and height of the directional light's shadow map
+    pub shadow_resolution: u32,
+    /// Share swapchain images between the graphics and presentation queue families
+    /// instead of transferring ownership every frame, only matters when the families differ
+    pub concurrent_present: bool,
+    /// Textures larger than this in either dimension are downscaled when loaded,
+    /// textures are always limited to the largest size supported by the device
+    pub max_texture_size: Option<u32>,
+    /// Skip drawing objects whose bounding box was hidden behind the depth buffer a few frames ago.
+    /// Helps large indoor scenes, but newly visible objects appear a couple of frames late
+    pub occlusion_culling: bool,
+    /// Near and far plane of the orthographic projection used for 2D sprites and ui
+    pub orthographic_depth: [f32; 2],
+    /// Near and far plane of the perspective projection, a far plane further away
+    /// trades depth precision for view distance. Clamped to `0 < near < far`
+    pub perspective_depth: [f32; 2],
+    /// Axis conventions of world space, should match the content being rendered
+    pub coordinate_system: CoordinateSystem,
+    /// Fail when a model or texture can't be loaded instead of substituting a placeholder
+    /// cube or magenta checkerboard, meant for CI runs
+    pub strict_assets: bool,
+    /// Exclusive fullscreen video mode of the primary monitor, windowed if None
+    pub fullscreen: Option<FullscreenMode>,
+    /// Most bytes of staging memory held by uploads at once, loads past it wait for
+    /// the gpu to finish copying earlier uploads. Unlimited if None
+    pub upload_budget: Option<u64>,
+    pub recording: RecordingMode,
+    /// Samples per pixel of the main pass, 1 disables multisampling.
+    /// Lowered to the most the device supports, see [GpuInfo::max_samples]
+    pub msaa: u32,
+    /// Linear rgba color the frame is cleared to before drawing,
+    /// components are clamped to `0.0..=1.0` when deserialized
+    #[serde(deserialize_with = "clamped_color")]
+    pub clear_color: [f32; 4],
+    /// Frames the cpu records ahead of the gpu, from 1 to 3. Fewer frames lower the latency
+    /// of input, more frames smooth out uneven frame times
+    pub frames_in_flight: u32,
+    /// Threads recording draws into secondary command buffers, at most 12.
+    /// Half the cores if None, an explicit count is used even by [RecordingMode::Auto]
+    pub render_threads: Option<usize>,
+    /// Part of the name of the gpu to run on, ignoring case. The first discrete gpu is used
+    /// if None or if no suitable gpu matches
+    pub gpu: Option<String>,
+}
+
+fn clamped_color<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<[f32; 4], D::Error> {
+    let color = <[f32; 4]>::deserialize(deserializer)?;
+    Ok(color.map(|component| component.clamp(0., 1.)))
+}
+
+/// Where the draws of a frame are recorded into command buffers
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+pub enum RecordingMode {
+    /// Single threaded when the machine only gets one render thread
+    Auto,
+    /// Render threads record secondary command buffers in parallel
+    Threaded,
+    /// Draws are recorded into the primary command buffer on the thread that submits them,
+    /// cheaper for small scenes and easier to debug
+    SingleThreaded,
+}
+
+/// Video mode used for exclusive fullscreen,
+/// the monitor's mode closest to it is chosen if it is not supported exactly
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+pub struct FullscreenMode {
+    pub resolution: [u32; 2],
+    /// Refresh rate in hertz
+    pub refresh_rate: u16,
+    /// Bits per pixel
+    pub bit_depth: u16,
+}
+
+/// Axis conventions of world space, chosen at engine init.
+///
+/// The view matrix is expected to follow the same convention,
+/// [look_at](CoordinateSystem::look_at) builds one for any of them.
+/// Meshes keep their authored winding, content authored for a convention renders
+/// with the front faces it was authored with. The orthographic 2D projection is not affected
+#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, Eq, PartialEq)]
+pub struct CoordinateSystem {
+    pub handedness: Handedness,
+    pub up: UpAxis,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, Eq, PartialEq)]
+pub enum Handedness {
+    /// Rotating x onto y is counter clockwise seen from positive z, cameras look along negative z.
+    /// Used by OpenGL, glTF and Blender's exporters
+    #[default]
+    Right,
+    /// Rotating x onto y is clockwise seen from positive z, cameras look along positive z.
+    /// Used by DirectX and Unity
+    Left,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, Eq, PartialEq)]
+pub enum UpAxis {
+    #[default]
+    YUp,
+    /// Positive y points down, as in screen space and some 2D authoring tools
+    YDown,
+}
+
+impl CoordinateSystem {
+    /// World space up direction
+    pub fn up(&self) -> Vector3<f32> {
+        match self.up {
+            UpAxis::YUp => Vector3::y(),
+            UpAxis::YDown => -Vector3::y(),
+        }
+    }
+
+    /// View matrix of a camera at `eye` looking at `target`, upright in this convention
+    pub fn look_at(&self, eye: &Point3<f32>, target: &Point3<f32>) -> Isometry3<f32> {
+        match self.handedness {
+            Handedness::Right => Isometry3::look_at_rh(eye, target, &self.up()),
+            Handedness::Left => Isometry3::look_at_lh(eye, target, &self.up()),
+        }
+    }
+
+    /// Converts view space of this convention to the right handed view space
+    /// the camera's projections expect, applied between the view and the projection matrix
+    pub fn view_correction(&self) -> Matrix4<f32> {
+        match self.handedness {
+            Handedness::Right => Matrix4::identity(),
+            Handedness::Left => Matrix4::new_nonuniform_scaling(&Vector3::new(1., 1., -1.)),
+        }
+    }
+}
+
+/// Properties of the gpu a rendering engine runs on
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GpuInfo {
+    pub name: String,
+    /// Dedicated graphics card rather than one integrated with the cpu
+    pub discrete: bool,
+    /// Bytes of device local memory, shared with the system on integrated gpus
+    pub video_memory: u64,
+    /// Highest multisample count supported by both color and depth attachments
+    pub max_samples: u32,
+    /// Largest supported width and height of 2D textures
+    pub max_texture_size: u32,
+}
+
+/// Rough performance class of a gpu, used to choose default graphics settings
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum GpuTier {
+    Low,
+    Medium,
+    High,
+}
+
+/// Discrete gpus with at least this much video memory are [GpuTier::High]
+const HIGH_TIER_VIDEO_MEMORY: u64 = 6 * 1024 * 1024 * 1024;
+
+impl GpuInfo {
+    pub fn tier(&self) -> GpuTier {
+        match (self.discrete, self.video_memory) {
+            (true, memory) if memory >= HIGH_TIER_VIDEO_MEMORY => GpuTier::High,
+            (true, _) => GpuTier::Medium,
+            (false, _) => GpuTier::Low,
+        }
+    }
+}
+
+/// Light shining on the whole world from one direction, casting the shadows of the shadow map
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DirectionalLight {
+    /// Direction the light travels in, doesn't need to be normalized
+    pub direction: Vector3<f32>,
+    /// Linear rgb color, can be brighter than 1
+    pub color: Vector3<f32>,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        DirectionalLight {
+            direction: Vector3::new(0.24525, -0.919709, -0.30656966),
+            color: Vector3::repeat(1.),
+        }
+    }
+}
+
+/// Where the time of the last frame went, measured around the engine's
+/// waits on the gpu and presentation
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct FramePacing {
+    /// Time since the previous frame not spent waiting, recording draws and game logic
+    pub cpu_ms: f32,
+    /// Time the gpu spent executing a frame, None without gpu timestamps.
+    /// Measured for the latest frame that finished, a few frames before the last one
+    pub gpu_ms: Option<f32>,
+    /// Time the last frame waited for an earlier frame to finish and be presented,
+    /// and for the next swapchain image
+    pub present_wait_ms: f32,
+    pub bound: Bottleneck,
+}
+
+/// What limits the frame rate
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum Bottleneck {
+    /// Frames are submitted no faster than they are recorded
+    #[default]
+    Cpu,
+    /// Frames wait for the gpu to finish earlier frames
+    Gpu,
+    /// Frames wait on vsync or the presentation engine while the gpu is mostly idle
+    Present,
 }
 
+/// Frames waiting at most this fraction of their cpu time are [Bottleneck::Cpu]
+const CPU_BOUND_WAIT: f32 = 0.1;
+/// Waiting frames whose gpu time is at least this fraction of the frame are [Bottleneck::Gpu]
+const GPU_BOUND_BUSY: f32 = 0.8;
+
+impl Bottleneck {
+    /// Guesses the bottleneck of a frame from its times in milliseconds,
+    /// without a gpu time waiting is attributed to presentation
+    pub fn classify(cpu_ms: f32, gpu_ms: Option<f32>, present_wait_ms: f32) -> Self {
+        if present_wait_ms <= cpu_ms * CPU_BOUND_WAIT {
+            Bottleneck::Cpu
+        } else if gpu_ms.map_or(false, |gpu| {
+            gpu >= (cpu_ms + present_wait_ms) * GPU_BOUND_BUSY
+        }) {
+            Bottleneck::Gpu
+        } else {
+            Bottleneck::Present
+        }
+    }
+}
+
+/// Cpu time of the last frames, the [cpu_ms](FramePacing::cpu_ms) of their frame pacing
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct FrameStats {
+    /// Milliseconds of the last frame
+    pub cpu_ms: f32,
+    /// Milliseconds averaged over the last 120 frames, or all frames right after startup
+    pub average_cpu_ms: f32,
+}
+
+/// Gpu memory used by the process, see [RenderingEngine::memory_report]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct MemoryReport {
+    /// Usage of every memory heap of the gpu, in the device's heap order
+    pub heaps: Vec<HeapUsage>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct HeapUsage {
+    /// Bytes of memory in the heap
+    pub size: u64,
+    /// Bytes of the heap used by the process, including memory not allocated by the engine
+    pub used: u64,
+    /// Bytes the driver estimates the process can use before allocations fail or slow down
+    pub budget: u64,
+    /// Video memory rather than system memory visible to the gpu
+    pub device_local: bool,
+}
+
+impl MemoryReport {
+    /// Bytes used across all heaps
+    pub fn used(&self) -> u64 {
+        self.heaps.iter().map(|heap| heap.used).sum()
+    }
+}
+
+impl GraphicsSettings {
+    /// Replaces the quality related settings with defaults suited to `gpu`,
+    /// the backend and options unrelated to quality are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `monitor`: size of the monitor the window is on, the resolution never exceeds it
+    pub fn with_preset(mut self, gpu: &GpuInfo, monitor: Option<[u32; 2]>) -> Self {
+        let (resolution, shadow_resolution, max_texture_size, msaa) = match gpu.tier() {
+            GpuTier::Low => ([1280, 720], 1024, Some(1024), 1),
+            GpuTier::Medium => ([1600, 900], 2048, Some(2048), 2),
+            GpuTier::High => ([1920, 1080], 4096, None, 4),
+        };
+        self.resolution = match monitor {
+            Some([width, height]) => [resolution[0].min(width), resolution[1].min(height)],
+            None => resolution,
+        };
+        self.shadow_resolution = shadow_resolution;
+        self.max_texture_size = max_texture_size;
+        self.msaa = msaa.min(gpu.max_samples).max(1);
+        self
+    }
+
+    /// Splits the changes from these running settings to `new` into the ones that can be
+    /// applied live and the ones that only take effect when the engine is recreated.
+    ///
+    /// returns: the running settings with the live changes applied and the names of the changes
+    pub fn diff(&self, new: &GraphicsSettings) -> (GraphicsSettings, SettingsChanges) {
+        let live = GraphicsSettings {
+            backend: self.backend,
+            shadow_resolution: self.shadow_resolution,
+            concurrent_present: self.concurrent_present,
+            occlusion_culling: self.occlusion_culling,
+            coordinate_system: self.coordinate_system,
+            recording: self.recording,
+            msaa: self.msaa,
+            frames_in_flight: self.frames_in_flight,
+            render_threads: self.render_threads,
+            gpu: self.gpu.clone(),
+            ..new.clone()
+        };
+        let changes = SettingsChanges {
+            applied: self.changed_fields(&live),
+            restart_required: live.changed_fields(new),
+        };
+        (live, changes)
+    }
+
+    /// Names of the settings that differ from `other`
+    fn changed_fields(&self, other: &GraphicsSettings) -> Vec<&'static str> {
+        // destructured so that new settings can't be forgotten here
+        let GraphicsSettings {
+            backend,
+            resolution,
+            fov,
+            vsync,
+            shadow_resolution,
+            concurrent_present,
+            max_texture_size,
+            occlusion_culling,
+            orthographic_depth,
+            perspective_depth,
+            coordinate_system,
+            strict_assets,
+            fullscreen,
+            upload_budget,
+            recording,
+            msaa,
+            clear_color,
+            frames_in_flight,
+            render_threads,
+            gpu,
+        } = self;
+        [
+            ("backend", *backend != other.backend),
+            ("resolution", *resolution != other.resolution),
+            ("fov", *fov != other.fov),
+            ("vsync", *vsync != other.vsync),
+            (
+                "shadow_resolution",
+                *shadow_resolution != other.shadow_resolution,
+            ),
+            (
+                "concurrent_present",
+                *concurrent_present != other.concurrent_present,
+            ),
+            (
+                "max_texture_size",
+                *max_texture_size != other.max_texture_size,
+            ),
+            (
+                "occlusion_culling",
+                *occlusion_culling != other.occlusion_culling,
+            ),
+            (
+                "orthographic_depth",
+                *orthographic_depth != other.orthographic_depth,
+            ),
+            (
+                "perspective_depth",
+                *perspective_depth != other.perspective_depth,
+            ),
+            (
+                "coordinate_system",
+                *coordinate_system != other.coordinate_system,
+            ),
+            ("strict_assets", *strict_assets != other.strict_assets),
+            ("fullscreen", *fullscreen != other.fullscreen),
+            ("upload_budget", *upload_budget != other.upload_budget),
+            ("recording", *recording != other.recording),
+            ("msaa", *msaa != other.msaa),
+            ("clear_color", *clear_color != other.clear_color),
+            (
+                "frames_in_flight",
+                *frames_in_flight != other.frames_in_flight,
+            ),
+            ("render_threads", *render_threads != other.render_threads),
+            ("gpu", *gpu != other.gpu),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+/// Settings changed by applying new graphics settings to a running engine
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct SettingsChanges {
+    /// Changes in effect from the next frame on.
+    /// The resolution, fullscreen mode, field of view and orthographic depth
+    /// are applied by the caller to the window and camera
+    pub applied: Vec<&'static str>,
+    /// Changes that are kept in the settings but only take effect once the engine is recreated
+    pub restart_required: Vec<&'static str>,
+}
+
+/// Options controlling how a model file is imported
+#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, Eq, PartialEq)]
+pub struct ModelOptions {
+    /// Replace the model's normals with smooth normals computed from its faces.
+    /// Normals are always recomputed if the model contains zero length or non finite normals
+    pub recompute_normals: bool,
+}
+
+/// Pixels of a rendered frame read back from the gpu,
+/// tightly packed rows of 8 bit rgba starting at the top left corner
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameCapture {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// Selects which [RenderingEngine] implementation the client creates
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+pub enum Backend {
+    Vulkan,
+    /// Renders nothing, see [null::NullEngine]
+    Null,
+}
+
+#[derive(Debug, Clone)]
 pub struct Camera {
     pub view: Isometry3<f32>,
     pub projection: Perspective3<f32>,
+    /// How the 3D scene is projected, read every frame so it can be switched at any time
+    pub projection_mode: ProjectionMode,
+    /// Projection of the 2D path, maps pixels to the screen with the origin in the bottom left
     pub orthographic: Orthographic3<f32>,
+    /// Number of distinct [sprite depths](Camera::sprite_depth) in the orthographic depth range
+    pub sprite_layers: u32,
+    /// Conventions [look_at](Camera::look_at) and [forward](Camera::forward) follow,
+    /// should match the engine's [coordinate_system](GraphicsSettings::coordinate_system)
+    pub coordinate_system: CoordinateSystem,
+}
+
+/// Projection of the 3D scene, the 2D path always uses the camera's orthographic projection
+#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, PartialEq)]
+pub enum ProjectionMode {
+    #[default]
+    Perspective,
+    /// Parallel projection with the aspect ratio and clip planes of the perspective projection,
+    /// `height` world units are visible from the bottom to the top of the screen
+    Orthographic { height: f32 },
+}
+
+/// Near and far plane of the orthographic projection unless configured otherwise
+pub const DEFAULT_ORTHOGRAPHIC_DEPTH: [f32; 2] = [0., 1.];
+/// Near and far plane of the perspective projection unless configured otherwise
+pub const DEFAULT_PERSPECTIVE_DEPTH: [f32; 2] = [0.1, 1000.];
+/// Closest the perspective projection's near plane can be to the camera
+const MIN_NEAR_PLANE: f32 = 1e-4;
+
+/// Clamps the perspective near plane to be in front of the camera and the far plane
+/// to be behind the near plane, the projected depth is only in `0..=1` for `0 < near < far`
+fn valid_perspective_depth([near, far]: [f32; 2]) -> [f32; 2] {
+    // also replaces NaN
+    let valid_near = if near >= MIN_NEAR_PLANE { near } else { MIN_NEAR_PLANE };
+    let valid_far = if far > valid_near {
+        far
+    } else {
+        valid_near + DEFAULT_PERSPECTIVE_DEPTH[1]
+    };
+    if [valid_near, valid_far] != [near, far] {
+        log::warn!(
+            "Invalid perspective depth range {near}..{far}, using {valid_near}..{valid_far}"
+        );
+    }
+    [valid_near, valid_far]
 }
 
 impl Camera {
     pub fn new(width:u32, height: u32, fov: Angle) -> Self {
-        let projection = Perspective3::new(
-            width as f32 / height as f32,
-            fov.value,
-            0.1,
-            1000.,
-        );
+        let [znear, zfar] = DEFAULT_PERSPECTIVE_DEPTH;
+        let projection = Perspective3::new(width as f32 / height as f32, fov.value, znear, zfar);
+        let [near, far] = DEFAULT_ORTHOGRAPHIC_DEPTH;
         let orthographic = Orthographic3::new(
             0.,
             width as f32,
             0.,
             height as f32,
-            0.1,
-            1000.,
+            near,
+            far,
         );
         Camera {
             view: Default::default(),
             projection,
+            projection_mode: ProjectionMode::Perspective,
             orthographic,
+            sprite_layers: 1024,
+            coordinate_system: CoordinateSystem::default(),
         }
     }
+
+    /// Replaces the conventions the camera is oriented in, the view is not changed
+    pub fn with_coordinate_system(mut self, coordinate_system: CoordinateSystem) -> Self {
+        self.coordinate_system = coordinate_system;
+        self
+    }
+
+    /// Position of the camera in world space
+    pub fn position(&self) -> Point3<f32> {
+        self.view.inverse_transform_point(&Point3::origin())
+    }
+
+    /// Direction the camera looks in, in world space
+    pub fn forward(&self) -> Vector3<f32> {
+        let forward = match self.coordinate_system.handedness {
+            Handedness::Right => -Vector3::z(),
+            Handedness::Left => Vector3::z(),
+        };
+        self.view.inverse_transform_vector(&forward)
+    }
+
+    /// Direction to the right of the camera as seen on screen, in world space
+    pub fn right(&self) -> Vector3<f32> {
+        self.view.inverse_transform_vector(&Vector3::x())
+    }
+
+    /// Moves the camera to `eye`, keeping the direction it looks in
+    pub fn set_position(&mut self, eye: Point3<f32>) {
+        self.view.translation.vector = -(self.view.rotation * eye.coords);
+    }
+
+    /// Turns the camera towards `target`, upright in its [coordinate_system](Camera::coordinate_system).
+    /// Nothing changes if the target is the camera's position
+    pub fn look_at(&mut self, target: Point3<f32>) {
+        let eye = self.position();
+        if eye != target {
+            self.view = self.coordinate_system.look_at(&eye, &target);
+        }
+    }
+
+    /// Moves the camera by `offset` in world space, keeping the direction it looks in
+    pub fn translate(&mut self, offset: Vector3<f32>) {
+        self.set_position(self.position() + offset);
+    }
+
+    /// Turns the camera by the world space `rotation` around its position
+    pub fn rotate(&mut self, rotation: UnitQuaternion<f32>) {
+        let eye = self.position();
+        self.view.rotation *= rotation.inverse();
+        self.set_position(eye);
+    }
+
+    /// Fits the projections to a viewport of `width` by `height` pixels,
+    /// a zero size is treated as a single pixel
+    pub fn set_aspect(&mut self, width: u32, height: u32) {
+        let (width, height) = (width.max(1) as f32, height.max(1) as f32);
+        self.projection.set_aspect(width / height);
+        self.orthographic.set_left_and_right(0., width);
+        self.orthographic.set_bottom_and_top(0., height);
+    }
+
+    /// Changes the vertical field of view of the perspective projection
+    pub fn set_fov(&mut self, fov: Angle) {
+        self.projection.set_fovy(fov.value);
+    }
+
+    /// Replaces the near and far plane of the perspective projection, which are also used
+    /// by the orthographic [projection_mode](Camera::projection_mode).
+    /// Invalid planes are clamped to `0 < near < far`
+    pub fn with_perspective_depth(mut self, depth: [f32; 2]) -> Self {
+        let [near, far] = valid_perspective_depth(depth);
+        self.projection.set_znear_and_zfar(near, far);
+        self
+    }
+
+    /// Replaces the near and far plane of the orthographic projection,
+    /// the perspective projection is not affected
+    pub fn with_orthographic_depth(mut self, [near, far]: [f32; 2]) -> Self {
+        self.orthographic.set_znear_and_zfar(near, far);
+        self
+    }
+
+    /// Projection matrix of the 3D scene in the current [projection_mode](Camera::projection_mode)
+    pub fn projection_matrix(&self) -> Matrix4<f32> {
+        match self.projection_mode {
+            ProjectionMode::Perspective => self.projection.to_homogeneous(),
+            ProjectionMode::Orthographic { height } => {
+                let top = height / 2.;
+                let right = top * self.projection.aspect();
+                Orthographic3::new(
+                    -right,
+                    right,
+                    -top,
+                    top,
+                    self.projection.znear(),
+                    self.projection.zfar(),
+                )
+                .to_homogeneous()
+            }
+        }
+    }
+
+    /// View space z coordinate of sprites on `layer` for the orthographic projection.
+    ///
+    /// Layers are spread evenly over the orthographic depth range and higher layers are in
+    /// front of lower ones, layers past [sprite_layers](Camera::sprite_layers) are clamped to the last one
+    pub fn sprite_depth(&self, layer: u32) -> f32 {
+        let layers = self.sprite_layers.max(1);
+        let t = (layer.min(layers - 1) as f32 + 0.5) / layers as f32;
+        let (near, far) = (self.orthographic.znear(), self.orthographic.zfar());
+        // the camera looks along negative z
+        -(far + (near - far) * t)
+    }
 }
 
 #[cfg(feature = "vulkan")]
@@ -75,28 +775,264 @@ pub fn create_rendering_engine(
     window: &dyn HasRawWindowHandle,
     settings: &GraphicsSettings,
 ) -> Box<vulkan::engine::Engine> {
-    Box::new(unsafe {
-        vulkan::engine::Engine::new(window, settings)
-            .expect("Failed to initialize rendering engine")
+    try_create_rendering_engine(window, settings).expect("Failed to initialize rendering engine")
+}
+
+/// Like [create_rendering_engine], but returns an error if no usable gpu is available
+#[cfg(feature = "vulkan")]
+pub fn try_create_rendering_engine(
+    window: &dyn HasRawWindowHandle,
+    settings: &GraphicsSettings,
+) -> anyhow::Result<Box<vulkan::engine::Engine>> {
+    Ok(Box::new(unsafe {
+        vulkan::engine::Engine::new(window, settings)?
+    }))
+}
+
+/// Gpus a rendering engine for `window` could run on, without creating the engine.
+///
+/// Any of them can be selected with the [gpu](GraphicsSettings::gpu) setting,
+/// empty if vulkan is unavailable
+#[cfg(feature = "vulkan")]
+pub fn enumerate_gpus(window: &dyn HasRawWindowHandle) -> Vec<GpuInfo> {
+    unsafe { vulkan::engine::init::enumerate_gpus(window) }.unwrap_or_else(|e| {
+        log::warn!("Failed to enumerate gpus: {e}");
+        Vec::new()
     })
 }
 
 impl Default for GraphicsSettings {
     fn default() -> Self {
         GraphicsSettings {
+            backend: if cfg!(feature = "vulkan") {
+                Backend::Vulkan
+            } else {
+                Backend::Null
+            },
             resolution: [800, 600],
             fov: Angle::new::<degree>(45.),
             vsync: true,
+            shadow_resolution: 2048,
+            concurrent_present: false,
+            max_texture_size: None,
+            occlusion_culling: false,
+            orthographic_depth: DEFAULT_ORTHOGRAPHIC_DEPTH,
+            perspective_depth: DEFAULT_PERSPECTIVE_DEPTH,
+            coordinate_system: CoordinateSystem::default(),
+            strict_assets: false,
+            fullscreen: None,
+            upload_budget: None,
+            recording: RecordingMode::Auto,
+            msaa: 1,
+            clear_color: [0., 0., 0., 1.],
+            frames_in_flight: 2,
+            render_threads: None,
+            gpu: None,
         }
     }
 }
 
-fn cull_test(
-    mesh: &Mesh,
-    model: &Matrix4<f32>,
-    view: &Matrix4<f32>,
-    projection: &Perspective3<f32>,
-) -> bool {
-    // todo
-    true
+/// False if the mesh's bounding box is outside of the view frustum
+#[cfg(feature = "vulkan")]
+fn cull_test(mesh: &Mesh, model: &Matrix4<f32>, view_projection: &Matrix4<f32>) -> bool {
+    use crate::geometry::Intersects;
+    geometry::frustum_from_matrix(&(view_projection * model)).intersects(&mesh.get_aabb())
+}
+
+/// Distance of the model's origin from the camera along the view direction,
+/// `view` includes the [view correction](CoordinateSystem::view_correction)
+#[cfg(feature = "vulkan")]
+fn view_depth(model: &Matrix4<f32>, view: &Matrix4<f32>) -> f32 {
+    -(view * model.column(3)).z
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::FRAC_PI_2;
+
+    use nalgebra::{Point3, UnitQuaternion, Vector3};
+    use serde::de::value::{Error, SeqDeserializer};
+    use uom::si::angle::degree;
+    use uom::si::f32::Angle;
+
+    use crate::{
+        clamped_color, valid_perspective_depth, Bottleneck, Camera, CoordinateSystem, GpuInfo,
+        GpuTier, GraphicsSettings, Handedness, ProjectionMode, RecordingMode, UpAxis,
+    };
+
+    #[test]
+    fn invalid_perspective_depth_is_clamped() {
+        assert_eq!(valid_perspective_depth([0.5, 5000.]), [0.5, 5000.]);
+        let [near, far] = valid_perspective_depth([0., 0.]);
+        assert!(near > 0. && far > near);
+        let [near, far] = valid_perspective_depth([10., 1.]);
+        assert!(near == 10. && far > near);
+    }
+
+    #[test]
+    fn resizing_the_camera_matches_a_new_one() {
+        let mut camera = Camera::new(800, 600, Angle::new::<degree>(45.));
+        camera.set_aspect(1920, 1080);
+        camera.set_fov(Angle::new::<degree>(60.));
+        let expected = Camera::new(1920, 1080, Angle::new::<degree>(60.));
+        let projection = camera.projection.to_homogeneous();
+        assert!((projection - expected.projection.to_homogeneous()).norm() < 1e-5);
+        assert_eq!(camera.orthographic, expected.orthographic);
+        camera.set_aspect(800, 0);
+        assert!((camera.projection.aspect() - 800.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn camera_setters_keep_the_view_consistent() {
+        let mut camera = Camera::new(800, 600, Angle::new::<degree>(45.));
+        let eye = Point3::new(1., 2., 3.);
+        let target = Point3::new(1., 2., -7.);
+        camera.set_position(eye);
+        camera.look_at(target);
+        assert!((camera.position() - eye).norm() < 1e-5);
+        assert!((camera.view * target - Point3::new(0., 0., -10.)).norm() < 1e-5);
+        assert!((camera.forward() + Vector3::z()).norm() < 1e-5);
+        assert!((camera.right() - Vector3::x()).norm() < 1e-5);
+
+        camera.translate(Vector3::new(0., 0., -1.));
+        assert!((camera.position() - Point3::new(1., 2., 2.)).norm() < 1e-5);
+        let turn = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), FRAC_PI_2);
+        camera.rotate(turn);
+        assert!((camera.position() - Point3::new(1., 2., 2.)).norm() < 1e-5);
+        assert!((camera.forward() + Vector3::x()).norm() < 1e-5);
+    }
+
+    #[test]
+    fn sprite_layers_are_sorted_front_to_back() {
+        let camera =
+            Camera::new(800, 600, Angle::new::<degree>(45.)).with_orthographic_depth([-1., 1.]);
+        let depth = |layer| {
+            let point = Point3::new(0., 0., camera.sprite_depth(layer));
+            camera.orthographic.project_point(&point).z
+        };
+        assert!(depth(1) < depth(0));
+        assert_eq!(depth(camera.sprite_layers), depth(camera.sprite_layers - 1));
+        for layer in [0, 1, camera.sprite_layers - 1] {
+            assert!((-1. ..=1.).contains(&depth(layer)));
+        }
+    }
+
+    #[test]
+    fn presets_follow_gpu_tier() {
+        let mut gpu = GpuInfo {
+            name: "test".into(),
+            discrete: false,
+            video_memory: 8 * 1024 * 1024 * 1024,
+            max_samples: 8,
+            max_texture_size: 16384,
+        };
+        assert_eq!(gpu.tier(), GpuTier::Low);
+        let low = GraphicsSettings::default().with_preset(&gpu, None);
+        gpu.discrete = true;
+        assert_eq!(gpu.tier(), GpuTier::High);
+        let high = GraphicsSettings::default().with_preset(&gpu, Some([1280, 1024]));
+        assert!(high.shadow_resolution > low.shadow_resolution);
+        assert_eq!(high.max_texture_size, None);
+        assert_eq!(high.resolution, [1280, 1024]);
+        assert_eq!(high.backend, GraphicsSettings::default().backend);
+        assert_eq!((low.msaa, high.msaa), (1, 4));
+        gpu.max_samples = 2;
+        assert_eq!(GraphicsSettings::default().with_preset(&gpu, None).msaa, 2);
+    }
+
+    #[test]
+    fn waiting_frames_are_gpu_or_present_bound() {
+        assert_eq!(Bottleneck::classify(16., Some(5.), 0.5), Bottleneck::Cpu);
+        assert_eq!(Bottleneck::classify(4., Some(15.), 12.), Bottleneck::Gpu);
+        assert_eq!(Bottleneck::classify(4., Some(3.), 12.), Bottleneck::Present);
+        assert_eq!(Bottleneck::classify(4., None, 12.), Bottleneck::Present);
+    }
+
+    #[test]
+    fn every_convention_sees_its_target() {
+        let camera = Camera::new(800, 600, Angle::new::<degree>(45.));
+        let eye = Point3::new(1., 2., 3.);
+        let target = Point3::new(-2., 0., -5.);
+        for handedness in [Handedness::Right, Handedness::Left] {
+            for up in [UpAxis::YUp, UpAxis::YDown] {
+                let coordinates = CoordinateSystem { handedness, up };
+                let view = coordinates.look_at(&eye, &target).to_homogeneous();
+                let clip = camera.projection.to_homogeneous()
+                    * coordinates.view_correction()
+                    * view
+                    * target.to_homogeneous();
+                assert!(clip.w > 0., "{coordinates:?}");
+                let ndc = clip.xyz() / clip.w;
+                assert!(ndc.x.abs() < 1e-4 && ndc.y.abs() < 1e-4, "{coordinates:?}");
+                assert!((-1. ..=1.).contains(&ndc.z), "{coordinates:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn restart_settings_are_kept_until_recreated() {
+        let running = GraphicsSettings::default();
+        let new = GraphicsSettings {
+            vsync: !running.vsync,
+            max_texture_size: Some(512),
+            shadow_resolution: running.shadow_resolution * 2,
+            recording: RecordingMode::SingleThreaded,
+            ..running.clone()
+        };
+        let (live, changes) = running.diff(&new);
+        assert_eq!(changes.applied, ["vsync", "max_texture_size"]);
+        assert_eq!(changes.restart_required, ["shadow_resolution", "recording"]);
+        assert_eq!(live.vsync, new.vsync);
+        assert_eq!(live.shadow_resolution, running.shadow_resolution);
+        assert_eq!(live.diff(&new).1.restart_required, changes.restart_required);
+        assert_eq!(running.diff(&running).1, Default::default());
+    }
+
+    #[test]
+    fn clear_color_is_clamped() {
+        let components = SeqDeserializer::<_, Error>::new([1.5f32, -0.25, 0.5, 1.].into_iter());
+        assert_eq!(clamped_color(components).unwrap(), [1., 0., 0.5, 1.]);
+        let running = GraphicsSettings::default();
+        let new = GraphicsSettings {
+            clear_color: [0.1, 0.2, 0.3, 1.],
+            ..running.clone()
+        };
+        assert_eq!(running.diff(&new).1.applied, ["clear_color"]);
+    }
+
+    #[test]
+    #[cfg(feature = "vulkan")]
+    fn left_handed_depth_grows_away_from_the_camera() {
+        use nalgebra::Matrix4;
+
+        use crate::view_depth;
+
+        let coordinates = CoordinateSystem {
+            handedness: Handedness::Left,
+            ..Default::default()
+        };
+        let view = coordinates
+            .look_at(&Point3::new(0., 0., -5.), &Point3::origin())
+            .to_homogeneous();
+        let view = coordinates.view_correction() * view;
+        let depth = |z| view_depth(&Matrix4::new_translation(&Vector3::new(1., 0., z)), &view);
+        assert!((depth(0.) - 5.).abs() < 1e-5);
+        assert!(depth(3.) > depth(0.));
+        assert!(depth(-8.) < 0.);
+    }
+
+    #[test]
+    fn orthographic_mode_ignores_distance() {
+        let mut camera = Camera::new(800, 400, Angle::new::<degree>(45.));
+        camera.projection_mode = ProjectionMode::Orthographic { height: 10. };
+        let projection = camera.projection_matrix();
+        for z in [-1., -50.] {
+            let clip = projection * Point3::new(10., 5., z).to_homogeneous();
+            assert!((clip.x / clip.w - 1.).abs() < 1e-5);
+            assert!((clip.y / clip.w - 1.).abs() < 1e-5);
+        }
+        camera.projection_mode = ProjectionMode::Perspective;
+        let perspective = camera.projection.to_homogeneous();
+        assert_eq!(camera.projection_matrix(), perspective);
+    }
 }
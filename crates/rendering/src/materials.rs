@@ -0,0 +1,233 @@
+use serde::{Deserialize, Serialize};
+
+pub mod database;
+
+/// Specialization constant id of the shading model, a bool that is true for unlit materials
+pub const UNLIT_CONSTANT_ID: u32 = 100;
+/// Specialization constant ids of the red, green and blue emissive factor
+pub const EMISSIVE_CONSTANT_IDS: [u32; 3] = [101, 102, 103];
+/// Descriptor set and binding of the uniform buffer holding a material's [PbrFactors]
+pub const PBR_FACTORS_BINDING: (u32, u32) = (2, 0);
+/// Descriptor set and binding of the combined image sampler of a material's
+/// [texture](MaterialDefinition::texture), like the one the built in `base` shaders sample.
+/// Only written for shaders that declare it, set 1 is left to the bones of skinned materials
+pub const TEXTURE_BINDING: (u32, u32) = (2, 1);
+
+/// Backend independent description of how a material is built
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MaterialDefinition {
+    /// File name of the compiled vertex shader in the shader asset directory
+    pub vertex_shader: String,
+    /// File name of the compiled fragment shader in the shader asset directory
+    pub fragment_shader: String,
+    /// File name of the texture in the asset directory, sampled at [TEXTURE_BINDING].
+    /// Shaders sampling a texture get a white one if this is None
+    pub texture: Option<String>,
+    /// Constant and slope scaled depth bias factors.
+    /// Pushes the depth of decals and shadow casters away from the surface they are drawn onto
+    pub depth_bias: Option<(f32, f32)>,
+    pub cull_mode: CullMode,
+    /// Write the depth of rendered fragments, depth testing is always enabled.
+    /// Ignored by blended materials, which never write depth
+    pub depth_write: bool,
+    /// How the depth of fragments is compared against the depth already in the framebuffer
+    #[serde(default)]
+    pub depth_compare: DepthCompare,
+    pub front_face: FrontFace,
+    /// Read model matrices from a per instance vertex buffer instead of push constants,
+    /// required for materials used by draw batches
+    pub instanced: bool,
+    /// Read bone indices and weights from the mesh's skin vertices and the bone matrices from set 1,
+    /// required for materials used with a [Skeleton](crate::Skeleton)
+    pub skinned: bool,
+    /// Constant ids and values of the shaders' specialization constants,
+    /// allows building variants of a material from the same shaders
+    pub specialization_constants: Vec<(u32, SpecializationValue)>,
+    pub shading: Shading,
+    /// Linear color added to the shaded color, unaffected by lighting and shadows
+    pub emissive: [f32; 3],
+    /// Factors uploaded to a uniform buffer at [PBR_FACTORS_BINDING] for shaders that read them,
+    /// like the built in `pbr` shaders. None for shaders that don't declare the buffer
+    #[serde(default)]
+    pub pbr: Option<PbrFactors>,
+    #[serde(default)]
+    pub blend: BlendMode,
+}
+
+/// Scalar parameters of the metallic roughness model, missing factors take their default
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[serde(default)]
+pub struct PbrFactors {
+    /// Linear rgba color multiplied with the albedo
+    pub base_color: [f32; 4],
+    /// 0 for dielectrics up to 1 for metals
+    pub metallic: f32,
+    /// 0 for mirror like up to 1 for completely diffuse surfaces
+    pub roughness: f32,
+}
+
+impl Default for PbrFactors {
+    fn default() -> Self {
+        PbrFactors {
+            base_color: [1.; 4],
+            metallic: 0.,
+            roughness: 1.,
+        }
+    }
+}
+
+/// How the built in fragment shader colors a material
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+pub enum Shading {
+    /// Ambient and diffuse lighting and shadows
+    Lit,
+    /// Albedo at full brightness without lighting or shadows,
+    /// for screens, light fixtures and stylized art
+    Unlit,
+}
+
+/// How the color of a material is combined with the color already in the framebuffer.
+///
+/// Only the color attachment is blended, object ids are always overwritten
+#[derive(Debug, Default, Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+pub enum BlendMode {
+    /// Replaces the framebuffer color
+    #[default]
+    Opaque,
+    /// Mixes with the framebuffer by the fragment's alpha, for glass, particles and the like.
+    /// Doesn't write depth, so blended draws should be submitted after opaque ones, back to front
+    Alpha,
+}
+
+/// Comparison a fragment's depth has to pass against the depth in the framebuffer to be drawn
+#[derive(Debug, Default, Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+pub enum DepthCompare {
+    /// Only fragments in front of what was drawn before pass
+    #[default]
+    Less,
+    /// Fragments at the same depth pass too, for geometry drawn at the far plane like skyboxes
+    LessOrEqual,
+}
+
+/// Which faces of a mesh are discarded during rasterization
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+pub enum CullMode {
+    Back,
+    Front,
+    /// Two sided rendering, for foliage, cloth, decals and the like
+    None,
+}
+
+/// Winding order of front facing triangles.
+///
+/// glTF always uses counter clockwise winding, some obj exporters use clockwise
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq)]
+pub enum FrontFace {
+    Ccw,
+    Cw,
+}
+
+/// Value of a specialization constant, every supported type is 32 bits wide
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq)]
+pub enum SpecializationValue {
+    Bool(bool),
+    Int(i32),
+    UInt(u32),
+    Float(f32),
+}
+
+impl SpecializationValue {
+    /// Native endian bytes of the value as the shader reads them, booleans are 32 bit integers
+    pub fn to_ne_bytes(self) -> [u8; 4] {
+        match self {
+            SpecializationValue::Bool(value) => (value as u32).to_ne_bytes(),
+            SpecializationValue::Int(value) => value.to_ne_bytes(),
+            SpecializationValue::UInt(value) => value.to_ne_bytes(),
+            SpecializationValue::Float(value) => value.to_ne_bytes(),
+        }
+    }
+}
+
+impl Default for MaterialDefinition {
+    fn default() -> Self {
+        MaterialDefinition {
+            vertex_shader: "base.vert.spv".into(),
+            fragment_shader: "base.frag.spv".into(),
+            texture: Some("texture.png".into()),
+            depth_bias: None,
+            cull_mode: CullMode::Back,
+            depth_write: true,
+            depth_compare: DepthCompare::Less,
+            front_face: FrontFace::Ccw,
+            instanced: false,
+            skinned: false,
+            specialization_constants: Vec::new(),
+            shading: Shading::Lit,
+            emissive: [0.; 3],
+            pbr: None,
+            blend: BlendMode::Opaque,
+        }
+    }
+}
+
+impl MaterialDefinition {
+    /// Definition using the built in physically based shaders with the given factors,
+    /// the emissive color is read from the same uniform buffer
+    pub fn pbr(factors: PbrFactors) -> Self {
+        MaterialDefinition {
+            vertex_shader: "pbr.vert.spv".into(),
+            fragment_shader: "pbr.frag.spv".into(),
+            pbr: Some(factors),
+            ..Default::default()
+        }
+    }
+
+    /// Shading model and emissive factor as specialization constants,
+    /// set on every pipeline next to [specialization_constants](Self::specialization_constants)
+    pub fn shading_constants(&self) -> [(u32, SpecializationValue); 4] {
+        let [r, g, b] = self.emissive;
+        [
+            (
+                UNLIT_CONSTANT_ID,
+                SpecializationValue::Bool(self.shading == Shading::Unlit),
+            ),
+            (EMISSIVE_CONSTANT_IDS[0], SpecializationValue::Float(r)),
+            (EMISSIVE_CONSTANT_IDS[1], SpecializationValue::Float(g)),
+            (EMISSIVE_CONSTANT_IDS[2], SpecializationValue::Float(b)),
+        ]
+    }
+}
+
+/// Backend independent sampling parameters, samplers with equal definitions are shared
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct SamplerDefinition {
+    pub filter: Filter,
+    pub address_mode: AddressMode,
+    /// Use the device's maximum anisotropy, requires linear filtering
+    pub anisotropic: bool,
+}
+
+/// How texels are combined when a texture is magnified or minified
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+/// How texture coordinates outside of 0 to 1 are handled
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum AddressMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+impl Default for SamplerDefinition {
+    fn default() -> Self {
+        SamplerDefinition {
+            filter: Filter::Linear,
+            address_mode: AddressMode::Repeat,
+            anisotropic: true,
+        }
+    }
+}
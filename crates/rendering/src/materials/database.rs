@@ -0,0 +1,171 @@
+//! Tables of the asset database describing the materials that can be loaded by name.
+//!
+//! `materials` holds one row per material with its shaders and pipeline state,
+//! `material_textures` the textures bound to it by slot.
+//! Only the texture in slot 0 is read, as the material's [texture](MaterialDefinition::texture)
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, ToSql};
+
+use crate::materials::{BlendMode, CullMode, MaterialDefinition};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS materials (
+    name TEXT PRIMARY KEY NOT NULL,
+    vertex_shader TEXT NOT NULL,
+    fragment_shader TEXT NOT NULL,
+    blend TEXT NOT NULL DEFAULT 'Opaque',
+    cull_mode TEXT NOT NULL DEFAULT 'Back'
+);
+CREATE TABLE IF NOT EXISTS material_textures (
+    material TEXT NOT NULL REFERENCES materials (name) ON DELETE CASCADE,
+    slot INTEGER NOT NULL,
+    texture TEXT NOT NULL,
+    PRIMARY KEY (material, slot)
+);
+";
+
+/// Creates the material tables if they don't exist yet
+pub fn init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(SCHEMA)
+}
+
+/// Reads the material `name`, None if there is no such material.
+///
+/// Settings the tables don't hold are the defaults of [MaterialDefinition]
+pub fn query(conn: &Connection, name: &str) -> rusqlite::Result<Option<MaterialDefinition>> {
+    conn.query_row(
+        "SELECT vertex_shader, fragment_shader, blend, cull_mode, texture FROM materials
+        LEFT JOIN material_textures ON material = name AND slot = 0
+        WHERE name = ?1",
+        [name],
+        |row| {
+            Ok(MaterialDefinition {
+                vertex_shader: row.get(0)?,
+                fragment_shader: row.get(1)?,
+                blend: row.get(2)?,
+                cull_mode: row.get(3)?,
+                texture: row.get(4)?,
+                ..Default::default()
+            })
+        },
+    )
+    .optional()
+}
+
+/// Row of the material tables, for tools that populate the database
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialRow {
+    name: String,
+    vertex_shader: String,
+    fragment_shader: String,
+    blend: BlendMode,
+    cull_mode: CullMode,
+    /// Texture of every slot, in order from slot 0
+    textures: Vec<String>,
+}
+
+impl MaterialRow {
+    /// Opaque material without textures that culls back faces
+    pub fn new(
+        name: impl Into<String>,
+        vertex_shader: impl Into<String>,
+        fragment_shader: impl Into<String>,
+    ) -> Self {
+        MaterialRow {
+            name: name.into(),
+            vertex_shader: vertex_shader.into(),
+            fragment_shader: fragment_shader.into(),
+            blend: BlendMode::Opaque,
+            cull_mode: CullMode::Back,
+            textures: Vec::new(),
+        }
+    }
+
+    pub fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn with_cull_mode(mut self, cull_mode: CullMode) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    /// Binds `texture` to the next free slot
+    pub fn with_texture(mut self, texture: impl Into<String>) -> Self {
+        self.textures.push(texture.into());
+        self
+    }
+
+    /// Inserts the material and its textures, replacing an existing material of the same name
+    pub fn insert(&self, conn: &Connection) -> rusqlite::Result<()> {
+        let transaction = conn.unchecked_transaction()?;
+        transaction.execute(
+            "DELETE FROM material_textures WHERE material = ?1",
+            [&self.name],
+        )?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO materials (name, vertex_shader, fragment_shader, blend, cull_mode)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                self.name,
+                self.vertex_shader,
+                self.fragment_shader,
+                self.blend,
+                self.cull_mode
+            ],
+        )?;
+        for (slot, texture) in self.textures.iter().enumerate() {
+            transaction.execute(
+                "INSERT INTO material_textures (material, slot, texture) VALUES (?1, ?2, ?3)",
+                params![self.name, slot, texture],
+            )?;
+        }
+        transaction.commit()
+    }
+}
+
+impl ToSql for BlendMode {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(match self {
+            BlendMode::Opaque => "Opaque",
+            BlendMode::Alpha => "Alpha",
+        }))
+    }
+}
+
+impl FromSql for BlendMode {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "Opaque" => Ok(BlendMode::Opaque),
+            "Alpha" => Ok(BlendMode::Alpha),
+            other => Err(FromSqlError::Other(
+                format!("Unknown blend mode {other}").into(),
+            )),
+        }
+    }
+}
+
+impl ToSql for CullMode {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(match self {
+            CullMode::Back => "Back",
+            CullMode::Front => "Front",
+            CullMode::None => "None",
+        }))
+    }
+}
+
+impl FromSql for CullMode {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "Back" => Ok(CullMode::Back),
+            "Front" => Ok(CullMode::Front),
+            "None" => Ok(CullMode::None),
+            other => Err(FromSqlError::Other(
+                format!("Unknown cull mode {other}").into(),
+            )),
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use nalgebra::Matrix4;
+
+use crate::{Camera, ModelOptions, RenderingEngine};
+
+/// Rendering engine that does not touch the gpu at all.
+///
+/// Every method is a no-op apart from counting how often it was called,
+/// which makes it usable for headless servers and for testing game logic.
+#[derive(Debug, Default)]
+pub struct NullEngine {
+    calls: CallCounts,
+}
+
+/// Number of times each [RenderingEngine] method was called on a [NullEngine]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct CallCounts {
+    pub begin_rendering: usize,
+    pub render: usize,
+    pub end_rendering: usize,
+    pub resize: usize,
+    pub load_model: usize,
+    pub load_material: usize,
+}
+
+/// Cpu only stand in for a mesh, only remembers where it was loaded from
+#[derive(Debug)]
+pub struct NullMesh {
+    pub path: PathBuf,
+}
+
+/// Cpu only stand in for a material
+#[derive(Debug, Default)]
+pub struct NullMaterial;
+
+impl NullEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn calls(&self) -> CallCounts {
+        self.calls
+    }
+}
+
+impl RenderingEngine for NullEngine {
+    type Mesh = NullMesh;
+    type Material = NullMaterial;
+
+    fn begin_rendering(&mut self, _camera: &Camera) {
+        self.calls.begin_rendering += 1;
+    }
+
+    fn render(
+        &mut self,
+        _mesh: &Arc<Self::Mesh>,
+        _material: &Arc<Self::Material>,
+        _transform: Matrix4<f32>,
+    ) {
+        self.calls.render += 1;
+    }
+
+    fn end_rendering(&mut self) {
+        self.calls.end_rendering += 1;
+    }
+
+    fn resize(&mut self, _width: u32, _height: u32) {
+        self.calls.resize += 1;
+    }
+
+    fn load_model_with_options(
+        &mut self,
+        path: &Path,
+        _options: &ModelOptions,
+    ) -> Result<Arc<Self::Mesh>, Box<dyn Error>> {
+        self.calls.load_model += 1;
+        Ok(Arc::new(NullMesh {
+            path: path.to_path_buf(),
+        }))
+    }
+
+    fn load_material(&mut self, _name: &str) -> Result<Arc<Self::Material>, Box<dyn Error>> {
+        self.calls.load_material += 1;
+        Ok(Arc::new(NullMaterial))
+    }
+
+    fn wait(&self) {}
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use nalgebra::Matrix4;
+    use uom::si::angle::degree;
+    use uom::si::f32::Angle;
+
+    use crate::null::NullEngine;
+    use crate::{Camera, RenderingEngine};
+
+    #[test]
+    fn counts_calls() {
+        let mut engine = NullEngine::new();
+        let mesh = engine.load_model("model.obj".as_ref()).unwrap();
+        let material = engine.load_material("base").unwrap();
+        let camera = Camera::new(800, 600, Angle::new::<degree>(45.));
+        engine.begin_rendering(&camera);
+        engine.render(&mesh, &material, Matrix4::identity());
+        engine.render(&mesh, &material, Matrix4::identity());
+        engine.end_rendering();
+
+        let calls = engine.calls();
+        assert_eq!(calls.load_model, 1);
+        assert_eq!(calls.load_material, 1);
+        assert_eq!(calls.begin_rendering, 1);
+        assert_eq!(calls.render, 2);
+        assert_eq!(calls.end_rendering, 1);
+        assert_eq!(mesh.path.to_str(), Some("model.obj"));
+    }
+
+    #[test]
+    fn batch_load_progress() {
+        let mut engine = NullEngine::new();
+        let paths = [Path::new("a.obj"), Path::new("b.obj"), Path::new("c.obj")];
+        let mut reports = Vec::new();
+        let meshes = engine
+            .load_models_with_progress(&paths, &mut |loaded, total| reports.push((loaded, total)))
+            .unwrap();
+        assert_eq!(meshes.len(), 3);
+        assert_eq!(reports, vec![(1, 3), (2, 3), (3, 3)]);
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{anyhow, bail, Result};
+use ash::vk;
+
+const MAGIC: &[u8; 4] = b"DDS ";
+/// Size of the magic number and the header that always follows it
+const HEADER_SIZE: usize = 128;
+/// Size of the extended header that follows the header when the four cc is `DX10`
+const DX10_HEADER_SIZE: usize = 20;
+const FLAG_MIPMAP_COUNT: u32 = 0x20000;
+const PIXEL_FORMAT_FOUR_CC: u32 = 0x4;
+const CAPS2_CUBEMAP: u32 = 0x200;
+
+/// Block compressed texture read from a DDS file, ready to be copied into an image as is
+#[derive(Debug)]
+pub(crate) struct CompressedTexture {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    /// Byte range of every mip level in `data`, largest first
+    pub levels: Vec<(usize, usize)>,
+    pub data: Vec<u8>,
+}
+
+/// Parses a DDS file holding a single 2D texture in a BCn format, with or without mip levels
+pub(crate) fn parse(data: Vec<u8>) -> Result<CompressedTexture> {
+    if data.len() < HEADER_SIZE || &data[..4] != MAGIC {
+        bail!("Not a DDS file");
+    }
+    let field = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    let (height, width) = (field(12), field(16));
+    let mip_count = if field(8) & FLAG_MIPMAP_COUNT != 0 {
+        field(28).max(1)
+    } else {
+        1
+    };
+    if width == 0 || height == 0 {
+        bail!("DDS texture has no texels");
+    }
+    // every level halves the size until the largest side is one texel
+    let max_mip_count = u32::BITS - width.max(height).leading_zeros();
+    if mip_count > max_mip_count {
+        bail!("DDS texture of size {width}x{height} can't have {mip_count} mip levels");
+    }
+    if field(112) & CAPS2_CUBEMAP != 0 {
+        bail!("DDS cubemaps are not supported");
+    }
+    if field(80) & PIXEL_FORMAT_FOUR_CC == 0 {
+        bail!("Uncompressed DDS textures are not supported");
+    }
+    let (format, start) = match &data[84..88] {
+        b"DX10" => {
+            if data.len() < HEADER_SIZE + DX10_HEADER_SIZE {
+                bail!("DDS file ends inside of its header");
+            }
+            if field(HEADER_SIZE + 12) > 1 {
+                bail!("DDS texture arrays are not supported");
+            }
+            (
+                dxgi_format(field(HEADER_SIZE))?,
+                HEADER_SIZE + DX10_HEADER_SIZE,
+            )
+        }
+        four_cc => (four_cc_format(four_cc)?, HEADER_SIZE),
+    };
+
+    let block_size = block_size(format);
+    let mut levels = Vec::with_capacity(mip_count as usize);
+    let mut offset = start;
+    for level in 0..mip_count {
+        let blocks = |size: u32| ((size >> level).max(1) as usize + 3) / 4;
+        let size = blocks(width) * blocks(height) * block_size;
+        if offset + size > data.len() {
+            bail!("DDS file ends inside of mip level {level}");
+        }
+        levels.push((offset, size));
+        offset += size;
+    }
+    Ok(CompressedTexture {
+        format,
+        width,
+        height,
+        levels,
+        data,
+    })
+}
+
+/// Formats of the legacy header, color formats are assumed to be srgb like png textures
+fn four_cc_format(four_cc: &[u8]) -> Result<vk::Format> {
+    Ok(match four_cc {
+        b"DXT1" => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        b"DXT3" => vk::Format::BC2_SRGB_BLOCK,
+        b"DXT5" => vk::Format::BC3_SRGB_BLOCK,
+        b"ATI1" | b"BC4U" => vk::Format::BC4_UNORM_BLOCK,
+        b"ATI2" | b"BC5U" => vk::Format::BC5_UNORM_BLOCK,
+        _ => {
+            return Err(anyhow!(
+                "Unsupported DDS format {}",
+                String::from_utf8_lossy(four_cc)
+            ))
+        }
+    })
+}
+
+/// Formats of the DX10 header, identified by their `DXGI_FORMAT` value
+fn dxgi_format(format: u32) -> Result<vk::Format> {
+    Ok(match format {
+        71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        74 => vk::Format::BC2_UNORM_BLOCK,
+        75 => vk::Format::BC2_SRGB_BLOCK,
+        77 => vk::Format::BC3_UNORM_BLOCK,
+        78 => vk::Format::BC3_SRGB_BLOCK,
+        80 => vk::Format::BC4_UNORM_BLOCK,
+        81 => vk::Format::BC4_SNORM_BLOCK,
+        83 => vk::Format::BC5_UNORM_BLOCK,
+        84 => vk::Format::BC5_SNORM_BLOCK,
+        95 => vk::Format::BC6H_UFLOAT_BLOCK,
+        96 => vk::Format::BC6H_SFLOAT_BLOCK,
+        98 => vk::Format::BC7_UNORM_BLOCK,
+        99 => vk::Format::BC7_SRGB_BLOCK,
+        _ => bail!("Unsupported DXGI format {format}"),
+    })
+}
+
+/// Bytes of every 4x4 block of texels
+fn block_size(format: vk::Format) -> usize {
+    match format {
+        vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK => 8,
+        _ => 16,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+
+    use crate::vulkan::dds::{parse, HEADER_SIZE};
+
+    fn header(width: u32, height: u32, mip_count: u32, four_cc: &[u8; 4]) -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE];
+        data[..4].copy_from_slice(b"DDS ");
+        let mut set = |offset: usize, value: u32| {
+            data[offset..offset + 4].copy_from_slice(&value.to_le_bytes())
+        };
+        set(4, 124);
+        set(8, 0x20000);
+        set(12, height);
+        set(16, width);
+        set(28, mip_count);
+        set(80, 0x4);
+        data[84..88].copy_from_slice(four_cc);
+        data
+    }
+
+    #[test]
+    fn mip_levels_are_sized_in_blocks() {
+        let mut data = header(16, 8, 5, b"DXT1");
+        data.resize(HEADER_SIZE + (8 + 2 + 1 + 1 + 1) * 8, 0);
+        let texture = parse(data).unwrap();
+        assert_eq!(texture.format, vk::Format::BC1_RGBA_SRGB_BLOCK);
+        let sizes = texture
+            .levels
+            .iter()
+            .map(|(_, size)| *size)
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [64, 16, 8, 8, 8]);
+        assert_eq!(texture.levels[1].0, HEADER_SIZE + 64);
+    }
+
+    #[test]
+    fn reads_dx10_header() {
+        let mut data = header(4, 4, 1, b"DX10");
+        data.extend_from_slice(&99u32.to_le_bytes());
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(&[0; 16]);
+        let texture = parse(data).unwrap();
+        assert_eq!(texture.format, vk::Format::BC7_SRGB_BLOCK);
+        assert_eq!(texture.levels, [(HEADER_SIZE + 20, 16)]);
+    }
+
+    #[test]
+    fn rejects_impossible_mip_counts() {
+        for mip_count in [5, 33, u32::MAX] {
+            let mut data = header(8, 8, mip_count, b"DXT1");
+            data.resize(HEADER_SIZE + 4096, 0);
+            assert!(parse(data).is_err());
+        }
+        let mut data = header(8, 8, 4, b"DXT1");
+        data.resize(HEADER_SIZE + (4 + 1 + 1 + 1) * 8, 0);
+        assert_eq!(parse(data).unwrap().levels.len(), 4);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let mut data = header(8, 8, 1, b"DXT5");
+        data.resize(HEADER_SIZE + 63, 0);
+        assert!(parse(data).is_err());
+        assert!(parse(b"DDS".to_vec()).is_err());
+    }
+}
@@ -1,9 +1,9 @@
+use ash::prelude::VkResult;
 use ash::vk;
-use ash::vk::DependencyFlags;
+use ash::vk::{DependencyFlags, Handle};
 use crossbeam_channel::{Receiver, Sender};
-use log::{error, info, log, Level};
-use nalgebra::{Matrix4, Perspective3};
-use obj::{load_obj, Obj};
+use log::{error, info, log, trace, warn, Level};
+use nalgebra::{Matrix3, Matrix4};
 use once_cell::sync::Lazy;
 use parking_lot::{Condvar, Mutex};
 use smallvec::SmallVec;
@@ -11,31 +11,98 @@ use std::default::Default;
 use std::error::Error;
 use std::ffi::CStr;
 use std::fs;
-use std::fs::File;
-use std::io::BufReader;
+use std::marker::PhantomData;
 use std::mem::ManuallyDrop;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicU64;
 use std::sync::{Arc, Barrier};
 use std::thread::JoinHandle;
+use std::time::Instant;
 use vk_mem::Allocator;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 use engine::filesystem::DIRS;
+use engine::telemetry::{self, TelemetryEvent};
 
-use crate::vulkan::engine::alloc::{GpuObject, Image};
-use crate::vulkan::engine::init::create_depth_image;
-use crate::vulkan::engine::pipeline::{cleanup_cache, create_pipeline};
+use crate::vulkan::engine::alloc::{Buffer, GpuObject, Image, StagingPool, StorageBuffer};
+use crate::vulkan::engine::batch::DrawBatch;
+use crate::vulkan::engine::compute::ComputePipeline;
+use crate::vulkan::engine::dynamic::DynamicVertexBuffer;
+use crate::vulkan::engine::environment::Environment;
+#[cfg(feature = "hot-reload")]
+use crate::vulkan::engine::deletion::Resource;
+#[cfg(feature = "hot-reload")]
+use crate::vulkan::engine::hot_reload::ShaderWatcher;
+use crate::vulkan::engine::init::{
+    create_depth_image, create_object_id_image, describe_gpu, get_present_mode,
+    suitable_gpu_names,
+};
+use crate::vulkan::engine::labels::Labels;
+use crate::vulkan::engine::msaa::MsaaTargets;
+use crate::vulkan::engine::occlusion::OcclusionQueries;
+use crate::vulkan::engine::passes::{FrameContext, FramePass};
+use crate::vulkan::engine::pipeline::{
+    cache_info, cleanup_cache, create_compute_pipeline, create_pipeline, declares_sampler,
+    set_viewport, PipelineParts,
+};
+use crate::vulkan::engine::screenshot::{bgra_to_rgba, write_png, CAPTURE_FORMATS};
+use crate::vulkan::engine::shadow::ShadowMap;
+use crate::vulkan::engine::skinning::{BonePools, Skeleton};
+use crate::vulkan::engine::skybox::{self, Skybox};
+use crate::vulkan::engine::stats::FrameTimes;
 use crate::vulkan::engine::swapchain::Swapchain;
-use crate::vulkan::mesh::Vertex;
-use crate::vulkan::texture::Texture;
-use crate::{Camera, cull_test, Material, Mesh, RenderingEngine};
+use crate::vulkan::engine::timer::GpuTimer;
+use crate::vulkan::engine::upload::{Ownership, Upload, UploadCommands};
+#[cfg(feature = "hot-reload")]
+use crate::vulkan::material::creation::load_definition;
+use crate::vulkan::material::PbrUniform;
+use crate::vulkan::mesh::{
+    parse_gltf, parse_obj, recompute_normals, unit_cube, SkinVertex, Vertex,
+};
+use crate::materials::{BlendMode, MaterialDefinition, SamplerDefinition, TEXTURE_BINDING};
+use crate::vulkan::sampler::Sampler;
+use crate::vulkan::texture::{decode_faces, Texture};
+use crate::{
+    cull_test, view_depth, Bottleneck, Camera, CoordinateSystem, DirectionalLight, FrameCapture,
+    FramePacing, FrameStats, GpuInfo, GraphicsSettings, HeapUsage, Material, MemoryReport, Mesh,
+    ModelOptions, RenderingEngine, SettingsChanges,
+};
 
 pub(crate) mod alloc;
-mod init;
+pub(crate) mod batch;
+pub(crate) mod compute;
+pub(crate) mod deletion;
+pub(crate) mod dynamic;
+mod environment;
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+pub(crate) mod init;
+mod labels;
+mod msaa;
+mod occlusion;
+mod passes;
 mod pipeline;
+mod screenshot;
+mod shadow;
+pub(crate) mod skinning;
+mod skybox;
+mod stats;
 mod swapchain;
+mod timer;
+pub(crate) mod upload;
 
-const FRAMES_IN_FLIGHT: usize = 2;
+/// Most frames in flight [frames_in_flight](GraphicsSettings::frames_in_flight) can request
+const MAX_FRAMES_IN_FLIGHT: usize = 3;
+/// Most render threads [render_threads](GraphicsSettings::render_threads) can request,
+/// every frame has a secondary command pool per thread
+const MAX_RENDER_THREADS: usize = 12;
+/// Format of the main pass attachment holding the object id of each pixel,
+/// the id's low and high half are stored in the red and green channel
+const OBJECT_ID_FORMAT: vk::Format = vk::Format::R32G32_UINT;
+/// Push constant offset of the fragment stage's object id, following the vertex stage's transforms
+const OBJECT_ID_OFFSET: u32 = std::mem::size_of::<Transforms>() as u32;
+/// Most bytes of staging buffers kept around for later uploads once theirs finished
+const STAGING_POOL_CAPACITY: vk::DeviceSize = 64 << 20;
 
 pub struct Engine {
     frame_count: u64,
@@ -47,48 +114,152 @@ pub struct Engine {
     #[cfg(feature = "validation-layers")]
     debug_messenger: (
         Box<ash::extensions::ext::DebugUtils>,
-        vk::DebugUtilsMessengerEXT,
+        Option<vk::DebugUtilsMessengerEXT>,
     ),
+    /// Records labels with the loader of the debug messenger, shared with the render threads
+    labels: Labels,
     surface: vk::SurfaceKHR,
     graphics_queue: vk::Queue,
     present_queue: vk::Queue,
     surface_format: vk::SurfaceFormatKHR,
     swapchain: ManuallyDrop<Swapchain>,
     allocator: Arc<Allocator>,
-    frames: [Frame; FRAMES_IN_FLIGHT],
-    render_channels: SmallVec<[Sender<RenderCommand>; 12]>,
-    render_thread_handles: SmallVec<[JoinHandle<()>; 12]>,
+    /// Mapped buffers uploads copy their data from, kept after they finish for the next ones
+    staging: ManuallyDrop<Arc<StagingPool>>,
+    /// One frame per frame in flight, indexed by [frame_index](Engine::frame_index)
+    frames: SmallVec<[Frame; MAX_FRAMES_IN_FLIGHT]>,
+    render_channels: SmallVec<[Sender<RenderCommand>; MAX_RENDER_THREADS]>,
+    render_thread_handles: SmallVec<[JoinHandle<()>; MAX_RENDER_THREADS]>,
     render_barrier: Arc<Barrier>,
     present_channel: ManuallyDrop<Sender<PresentData>>,
     present_thread_handle: ManuallyDrop<JoinHandle<()>>,
-    last_mesh: *const Mesh,
-    last_material: *const Material,
+    /// Addresses of the last mesh and material sent to a render thread,
+    /// only ever compared for identity and never dereferenced
+    last_draw: (usize, usize),
     current_thread: usize,
+    /// Some when draws are recorded on the main thread instead of by render threads
+    inline_draws: Option<InlineDraws>,
     utility_pool: vk::CommandPool,
     global_descriptor_layout: vk::DescriptorSetLayout,
     descriptor_pool: vk::DescriptorPool,
+    /// Skeletons allocate their bone sets from these instead of the global pool
+    bone_pools: BonePools,
     depth_format: vk::Format,
     depth_image: ManuallyDrop<Image>,
     depth_view: vk::ImageView,
+    object_id_image: ManuallyDrop<Image>,
+    object_id_view: vk::ImageView,
+    /// Samples per pixel of the main pass' attachments and pipelines
+    samples: vk::SampleCountFlags,
+    /// None without multisampling
+    msaa: Option<MsaaTargets>,
+    /// The object id image holds the ids of a submitted frame, false after it is recreated
+    object_ids_rendered: bool,
+    /// The current frame is dropped because the surface has no area, e.g. while minimized
+    frame_skipped: bool,
+    shadow_map: ManuallyDrop<ShadowMap>,
+    /// Ambient light of pbr materials, see [set_environment](Engine::set_environment)
+    environment: ManuallyDrop<Environment>,
+    /// Bound to materials whose shaders sample a texture they don't have
+    white_texture: ManuallyDrop<Arc<Texture>>,
+    /// Drawn before the other geometry of the main pass, see [set_skybox](RenderingEngine::set_skybox)
+    skybox: Option<Arc<Skybox>>,
+    /// Every mesh rendered this frame, drawn again into the shadow map before the main pass
+    shadow_casters: Vec<(Arc<Mesh>, Matrix4<f32>)>,
+    /// Draws of transparent materials and their depth, recorded back to front after the opaque ones
+    transparent_draws: Vec<(f32, DrawCommand)>,
+    /// View of the current frame's camera including the view correction, see [view_depth]
+    camera_view: Matrix4<f32>,
+    /// Compute work recorded at the start of the next frame
+    dispatches: Vec<(Arc<ComputePipeline>, [u32; 3])>,
+    /// Per frame vertices of immediate mode geometry
+    dynamic_vertices: ManuallyDrop<DynamicVertexBuffer>,
+    /// Present when occlusion culling is enabled in the graphics settings
+    occlusion: Option<OcclusionQueries>,
+    /// None if the graphics queue can't write timestamps
+    gpu_timer: Option<GpuTimer>,
+    /// None if the shader directory couldn't be watched
+    #[cfg(feature = "hot-reload")]
+    shader_watcher: Option<ShaderWatcher>,
+    /// When the last frame finished waiting in [begin_rendering](RenderingEngine::begin_rendering)
+    last_wait_end: Option<Instant>,
+    pacing: FramePacing,
+    frame_times: FrameTimes,
+    light: DirectionalLight,
+    /// Recorded into the primary command buffer in order every frame
+    passes: Vec<Box<dyn FramePass>>,
     queue_families: [u32; 2],
+    /// Queue of a transfer family separate from the graphics family and the family's index,
+    /// uploads use the graphics queue without one
+    transfer_queue: Option<(vk::Queue, u32)>,
+    concurrent_present: bool,
     resolution: [u32; 2],
-    vsync: bool,
+    present_mode: vk::PresentModeKHR,
+    /// Recreate the swapchain at the start of the next frame, even if it is still valid
+    recreate_swapchain: bool,
+    max_texture_size: Option<u32>,
+    /// World space conventions the view matrices of cameras follow
+    coordinates: CoordinateSystem,
+    /// Return errors for assets that failed to load instead of placeholders
+    strict_assets: bool,
+    /// Settings the engine is running with, see [apply_settings](Engine::apply_settings)
+    settings: GraphicsSettings,
+    /// Cube returned for models that failed to load, created when it is first needed
+    placeholder_mesh: Option<Arc<Mesh>>,
+    capture_requested: bool,
+    /// Frame index, host visible copy and extent of a requested frame capture
+    pending_capture: Option<(usize, Buffer, vk::Extent2D)>,
+    /// Where the pending capture is saved once it is rendered, see
+    /// [capture_screenshot](RenderingEngine::capture_screenshot)
+    screenshot: Option<PathBuf>,
+    /// The engine is driven from the thread that created it, the render and presentation threads
+    /// only receive handles and `Arc`s through channels. Vulkan requires external synchronization
+    /// for the command pools and swapchain owned here, so this keeps `Engine` `!Send` and `!Sync`
+    _single_thread: PhantomData<*const ()>,
 }
 
 #[derive(Debug)]
 struct Frame {
     primary_buffer: vk::CommandBuffer,
     primary_pool: vk::CommandPool,
-    secondary_buffers: SmallVec<[vk::CommandBuffer; 12]>,
-    secondary_pools: SmallVec<[vk::CommandPool; 12]>,
+    secondary_buffers: SmallVec<[vk::CommandBuffer; MAX_RENDER_THREADS]>,
+    secondary_pools: SmallVec<[vk::CommandPool; MAX_RENDER_THREADS]>,
     fence: vk::Fence,
     graphics_semaphore: vk::Semaphore,
     present_semaphore: vk::Semaphore,
+    ownership_transfer: Option<OwnershipTransfer>,
     ubo: ManuallyDrop<GpuObject<Ubo>>,
     global_descriptor: vk::DescriptorSet,
     sync_data: Arc<(Mutex<RenderResult>, Condvar)>,
 }
 
+impl Frame {
+    /// Waits until the frame's last image was presented and takes the result
+    fn take_present_result(&self) -> RenderResult {
+        let (result, presented) = &*self.sync_data;
+        let mut result = result.lock();
+        presented.wait_while(&mut result, |result| *result == RenderResult::NotDone);
+        std::mem::replace(&mut *result, RenderResult::Ok)
+    }
+
+    /// Waits until the frame's last image was presented,
+    /// leaving the result to [take_present_result](Frame::take_present_result)
+    fn wait_presented(&self) {
+        let (result, presented) = &*self.sync_data;
+        presented.wait_while(&mut result.lock(), |result| *result == RenderResult::NotDone);
+    }
+}
+
+/// Acquires the swapchain image on the presentation queue family
+/// when graphics and presentation use different families with exclusive images
+#[derive(Debug)]
+struct OwnershipTransfer {
+    pool: vk::CommandPool,
+    cmd: vk::CommandBuffer,
+    /// Signaled once the presentation family owns the image
+    semaphore: vk::Semaphore,
+}
+
 #[derive(Eq, PartialEq, Debug)]
 enum RenderResult {
     NotDone,
@@ -96,26 +267,80 @@ enum RenderResult {
     OutOfDate,
 }
 
+#[repr(C)]
 #[derive(Debug)]
 struct Ubo {
     view: Matrix4<f32>,
     projection: Matrix4<f32>,
-    orthographic: Matrix4<f32>
+    orthographic: Matrix4<f32>,
+    light_space: Matrix4<f32>,
+    /// Normalized direction of the directional light, w is unused
+    light_direction: [f32; 4],
+    /// Only rgb is used
+    light_color: [f32; 4],
+}
+
+/// Push constants of the vertex stage of every draw
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Transforms {
+    model: Matrix4<f32>,
+    /// Inverse transpose of the model matrix's upper 3x3 that transforms normals,
+    /// its columns are padded to 16 bytes like the columns of a glsl mat3
+    normal: [[f32; 4]; 3],
+}
+
+impl Transforms {
+    fn new(model: &Matrix4<f32>) -> Self {
+        let linear: Matrix3<f32> = model.fixed_slice::<3, 3>(0, 0).into_owned();
+        // a singular model flattens the mesh, so any normal matrix will do
+        let normal = linear.try_inverse().unwrap_or(linear).transpose();
+        let column = |i: usize| [normal[(0, i)], normal[(1, i)], normal[(2, i)], 0.];
+        Transforms {
+            model: *model,
+            normal: [column(0), column(1), column(2)],
+        }
+    }
 }
 
 enum RenderCommand {
+    /// Begins the thread's secondary buffer, draws are culled against the view projection matrix
+    /// and cover the swapchain extent
     Begin(
         vk::CommandBuffer,
         Matrix4<f32>,
-        Perspective3<f32>,
         vk::DescriptorSet,
         vk::Format,
         vk::Format,
+        vk::SampleCountFlags,
+        vk::Extent2D,
     ),
-    Render(Arc<Mesh>, Arc<Material>, Matrix4<f32>),
+    Draw(DrawCommand),
     End,
 }
 
+enum DrawCommand {
+    /// Draw tagged with an object id, zero if it is not tagged
+    Render(Arc<Mesh>, Arc<Material>, Matrix4<f32>, u64),
+    Batch(Arc<DrawBatch>),
+    /// Skinned draw, the descriptor set holds the bones of the frame being recorded
+    Skinned(
+        Arc<Mesh>,
+        Arc<Material>,
+        Arc<Skeleton>,
+        vk::DescriptorSet,
+        Matrix4<f32>,
+    ),
+    /// The sky behind everything else, recorded before any other draw of the frame
+    Skybox(Arc<Skybox>),
+}
+
+/// Draws of a frame recorded on the main thread, without render threads
+struct InlineDraws {
+    draws: Vec<DrawCommand>,
+    view_projection: Matrix4<f32>,
+}
+
 struct PresentData {
     render_semaphore: vk::Semaphore,
     present_semaphore: vk::Semaphore,
@@ -125,6 +350,8 @@ struct PresentData {
     image_index: u32,
     signal_fence: vk::Fence,
     sync_data: Arc<(Mutex<RenderResult>, Condvar)>,
+    /// Command buffer acquiring the image on the presentation queue and the semaphore it signals
+    ownership_transfer: Option<(vk::CommandBuffer, vk::Semaphore)>,
 }
 
 /// Converts opengl to vulkan coordinate system
@@ -139,79 +366,83 @@ static COORDINATE_CORRECTION: Lazy<Matrix4<f32>> = Lazy::new(|| {
 });
 
 impl RenderingEngine for Engine {
+    type Mesh = Mesh;
+    type Material = Material;
+
     fn begin_rendering(&mut self, camera: &Camera) {
-        let proj = *COORDINATE_CORRECTION * camera.projection.to_homogeneous();
-        let frame = &mut self.frames[self.frame_count as usize % FRAMES_IN_FLIGHT];
-        let fences = [frame.fence];
+        let proj = *COORDINATE_CORRECTION
+            * camera.projection_matrix()
+            * self.coordinates.view_correction();
+        let frame_index = self.frame_index();
+        let fences = [self.frames[frame_index].fence];
+        let wait_start = Instant::now();
         unsafe {
             if let Err(err) = self.device.wait_for_fences(&fences, true, u64::MAX) {
                 error!("Error waiting on fence: {err}");
+                report_device_lost(err);
             }
-            let suboptimal = {
-                let mut lock = frame.sync_data.0.lock();
-                frame
-                    .sync_data
-                    .1
-                    .wait_while(&mut lock, |e| *e == RenderResult::NotDone);
-                if *lock == RenderResult::OutOfDate {
-                    *lock = RenderResult::Ok;
-                    true
-                } else {
-                    *lock = RenderResult::NotDone;
-                    false
+            // nothing can be presented until the window is restored. The frame is dropped
+            // before its fence is reset, so it stays signaled and later frames don't block on it
+            let empty = self.surface_is_empty();
+            if empty && !self.frame_skipped {
+                info!("Surface has no area, skipping frames until the window is restored");
+            }
+            self.frame_skipped = empty;
+            if empty {
+                self.last_wait_end = None;
+                return;
+            }
+            let gpu_ms = self
+                .gpu_timer
+                .as_mut()
+                .and_then(|timer| timer.collect(frame_index));
+            deletion::collect(&self.device, self.frame_count, self.frames.len());
+            // a captured frame is finished once the engine waited on its frame in flight again
+            if matches!(self.pending_capture, Some((index, ..)) if index == frame_index) {
+                if let Some(path) = self.screenshot.take() {
+                    self.save_screenshot(path);
                 }
-            };
-            if suboptimal
-                || match self.swapchain.next(frame.present_semaphore) {
-                    Ok(val) => val,
-                    Err(e)
-                        if e == vk::Result::SUBOPTIMAL_KHR
-                            || e == vk::Result::ERROR_OUT_OF_DATE_KHR =>
-                    {
-                        true
+            }
+            #[cfg(feature = "hot-reload")]
+            self.reload_changed_shaders();
+            self.dynamic_vertices.reset(frame_index);
+            if let Some(occlusion) = &mut self.occlusion {
+                occlusion.collect(frame_index, &camera.view.to_homogeneous());
+            }
+            // out of date presents and acquires both end up recreating the swapchain here,
+            // before anything of this frame is recorded
+            if self.frames[frame_index].take_present_result() == RenderResult::OutOfDate {
+                self.recreate_swapchain = true;
+            }
+            if self.recreate_swapchain {
+                self.rebuild_swapchain();
+            }
+            let present_semaphore = self.frames[frame_index].present_semaphore;
+            loop {
+                match self.swapchain.next(present_semaphore) {
+                    // a suboptimal image can still be presented, so this frame is not dropped
+                    Ok(suboptimal) => {
+                        self.recreate_swapchain |= suboptimal;
+                        break;
                     }
+                    // nothing was acquired, so the semaphore is still unsignaled and can be reused
+                    Err(e) if e == vk::Result::ERROR_OUT_OF_DATE_KHR => self.rebuild_swapchain(),
                     Err(e) => panic!("Failed to acquire swapchain image: {e:?}"),
                 }
-            {
-                self.device.device_wait_idle().unwrap();
-                let old = ManuallyDrop::take(&mut self.swapchain);
-                self.swapchain = ManuallyDrop::new(
-                    Swapchain::new(
-                        &self.instance,
-                        self.device.clone(),
-                        self.physical_device,
-                        self.surface,
-                        &self.surface_loader,
-                        &self.queue_families,
-                        self.surface_format.format,
-                        self.vsync,
-                        &self.resolution,
-                        Some(&old),
-                    )
-                    .expect("Failed to recreate swapchain"),
-                );
-                ManuallyDrop::drop(&mut self.depth_image);
-                self.device.destroy_image_view(self.depth_view, None);
-                let (image, depth_view) = create_depth_image(
-                    &self.device,
-                    self.depth_format,
-                    self.swapchain.extent,
-                    self.allocator.clone(),
-                )
-                .unwrap();
-                self.depth_image = ManuallyDrop::new(image);
-                self.depth_view = depth_view;
-                info!(
-                    "Swapchain resized to {}x{}",
-                    self.swapchain.extent.width, self.swapchain.extent.height
-                );
-                self.begin_rendering(camera);
-                return;
             }
+            self.update_pacing(wait_start, gpu_ms);
+            let frame = &mut self.frames[frame_index];
+            *frame.sync_data.0.lock() = RenderResult::NotDone;
             self.device.reset_fences(&fences).unwrap();
             frame.ubo.view = camera.view.to_homogeneous();
+            self.camera_view = self.coordinates.view_correction() * frame.ubo.view;
             frame.ubo.projection = proj;
             frame.ubo.orthographic = *COORDINATE_CORRECTION * camera.orthographic.to_homogeneous();
+            frame.ubo.light_space = self.shadow_map.light_space();
+            frame.ubo.light_direction = self.light.direction.normalize().push(0.).into();
+            frame.ubo.light_color = self.light.color.push(1.).into();
+            frame.ubo.flush().unwrap();
+            self.shadow_casters.clear();
             self.device
                 .reset_command_pool(frame.primary_pool, vk::CommandPoolResetFlags::empty())
                 .unwrap();
@@ -225,94 +456,218 @@ impl RenderingEngine for Engine {
             self.device
                 .begin_command_buffer(frame.primary_buffer, &begin_info)
                 .unwrap();
+            if let Some(timer) = &self.gpu_timer {
+                timer.begin(frame.primary_buffer, frame_index);
+            }
 
+            self.labels.begin(frame.primary_buffer, "acquire-transition");
             pre_image_transition(
                 &self.device,
                 frame.primary_buffer,
                 self.swapchain.get_current_image(),
                 **self.depth_image,
+                **self.object_id_image,
+                self.msaa.as_ref().map(MsaaTargets::images),
             );
+            self.labels.end(frame.primary_buffer);
 
-            begin(
-                self.swapchain.get_current_image_view(),
-                self.depth_view,
-                self.swapchain.extent,
-                frame.primary_buffer,
-                &self.device,
-            );
+            // culled in the clip space of the camera's projection, before the vulkan correction
+            let view_projection = camera.projection_matrix()
+                * self.coordinates.view_correction()
+                * camera.view.to_homogeneous();
+            if let Some(inline) = &mut self.inline_draws {
+                inline.view_projection = view_projection;
+            }
             for (index, channel) in self.render_channels.iter().enumerate() {
                 channel
                     .send(RenderCommand::Begin(
                         frame.secondary_buffers[index],
-                        camera.view.to_homogeneous(),
-                        camera.projection,
+                        view_projection,
                         frame.global_descriptor,
                         self.surface_format.format,
                         self.depth_format,
+                        self.samples,
+                        self.swapchain.extent,
                     ))
                     .unwrap();
             }
+            // the first secondary buffer is executed first, so the sky is behind every draw
+            if let Some(skybox) = &self.skybox {
+                let draw = DrawCommand::Skybox(skybox.clone());
+                match &mut self.inline_draws {
+                    Some(inline) => inline.draws.push(draw),
+                    None => self.render_channels[0]
+                        .send(RenderCommand::Draw(draw))
+                        .expect("Failed to send render command"),
+                }
+            }
         }
     }
 
     fn render(&mut self, mesh: &Arc<Mesh>, material: &Arc<Material>, transform: Matrix4<f32>) {
-        if !(std::ptr::eq(mesh.as_ref(), self.last_mesh)
-            && std::ptr::eq(material.as_ref(), self.last_material))
-        {
-            self.current_thread = (self.current_thread + 1) % self.render_channels.len();
-            self.last_mesh = mesh.as_ref();
-            self.last_material = material.as_ref();
+        self.send_draw(mesh, material, transform, 0);
+    }
+
+    fn render_with_id(
+        &mut self,
+        mesh: &Arc<Mesh>,
+        material: &Arc<Material>,
+        transform: Matrix4<f32>,
+        id: u64,
+    ) {
+        // zero is left for pixels without a tagged draw
+        self.send_draw(mesh, material, transform, id.wrapping_add(1));
+    }
+
+    fn pick(&self, x: u32, y: u32) -> Option<u64> {
+        let extent = self.swapchain.extent;
+        if !self.object_ids_rendered || x >= extent.width || y >= extent.height {
+            return None;
+        }
+        match unsafe { self.read_object_id(x, y) } {
+            Ok(id) => id.checked_sub(1),
+            Err(e) => {
+                error!("Failed to read object id: {e}");
+                None
+            }
         }
-        let channel = &self.render_channels[self.current_thread];
-        channel
-            .send(RenderCommand::Render(
-                mesh.clone(),
-                material.clone(),
-                transform,
-            ))
-            .expect("Failed to send render command");
     }
 
     fn end_rendering(&mut self) {
+        if self.frame_skipped {
+            self.dispatches.clear();
+            return;
+        }
+        self.queue_transparent_draws();
         for channel in &self.render_channels {
             channel.send(RenderCommand::End).unwrap();
         }
         self.render_barrier.wait();
-        let frame = &self.frames[self.frame_count as usize % FRAMES_IN_FLIGHT];
+        let frame_index = self.frame_index();
+        let frame = &self.frames[frame_index];
+        if let Some(occlusion) = &mut self.occlusion {
+            occlusion.finish_frame(frame_index);
+        }
 
-        let image_barrier = [vk::ImageMemoryBarrier::builder()
-            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
-            .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-            .image(self.swapchain.get_current_image())
-            .subresource_range(vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            })
+        // releases the image to the presentation family if it is acquired there
+        let (src_family, dst_family) = if frame.ownership_transfer.is_some() {
+            (self.queue_families[0], self.queue_families[1])
+        } else {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        };
+        if self.capture_requested && !CAPTURE_FORMATS.contains(&self.surface_format.format) {
+            error!(
+                "Frames of surface format {:?} can't be captured",
+                self.surface_format.format
+            );
+            self.capture_requested = false;
+            self.screenshot = None;
+        }
+        let capture = if self.capture_requested {
+            self.capture_requested = false;
+            match unsafe { create_capture_buffer(self.swapchain.extent, self.allocator.clone()) } {
+                Ok(buffer) => Some(buffer),
+                Err(e) => {
+                    error!("Failed to create frame capture buffer: {e}");
+                    self.screenshot = None;
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let (old_layout, src_access, src_stage) = if capture.is_some() {
+            (
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::PipelineStageFlags::TRANSFER,
+            )
+        } else {
+            (
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )
+        };
+        let image = self.swapchain.get_current_image();
+        let image_barrier = [present_barrier(image, src_family, dst_family)
+            .old_layout(old_layout)
+            .src_access_mask(src_access)
             .build()];
 
+        let context = FrameContext {
+            device: &self.device,
+            cmd: frame.primary_buffer,
+            global_descriptor: frame.global_descriptor,
+            color_view: self.swapchain.get_current_image_view(),
+            depth_view: self.depth_view,
+            object_id_view: self.object_id_view,
+            msaa: self.msaa.as_ref(),
+            extent: self.swapchain.extent,
+            secondary_buffers: &frame.secondary_buffers,
+            shadow_map: &self.shadow_map,
+            shadow_casters: &self.shadow_casters,
+            occlusion: self.occlusion.as_ref(),
+            inline_draws: self.inline_draws.as_ref(),
+            frame_index,
+            dispatches: &self.dispatches,
+            clear_color: self.settings.clear_color,
+        };
+
         unsafe {
-            self.device
-                .cmd_execute_commands(frame.primary_buffer, &frame.secondary_buffers);
-            self.device.cmd_end_rendering(frame.primary_buffer);
+            for pass in &mut self.passes {
+                trace!("Recording {} pass", pass.name());
+                self.labels.begin(frame.primary_buffer, pass.name());
+                pass.record(&context);
+                self.labels.end(frame.primary_buffer);
+            }
+            if let Some(inline) = &mut self.inline_draws {
+                inline.draws.clear();
+            }
+            self.dispatches.clear();
 
+            if let Some(buffer) = &capture {
+                self.labels.begin(frame.primary_buffer, "capture");
+                record_capture(
+                    &self.device,
+                    frame.primary_buffer,
+                    image,
+                    **buffer,
+                    self.swapchain.extent,
+                );
+                self.labels.end(frame.primary_buffer);
+            }
+
+            self.labels.begin(frame.primary_buffer, "present-transition");
             self.device.cmd_pipeline_barrier(
                 frame.primary_buffer,
-                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                src_stage,
                 vk::PipelineStageFlags::BOTTOM_OF_PIPE,
                 DependencyFlags::empty(),
                 &[],
                 &[],
                 &image_barrier,
             );
+            self.labels.end(frame.primary_buffer);
 
+            if let Some(timer) = &mut self.gpu_timer {
+                timer.end(frame.primary_buffer, frame_index);
+            }
             self.device
                 .end_command_buffer(frame.primary_buffer)
                 .unwrap();
         }
+        if let Some(transfer) = &frame.ownership_transfer {
+            unsafe {
+                record_ownership_acquire(
+                    &self.device,
+                    transfer,
+                    self.swapchain.get_current_image(),
+                    &self.queue_families,
+                )
+                .expect("Failed to record swapchain ownership transfer");
+            }
+        }
 
         self.present_channel
             .send(PresentData {
@@ -324,65 +679,734 @@ impl RenderingEngine for Engine {
                 image_index: self.swapchain.current_image_index as u32,
                 signal_fence: frame.fence,
                 sync_data: frame.sync_data.clone(),
+                ownership_transfer: frame
+                    .ownership_transfer
+                    .as_ref()
+                    .map(|transfer| (transfer.cmd, transfer.semaphore)),
             })
             .unwrap();
+        if let Some(buffer) = capture {
+            self.pending_capture = Some((frame_index, buffer, self.swapchain.extent));
+        }
+        self.object_ids_rendered = true;
         self.frame_count += 1;
     }
 
-    fn resize(&mut self, width: u32, height: u32) {
-        self.resolution = [width, height];
-    }
-
-    fn load_model(&mut self, path: &Path) -> Result<Arc<Mesh>, Box<dyn Error>> {
-        let obj: Obj = load_obj(BufReader::new(File::open(path)?))?;
-        let vertices = obj
-            .vertices
-            .into_iter()
-            .map(|vertex| Vertex {
-                position: nalgebra::Vector3::from(vertex.position),
-                normal: nalgebra::UnitVector3::new_normalize(nalgebra::Vector3::from(
-                    vertex.normal,
-                )),
-                uv: Default::default(), //todo
+    fn frame_stats(&self) -> FrameStats {
+        self.frame_times.stats()
+    }
+
+    fn memory_report(&self) -> MemoryReport {
+        let budgets = match self.allocator.get_heap_budgets() {
+            Ok(budgets) => budgets,
+            Err(e) => {
+                error!("Failed to query memory budgets: {e}");
+                return MemoryReport::default();
+            }
+        };
+        let memory = unsafe {
+            self.instance
+                .get_physical_device_memory_properties(self.physical_device)
+        };
+        let heaps = memory.memory_heaps[..memory.memory_heap_count as usize]
+            .iter()
+            .zip(budgets)
+            .map(|(heap, budget)| HeapUsage {
+                size: heap.size,
+                used: budget.usage,
+                budget: budget.budget,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
             })
             .collect();
-        let indices = obj.indices.into_iter().map(|index| index as u32).collect();
+        MemoryReport { heaps }
+    }
+
+    fn capture_screenshot(&mut self, path: &Path) {
+        self.screenshot = Some(path.to_owned());
+        self.capture_next_frame();
+    }
+
+    /// The swapchain is recreated with the new present mode at the start of the next frame
+    fn set_vsync(&mut self, vsync: bool) {
+        self.settings.vsync = vsync;
+        let present_mode = unsafe {
+            get_present_mode(
+                self.physical_device,
+                self.surface,
+                &self.surface_loader,
+                vsync,
+            )
+        };
+        match present_mode {
+            Ok(mode) if mode != self.present_mode => {
+                self.present_mode = mode;
+                self.recreate_swapchain = true;
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to query surface present modes: {e}"),
+        }
+    }
+
+    /// Frames in flight keep drawing the previous skybox until they finish,
+    /// its resources are destroyed through the deletion queue
+    fn set_skybox(&mut self, faces: [&Path; 6]) -> Result<(), Box<dyn Error>> {
+        let skybox = self.create_skybox(faces)?;
+        self.skybox = Some(Arc::new(skybox));
+        info!("Skybox set");
+        Ok(())
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        // the swapchain is not always out of date after a resize,
+        // e.g. when switching fullscreen modes on some platforms
+        if self.resolution != [width, height] {
+            self.resolution = [width, height];
+            self.recreate_swapchain = true;
+        }
+    }
+
+    fn load_model_with_options(
+        &mut self,
+        path: &Path,
+        options: &ModelOptions,
+    ) -> Result<Arc<Mesh>, Box<dyn Error>> {
+        Ok(self.load_model_async(path, options)?.wait()?)
+    }
+
+    fn load_material(&mut self, name: &str) -> Result<Arc<Material>, Box<dyn Error>> {
+        Ok(self.load_material_async(name)?.wait()?)
+    }
+
+    fn wait(&self) {
+        unsafe { self.device.device_wait_idle().unwrap() };
+    }
+}
+
+impl Engine {
+    /// Loads a model like [load_model_with_options](RenderingEngine::load_model_with_options),
+    /// returning as soon as its vertices are submitted instead of waiting for the upload.
+    ///
+    /// The model is parsed before returning, only the copy to the gpu is left to poll for
+    pub fn load_model_async(
+        &mut self,
+        path: &Path,
+        options: &ModelOptions,
+    ) -> Result<Upload<Arc<Mesh>>, Box<dyn Error>> {
+        let result = self.load_model_file(path, options);
+        if let Err(e) = &result {
+            telemetry::emit(TelemetryEvent::AssetFailed {
+                path: path.to_string_lossy().into_owned(),
+                error: e.to_string(),
+            });
+        }
+        match result {
+            Err(e) if !self.strict_assets => {
+                warn!("Failed to load model {path:?}, using a placeholder: {e}");
+                Ok(Upload::ready(self.placeholder_mesh()?))
+            }
+            result => result,
+        }
+    }
+
+    /// Loads a material like [load_material](RenderingEngine::load_material),
+    /// returning as soon as its texture is submitted instead of waiting for the upload.
+    ///
+    /// The material is cached once its upload finished,
+    /// loading it again before that creates another one
+    pub fn load_material_async(
+        &mut self,
+        name: &str,
+    ) -> Result<Upload<Arc<Material>>, Box<dyn Error>> {
+        Material::new_async(name, |definition| {
+            self.load_material_with_definition_async(definition)
+        })
+    }
+
+    /// Loads a Wavefront obj model, or a `.gltf` or `.glb` model,
+    /// without falling back to the placeholder
+    fn load_model_file(
+        &mut self,
+        path: &Path,
+        options: &ModelOptions,
+    ) -> Result<Upload<Arc<Mesh>>, Box<dyn Error>> {
+        let is_gltf = path.extension().map_or(false, |ext| {
+            ext.eq_ignore_ascii_case("gltf") || ext.eq_ignore_ascii_case("glb")
+        });
+        let (mut vertices, skin, indices, valid_normals) = if is_gltf {
+            let (document, buffers, _) = gltf::import(path)?;
+            parse_gltf(&document, &buffers)?
+        } else {
+            let (vertices, indices, valid_normals) = parse_obj(&fs::read(path)?)?;
+            (vertices, Vec::new(), indices, valid_normals)
+        };
+        if !valid_normals {
+            warn!("Model {path:?} has missing or invalid normals, recomputing them");
+        }
+        if options.recompute_normals || !valid_normals {
+            recompute_normals(&mut vertices, &indices);
+        }
+
+        let vertex_count = vertices.len();
+        let mesh = self.create_skinned_mesh_async(vertices, skin, indices)?;
+        info!("Loaded model {path:?}");
+        telemetry::emit(TelemetryEvent::MeshLoaded {
+            path: path.to_string_lossy().into_owned(),
+            vertices: vertex_count,
+        });
+        Ok(mesh)
+    }
+
+    /// Recreates the swapchain and the attachments sized like it.
+    ///
+    /// Waits until every submitted frame was presented, their results refer to the old swapchain
+    /// and are discarded, so images that were out of date together only cause one recreation
+    unsafe fn rebuild_swapchain(&mut self) {
+        for frame in &self.frames {
+            frame.take_present_result();
+        }
+        self.recreate_swapchain = false;
+        self.device.device_wait_idle().unwrap();
+        let old = ManuallyDrop::take(&mut self.swapchain);
+        self.swapchain = ManuallyDrop::new(
+            Swapchain::new(
+                &self.instance,
+                self.device.clone(),
+                self.physical_device,
+                self.surface,
+                &self.surface_loader,
+                &self.queue_families,
+                self.concurrent_present,
+                self.surface_format.format,
+                self.present_mode,
+                &self.resolution,
+                Some(&old),
+            )
+            .expect("Failed to recreate swapchain"),
+        );
+        ManuallyDrop::drop(&mut self.depth_image);
+        self.device.destroy_image_view(self.depth_view, None);
+        let (image, depth_view) = create_depth_image(
+            &self.device,
+            self.depth_format,
+            self.swapchain.extent,
+            self.samples,
+            self.allocator.clone(),
+        )
+        .unwrap();
+        self.depth_image = ManuallyDrop::new(image);
+        self.depth_view = depth_view;
+        ManuallyDrop::drop(&mut self.object_id_image);
+        self.device.destroy_image_view(self.object_id_view, None);
+        let (image, object_id_view) = create_object_id_image(
+            &self.device,
+            self.swapchain.extent,
+            vk::SampleCountFlags::TYPE_1,
+            self.allocator.clone(),
+        )
+        .unwrap();
+        self.object_id_image = ManuallyDrop::new(image);
+        self.object_id_view = object_id_view;
+        if self.msaa.is_some() {
+            self.msaa = None;
+            self.msaa = Some(
+                MsaaTargets::new(
+                    self.device.clone(),
+                    self.surface_format.format,
+                    self.swapchain.extent,
+                    self.samples,
+                    self.allocator.clone(),
+                )
+                .expect("Failed to recreate multisampled attachments"),
+            );
+        }
+        self.object_ids_rendered = false;
+        info!(
+            "Swapchain resized to {}x{}",
+            self.swapchain.extent.width, self.swapchain.extent.height
+        );
+        telemetry::emit(TelemetryEvent::SwapchainRecreated {
+            width: self.swapchain.extent.width,
+            height: self.swapchain.extent.height,
+        });
+    }
+
+    /// Index of the current frame in flight, the next one once a frame ended
+    fn frame_index(&self) -> usize {
+        self.frame_count as usize % self.frames.len()
+    }
+
+    /// Whether the surface has a width or height of zero, as while the window is minimized.
+    /// No swapchain can be created for it
+    unsafe fn surface_is_empty(&self) -> bool {
+        match self
+            .surface_loader
+            .get_physical_device_surface_capabilities(self.physical_device, self.surface)
+        {
+            Ok(capabilities) => {
+                let extent = capabilities.current_extent;
+                extent.width == 0 || extent.height == 0
+            }
+            Err(e) => {
+                error!("Failed to query surface capabilities: {e}");
+                false
+            }
+        }
+    }
+
+    /// Unit cube standing in for models that failed to load
+    fn placeholder_mesh(&mut self) -> Result<Arc<Mesh>> {
+        if let Some(mesh) = &self.placeholder_mesh {
+            return Ok(mesh.clone());
+        }
+        let (vertices, indices) = unit_cube();
+        let mesh = self.create_mesh(vertices, indices)?;
+        self.placeholder_mesh = Some(mesh.clone());
+        Ok(mesh)
+    }
+
+    /// Sends a draw to the next render thread,
+    /// `object_id` is written to the object id attachment and is zero for untagged draws
+    fn send_draw(
+        &mut self,
+        mesh: &Arc<Mesh>,
+        material: &Arc<Material>,
+        transform: Matrix4<f32>,
+        object_id: u64,
+    ) {
+        if self.frame_skipped {
+            return;
+        }
+        let draw = (Arc::as_ptr(mesh) as usize, Arc::as_ptr(material) as usize);
+        if draw != self.last_draw {
+            self.next_thread();
+            self.last_draw = draw;
+        }
+        self.shadow_casters.push((mesh.clone(), transform));
+        // occluded objects are still drawn into the shadow map, since they may cast visible shadows
+        if let Some(occlusion) = &mut self.occlusion {
+            if !occlusion.test(mesh, &transform) {
+                return;
+            }
+        }
+        let draw = DrawCommand::Render(mesh.clone(), material.clone(), transform, object_id);
+        if material.transparent {
+            let depth = view_depth(&transform, &self.camera_view);
+            self.transparent_draws.push((depth, draw));
+        } else {
+            self.queue_draw(draw);
+        }
+    }
+
+    /// Sends the frame's transparent draws back to front to the last render thread,
+    /// whose secondary buffer is executed after the opaque draws of every thread
+    fn queue_transparent_draws(&mut self) {
+        let mut draws = std::mem::take(&mut self.transparent_draws);
+        draws.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        self.current_thread = self.render_channels.len().saturating_sub(1);
+        for (_, draw) in draws.drain(..) {
+            self.queue_draw(draw);
+        }
+        // keeps the allocation for the next frame
+        self.transparent_draws = draws;
+    }
+
+    /// Moves on to the next render thread, so consecutive draws are spread across the threads
+    fn next_thread(&mut self) {
+        if !self.render_channels.is_empty() {
+            self.current_thread = (self.current_thread + 1) % self.render_channels.len();
+        }
+    }
+
+    /// Sends a draw to the current render thread,
+    /// or keeps it for the main pass when recording on the main thread
+    fn queue_draw(&mut self, draw: DrawCommand) {
+        if self.frame_skipped {
+            return;
+        }
+        match &mut self.inline_draws {
+            Some(inline) => inline.draws.push(draw),
+            None => self.render_channels[self.current_thread]
+                .send(RenderCommand::Draw(draw))
+                .expect("Failed to send render command"),
+        }
+    }
+
+    /// Copies one texel of the object id attachment to the host, blocking until it is read.
+    ///
+    /// Frames are submitted by the presentation thread, so this first waits until the last frame
+    /// passed to [end_rendering](RenderingEngine::end_rendering) was presented.
+    /// The copy is submitted after it and reads that frame's ids
+    unsafe fn read_object_id(&self, x: u32, y: u32) -> Result<u64> {
+        let last_frame = (self.frame_count - 1) as usize % self.frames.len();
+        self.frames[last_frame].wait_presented();
+
+        let create_info = vk::BufferCreateInfo::builder()
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .size(std::mem::size_of::<[u32; 2]>() as vk::DeviceSize)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let alloc_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuToCpu,
+            flags: vk_mem::AllocationCreateFlags::MAPPED,
+            required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE
+                | vk::MemoryPropertyFlags::HOST_COHERENT,
+            ..Default::default()
+        };
+        let buffer = Buffer::new(&create_info, &alloc_info, self.allocator.clone())?;
 
         let alloc = vk::CommandBufferAllocateInfo::builder()
             .command_buffer_count(1)
             .command_pool(self.utility_pool)
             .level(vk::CommandBufferLevel::PRIMARY);
-        let cmd = unsafe { self.device.allocate_command_buffers(&alloc)? }[0];
-        let mesh = Mesh::new(
-            vertices,
-            indices,
+        let cmd = [self.device.allocate_command_buffers(&alloc)?[0]];
+        let image = **self.object_id_image;
+        let submit_info = [vk::SubmitInfo::builder().command_buffers(&cmd).build()];
+        let result = record_object_id_copy(&self.device, cmd[0], image, *buffer, x, y)
+            .and_then(|_| {
+                self.device
+                    .queue_submit(self.graphics_queue, &submit_info, vk::Fence::null())
+            })
+            .and_then(|_| self.device.queue_wait_idle(self.graphics_queue));
+        self.device.free_command_buffers(self.utility_pool, &cmd);
+        result?;
+
+        let ptr = buffer.get_info().get_mapped_data() as *const u32;
+        let id = std::slice::from_raw_parts(ptr, 2);
+        Ok(id[0] as u64 | (id[1] as u64) << 32)
+    }
+
+    /// Creates a material from a backend independent definition
+    pub fn load_material_with_definition(
+        &mut self,
+        definition: &MaterialDefinition,
+    ) -> Result<Arc<Material>, Box<dyn Error>> {
+        Ok(self
+            .load_material_with_definition_async(definition)?
+            .wait()?)
+    }
+
+    /// Like [load_material_with_definition](Engine::load_material_with_definition),
+    /// but returns as soon as the material's texture is submitted
+    pub fn load_material_with_definition_async(
+        &mut self,
+        definition: &MaterialDefinition,
+    ) -> Result<Upload<Arc<Material>>, Box<dyn Error>> {
+        let fragment_shader = DIRS.asset.join("shaders").join(&definition.fragment_shader);
+        let samples_texture = declares_sampler(&fs::read(fragment_shader)?, TEXTURE_BINDING)?;
+        let (pipeline, layout, descriptor_layouts) = self.create_material_pipeline(definition)?;
+        let descriptor_sets = if descriptor_layouts.is_empty() {
+            Vec::new()
+        } else {
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(self.descriptor_pool)
+                .set_layouts(&descriptor_layouts);
+            unsafe { self.device.allocate_descriptor_sets(&alloc_info)? }
+        };
+        let (texture, transfer) = match &definition.texture {
+            Some(name) => {
+                let (texture, transfer) =
+                    self.load_texture_async(DIRS.asset.join(name))?.into_parts();
+                (Some(Arc::new(texture)), transfer)
+            }
+            // shaders sampling a texture get a white one, so untextured materials keep their color
+            None if samples_texture => (Some(Arc::clone(&self.white_texture)), None),
+            None => (None, None),
+        };
+        let factors = match &definition.pbr {
+            Some(factors) => {
+                let mut uniform = GpuObject::<PbrUniform>::new(
+                    self.allocator.clone(),
+                    vk::BufferUsageFlags::UNIFORM_BUFFER,
+                )?;
+                *uniform = PbrUniform::new(factors, definition.emissive);
+                Some(uniform)
+            }
+            None => None,
+        };
+        info!("Created graphics pipeline");
+        telemetry::emit(TelemetryEvent::MaterialLoaded {
+            vertex_shader: definition.vertex_shader.clone(),
+            fragment_shader: definition.fragment_shader.clone(),
+        });
+        let material = Material {
+            pipeline: AtomicU64::new(pipeline.as_raw()),
+            layout,
+            device: self.device.clone(),
+            texture,
+            instanced: definition.instanced,
+            skinned: definition.skinned,
+            transparent: definition.blend != BlendMode::Opaque,
+            descriptor_layouts,
+            descriptor_sets,
+            descriptor_pool: self.descriptor_pool,
+            factors,
+        };
+        material.write_factors()?;
+        // descriptors can point at the texture while it is uploading, it isn't read before it's done
+        if samples_texture {
+            material.write_texture()?;
+        }
+        Ok(Upload::new(Arc::new(material), transfer))
+    }
+
+    /// Creates the pipeline of a material drawn to the swapchain from its compiled shaders
+    fn create_material_pipeline(
+        &self,
+        definition: &MaterialDefinition,
+    ) -> Result<PipelineParts, Box<dyn Error>> {
+        let shaders = DIRS.asset.join("shaders");
+        let data = vec![
+            fs::read(shaders.join(&definition.vertex_shader))?,
+            fs::read(shaders.join(&definition.fragment_shader))?,
+        ];
+        create_pipeline(
             &self.device,
+            Some(self.surface_format.format),
+            self.depth_format,
+            self.samples,
+            data,
+            self.global_descriptor_layout,
+            definition,
+        )
+    }
+
+    /// Rebuilds the pipelines of loaded materials using a shader written since the last frame.
+    ///
+    /// Only materials loaded by name are reloaded, reading their definition again.
+    /// Their shaders must keep the material's descriptor sets, which are not recreated
+    #[cfg(feature = "hot-reload")]
+    fn reload_changed_shaders(&mut self) {
+        let changed = match &self.shader_watcher {
+            Some(watcher) => watcher.changed(),
+            None => return,
+        };
+        if changed.is_empty() {
+            return;
+        }
+        for (name, material) in Material::cached() {
+            let material = match material {
+                Some(material) => material,
+                None => continue,
+            };
+            let definition = match load_definition(&name) {
+                Ok(definition) => definition,
+                Err(e) => {
+                    error!("Failed to reload material {name}: {e}");
+                    continue;
+                }
+            };
+            if !changed.contains(&definition.vertex_shader)
+                && !changed.contains(&definition.fragment_shader)
+            {
+                continue;
+            }
+            match self.create_material_pipeline(&definition) {
+                Ok((pipeline, layout, descriptor_layouts)) => {
+                    // identically defined layouts are compatible,
+                    // so the pipeline is bound with the material's old ones
+                    let device = &self.device;
+                    deletion::queue(device.clone(), Resource::PipelineLayout(layout));
+                    for layout in descriptor_layouts {
+                        deletion::queue(device.clone(), Resource::DescriptorSetLayout(layout));
+                    }
+                    material.replace_pipeline(pipeline);
+                    info!("Reloaded the shaders of material {name}");
+                }
+                Err(e) => error!("Failed to reload material {name}: {e}"),
+            }
+        }
+    }
+
+    /// Creates a compute pipeline from the compiled compute shader `shader` in the asset directory,
+    /// its storage buffers are bound with [write_storage_buffer](ComputePipeline::write_storage_buffer)
+    pub fn load_compute_pipeline(
+        &mut self,
+        shader: &str,
+    ) -> Result<Arc<ComputePipeline>, Box<dyn Error>> {
+        let data = fs::read(DIRS.asset.join("shaders").join(shader))?;
+        let parts = create_compute_pipeline(&self.device, data)?;
+        let pipeline = ComputePipeline::new(self.device.clone(), self.descriptor_pool, parts)?;
+        info!("Created compute pipeline {shader}");
+        Ok(Arc::new(pipeline))
+    }
+
+    /// Dispatches `group_count` workgroups of `pipeline` in the next frame that is rendered,
+    /// before anything is drawn.
+    ///
+    /// Dispatches run in the order they were made. Their storage buffer writes are visible
+    /// to the frame's draws, whether they read them as vertices, indirect draws or in shaders
+    pub fn dispatch(&mut self, pipeline: &Arc<ComputePipeline>, group_count: [u32; 3]) {
+        self.dispatches.push((pipeline.clone(), group_count));
+    }
+
+    /// Loads a texture that can be bound to materials
+    /// with [write_sampled_image](Material::write_sampled_image),
+    /// blocking until the upload is finished.
+    ///
+    /// Block compressed `.dds` textures are uploaded with their mip levels
+    /// if the device supports their format, otherwise the `.png` texture of the same name is used.
+    /// Textures that fail to load are replaced with a magenta checkerboard
+    /// unless [strict_assets](crate::GraphicsSettings::strict_assets) is set
+    pub fn load_texture(&mut self, path: impl AsRef<Path>) -> Result<Texture> {
+        self.load_texture_async(path)?.wait()
+    }
+
+    /// Like [load_texture](Engine::load_texture), but returns as soon as the texture is submitted.
+    ///
+    /// Placeholders for textures that failed to load are uploaded before returning
+    pub fn load_texture_async(&mut self, path: impl AsRef<Path>) -> Result<Upload<Texture>> {
+        let path = path.as_ref();
+        let (pools, commands) = unsafe { self.create_upload_commands()? };
+        let limits = unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device)
+                .limits
+        };
+        let max_size = self
+            .max_texture_size
+            .map_or(limits.max_image_dimension2_d, |size| {
+                size.min(limits.max_image_dimension2_d)
+            });
+        let texture = Texture::new_async(
+            path,
+            self.device.clone(),
+            commands,
+            &self.staging,
+            limits.max_sampler_anisotropy,
+            max_size,
+            self.allocator.clone(),
+            |format| unsafe {
+                self.instance
+                    .get_physical_device_format_properties(self.physical_device, format)
+                    .optimal_tiling_features
+            },
+        );
+        // decoding fails before anything is recorded, so the command buffer can be reused
+        if let Err(e) = &texture {
+            telemetry::emit(TelemetryEvent::AssetFailed {
+                path: path.to_string_lossy().into_owned(),
+                error: e.to_string(),
+            });
+        }
+        let texture = match texture {
+            Ok(upload) => return Ok(upload.with_command_pools(pools)),
+            Err(e) if !self.strict_assets => {
+                warn!("Failed to load texture {path:?}, using a placeholder: {e}");
+                Texture::placeholder(
+                    self.device.clone(),
+                    commands.graphics_cmd(),
+                    self.graphics_queue,
+                    limits.max_sampler_anisotropy,
+                    self.allocator.clone(),
+                )
+                .map(Upload::ready)
+            }
+            Err(e) => Err(e),
+        };
+        unsafe { self.destroy_command_pools(pools) };
+        texture
+    }
+
+    /// Creates the command buffers to record an upload in, each in a command pool of its own.
+    ///
+    /// Copies are recorded for the transfer queue if there is one, with a graphics queue
+    /// command buffer taking ownership of the uploaded resources.
+    /// Uploads that finish after returning take the pools and destroy them once they're done
+    unsafe fn create_upload_commands(&self) -> Result<(Vec<vk::CommandPool>, UploadCommands)> {
+        let mut pools = Vec::with_capacity(2);
+        let mut allocate = |family| -> VkResult<vk::CommandBuffer> {
+            let pool_info = vk::CommandPoolCreateInfo::builder()
+                .queue_family_index(family)
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT);
+            let pool = self.device.create_command_pool(&pool_info, None)?;
+            pools.push(pool);
+            let alloc = vk::CommandBufferAllocateInfo::builder()
+                .command_buffer_count(1)
+                .command_pool(pool)
+                .level(vk::CommandBufferLevel::PRIMARY);
+            Ok(self.device.allocate_command_buffers(&alloc)?[0])
+        };
+        let graphics_family = self.queue_families[0];
+        let commands = allocate(graphics_family).and_then(|graphics| match self.transfer_queue {
+            Some((queue, transfer_family)) => Ok(UploadCommands {
+                copy: allocate(transfer_family)?,
+                copy_queue: queue,
+                ownership: Some(Ownership {
+                    transfer_family,
+                    graphics_family,
+                    acquire: graphics,
+                    graphics_queue: self.graphics_queue,
+                }),
+            }),
+            None => Ok(UploadCommands::graphics(graphics, self.graphics_queue)),
+        });
+        match commands {
+            Ok(commands) => Ok((pools, commands)),
+            Err(e) => {
+                self.destroy_command_pools(pools);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Destroys the pools of an upload that failed or finished before returning
+    unsafe fn destroy_command_pools(&self, pools: Vec<vk::CommandPool>) {
+        for pool in pools {
+            self.device.destroy_command_pool(pool, None);
+        }
+    }
+
+    /// Replaces the environment cubemap that provides the ambient light of pbr materials,
+    /// blocking until it is uploaded.
+    ///
+    /// `faces` are png, jpeg, bmp or tga images of the +x, -x, +y, -y, +z and -z faces,
+    /// squares of the same size.
+    /// Waits for the device to be idle since the descriptor sets of every frame are rewritten
+    pub fn set_environment(&mut self, faces: [impl AsRef<Path>; 6]) -> Result<()> {
+        let faces = decode_faces(&faces)?;
+        // the presentation thread submits on the graphics queue until every frame was presented
+        for frame in &self.frames {
+            if frame.take_present_result() == RenderResult::OutOfDate {
+                self.recreate_swapchain = true;
+            }
+        }
+        let alloc = vk::CommandBufferAllocateInfo::builder()
+            .command_buffer_count(1)
+            .command_pool(self.utility_pool)
+            .level(vk::CommandBufferLevel::PRIMARY);
+        let cmd = unsafe { self.device.allocate_command_buffers(&alloc)? }[0];
+        let anisotropy = unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device)
+                .limits
+                .max_sampler_anisotropy
+        };
+        let environment = Environment::new(
+            faces,
+            self.device.clone(),
             cmd,
             self.graphics_queue,
+            anisotropy,
             self.allocator.clone(),
-        )
-        .map(Arc::new);
+        );
         let cmd = [cmd];
-        unsafe { self.device.free_command_buffers(self.utility_pool, &cmd) };
-        info!("Loaded model {path:?}");
-        Ok(mesh?)
+        unsafe {
+            self.device.free_command_buffers(self.utility_pool, &cmd);
+            let environment = environment?;
+            self.device.device_wait_idle()?;
+            environment.write_descriptors(
+                &self.device,
+                self.frames.iter().map(|frame| frame.global_descriptor),
+            );
+            ManuallyDrop::drop(&mut self.environment);
+            self.environment = ManuallyDrop::new(environment);
+        }
+        info!("Environment cubemap set");
+        Ok(())
     }
 
-    fn load_material(&mut self) -> Result<Arc<Material>, Box<dyn Error>> {
-        let shaders = DIRS.asset.join("shaders");
-        let data = vec![
-            fs::read(shaders.join("base.vert.spv"))?,
-            fs::read(shaders.join("base.frag.spv"))?,
-        ];
-
-        let (pipeline, layout) = create_pipeline(
-            &self.device,
-            self.surface_format.format,
-            self.depth_format,
-            self.swapchain.extent,
-            data,
-            self.global_descriptor_layout,
-        )?;
+    /// Uploads the faces of the skybox, blocking until they are uploaded, and creates its pipeline
+    fn create_skybox(&mut self, faces: [&Path; 6]) -> Result<Skybox> {
+        let faces = decode_faces(&faces)?;
         let alloc = vk::CommandBufferAllocateInfo::builder()
             .command_buffer_count(1)
             .command_pool(self.utility_pool)
@@ -394,8 +1418,8 @@ impl RenderingEngine for Engine {
                 .limits
                 .max_sampler_anisotropy
         };
-        let texture = Texture::new(
-            "texture.png",
+        let texture = Texture::cubemap(
+            &faces,
             self.device.clone(),
             cmd,
             self.graphics_queue,
@@ -404,18 +1428,328 @@ impl RenderingEngine for Engine {
         );
         let cmd = [cmd];
         unsafe { self.device.free_command_buffers(self.utility_pool, &cmd) };
+        let texture = texture?;
+        // the viewport is dynamic, so the pipeline is kept when the swapchain is recreated
+        let parts = self
+            .create_material_pipeline(&skybox::definition())
+            .map_err(|e| anyhow!("Failed to create skybox pipeline: {e}"))?;
+        Skybox::new(texture, parts, self.descriptor_pool, self.device.clone())
+    }
 
-        info!("Created graphics pipeline");
-        Ok(Arc::new(Material {
-            pipeline,
-            layout,
-            device: self.device.clone(),
-            texture: texture.ok(),
-        }))
+    /// Returns a sampler that can be shared by any number of textures
+    /// through [write_sampler](Material::write_sampler), equal definitions share one sampler
+    pub fn create_sampler(&self, definition: &SamplerDefinition) -> Result<Arc<Sampler>> {
+        let anisotropy = unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device)
+                .limits
+                .max_sampler_anisotropy
+        };
+        Sampler::get(definition, self.device.clone(), anisotropy)
     }
 
-    fn wait(&self) {
-        unsafe { self.device.device_wait_idle().unwrap() };
+    /// Uploads cpu side mesh data to the gpu, blocking until the upload is finished.
+    ///
+    /// Combined with [merge_meshes](crate::merge_meshes) this allows drawing static geometry
+    /// with a single draw call
+    pub fn create_mesh(&mut self, vertices: Vec<Vertex>, indices: Vec<u32>) -> Result<Arc<Mesh>> {
+        self.create_mesh_async(vertices, indices)?.wait()
+    }
+
+    /// Like [create_mesh](Engine::create_mesh), but returns as soon as the upload is submitted
+    pub fn create_mesh_async(
+        &mut self,
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+    ) -> Result<Upload<Arc<Mesh>>> {
+        self.create_skinned_mesh_async(vertices, Vec::new(), indices)
+    }
+
+    /// Like [create_mesh_async](Engine::create_mesh_async), with the joints and weights
+    /// skinned materials read, `skin` is either empty or has one entry per vertex
+    pub fn create_skinned_mesh_async(
+        &mut self,
+        vertices: Vec<Vertex>,
+        skin: Vec<SkinVertex>,
+        indices: Vec<u32>,
+    ) -> Result<Upload<Arc<Mesh>>> {
+        let (pools, commands) = unsafe { self.create_upload_commands()? };
+        let mesh = Mesh::new_skinned_async(
+            vertices,
+            skin,
+            indices,
+            self.device.clone(),
+            commands,
+            &self.staging,
+            self.allocator.clone(),
+        );
+        match mesh {
+            Ok(upload) => Ok(upload.with_command_pools(pools).map(Arc::new)),
+            Err(e) => {
+                // a failed upload already waited for anything it submitted
+                unsafe { self.destroy_command_pools(pools) };
+                Err(e)
+            }
+        }
+    }
+
+    /// Creates a storage buffer of `size` bytes that can be bound to materials
+    /// with [write_storage_buffer](Material::write_storage_buffer)
+    pub fn create_storage_buffer(&self, size: vk::DeviceSize) -> Result<StorageBuffer> {
+        StorageBuffer::new(self.device.clone(), self.allocator.clone(), size)
+    }
+
+    /// Changes the directional light from the next frame on, moving its shadows along with it.
+    ///
+    /// A light without a direction is ignored
+    pub fn set_light(&mut self, light: DirectionalLight) {
+        if light.direction.norm_squared() <= f32::EPSILON {
+            warn!("Directional light has no direction, keeping the current light");
+            return;
+        }
+        self.light = light;
+        self.shadow_map.set_direction(light.direction);
+    }
+
+    pub fn light(&self) -> DirectionalLight {
+        self.light
+    }
+
+    /// Where the time of the last frame went and whether the cpu, the gpu or presentation
+    /// limits the frame rate, see [Bottleneck::classify]
+    pub fn frame_pacing(&self) -> FramePacing {
+        self.pacing
+    }
+
+    /// Measures the frame that just finished waiting for its frame in flight and swapchain image
+    fn update_pacing(&mut self, wait_start: Instant, gpu_ms: Option<f32>) {
+        let now = Instant::now();
+        let waited = now - wait_start;
+        // the first frame has nothing to compare against
+        if let Some(last) = self.last_wait_end.replace(now) {
+            let cpu_ms = (now - last).saturating_sub(waited).as_secs_f32() * 1000.;
+            let present_wait_ms = waited.as_secs_f32() * 1000.;
+            // frames without timestamps keep the last gpu time
+            let gpu_ms = gpu_ms.or(self.pacing.gpu_ms);
+            self.pacing = FramePacing {
+                cpu_ms,
+                gpu_ms,
+                present_wait_ms,
+                bound: Bottleneck::classify(cpu_ms, gpu_ms, present_wait_ms),
+            };
+            self.frame_times.push(cpu_ms);
+        }
+    }
+
+    /// Describes the gpu the engine runs on
+    pub fn gpu_info(&self) -> GpuInfo {
+        unsafe { describe_gpu(&self.instance, self.physical_device) }
+    }
+
+    /// Names of the gpus the engine could run on, any of them can be selected
+    /// with the [gpu](GraphicsSettings::gpu) setting
+    pub fn gpu_names(&self) -> Result<Vec<String>> {
+        unsafe { suitable_gpu_names(&self.instance, self.surface, &self.surface_loader) }
+    }
+
+    /// Limits the staging memory of uploads in flight across all threads to `bytes`,
+    /// uploads that would exceed it block until the gpu finished copying earlier ones,
+    /// whether or not their [Upload] handles were polled yet. Unlimited if None
+    pub fn set_upload_budget(&mut self, bytes: Option<vk::DeviceSize>) {
+        upload::set_limit(bytes);
+        self.settings.upload_budget = bytes;
+    }
+
+    /// Describes the pipeline cache and the cached materials as indented text, for debugging
+    pub fn debug_report(&self) -> String {
+        use std::fmt::Write;
+
+        let mut report = String::new();
+        let (path, file_size, data_size) = cache_info(&self.device);
+        let size = |size: Option<String>| size.unwrap_or_else(|| "none".into());
+        writeln!(report, "pipeline cache:").unwrap();
+        writeln!(report, "  path: {}", path.display()).unwrap();
+        writeln!(
+            report,
+            "  file size: {}",
+            size(file_size.map(|bytes| format!("{bytes} bytes")))
+        )
+        .unwrap();
+        writeln!(
+            report,
+            "  data size: {}",
+            size(data_size.map(|bytes| format!("{bytes} bytes")))
+        )
+        .unwrap();
+
+        let mut materials = Material::cached();
+        materials.sort_by(|a, b| a.0.cmp(&b.0));
+        writeln!(report, "materials: {}", materials.len()).unwrap();
+        for (name, material) in materials {
+            match material {
+                Some(material) => writeln!(
+                    report,
+                    "  {name}: pipeline {:?}, layout {:?}, {} descriptor sets, textured: {}",
+                    material.get_pipeline(),
+                    material.layout,
+                    material.descriptor_sets.len(),
+                    material.texture.is_some()
+                ),
+                None => writeln!(report, "  {name}: dropped"),
+            }
+            .unwrap();
+        }
+        report
+    }
+
+    /// Applies the graphics settings that can change while running and reports the ones
+    /// that need the engine to be recreated, which keep their current value until then.
+    ///
+    /// The window and camera settings are only reported, the caller applies them
+    pub fn apply_settings(&mut self, settings: &GraphicsSettings) -> SettingsChanges {
+        let (live, changes) = self.settings.diff(settings);
+        if live.vsync != self.settings.vsync {
+            self.set_vsync(live.vsync);
+        }
+        if live.upload_budget != self.settings.upload_budget {
+            self.set_upload_budget(live.upload_budget);
+        }
+        self.max_texture_size = live.max_texture_size;
+        self.strict_assets = live.strict_assets;
+        self.settings = live;
+        if !changes.restart_required.is_empty() {
+            info!(
+                "Graphics settings {:?} take effect after a restart",
+                changes.restart_required
+            );
+        }
+        changes
+    }
+
+    /// Vertex buffer for geometry rebuilt every frame, its contents are discarded
+    /// by [begin_rendering](RenderingEngine::begin_rendering)
+    #[inline]
+    pub fn dynamic_vertices(&mut self) -> &mut DynamicVertexBuffer {
+        &mut self.dynamic_vertices
+    }
+
+    /// Uploads the model matrices of many instances of a mesh,
+    /// which are then drawn with a single indirect draw call by [render_batch](Engine::render_batch).
+    ///
+    /// Intended for large numbers of static objects, dynamic objects should use
+    /// [render](RenderingEngine::render). The material must be created with
+    /// [instanced](MaterialDefinition::instanced) set
+    pub fn create_batch(
+        &mut self,
+        mesh: Arc<Mesh>,
+        material: Arc<Material>,
+        transforms: &[Matrix4<f32>],
+    ) -> Result<Arc<DrawBatch>> {
+        unsafe { DrawBatch::new(mesh, material, transforms, self.allocator.clone()).map(Arc::new) }
+    }
+
+    /// Draws every instance of a batch this frame,
+    /// must be called between [begin_rendering](RenderingEngine::begin_rendering)
+    /// and [end_rendering](RenderingEngine::end_rendering)
+    pub fn render_batch(&mut self, batch: &Arc<DrawBatch>) {
+        self.next_thread();
+        self.last_draw = (0, 0);
+        self.queue_draw(DrawCommand::Batch(batch.clone()));
+    }
+
+    /// Creates the bone buffers of one skinned instance drawn with `material`,
+    /// which must be created with [skinned](MaterialDefinition::skinned) set
+    pub fn create_skeleton(
+        &mut self,
+        material: &Material,
+        joint_count: usize,
+    ) -> Result<Arc<Skeleton>> {
+        Skeleton::new(
+            material,
+            joint_count,
+            self.frames.len(),
+            self.allocator.clone(),
+            &self.bone_pools,
+        )
+        .map(Arc::new)
+    }
+
+    /// Sets the model space bone matrices a skeleton is drawn with this frame,
+    /// must be called between [begin_rendering](RenderingEngine::begin_rendering)
+    /// and [end_rendering](RenderingEngine::end_rendering).
+    ///
+    /// Bones that are not updated keep the matrices of the frame that last used the same buffer,
+    /// so all bones should be updated every frame the skeleton moves
+    pub fn update_bones(&self, skeleton: &Skeleton, bones: &[Matrix4<f32>]) -> Result<()> {
+        skeleton.write(self.frame_index(), bones)
+    }
+
+    /// Draws a skinned mesh with the bone matrices last written by
+    /// [update_bones](Engine::update_bones) this frame
+    pub fn render_skinned(
+        &mut self,
+        mesh: &Arc<Mesh>,
+        material: &Arc<Material>,
+        skeleton: &Arc<Skeleton>,
+        transform: Matrix4<f32>,
+    ) {
+        if !mesh.is_skinned() {
+            warn!("Skipping skinned draw of a mesh without joints and weights");
+            return;
+        }
+        let descriptor_set = skeleton.get_descriptor_set(self.frame_index());
+        self.queue_draw(DrawCommand::Skinned(
+            mesh.clone(),
+            material.clone(),
+            skeleton.clone(),
+            descriptor_set,
+            transform,
+        ));
+    }
+
+    /// Copies the next frame that is rendered to host memory,
+    /// the copy can be retrieved with [take_capture](Engine::take_capture) once it was rendered
+    pub fn capture_next_frame(&mut self) {
+        self.capture_requested = true;
+    }
+
+    /// Returns the pixels of the last captured frame, blocking until the gpu finished rendering it.
+    ///
+    /// Returns None if no capture was requested
+    /// or [end_rendering](RenderingEngine::end_rendering) was not called since
+    pub fn take_capture(&mut self) -> Option<FrameCapture> {
+        let (frame_index, buffer, extent) = self.pending_capture.take()?;
+        let fences = [self.frames[frame_index].fence];
+        unsafe {
+            if let Err(err) = self.device.wait_for_fences(&fences, true, u64::MAX) {
+                error!("Error waiting on fence: {err}");
+                return None;
+            }
+        }
+        let size = extent.width as usize * extent.height as usize * 4;
+        let ptr = buffer.get_info().get_mapped_data();
+        let mut pixels = unsafe { std::slice::from_raw_parts(ptr, size) }.to_vec();
+        if matches!(
+            self.surface_format.format,
+            vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM
+        ) {
+            bgra_to_rgba(&mut pixels);
+        }
+        Some(FrameCapture {
+            width: extent.width,
+            height: extent.height,
+            pixels,
+        })
+    }
+
+    /// Encodes the finished capture to `path` on its own thread, so the frame doesn't wait on it
+    fn save_screenshot(&mut self, path: PathBuf) {
+        if let Some(capture) = self.take_capture() {
+            std::thread::spawn(move || match write_png(&path, &capture) {
+                Ok(()) => info!("Saved screenshot to {path:?}"),
+                Err(e) => error!("Failed to save screenshot to {path:?}: {e}"),
+            });
+        }
     }
 }
 
@@ -423,35 +1757,40 @@ impl RenderingEngine for Engine {
 ///
 /// # Arguments
 ///
+/// * `index`: index of the thread, used to label its command buffers
 /// * `receiver`: channel to receive rendering commands on
 /// * `device`: device handle
 /// * `barrier`: barrier for synchronizing worker threads with the main thread
-fn render_thread(receiver: Receiver<RenderCommand>, device: &ash::Device, barrier: &Barrier) {
-    let mut cmd = vk::CommandBuffer::null();
-    let mut last_mesh = std::ptr::null();
-    let mut last_material = std::ptr::null();
-    let mut view = Default::default();
-    let mut projection = Perspective3::from_matrix_unchecked(Default::default());
-    let mut global_descriptors = [vk::DescriptorSet::null()];
+fn render_thread(
+    index: usize,
+    receiver: Receiver<RenderCommand>,
+    device: &ash::Device,
+    barrier: &Barrier,
+    labels: &Labels,
+) {
+    let label = format!("thread {index}");
+    let mut recorder = Recorder::new(
+        vk::CommandBuffer::null(),
+        Matrix4::identity(),
+        vk::DescriptorSet::null(),
+    );
     while let Ok(command) = receiver.recv() {
         match command {
             // initialize some per frame data for this thread and begin the command buffer
             RenderCommand::Begin(
-                cmd_buf,
-                view_matrix,
-                proj,
+                cmd,
+                view_projection,
                 desc,
                 surface_format,
                 depth_format,
+                samples,
+                extent,
             ) => unsafe {
-                cmd = cmd_buf;
-                view = view_matrix;
-                projection = proj;
-                global_descriptors[0] = desc;
-                let colors = [surface_format];
+                recorder = Recorder::new(cmd, view_projection, desc);
+                let colors = [surface_format, OBJECT_ID_FORMAT];
                 let mut rendering_info = vk::CommandBufferInheritanceRenderingInfo::builder()
                     .color_attachment_formats(&colors)
-                    .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                    .rasterization_samples(samples)
                     .depth_attachment_format(depth_format);
                 let inheritance_info =
                     vk::CommandBufferInheritanceInfo::builder().push_next(&mut rendering_info);
@@ -462,59 +1801,204 @@ fn render_thread(receiver: Receiver<RenderCommand>, device: &ash::Device, barrie
                             | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
                     );
                 device.begin_command_buffer(cmd, &begin_info).unwrap();
+                labels.begin(cmd, &label);
+                set_viewport(device, cmd, extent);
             },
 
-            // record the rendering commands
-            RenderCommand::Render(mesh, material, transform) => {
-                debug_assert_ne!(cmd, vk::CommandBuffer::null());
-                if cull_test(&mesh, &transform, &view, &projection) {
-                    unsafe {
-                        if !std::ptr::eq(mesh.as_ref(), last_mesh) {
-                            last_mesh = mesh.as_ref();
-                            mesh.bind(device, cmd);
-                        }
-
-                        if !std::ptr::eq(material.as_ref(), last_material) {
-                            last_material = material.as_ref();
-                            material.bind(device, cmd);
-                            device.cmd_bind_descriptor_sets(
-                                cmd,
-                                vk::PipelineBindPoint::GRAPHICS,
-                                material.get_pipeline_layout(),
-                                0,
-                                &global_descriptors,
-                                &[],
-                            );
-                        }
-
-                        device.cmd_push_constants(
-                            cmd,
-                            material.get_pipeline_layout(),
-                            vk::ShaderStageFlags::VERTEX,
-                            0,
-                            std::slice::from_raw_parts(
-                                transform.as_ptr() as *const u8,
-                                std::mem::size_of::<Matrix4<f32>>(),
-                            ),
-                        );
-
-                        device.cmd_draw_indexed(cmd, mesh.get_index_count(), 1, 0, 0, 0);
-                    }
-                }
-            }
+            RenderCommand::Draw(draw) => unsafe {
+                debug_assert_ne!(recorder.cmd, vk::CommandBuffer::null());
+                recorder.record(device, &draw);
+            },
 
-            // end the command buffer, reset pointers, and synchronize with the other threads using the barrier
+            // end the command buffer and synchronize with the other threads using the barrier
             RenderCommand::End => unsafe {
-                device.end_command_buffer(cmd).unwrap();
-                last_mesh = std::ptr::null();
-                last_material = std::ptr::null();
-                cmd = vk::CommandBuffer::null();
+                labels.end(recorder.cmd);
+                device.end_command_buffer(recorder.cmd).unwrap();
+                recorder = Recorder::new(
+                    vk::CommandBuffer::null(),
+                    Matrix4::identity(),
+                    vk::DescriptorSet::null(),
+                );
                 barrier.wait();
             },
         }
     }
 }
 
+/// Records draws into a command buffer inside the main pass,
+/// remembering the last bound mesh and material to skip binding them again
+struct Recorder {
+    cmd: vk::CommandBuffer,
+    last_mesh: *const Mesh,
+    last_material: *const Material,
+    /// Draws whose bounds are outside of this matrix' frustum are culled
+    view_projection: Matrix4<f32>,
+    global_descriptors: [vk::DescriptorSet; 1],
+}
+
+impl Recorder {
+    fn new(
+        cmd: vk::CommandBuffer,
+        view_projection: Matrix4<f32>,
+        global_descriptor: vk::DescriptorSet,
+    ) -> Self {
+        Recorder {
+            cmd,
+            last_mesh: std::ptr::null(),
+            last_material: std::ptr::null(),
+            view_projection,
+            global_descriptors: [global_descriptor],
+        }
+    }
+
+    unsafe fn record(&mut self, device: &ash::Device, draw: &DrawCommand) {
+        let cmd = self.cmd;
+        match draw {
+            DrawCommand::Render(mesh, material, transform, object_id) => {
+                if cull_test(mesh, transform, &self.view_projection) {
+                    bind_draw(
+                        device,
+                        cmd,
+                        (mesh, &mut self.last_mesh),
+                        (material, &mut self.last_material),
+                        &self.global_descriptors,
+                    );
+
+                    push_transforms(device, cmd, material, transform);
+                    push_object_id(device, cmd, material, *object_id);
+
+                    device.cmd_draw_indexed(cmd, mesh.get_index_count(), 1, 0, 0, 0);
+                }
+            }
+
+            // record a batch of instances with a single indirect draw
+            DrawCommand::Batch(batch) => {
+                bind_draw(
+                    device,
+                    cmd,
+                    (&batch.mesh, &mut self.last_mesh),
+                    (&batch.material, &mut self.last_material),
+                    &self.global_descriptors,
+                );
+                push_object_id(device, cmd, &batch.material, 0);
+                batch.draw(device, cmd);
+            }
+
+            // record a skinned draw, the skeleton's bones replace the material's own set 1.
+            // Skinned meshes are not culled since their bounds change with the animation
+            DrawCommand::Skinned(mesh, material, _skeleton, bones, transform) => {
+                bind_draw(
+                    device,
+                    cmd,
+                    (mesh, &mut self.last_mesh),
+                    (material, &mut self.last_material),
+                    &self.global_descriptors,
+                );
+                mesh.bind_skin(device, cmd);
+                let sets = [*bones];
+                device.cmd_bind_descriptor_sets(
+                    cmd,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    material.get_pipeline_layout(),
+                    1,
+                    &sets,
+                    &[],
+                );
+                push_transforms(device, cmd, material, transform);
+                push_object_id(device, cmd, material, 0);
+                device.cmd_draw_indexed(cmd, mesh.get_index_count(), 1, 0, 0, 0);
+            }
+
+            // the skybox binds its own pipeline, so the next draw binds its material again
+            DrawCommand::Skybox(skybox) => {
+                skybox.record(device, cmd, self.global_descriptors[0]);
+                self.last_material = std::ptr::null();
+            }
+        }
+    }
+}
+
+/// Binds a mesh and material unless they are already bound
+///
+/// # Arguments
+///
+/// * `mesh`: the mesh to bind and the last mesh bound to the command buffer
+/// * `material`: the material to bind and the last material bound to the command buffer
+/// * `global_descriptors`: descriptor sets bound along with each new material
+unsafe fn bind_draw(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    (mesh, last_mesh): (&Mesh, &mut *const Mesh),
+    (material, last_material): (&Material, &mut *const Material),
+    global_descriptors: &[vk::DescriptorSet],
+) {
+    if !std::ptr::eq(mesh, *last_mesh) {
+        *last_mesh = mesh;
+        mesh.bind(device, cmd);
+    }
+
+    if !std::ptr::eq(material, *last_material) {
+        *last_material = material;
+        material.bind(device, cmd);
+        device.cmd_bind_descriptor_sets(
+            cmd,
+            vk::PipelineBindPoint::GRAPHICS,
+            material.get_pipeline_layout(),
+            0,
+            global_descriptors,
+            &[],
+        );
+        let material_sets = material.get_descriptor_sets();
+        if !material_sets.is_empty() {
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::GRAPHICS,
+                material.get_pipeline_layout(),
+                1,
+                material_sets,
+                &[],
+            );
+        }
+    }
+}
+
+/// Pushes the model matrix and the normal matrix derived from it to the vertex stage
+unsafe fn push_transforms(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    material: &Material,
+    model: &Matrix4<f32>,
+) {
+    let transforms = Transforms::new(model);
+    device.cmd_push_constants(
+        cmd,
+        material.get_pipeline_layout(),
+        vk::ShaderStageFlags::VERTEX,
+        0,
+        std::slice::from_raw_parts(
+            &transforms as *const Transforms as *const u8,
+            std::mem::size_of::<Transforms>(),
+        ),
+    );
+}
+
+/// Pushes the id the fragment shader writes to the object id attachment
+unsafe fn push_object_id(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    material: &Material,
+    object_id: u64,
+) {
+    let id = [object_id as u32, (object_id >> 32) as u32];
+    device.cmd_push_constants(
+        cmd,
+        material.get_pipeline_layout(),
+        vk::ShaderStageFlags::FRAGMENT,
+        OBJECT_ID_OFFSET,
+        std::slice::from_raw_parts(id.as_ptr() as *const u8, std::mem::size_of_val(&id)),
+    );
+}
+
 /// This function is used to perform queue submission and
 /// presentation in a dedicated thread
 ///
@@ -531,14 +2015,23 @@ fn presentation_thread(
     presentation_queue: vk::Queue,
 ) {
     while let Ok(data) = receiver.recv() {
+        let cmd = [data.cmd];
+        let acquire_semaphore = [data.present_semaphore];
+        let render_semaphore = [data.render_semaphore];
         let submit_info = [vk::SubmitInfo::builder()
-            .command_buffers(&[data.cmd])
-            .wait_semaphores(&[data.present_semaphore])
-            .signal_semaphores(&[data.render_semaphore])
+            .command_buffers(&cmd)
+            .wait_semaphores(&acquire_semaphore)
+            .signal_semaphores(&render_semaphore)
             .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
             .build()];
 
-        let wait_semaphore = [data.render_semaphore];
+        // when the image's ownership is transferred the fence is signaled by the second submission,
+        // which can only complete after the graphics submission
+        let (graphics_fence, present_wait) = match data.ownership_transfer {
+            Some((_, semaphore)) => (vk::Fence::null(), semaphore),
+            None => (data.signal_fence, data.render_semaphore),
+        };
+        let wait_semaphore = [present_wait];
         let swapchain = [data.swapchain];
         let image_index = [data.image_index];
         let present_info = vk::PresentInfoKHR::builder()
@@ -548,16 +2041,42 @@ fn presentation_thread(
 
         unsafe {
             device
-                .queue_submit(graphics_queue, &submit_info, data.signal_fence)
-                .map_err(|e| error!("Queue submission error {e:?}"))
+                .queue_submit(graphics_queue, &submit_info, graphics_fence)
+                .map_err(|e| {
+                    error!("Queue submission error {e:?}");
+                    report_device_lost(e);
+                })
                 .expect("Queue submit failed");
+            if let Some((transfer_cmd, transfer_semaphore)) = data.ownership_transfer {
+                let transfer_cmd = [transfer_cmd];
+                let transfer_semaphore = [transfer_semaphore];
+                let submit_info = [vk::SubmitInfo::builder()
+                    .command_buffers(&transfer_cmd)
+                    .wait_semaphores(&render_semaphore)
+                    .signal_semaphores(&transfer_semaphore)
+                    .wait_dst_stage_mask(&[vk::PipelineStageFlags::ALL_COMMANDS])
+                    .build()];
+                device
+                    .queue_submit(presentation_queue, &submit_info, data.signal_fence)
+                    .map_err(|e| {
+                        error!("Queue submission error {e:?}");
+                        report_device_lost(e);
+                    })
+                    .expect("Queue submit failed");
+            }
             let suboptimal = match data
                 .swapchain_loader
                 .queue_present(presentation_queue, &present_info)
             {
                 Ok(val) => val,
                 Err(e) if e == vk::Result::ERROR_OUT_OF_DATE_KHR => true,
-                Err(e) => panic!("Swapchain presentation error: {e}"),
+                // the engine would wait for this result forever if the thread panicked,
+                // recreating the swapchain fails loudly if the surface is really gone
+                Err(e) => {
+                    error!("Swapchain presentation error: {e}");
+                    report_device_lost(e);
+                    true
+                }
             };
             {
                 let mut lock = data.sync_data.0.lock();
@@ -572,30 +2091,72 @@ fn presentation_thread(
     }
 }
 
-/// Helper function to handle transitioning the color image and depth image to the correct layout
+/// Emits a telemetry event if `result` means the device was lost
+fn report_device_lost(result: vk::Result) {
+    if result == vk::Result::ERROR_DEVICE_LOST {
+        telemetry::emit(TelemetryEvent::DeviceLost);
+    }
+}
+
+/// Helper function to handle transitioning the color image and depth image to the correct layout.
+///
+/// With multisampling the main pass renders into the multisampled targets,
+/// which are resolved into the color and object id image
+#[allow(clippy::too_many_arguments)]
 unsafe fn begin(
     image_view: vk::ImageView,
     depth_view: vk::ImageView,
+    object_id_view: vk::ImageView,
+    msaa: Option<&MsaaTargets>,
     extent: vk::Extent2D,
     cmd: vk::CommandBuffer,
     device: &ash::Device,
+    flags: vk::RenderingFlags,
+    clear_color: [f32; 4],
 ) {
-    let color_attachment = [vk::RenderingAttachmentInfo::builder()
-        .image_view(image_view)
-        .image_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL)
-        .load_op(vk::AttachmentLoadOp::CLEAR)
-        .store_op(vk::AttachmentStoreOp::STORE)
-        .clear_value(vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0., 0., 0., 1.],
-            },
-        })
-        .build()];
+    let mut color_attachment = [
+        vk::RenderingAttachmentInfo::builder()
+            .image_view(image_view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: clear_color,
+                },
+            })
+            .build(),
+        // kept for picking after the frame, zero marks pixels without a tagged draw
+        vk::RenderingAttachmentInfo::builder()
+            .image_view(object_id_view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(vk::ClearValue {
+                color: vk::ClearColorValue { uint32: [0; 4] },
+            })
+            .build(),
+    ];
+    if let Some(msaa) = msaa {
+        // object ids can't be averaged, the first sample's id is picked
+        let targets = [
+            (msaa.color_view, vk::ResolveModeFlags::AVERAGE),
+            (msaa.object_id_view, vk::ResolveModeFlags::SAMPLE_ZERO),
+        ];
+        for (attachment, (view, mode)) in color_attachment.iter_mut().zip(targets) {
+            attachment.resolve_image_view = attachment.image_view;
+            attachment.resolve_image_layout = attachment.image_layout;
+            attachment.resolve_mode = mode;
+            attachment.image_view = view;
+            attachment.store_op = vk::AttachmentStoreOp::DONT_CARE;
+        }
+    }
     let depth_attachment = vk::RenderingAttachmentInfo::builder()
         .image_view(depth_view)
         .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
         .load_op(vk::AttachmentLoadOp::CLEAR)
-        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+        // the occlusion pass tests bounding boxes against the depth of the main pass
+        .store_op(vk::AttachmentStoreOp::STORE)
         .clear_value(vk::ClearValue {
             depth_stencil: vk::ClearDepthStencilValue {
                 depth: 1.,
@@ -604,7 +2165,7 @@ unsafe fn begin(
         });
 
     let rendering_info = vk::RenderingInfo::builder()
-        .flags(vk::RenderingFlagsKHR::CONTENTS_SECONDARY_COMMAND_BUFFERS)
+        .flags(flags)
         .layer_count(1)
         .color_attachments(&color_attachment)
         .depth_attachment(&depth_attachment)
@@ -616,17 +2177,159 @@ unsafe fn begin(
     device.cmd_begin_rendering(cmd, &rendering_info);
 }
 
-unsafe fn pre_image_transition(
+/// Host visible buffer large enough to hold a copy of a swapchain image
+unsafe fn create_capture_buffer(extent: vk::Extent2D, allocator: Arc<Allocator>) -> Result<Buffer> {
+    let create_info = vk::BufferCreateInfo::builder()
+        .usage(vk::BufferUsageFlags::TRANSFER_DST)
+        .size(extent.width as vk::DeviceSize * extent.height as vk::DeviceSize * 4)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let alloc_info = vk_mem::AllocationCreateInfo {
+        usage: vk_mem::MemoryUsage::GpuToCpu,
+        flags: vk_mem::AllocationCreateFlags::MAPPED,
+        required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE
+            | vk::MemoryPropertyFlags::HOST_COHERENT,
+        ..Default::default()
+    };
+    Ok(Buffer::new(&create_info, &alloc_info, allocator)?)
+}
+
+/// Copies the rendered swapchain image into `buffer`,
+/// leaving the image in `TRANSFER_SRC_OPTIMAL` layout
+unsafe fn record_capture(
     device: &ash::Device,
     cmd: vk::CommandBuffer,
-    color_image: vk::Image,
-    depth_image: vk::Image,
+    image: vk::Image,
+    buffer: vk::Buffer,
+    extent: vk::Extent2D,
 ) {
-    let image_barrier = [vk::ImageMemoryBarrier::builder()
+    let barrier = [
+        present_barrier(image, vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .build(),
+    ];
+    device.cmd_pipeline_barrier(
+        cmd,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::TRANSFER,
+        DependencyFlags::empty(),
+        &[],
+        &[],
+        &barrier,
+    );
+    let region = [vk::BufferImageCopy::builder()
+        .image_subresource(vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .image_extent(vk::Extent3D::from(extent))
+        .build()];
+    device.cmd_copy_image_to_buffer(
+        cmd,
+        image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        buffer,
+        &region,
+    );
+}
+
+/// Copies the object id texel at `x`, `y` into `buffer`,
+/// leaving the image in `COLOR_ATTACHMENT_OPTIMAL` layout for the next frame
+unsafe fn record_object_id_copy(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    image: vk::Image,
+    buffer: vk::Buffer,
+    x: u32,
+    y: u32,
+) -> ash::prelude::VkResult<()> {
+    let begin_info =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(cmd, &begin_info)?;
+    let subresource_range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    let barrier = [vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+        .image(image)
+        .subresource_range(subresource_range)
+        .build()];
+    device.cmd_pipeline_barrier(
+        cmd,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::TRANSFER,
+        DependencyFlags::empty(),
+        &[],
+        &[],
+        &barrier,
+    );
+    let region = [vk::BufferImageCopy::builder()
+        .image_subresource(vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .image_offset(vk::Offset3D {
+            x: x as i32,
+            y: y as i32,
+            z: 0,
+        })
+        .image_extent(vk::Extent3D {
+            width: 1,
+            height: 1,
+            depth: 1,
+        })
+        .build()];
+    device.cmd_copy_image_to_buffer(
+        cmd,
+        image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        buffer,
+        &region,
+    );
+    let barrier = [vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .src_access_mask(vk::AccessFlags::TRANSFER_READ)
         .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
-        .old_layout(vk::ImageLayout::UNDEFINED)
-        .new_layout(vk::ImageLayout::ATTACHMENT_OPTIMAL)
-        .image(color_image)
+        .image(image)
+        .subresource_range(subresource_range)
+        .build()];
+    device.cmd_pipeline_barrier(
+        cmd,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        DependencyFlags::empty(),
+        &[],
+        &[],
+        &barrier,
+    );
+    device.end_command_buffer(cmd)
+}
+
+/// Barrier transitioning a rendered swapchain image to the present layout
+fn present_barrier<'a>(
+    image: vk::Image,
+    src_family: u32,
+    dst_family: u32,
+) -> vk::ImageMemoryBarrierBuilder<'a> {
+    vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .src_queue_family_index(src_family)
+        .dst_queue_family_index(dst_family)
+        .image(image)
         .subresource_range(vk::ImageSubresourceRange {
             aspect_mask: vk::ImageAspectFlags::COLOR,
             base_mip_level: 0,
@@ -634,7 +2337,60 @@ unsafe fn pre_image_transition(
             base_array_layer: 0,
             layer_count: 1,
         })
-        .build()];
+}
+
+/// Records the acquire half of the swapchain image's queue family ownership transfer,
+/// matching the release barrier at the end of the frame's primary command buffer
+unsafe fn record_ownership_acquire(
+    device: &ash::Device,
+    transfer: &OwnershipTransfer,
+    image: vk::Image,
+    queue_families: &[u32; 2],
+) -> ash::prelude::VkResult<()> {
+    device.reset_command_pool(transfer.pool, vk::CommandPoolResetFlags::empty())?;
+    let begin_info =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(transfer.cmd, &begin_info)?;
+    let barrier = [present_barrier(image, queue_families[0], queue_families[1]).build()];
+    device.cmd_pipeline_barrier(
+        transfer.cmd,
+        vk::PipelineStageFlags::TOP_OF_PIPE,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        DependencyFlags::empty(),
+        &[],
+        &[],
+        &barrier,
+    );
+    device.end_command_buffer(transfer.cmd)
+}
+
+unsafe fn pre_image_transition(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    color_image: vk::Image,
+    depth_image: vk::Image,
+    object_id_image: vk::Image,
+    msaa_images: Option<[vk::Image; 2]>,
+) {
+    let images = [color_image, object_id_image]
+        .into_iter()
+        .chain(msaa_images.into_iter().flatten());
+    let image_barrier = images.map(|image| {
+        vk::ImageMemoryBarrier::builder()
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .image(image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .build()
+    });
+    let image_barrier = image_barrier.collect::<SmallVec<[_; 4]>>();
 
     device.cmd_pipeline_barrier(
         cmd,
@@ -700,18 +2456,39 @@ impl Drop for Engine {
                 self.device
                     .destroy_semaphore(frame.graphics_semaphore, None);
                 self.device.destroy_semaphore(frame.present_semaphore, None);
+                if let Some(transfer) = &frame.ownership_transfer {
+                    self.device.destroy_command_pool(transfer.pool, None);
+                    self.device.destroy_semaphore(transfer.semaphore, None);
+                }
                 self.device.destroy_fence(frame.fence, None);
                 ManuallyDrop::drop(&mut frame.ubo);
             }
 
             ManuallyDrop::drop(&mut self.depth_image);
             self.device.destroy_image_view(self.depth_view, None);
+            ManuallyDrop::drop(&mut self.object_id_image);
+            self.device.destroy_image_view(self.object_id_view, None);
+            self.msaa = None;
+            ManuallyDrop::drop(&mut self.dynamic_vertices);
+            self.shadow_casters.clear();
+            self.dispatches.clear();
+            self.placeholder_mesh = None;
+            ManuallyDrop::drop(&mut self.shadow_map);
+            ManuallyDrop::drop(&mut self.environment);
+            ManuallyDrop::drop(&mut self.white_texture);
+            self.skybox = None;
+            self.occlusion = None;
+            self.gpu_timer = None;
+            ManuallyDrop::drop(&mut self.staging);
+            deletion::flush(&self.device);
+            self.bone_pools.destroy();
             self.device
                 .destroy_descriptor_pool(self.descriptor_pool, None);
             self.device
                 .destroy_descriptor_set_layout(self.global_descriptor_layout, None);
 
             ManuallyDrop::drop(&mut self.swapchain);
+            self.pending_capture = None;
 
             if let Some(alloc) = Arc::get_mut(&mut self.allocator) {
                 alloc.destroy();
@@ -724,9 +2501,11 @@ impl Drop for Engine {
             self.device.destroy_device(None);
             self.surface_loader.destroy_surface(self.surface, None);
             #[cfg(feature = "validation-layers")]
-            self.debug_messenger
-                .0
-                .destroy_debug_utils_messenger(self.debug_messenger.1, None);
+            if let Some(messenger) = self.debug_messenger.1 {
+                self.debug_messenger
+                    .0
+                    .destroy_debug_utils_messenger(messenger, None);
+            }
             self.instance.destroy_instance(None);
         }
     }
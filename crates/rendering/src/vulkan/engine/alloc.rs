@@ -1,14 +1,18 @@
 use std::fmt::{Debug, Formatter};
 use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 
 use ash::prelude::VkResult;
 use ash::vk;
 use ash::vk::DeviceSize;
+use parking_lot::Mutex;
 use vk_mem::{Allocator, AllocatorCreateInfo};
 use anyhow::Result;
 
+use crate::vulkan::engine::deletion::{self, Resource};
+
 pub(super) fn create_allocator(
     entry: &ash::Entry,
     instance: &ash::Instance,
@@ -142,6 +146,8 @@ impl Drop for Buffer {
 #[derive(Debug)]
 pub struct GpuObject<T: Sized> {
     buffer: Buffer,
+    /// Writes are visible to the device without a [flush](GpuObject::flush)
+    coherent: bool,
     _spooky: PhantomData<T>,
 }
 
@@ -162,15 +168,115 @@ impl<T> GpuObject<T> {
             ..Default::default()
         };
         let buffer = unsafe { Buffer::new(&create_info, &alloc_info, allocator)? };
+        let properties = buffer
+            .allocation
+            .allocator
+            .get_memory_type_properties(buffer.get_info().get_memory_type())?;
         Ok(GpuObject {
             buffer,
+            coherent: properties.contains(vk::MemoryPropertyFlags::HOST_COHERENT),
             _spooky: Default::default(),
         })
     }
 
+    /// Makes writes through the mapped object visible to the device,
+    /// does nothing if its memory is host coherent
+    pub fn flush(&self) -> Result<()> {
+        if !self.coherent {
+            let allocation = &self.buffer.allocation;
+            allocation.allocator.flush_allocation(
+                &allocation.allocation,
+                0,
+                std::mem::size_of::<T>(),
+            )?;
+        }
+        Ok(())
+    }
+
     pub fn get_buffer(&self) -> vk::Buffer {
         self.buffer.buffer
     }
+
+    /// The buffer backing the object, to queue it for deletion
+    pub fn into_buffer(self) -> Buffer {
+        self.buffer
+    }
+}
+
+/// Host visible storage buffer with a size chosen at runtime,
+/// for data like skinning matrices or large instance arrays.
+/// Dropping it queues the buffer for deletion, frames in flight may still read from it
+pub struct StorageBuffer {
+    device: Arc<ash::Device>,
+    buffer: ManuallyDrop<Buffer>,
+    size: DeviceSize,
+}
+
+impl StorageBuffer {
+    pub fn new(
+        device: Arc<ash::Device>,
+        allocator: Arc<Allocator>,
+        size: DeviceSize,
+    ) -> Result<Self> {
+        let create_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .usage(vk::BufferUsageFlags::STORAGE_BUFFER);
+        let alloc_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::CpuToGpu,
+            flags: vk_mem::AllocationCreateFlags::MAPPED,
+            required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE
+                | vk::MemoryPropertyFlags::HOST_COHERENT,
+            ..Default::default()
+        };
+        let buffer = unsafe { Buffer::new(&create_info, &alloc_info, allocator)? };
+        Ok(StorageBuffer {
+            device,
+            buffer: ManuallyDrop::new(buffer),
+            size,
+        })
+    }
+
+    /// Copies `data` into the buffer starting `offset` bytes into it
+    ///
+    /// # Panics
+    /// Panics if the data does not fit into the buffer
+    pub fn write<T: Copy>(&mut self, offset: DeviceSize, data: &[T]) {
+        let len = std::mem::size_of_val(data) as DeviceSize;
+        assert!(offset + len <= self.size, "Write exceeds the storage buffer's size");
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                self.buffer.allocation.info.get_mapped_data().add(offset as usize),
+                len as usize,
+            );
+        }
+    }
+
+    #[inline]
+    pub fn size(&self) -> DeviceSize {
+        self.size
+    }
+
+    pub fn get_buffer(&self) -> vk::Buffer {
+        self.buffer.buffer
+    }
+}
+
+impl Debug for StorageBuffer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StorageBuffer")
+            .field("buffer", &self.buffer)
+            .field("size", &self.size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for StorageBuffer {
+    fn drop(&mut self) {
+        let buffer = unsafe { ManuallyDrop::take(&mut self.buffer) };
+        deletion::queue(self.device.clone(), Resource::Buffer(buffer));
+    }
 }
 
 impl<T> Deref for GpuObject<T> {
@@ -186,3 +292,142 @@ impl<T> DerefMut for GpuObject<T> {
         unsafe { &mut *(self.buffer.allocation.info.get_mapped_data() as *mut T) }
     }
 }
+
+/// Mapped staging buffers kept for later uploads instead of being freed,
+/// at most `capacity` bytes of them
+pub(crate) struct StagingPool<B = Buffer> {
+    /// Returned buffers with their size
+    free: Mutex<Vec<(DeviceSize, B)>>,
+    capacity: DeviceSize,
+    allocate: Box<dyn Fn(DeviceSize) -> VkResult<B> + Send + Sync>,
+}
+
+/// Staging buffer taken from a [StagingPool], returned to it when dropped
+pub(crate) struct StagingBuffer<B = Buffer> {
+    buffer: Option<B>,
+    size: DeviceSize,
+    pool: Arc<StagingPool<B>>,
+}
+
+impl StagingPool {
+    /// Pool of host visible, host coherent buffers that stay mapped and can be copied from
+    pub(crate) fn mapped(allocator: Arc<Allocator>, capacity: DeviceSize) -> Arc<Self> {
+        StagingPool::new(capacity, move |size| {
+            let create_info = vk::BufferCreateInfo::builder()
+                .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+                .size(size)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            let alloc_info = vk_mem::AllocationCreateInfo {
+                usage: vk_mem::MemoryUsage::CpuToGpu,
+                flags: vk_mem::AllocationCreateFlags::MAPPED,
+                required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE
+                    | vk::MemoryPropertyFlags::HOST_COHERENT,
+                ..Default::default()
+            };
+            unsafe { Buffer::new(&create_info, &alloc_info, allocator.clone()) }
+        })
+    }
+}
+
+impl<B> StagingPool<B> {
+    pub(crate) fn new(
+        capacity: DeviceSize,
+        allocate: impl Fn(DeviceSize) -> VkResult<B> + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Arc::new(StagingPool {
+            free: Mutex::new(Vec::new()),
+            capacity,
+            allocate: Box::new(allocate),
+        })
+    }
+
+    /// Takes the smallest returned buffer of at least `size` bytes,
+    /// allocating a buffer of exactly `size` bytes if none is large enough
+    pub(crate) fn take(self: &Arc<Self>, size: DeviceSize) -> VkResult<StagingBuffer<B>> {
+        let mut free = self.free.lock();
+        let best = free
+            .iter()
+            .enumerate()
+            .filter(|(_, (free_size, _))| *free_size >= size)
+            .min_by_key(|(_, (free_size, _))| *free_size)
+            .map(|(index, _)| index);
+        let (size, buffer) = match best {
+            Some(index) => free.swap_remove(index),
+            None => {
+                drop(free);
+                (size, (self.allocate)(size)?)
+            }
+        };
+        Ok(StagingBuffer {
+            buffer: Some(buffer),
+            size,
+            pool: self.clone(),
+        })
+    }
+
+    /// Keeps a returned buffer if it fits into the pool's capacity, otherwise it is freed
+    fn recycle(&self, size: DeviceSize, buffer: B) {
+        let mut free = self.free.lock();
+        let kept = free.iter().map(|(size, _)| size).sum::<DeviceSize>();
+        if kept + size <= self.capacity {
+            free.push((size, buffer));
+        }
+    }
+}
+
+impl<B> Deref for StagingBuffer<B> {
+    type Target = B;
+
+    fn deref(&self) -> &Self::Target {
+        self.buffer.as_ref().unwrap()
+    }
+}
+
+impl<B> Drop for StagingBuffer<B> {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.pool.recycle(self.size, buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use crate::vulkan::engine::alloc::StagingPool;
+
+    /// Pool handing out numbered fake buffers, counting how many were allocated
+    fn counting_pool(capacity: u64) -> (Arc<StagingPool<u64>>, Arc<AtomicU64>) {
+        let allocated = Arc::new(AtomicU64::new(0));
+        let counter = allocated.clone();
+        let pool = StagingPool::new(capacity, move |_| {
+            Ok(counter.fetch_add(1, Ordering::Relaxed))
+        });
+        (pool, allocated)
+    }
+
+    #[test]
+    fn staging_buffers_are_reused() {
+        let (pool, allocated) = counting_pool(1024);
+        let first = *pool.take(256).unwrap();
+        let second = *pool.take(256).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(allocated.load(Ordering::Relaxed), 1);
+        // a smaller request reuses the larger buffer, a larger one can't
+        assert_eq!(*pool.take(16).unwrap(), first);
+        assert_ne!(*pool.take(512).unwrap(), first);
+    }
+
+    #[test]
+    fn staging_pool_is_capped() {
+        let (pool, allocated) = counting_pool(256);
+        let held = pool.take(256).unwrap();
+        drop(pool.take(128).unwrap());
+        drop(held);
+        // the 128 byte buffer filled part of the capacity, so the larger one was freed
+        pool.take(256).unwrap();
+        assert_eq!(allocated.load(Ordering::Relaxed), 3);
+    }
+}
@@ -0,0 +1,124 @@
+use std::mem::ManuallyDrop;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+use nalgebra::Matrix4;
+use vk_mem::Allocator;
+
+use crate::vulkan::engine::alloc::Buffer;
+use crate::vulkan::engine::deletion::{self, Resource};
+use crate::{Material, Mesh};
+
+/// Many instances of the same mesh and material drawn with a single indirect draw call.
+///
+/// The per instance model matrices are read from a second vertex buffer, so the material
+/// must be created with [instanced](crate::materials::MaterialDefinition::instanced) set.
+/// The draw command lives in a storage buffer so a compute pass can cull instances on the gpu.
+/// Batches are not drawn into the shadow map yet
+pub struct DrawBatch {
+    pub(super) mesh: Arc<Mesh>,
+    pub(super) material: Arc<Material>,
+    instances: ManuallyDrop<Buffer>,
+    commands: ManuallyDrop<Buffer>,
+    instance_count: u32,
+}
+
+impl DrawBatch {
+    pub(super) unsafe fn new(
+        mesh: Arc<Mesh>,
+        material: Arc<Material>,
+        transforms: &[Matrix4<f32>],
+        allocator: Arc<Allocator>,
+    ) -> Result<Self> {
+        if transforms.is_empty() {
+            return Err(anyhow!("Draw batches need at least one instance"));
+        }
+        if !material.instanced {
+            return Err(anyhow!("Draw batches require an instanced material"));
+        }
+
+        let size = std::mem::size_of_val(transforms);
+        let instances =
+            create_mapped_buffer(size, vk::BufferUsageFlags::VERTEX_BUFFER, allocator.clone())?;
+        std::ptr::copy_nonoverlapping(
+            transforms.as_ptr() as *const u8,
+            instances.get_info().get_mapped_data(),
+            size,
+        );
+
+        let command = vk::DrawIndexedIndirectCommand {
+            index_count: mesh.get_index_count(),
+            instance_count: transforms.len() as u32,
+            first_index: 0,
+            vertex_offset: 0,
+            first_instance: 0,
+        };
+        let commands = create_mapped_buffer(
+            std::mem::size_of::<vk::DrawIndexedIndirectCommand>(),
+            vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+            allocator,
+        )?;
+        std::ptr::write(
+            commands.get_info().get_mapped_data() as *mut vk::DrawIndexedIndirectCommand,
+            command,
+        );
+
+        Ok(DrawBatch {
+            mesh,
+            material,
+            instances: ManuallyDrop::new(instances),
+            commands: ManuallyDrop::new(commands),
+            instance_count: transforms.len() as u32,
+        })
+    }
+
+    #[inline]
+    pub fn instance_count(&self) -> u32 {
+        self.instance_count
+    }
+
+    /// Records the indirect draw, the batch's mesh and material must already be bound
+    pub(super) unsafe fn draw(&self, device: &ash::Device, cmd: vk::CommandBuffer) {
+        let buffers = [**self.instances];
+        device.cmd_bind_vertex_buffers(cmd, 1, &buffers, &[0]);
+        device.cmd_draw_indexed_indirect(
+            cmd,
+            **self.commands,
+            0,
+            1,
+            std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32,
+        );
+    }
+}
+
+impl Drop for DrawBatch {
+    fn drop(&mut self) {
+        let device = self.material.device.clone();
+        unsafe {
+            let instances = ManuallyDrop::take(&mut self.instances);
+            let commands = ManuallyDrop::take(&mut self.commands);
+            deletion::queue(device.clone(), Resource::Buffer(instances));
+            deletion::queue(device, Resource::Buffer(commands));
+        }
+    }
+}
+
+unsafe fn create_mapped_buffer(
+    size: usize,
+    usage: vk::BufferUsageFlags,
+    allocator: Arc<Allocator>,
+) -> Result<Buffer> {
+    let create_info = vk::BufferCreateInfo::builder()
+        .size(size as vk::DeviceSize)
+        .usage(usage)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE);
+    let alloc_info = vk_mem::AllocationCreateInfo {
+        usage: vk_mem::MemoryUsage::CpuToGpu,
+        flags: vk_mem::AllocationCreateFlags::MAPPED,
+        required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE
+            | vk::MemoryPropertyFlags::HOST_COHERENT,
+        ..Default::default()
+    };
+    Ok(Buffer::new(&create_info, &alloc_info, allocator)?)
+}
@@ -0,0 +1,178 @@
+use std::error::Error;
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::vulkan::engine::alloc::StorageBuffer;
+use crate::vulkan::engine::deletion::{self, Resource};
+use crate::vulkan::engine::pipeline::PipelineParts;
+
+/// A compute shader and its descriptor sets, dispatched with [dispatch](super::Engine::dispatch)
+pub struct ComputePipeline {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    descriptor_layouts: Vec<vk::DescriptorSetLayout>,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    descriptor_pool: vk::DescriptorPool,
+    device: Arc<ash::Device>,
+}
+
+impl ComputePipeline {
+    /// Takes ownership of the pipeline and allocates one set of every layout from `descriptor_pool`
+    pub(super) fn new(
+        device: Arc<ash::Device>,
+        descriptor_pool: vk::DescriptorPool,
+        (pipeline, layout, descriptor_layouts): PipelineParts,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut compute = ComputePipeline {
+            pipeline,
+            layout,
+            descriptor_layouts,
+            descriptor_sets: Vec::new(),
+            descriptor_pool,
+            device,
+        };
+        if !compute.descriptor_layouts.is_empty() {
+            let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&compute.descriptor_layouts);
+            compute.descriptor_sets =
+                unsafe { compute.device.allocate_descriptor_sets(&alloc_info)? };
+        }
+        Ok(compute)
+    }
+
+    /// Points a storage buffer binding of the shader at `buffer`.
+    ///
+    /// Like for materials, descriptors can't be changed while a frame dispatching the pipeline
+    /// is in flight, so storage buffers should be written before the first dispatch
+    ///
+    /// # Arguments
+    ///
+    /// * `set`: set number as declared in the shader
+    /// * `binding`: binding number of the storage buffer inside the set
+    pub fn write_storage_buffer(
+        &self,
+        set: u32,
+        binding: u32,
+        buffer: &StorageBuffer,
+    ) -> Result<(), Box<dyn Error>> {
+        let set = *self
+            .descriptor_sets
+            .get(set as usize)
+            .ok_or_else(|| format!("Compute shader has no descriptor set {set}"))?;
+        let buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(buffer.get_buffer())
+            .offset(0)
+            .range(buffer.size())
+            .build()];
+        let write = [vk::WriteDescriptorSet::builder()
+            .dst_set(set)
+            .dst_binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&buffer_info)
+            .build()];
+        unsafe { self.device.update_descriptor_sets(&write, &[]) };
+        Ok(())
+    }
+
+    /// Binds the pipeline and its sets and dispatches `group_count` workgroups
+    pub(super) unsafe fn record(&self, cmd: vk::CommandBuffer, group_count: [u32; 3]) {
+        let device = &self.device;
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+        if !self.descriptor_sets.is_empty() {
+            device.cmd_bind_descriptor_sets(
+                cmd,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &self.descriptor_sets,
+                &[],
+            );
+        }
+        let [x, y, z] = group_count;
+        device.cmd_dispatch(cmd, x, y, z);
+    }
+}
+
+impl Drop for ComputePipeline {
+    fn drop(&mut self) {
+        deletion::queue(self.device.clone(), Resource::Pipeline(self.pipeline));
+        deletion::queue(self.device.clone(), Resource::PipelineLayout(self.layout));
+        if !self.descriptor_sets.is_empty() {
+            let sets = std::mem::take(&mut self.descriptor_sets);
+            deletion::queue(
+                self.device.clone(),
+                Resource::DescriptorSets(self.descriptor_pool, sets),
+            );
+        }
+        for layout in self.descriptor_layouts.drain(..) {
+            deletion::queue(self.device.clone(), Resource::DescriptorSetLayout(layout));
+        }
+    }
+}
+
+/// Records the frame's dispatches in order, each one seeing the storage buffer writes
+/// of the ones before it.
+///
+/// Their writes are made visible to the frame's draws, whether they read the results
+/// as vertices, as indirect draws or from their shaders
+pub(super) unsafe fn record_dispatches(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    dispatches: &[(Arc<ComputePipeline>, [u32; 3])],
+) {
+    if dispatches.is_empty() {
+        return;
+    }
+    let compute = vk::PipelineStageFlags::COMPUTE_SHADER;
+    // the previous frame's draws may still be reading the buffers
+    let none = vk::AccessFlags::empty();
+    memory_barrier(device, cmd, (draw_stages(), none), (compute, none));
+    for (index, (pipeline, group_count)) in dispatches.iter().enumerate() {
+        if index > 0 {
+            let access = vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE;
+            let written = (compute, vk::AccessFlags::SHADER_WRITE);
+            memory_barrier(device, cmd, written, (compute, access));
+        }
+        pipeline.record(cmd, *group_count);
+    }
+    let read = vk::AccessFlags::SHADER_READ
+        | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+        | vk::AccessFlags::INDIRECT_COMMAND_READ;
+    memory_barrier(
+        device,
+        cmd,
+        (compute, vk::AccessFlags::SHADER_WRITE),
+        (draw_stages(), read),
+    );
+}
+
+/// Stages of the graphics pipeline that can read the results of compute work
+fn draw_stages() -> vk::PipelineStageFlags {
+    vk::PipelineStageFlags::DRAW_INDIRECT
+        | vk::PipelineStageFlags::VERTEX_INPUT
+        | vk::PipelineStageFlags::VERTEX_SHADER
+        | vk::PipelineStageFlags::FRAGMENT_SHADER
+}
+
+unsafe fn memory_barrier(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    (src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+    (dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+) {
+    let barrier = [vk::MemoryBarrier::builder()
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access)
+        .build()];
+    device.cmd_pipeline_barrier(
+        cmd,
+        src_stage,
+        dst_stage,
+        vk::DependencyFlags::empty(),
+        &barrier,
+        &[],
+        &[],
+    );
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ash::vk;
+use log::error;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::vulkan::engine::alloc::{Buffer, Image};
+
+/// Gpu resource whose destruction is deferred until no frame in flight can still be using it
+pub(crate) enum Resource {
+    Pipeline(vk::Pipeline),
+    PipelineLayout(vk::PipelineLayout),
+    Sampler(vk::Sampler),
+    ImageView(vk::ImageView),
+    Image(Image),
+    Buffer(Buffer),
+    DescriptorSetLayout(vk::DescriptorSetLayout),
+    /// Sets allocated from a pool created with `FREE_DESCRIPTOR_SET`
+    DescriptorSets(vk::DescriptorPool, Vec<vk::DescriptorSet>),
+}
+
+#[derive(Default)]
+struct DeletionQueue {
+    /// Frame the engine is currently recording
+    frame: u64,
+    pending: Vec<(u64, Resource)>,
+}
+
+/// Queue of every live engine, keyed by device so engines never destroy each other's resources
+static QUEUES: Lazy<Mutex<HashMap<vk::Device, DeletionQueue>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Creates the queue of a newly created device
+pub(super) fn register(device: &ash::Device) {
+    QUEUES.lock().insert(device.handle(), DeletionQueue::default());
+}
+
+/// Queues a resource to be destroyed once every frame that might have recorded it has finished,
+/// instead of stalling on `device_wait_idle`.
+/// Resources of a device whose queue was already flushed are destroyed immediately,
+/// the engine only flushes once the device is idle
+pub(crate) fn queue(device: Arc<ash::Device>, resource: Resource) {
+    let mut queues = QUEUES.lock();
+    if let Some(queue) = queues.get_mut(&device.handle()) {
+        let frame = queue.frame;
+        queue.pending.push((frame, resource));
+        return;
+    }
+    drop(queues);
+    unsafe { destroy(&device, vec![resource]) }
+}
+
+/// Destroys all resources of `device` queued at least `frames_in_flight` frames ago.
+///
+/// # Safety
+/// Must be called after waiting on the fence of `frame`,
+/// which guarantees the frame that last used its slot is finished
+pub(super) unsafe fn collect(device: &ash::Device, frame: u64, frames_in_flight: usize) {
+    let mut queues = QUEUES.lock();
+    let queue = queues.entry(device.handle()).or_default();
+    queue.frame = frame;
+    let (expired, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut queue.pending)
+        .into_iter()
+        .partition(|(queued, _)| queued + frames_in_flight as u64 <= frame);
+    queue.pending = pending;
+    drop(queues);
+    destroy(device, expired.into_iter().map(|(_, resource)| resource).collect());
+}
+
+/// Destroys every queued resource of `device` regardless of when it was queued.
+/// The queue is removed, anything queued afterwards is destroyed right away.
+///
+/// # Safety
+/// The device must be idle
+pub(super) unsafe fn flush(device: &ash::Device) {
+    let expired = QUEUES
+        .lock()
+        .remove(&device.handle())
+        .map(|queue| queue.pending)
+        .unwrap_or_default();
+    destroy(device, expired.into_iter().map(|(_, resource)| resource).collect());
+}
+
+unsafe fn destroy(device: &ash::Device, resources: Vec<Resource>) {
+    for resource in resources {
+        match resource {
+            Resource::Pipeline(pipeline) => device.destroy_pipeline(pipeline, None),
+            Resource::PipelineLayout(layout) => device.destroy_pipeline_layout(layout, None),
+            Resource::Sampler(sampler) => device.destroy_sampler(sampler, None),
+            Resource::ImageView(view) => device.destroy_image_view(view, None),
+            Resource::Image(image) => drop(image),
+            Resource::Buffer(buffer) => drop(buffer),
+            Resource::DescriptorSetLayout(layout) => {
+                device.destroy_descriptor_set_layout(layout, None)
+            }
+            Resource::DescriptorSets(pool, sets) => {
+                if let Err(e) = device.free_descriptor_sets(pool, &sets) {
+                    error!("Failed to free descriptor sets: {e}");
+                }
+            }
+        }
+    }
+}
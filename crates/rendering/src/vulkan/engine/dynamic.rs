@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk;
+use ash::vk::DeviceSize;
+use log::warn;
+use vk_mem::Allocator;
+
+use crate::vulkan::engine::alloc::Buffer;
+
+/// Size in bytes of each frame's region of the dynamic vertex buffer
+const DYNAMIC_VERTEX_BUFFER_SIZE: DeviceSize = 4 * 1024 * 1024;
+/// Offsets handed out are aligned to this, which satisfies every vertex attribute format
+const ALIGNMENT: DeviceSize = 16;
+
+/// Persistently mapped vertex buffer for geometry that is rebuilt every frame,
+/// shared by debug lines, text, sprites and particles.
+///
+/// Every frame in flight has its own buffer, vertices are written by bumping a cursor
+/// that is reset when the frame begins, so the data only lives until the end of the frame
+pub struct DynamicVertexBuffer {
+    buffers: Vec<Buffer>,
+    frame: usize,
+    cursor: DeviceSize,
+}
+
+impl DynamicVertexBuffer {
+    pub(super) fn new(allocator: Arc<Allocator>, frames_in_flight: usize) -> Result<Self> {
+        let create_info = vk::BufferCreateInfo::builder()
+            .size(DYNAMIC_VERTEX_BUFFER_SIZE)
+            .usage(vk::BufferUsageFlags::VERTEX_BUFFER)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let alloc_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::CpuToGpu,
+            flags: vk_mem::AllocationCreateFlags::MAPPED,
+            required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE
+                | vk::MemoryPropertyFlags::HOST_COHERENT,
+            ..Default::default()
+        };
+        let buffers = (0..frames_in_flight)
+            .map(|_| unsafe { Buffer::new(&create_info, &alloc_info, allocator.clone()) })
+            .collect::<Result<_, _>>()?;
+        Ok(DynamicVertexBuffer {
+            buffers,
+            frame: 0,
+            cursor: 0,
+        })
+    }
+
+    /// Starts writing into the buffer of the frame in flight `frame`,
+    /// must be called after waiting on the frame's fence
+    pub(super) fn reset(&mut self, frame: usize) {
+        self.frame = frame;
+        self.cursor = 0;
+    }
+
+    /// Copies vertices into this frame's buffer.
+    ///
+    /// returns: byte offset to bind the buffer at and the number of vertices,
+    /// or None if the frame's buffer is full
+    pub fn push<V: Copy>(&mut self, vertices: &[V]) -> Option<(DeviceSize, u32)> {
+        let size = std::mem::size_of_val(vertices) as DeviceSize;
+        let offset = match allocate(self.cursor, size, DYNAMIC_VERTEX_BUFFER_SIZE) {
+            Some(offset) => offset,
+            None => {
+                warn!(
+                    "Dynamic vertex buffer is full, dropping {} vertices",
+                    vertices.len()
+                );
+                return None;
+            }
+        };
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                vertices.as_ptr() as *const u8,
+                self.buffers[self.frame]
+                    .get_info()
+                    .get_mapped_data()
+                    .add(offset as usize),
+                size as usize,
+            );
+        }
+        self.cursor = offset + size;
+        Some((offset, vertices.len() as u32))
+    }
+
+    /// Bytes written this frame
+    #[inline]
+    pub fn used(&self) -> DeviceSize {
+        self.cursor
+    }
+
+    /// Buffer of the current frame, push offsets are relative to it
+    pub(crate) fn get_buffer(&self) -> vk::Buffer {
+        *self.buffers[self.frame]
+    }
+}
+
+/// Aligned offset of an allocation of `size` bytes after `cursor`,
+/// None if it does not fit into `capacity`
+fn allocate(cursor: DeviceSize, size: DeviceSize, capacity: DeviceSize) -> Option<DeviceSize> {
+    let offset = (cursor + ALIGNMENT - 1) / ALIGNMENT * ALIGNMENT;
+    (offset + size <= capacity).then_some(offset)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vulkan::engine::dynamic::allocate;
+
+    #[test]
+    fn allocations_are_aligned() {
+        assert_eq!(allocate(0, 12, 64), Some(0));
+        assert_eq!(allocate(12, 12, 64), Some(16));
+        assert_eq!(allocate(32, 32, 64), Some(32));
+        assert_eq!(allocate(33, 16, 64), None);
+    }
+}
@@ -0,0 +1,243 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk;
+use image::{Rgba, RgbaImage};
+use nalgebra::Vector3;
+use smallvec::SmallVec;
+use vk_mem::Allocator;
+
+use crate::vulkan::engine::alloc::GpuObject;
+use crate::vulkan::engine::MAX_FRAMES_IN_FLIGHT;
+use crate::vulkan::texture::Texture;
+
+/// Binding of the global set holding the prefiltered environment cubemap
+const CUBEMAP_BINDING: u32 = 2;
+/// Binding of the global set holding the [EnvironmentUniform]
+pub(super) const UNIFORM_BINDING: u32 = 3;
+/// Constant ambient light used until an environment is set
+const DEFAULT_AMBIENT: [f32; 3] = [0.03; 3];
+/// Scale of the first spherical harmonics basis function, constant in every direction
+const SH_CONSTANT: f32 = 0.282095;
+
+/// Cubemap that lights pbr materials, the diffuse part through spherical harmonics of its
+/// irradiance and the specular part by sampling its mip levels by roughness
+pub(super) struct Environment {
+    texture: Texture,
+    uniform: GpuObject<EnvironmentUniform>,
+}
+
+/// Layout of the environment uniform of the pbr shaders, std140
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(super) struct EnvironmentUniform {
+    /// Order 2 spherical harmonics of the irradiance divided by pi, rgb of every coefficient
+    sh: [[f32; 4]; 9],
+    /// Mip level sampled for fully rough surfaces
+    max_lod: f32,
+    _padding: [f32; 3],
+}
+
+impl Environment {
+    /// Uploads the faces of a cubemap in the order +x, -x, +y, -y, +z, -z,
+    /// blocking until the upload is finished
+    pub(super) fn new(
+        faces: [RgbaImage; 6],
+        device: Arc<ash::Device>,
+        cmd: vk::CommandBuffer,
+        queue: vk::Queue,
+        anisotropy: f32,
+        allocator: Arc<Allocator>,
+    ) -> Result<Self> {
+        let sh = irradiance(&faces);
+        Self::create(&faces, sh, device, cmd, queue, anisotropy, allocator)
+    }
+
+    /// Black environment that only adds a constant ambient light
+    pub(super) fn placeholder(
+        device: Arc<ash::Device>,
+        cmd: vk::CommandBuffer,
+        queue: vk::Queue,
+        anisotropy: f32,
+        allocator: Arc<Allocator>,
+    ) -> Result<Self> {
+        let faces = [(); 6].map(|_| RgbaImage::from_pixel(1, 1, Rgba([0, 0, 0, 255])));
+        let mut sh = [[0.; 3]; 9];
+        sh[0] = DEFAULT_AMBIENT.map(|c| c / SH_CONSTANT);
+        Self::create(&faces, sh, device, cmd, queue, anisotropy, allocator)
+    }
+
+    fn create(
+        faces: &[RgbaImage; 6],
+        sh: [[f32; 3]; 9],
+        device: Arc<ash::Device>,
+        cmd: vk::CommandBuffer,
+        queue: vk::Queue,
+        anisotropy: f32,
+        allocator: Arc<Allocator>,
+    ) -> Result<Self> {
+        let texture = Texture::cubemap(faces, device, cmd, queue, anisotropy, allocator.clone())?;
+        let mut uniform = GpuObject::new(allocator, vk::BufferUsageFlags::UNIFORM_BUFFER)?;
+        *uniform = EnvironmentUniform {
+            sh: sh.map(|[r, g, b]| [r, g, b, 0.]),
+            max_lod: (faces[0].width() as f32).log2().floor(),
+            _padding: [0.; 3],
+        };
+        Ok(Environment { texture, uniform })
+    }
+
+    /// Points the environment bindings of the global descriptor sets at this environment
+    ///
+    /// # Safety
+    /// None of the sets may be in use by the gpu
+    pub(super) unsafe fn write_descriptors(
+        &self,
+        device: &ash::Device,
+        sets: impl IntoIterator<Item = vk::DescriptorSet>,
+    ) {
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(self.texture.view)
+            .sampler(self.texture.sampler)
+            .build()];
+        let buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(self.uniform.get_buffer())
+            .offset(0)
+            .range(vk::WHOLE_SIZE)
+            .build()];
+        let writes = sets
+            .into_iter()
+            .flat_map(|set| {
+                [
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(set)
+                        .dst_binding(CUBEMAP_BINDING)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&image_info)
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_set(set)
+                        .dst_binding(UNIFORM_BINDING)
+                        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                        .buffer_info(&buffer_info)
+                        .build(),
+                ]
+            })
+            .collect::<SmallVec<[_; MAX_FRAMES_IN_FLIGHT * 2]>>();
+        device.update_descriptor_sets(&writes, &[]);
+    }
+}
+
+/// Projects the light arriving from every texel of a cubemap onto order 2 spherical harmonics
+/// and convolves it with the clamped cosine, the result divided by pi is the diffuse light
+/// reflected by a white surface facing any direction
+fn irradiance(faces: &[RgbaImage; 6]) -> [[f32; 3]; 9] {
+    // convolution with the clamped cosine of every band, divided by pi
+    const BANDS: [f32; 9] = [1., 2. / 3., 2. / 3., 2. / 3., 0.25, 0.25, 0.25, 0.25, 0.25];
+    let mut sh = [[0.; 3]; 9];
+    let mut total_weight = 0.;
+    for (face, image) in faces.iter().enumerate() {
+        let size = image.width() as f32;
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let u = 2. * (x as f32 + 0.5) / size - 1.;
+            let v = 2. * (y as f32 + 0.5) / size - 1.;
+            // texels near the edges of a face cover a smaller solid angle
+            let weight = (1. + u * u + v * v).powf(-1.5);
+            let color = [0, 1, 2].map(|channel| srgb_to_linear(pixel[channel]));
+            let basis = sh_basis(&face_direction(face, u, v).normalize());
+            for (coefficient, basis) in sh.iter_mut().zip(basis) {
+                for (c, color) in coefficient.iter_mut().zip(color) {
+                    *c += color * basis * weight;
+                }
+            }
+            total_weight += weight;
+        }
+    }
+    let scale = 4. * PI / total_weight;
+    for (coefficient, band) in sh.iter_mut().zip(BANDS) {
+        for c in coefficient {
+            *c *= scale * band;
+        }
+    }
+    sh
+}
+
+/// Direction through a point of a cubemap face, `u` and `v` are from -1 to 1
+/// following vulkan's cubemap face orientation
+fn face_direction(face: usize, u: f32, v: f32) -> Vector3<f32> {
+    match face {
+        0 => Vector3::new(1., -v, -u),
+        1 => Vector3::new(-1., -v, u),
+        2 => Vector3::new(u, 1., v),
+        3 => Vector3::new(u, -1., -v),
+        4 => Vector3::new(u, -v, 1.),
+        _ => Vector3::new(-u, -v, -1.),
+    }
+}
+
+/// Order 2 real spherical harmonics basis functions of a unit direction,
+/// in the order the pbr shader evaluates them
+fn sh_basis(d: &Vector3<f32>) -> [f32; 9] {
+    [
+        SH_CONSTANT,
+        0.488603 * d.y,
+        0.488603 * d.z,
+        0.488603 * d.x,
+        1.092548 * d.x * d.y,
+        1.092548 * d.y * d.z,
+        0.315392 * (3. * d.z * d.z - 1.),
+        1.092548 * d.x * d.z,
+        0.546274 * (d.x * d.x - d.y * d.y),
+    ]
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{Rgba, RgbaImage};
+    use nalgebra::Vector3;
+
+    use crate::vulkan::engine::environment::{irradiance, sh_basis, srgb_to_linear};
+
+    fn evaluate(sh: &[[f32; 3]; 9], direction: Vector3<f32>) -> [f32; 3] {
+        let basis = sh_basis(&direction);
+        [0, 1, 2].map(|c| sh.iter().zip(basis).map(|(sh, basis)| sh[c] * basis).sum())
+    }
+
+    #[test]
+    fn uniform_environment_lights_every_direction_equally() {
+        let color = Rgba([200, 100, 50, 255]);
+        let faces = [(); 6].map(|_| RgbaImage::from_pixel(8, 8, color));
+        let sh = irradiance(&faces);
+        let diagonal = Vector3::new(1., 1., -1.).normalize();
+        for direction in [Vector3::x(), -Vector3::y(), diagonal] {
+            let light = evaluate(&sh, direction);
+            for (light, channel) in light.into_iter().zip(color.0) {
+                assert!((light - srgb_to_linear(channel)).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn bright_face_lights_surfaces_facing_it() {
+        let black = RgbaImage::from_pixel(8, 8, Rgba([0, 0, 0, 255]));
+        let mut faces = [(); 6].map(|_| black.clone());
+        // +y
+        faces[2] = RgbaImage::from_pixel(8, 8, Rgba([255; 4]));
+        let sh = irradiance(&faces);
+        let up = evaluate(&sh, Vector3::y())[0];
+        let side = evaluate(&sh, Vector3::x())[0];
+        let down = evaluate(&sh, -Vector3::y())[0];
+        assert!(up > side && side > down);
+        assert!(down.abs() < 0.05);
+    }
+}
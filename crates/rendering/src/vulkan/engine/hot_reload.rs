@@ -0,0 +1,71 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crossbeam_channel::{Receiver, Sender};
+use log::warn;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use engine::filesystem::DIRS;
+
+/// Watches the compiled shaders in the asset directory
+/// so the materials using them can be rebuilt while the engine runs
+pub(super) struct ShaderWatcher {
+    _watcher: RecommendedWatcher,
+    changed: Receiver<String>,
+}
+
+impl ShaderWatcher {
+    pub(super) fn new() -> notify::Result<Self> {
+        let (sender, changed) = crossbeam_channel::unbounded();
+        let mut watcher = notify::recommended_watcher(move |event| send_changes(event, &sender))?;
+        watcher.watch(&DIRS.asset.join("shaders"), RecursiveMode::NonRecursive)?;
+        Ok(ShaderWatcher {
+            _watcher: watcher,
+            changed,
+        })
+    }
+
+    /// File names of the shaders written since the last call
+    pub(super) fn changed(&self) -> HashSet<String> {
+        self.changed.try_iter().collect()
+    }
+}
+
+/// Runs on the watcher's thread, forwarding the names of created or modified shaders
+fn send_changes(event: notify::Result<Event>, sender: &Sender<String>) {
+    match event {
+        Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+            let paths = event.paths.iter().map(PathBuf::as_path);
+            for name in paths.filter_map(shader_name) {
+                // the engine might have been dropped before the watcher
+                let _ = sender.send(name.to_owned());
+            }
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Failed to watch shaders: {e}"),
+    }
+}
+
+/// File name of a compiled shader, the name materials refer to it by
+fn shader_name(path: &Path) -> Option<&str> {
+    if path.extension()? == "spv" {
+        path.file_name()?.to_str()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::vulkan::engine::hot_reload::shader_name;
+
+    #[test]
+    fn only_compiled_shaders_are_reloaded() {
+        let name = shader_name(Path::new("asset/shaders/base.frag.spv"));
+        assert_eq!(name, Some("base.frag.spv"));
+        assert_eq!(shader_name(Path::new("asset/shaders/base.frag")), None);
+        assert_eq!(shader_name(Path::new("asset/shaders")), None);
+    }
+}
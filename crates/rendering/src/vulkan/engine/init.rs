@@ -1,5 +1,6 @@
 use anyhow::{anyhow, Result};
 use std::ffi::{CStr, CString};
+use std::marker::PhantomData;
 use std::mem::ManuallyDrop;
 use std::num::NonZeroUsize;
 use std::sync::{Arc, Barrier};
@@ -11,18 +12,43 @@ use ash::{vk, Device};
 use crossbeam_channel::Sender;
 use itertools::Itertools;
 use log::{info, warn};
+use nalgebra::Matrix4;
 use parking_lot::Mutex;
 use raw_window_handle::HasRawWindowHandle;
 use smallvec::SmallVec;
 use vk_mem::Allocator;
 
-use crate::vulkan::engine::alloc::{create_allocator, GpuObject, Image};
+use engine::telemetry::{self, TelemetryEvent};
+
+use crate::vulkan::engine::alloc::{create_allocator, GpuObject, Image, StagingPool};
+use crate::vulkan::engine::deletion;
+use crate::vulkan::engine::dynamic::DynamicVertexBuffer;
+use crate::vulkan::engine::environment::Environment;
+#[cfg(feature = "hot-reload")]
+use crate::vulkan::engine::hot_reload::ShaderWatcher;
+#[cfg(feature = "validation-layers")]
+use crate::vulkan::engine::labels::Labels;
+use crate::vulkan::engine::msaa::{sample_count, MsaaTargets};
+use crate::vulkan::engine::occlusion::OcclusionQueries;
+use crate::vulkan::engine::passes::create_passes;
+use crate::vulkan::engine::shadow::ShadowMap;
+use crate::vulkan::engine::skinning::BonePools;
+use crate::vulkan::engine::stats::FrameTimes;
 use crate::vulkan::engine::swapchain::Swapchain;
+use crate::vulkan::engine::timer::GpuTimer;
+use crate::vulkan::engine::upload;
 use crate::vulkan::engine::{
-    debug_callback, presentation_thread, render_thread, Engine, Frame, PresentData, RenderResult,
-    Ubo, FRAMES_IN_FLIGHT,
+    debug_callback, presentation_thread, render_thread, Engine, Frame, InlineDraws,
+    OwnershipTransfer, PresentData, RenderResult, Ubo, MAX_FRAMES_IN_FLIGHT, MAX_RENDER_THREADS,
+    OBJECT_ID_FORMAT, STAGING_POOL_CAPACITY,
 };
-use crate::GraphicsSettings;
+use crate::vulkan::texture::Texture;
+use crate::{DirectionalLight, FramePacing, GpuInfo, GraphicsSettings, RecordingMode};
+
+/// Compute work is dispatched on the graphics queue,
+/// devices with graphics queues always have one that can also compute
+const GRAPHICS_QUEUE_FLAGS: vk::QueueFlags =
+    vk::QueueFlags::from_raw(vk::QueueFlags::GRAPHICS.as_raw() | vk::QueueFlags::COMPUTE.as_raw());
 
 impl Engine {
     /// Creates the vulkan rendering engine using a window handle and the graphics settings
@@ -36,25 +62,53 @@ impl Engine {
         let instance = create_instance(&entry, window)?;
 
         #[cfg(feature = "validation-layers")]
-        let debug_messenger = create_debug_messenger(&entry, &instance)?;
+        let debug_messenger =
+            create_debug_messenger(&entry, &instance, debug_severity(log::max_level()))?;
+        #[cfg(feature = "validation-layers")]
+        let labels = Labels::new(&debug_messenger.0);
+        #[cfg(not(feature = "validation-layers"))]
+        let labels = Labels::default();
 
         let surface_loader = Box::new(ash::extensions::khr::Surface::new(&entry, &instance));
         let surface = ash_window::create_surface(&entry, &instance, window, None)?;
-        let extensions = vec![
-            ash::extensions::khr::Swapchain::name(),
-            ash::extensions::khr::DynamicRendering::name(),
-            vk::ExtMemoryBudgetFn::name(),
-        ];
-        let physical_device =
-            get_physical_device(&instance, surface, &surface_loader, &extensions)?;
-        let queue_families =
+        let extensions = device_extensions();
+        let physical_device = get_physical_device(
+            &instance,
+            surface,
+            &surface_loader,
+            &extensions,
+            settings.gpu.as_deref(),
+        )?;
+        let (queue_families, transfer_family) =
             get_queue_families(&instance, physical_device, surface, &surface_loader)?;
-        let device = create_device(&instance, physical_device, &extensions, &queue_families)?;
+        let [graphics_family, present_family] = queue_families;
+        match transfer_family {
+            Some(transfer_family) => info!(
+                "Using queue family {graphics_family} for graphics, {present_family} for presentation \
+                and {transfer_family} for uploads"
+            ),
+            None => info!(
+                "Using queue family {graphics_family} for graphics and {present_family} for presentation, \
+                uploads run on the graphics queue"
+            ),
+        }
+        let device_families = queue_families
+            .iter()
+            .chain(&transfer_family)
+            .copied()
+            .collect_vec();
+        let device = create_device(&instance, physical_device, &extensions, &device_families)?;
+        deletion::register(&device);
         let allocator = create_allocator(&entry, &instance, physical_device, &device)?;
-        let graphics_queue = device.get_device_queue(queue_families[0], 0);
-        let presentation_queue = device.get_device_queue(queue_families[1], 0);
+        let staging = StagingPool::mapped(allocator.clone(), STAGING_POOL_CAPACITY);
+        let graphics_queue = device.get_device_queue(graphics_family, 0);
+        let presentation_queue = device.get_device_queue(present_family, 0);
+        let transfer_queue =
+            transfer_family.map(|family| (device.get_device_queue(family, 0), family));
         let surface_format = get_surface_format(physical_device, surface, &surface_loader)?;
 
+        let present_mode =
+            get_present_mode(physical_device, surface, &surface_loader, settings.vsync)?;
         let swapchain = ManuallyDrop::new(Swapchain::new(
             &instance,
             device.clone(),
@@ -62,44 +116,72 @@ impl Engine {
             surface,
             &surface_loader,
             &queue_families,
+            settings.concurrent_present,
             surface_format.format,
-            settings.vsync,
+            present_mode,
             &settings.resolution,
             None,
         )?);
 
-        let thread_count = 1.max(
-            // half the number of cores, minimum of 1 thread
-            available_parallelism()
-                .map(NonZeroUsize::get)
-                .unwrap_or_default()
-                / 2,
-        );
-        info!("Using {thread_count} render threads");
+        let thread_count = render_thread_count(settings.render_threads);
+        let single_threaded = match settings.recording {
+            RecordingMode::Auto => settings.render_threads.is_none() && thread_count == 1,
+            RecordingMode::Threaded => false,
+            RecordingMode::SingleThreaded => true,
+        };
+        let thread_count = if single_threaded {
+            info!("Recording draws on the main thread");
+            0
+        } else {
+            info!("Using {thread_count} render threads");
+            thread_count
+        };
         let global_descriptor_layout = create_global_descriptor_layout(&device)?;
         let descriptor_pool = create_descriptor_pool(&device)?;
-        let frames = (0..FRAMES_IN_FLIGHT)
+        let ownership_family = (queue_families[0] != queue_families[1]
+            && !settings.concurrent_present)
+            .then_some(queue_families[1]);
+        if ownership_family.is_some() {
+            info!("Transferring ownership of swapchain images to the presentation queue family");
+        }
+        let frames = (0..frames_in_flight(settings.frames_in_flight))
             .map(|_| {
                 create_frame(
                     &device,
                     queue_families[0],
+                    ownership_family,
                     thread_count,
                     &allocator,
                     global_descriptor_layout,
                     descriptor_pool,
                 )
             })
-            .collect::<Result<SmallVec<[_; FRAMES_IN_FLIGHT]>>>()?;
+            .collect::<Result<SmallVec<[_; MAX_FRAMES_IN_FLIGHT]>>>()?;
+        let light = DirectionalLight::default();
+        let shadow_map = ShadowMap::new(
+            &instance,
+            physical_device,
+            device.clone(),
+            allocator.clone(),
+            settings.shadow_resolution,
+            global_descriptor_layout,
+            settings.coordinate_system,
+            light.direction,
+        )?;
+        write_shadow_descriptors(&device, &frames, &shadow_map);
 
         let render_barrier = Arc::new(Barrier::new(thread_count + 1));
         let (render_channels, render_thread_handles) = (0..thread_count)
-            .map(|_| {
+            .map(|index| {
                 let (sender, receiver) = crossbeam_channel::bounded(16);
                 let device = device.clone();
                 let render_barrier = render_barrier.clone();
+                let labels = labels.clone();
                 (
                     sender,
-                    spawn(move || render_thread(receiver, &device, &render_barrier)),
+                    spawn(move || {
+                        render_thread(index, receiver, &device, &render_barrier, &labels)
+                    }),
                 )
             })
             .unzip();
@@ -112,12 +194,88 @@ impl Engine {
             .flags(vk::CommandPoolCreateFlags::TRANSIENT);
         let utility_pool = device.create_command_pool(&pool_info, None)?;
 
+        let alloc = vk::CommandBufferAllocateInfo::builder()
+            .command_buffer_count(2)
+            .command_pool(utility_pool)
+            .level(vk::CommandBufferLevel::PRIMARY);
+        let cmd = device.allocate_command_buffers(&alloc)?;
+        let properties = instance.get_physical_device_properties(physical_device);
+        let environment = Environment::placeholder(
+            device.clone(),
+            cmd[0],
+            graphics_queue,
+            properties.limits.max_sampler_anisotropy,
+            allocator.clone(),
+        )?;
+        let white_texture = Texture::white(
+            device.clone(),
+            cmd[1],
+            graphics_queue,
+            properties.limits.max_sampler_anisotropy,
+            allocator.clone(),
+        )?;
+        device.free_command_buffers(utility_pool, &cmd);
+        environment.write_descriptors(&device, frames.iter().map(|frame| frame.global_descriptor));
+
         let depth_format = get_depth_format(physical_device, &instance, vk::ImageTiling::OPTIMAL)?;
-        let (depth_image, depth_view) =
-            create_depth_image(&device, depth_format, swapchain.extent, allocator.clone())?;
+        let samples = get_sample_count(&instance, physical_device, settings.msaa);
+        let (depth_image, depth_view) = create_depth_image(
+            &device,
+            depth_format,
+            swapchain.extent,
+            samples,
+            allocator.clone(),
+        )?;
+        let (object_id_image, object_id_view) = create_object_id_image(
+            &device,
+            swapchain.extent,
+            vk::SampleCountFlags::TYPE_1,
+            allocator.clone(),
+        )?;
+        let msaa = if samples == vk::SampleCountFlags::TYPE_1 {
+            None
+        } else {
+            Some(MsaaTargets::new(
+                device.clone(),
+                surface_format.format,
+                swapchain.extent,
+                samples,
+                allocator.clone(),
+            )?)
+        };
+
+        let dynamic_vertices = DynamicVertexBuffer::new(allocator.clone(), frames.len())?;
+        let occlusion = if settings.occlusion_culling {
+            info!("Occlusion culling enabled");
+            Some(OcclusionQueries::new(
+                device.clone(),
+                depth_format,
+                samples,
+                global_descriptor_layout,
+                frames.len(),
+            )?)
+        } else {
+            None
+        };
+        let families = instance.get_physical_device_queue_family_properties(physical_device);
+        let gpu_timer = GpuTimer::new(
+            device.clone(),
+            &properties,
+            &families[queue_families[0] as usize],
+            frames.len(),
+        )?;
+        if gpu_timer.is_none() {
+            warn!("The graphics queue has no timestamps, gpu frame times are not measured");
+        }
+        #[cfg(feature = "hot-reload")]
+        let shader_watcher = ShaderWatcher::new()
+            .map_err(|e| warn!("Failed to watch the shaders, they won't be reloaded: {e}"))
+            .ok();
+
+        let bone_pools = BonePools::new(device.clone(), frames.len());
 
         info!("Rendering engine initialization finished");
-        Ok(Engine {
+        let engine = Engine {
             frame_count: 0,
             _entry: entry,
             instance,
@@ -126,31 +284,78 @@ impl Engine {
             surface_loader,
             #[cfg(feature = "validation-layers")]
             debug_messenger,
+            labels,
             surface,
             graphics_queue,
             present_queue: presentation_queue,
             surface_format,
             swapchain,
             allocator,
-            frames: frames.into_inner().unwrap(),
+            staging: ManuallyDrop::new(staging),
+            frames,
             render_channels,
             render_thread_handles,
             render_barrier,
             present_channel: ManuallyDrop::new(present_channel),
             present_thread_handle: ManuallyDrop::new(present_thread_handle),
-            last_mesh: std::ptr::null(),
-            last_material: std::ptr::null(),
+            last_draw: (0, 0),
             current_thread: 0,
+            inline_draws: single_threaded.then(|| InlineDraws {
+                draws: Vec::new(),
+                view_projection: Matrix4::identity(),
+            }),
             utility_pool,
             global_descriptor_layout,
             descriptor_pool,
+            bone_pools,
             depth_format,
             depth_image: ManuallyDrop::new(depth_image),
             depth_view,
+            object_id_image: ManuallyDrop::new(object_id_image),
+            object_id_view,
+            samples,
+            msaa,
+            object_ids_rendered: false,
+            frame_skipped: false,
+            shadow_map: ManuallyDrop::new(shadow_map),
+            environment: ManuallyDrop::new(environment),
+            white_texture: ManuallyDrop::new(Arc::new(white_texture)),
+            skybox: None,
+            shadow_casters: Vec::new(),
+            transparent_draws: Vec::new(),
+            camera_view: Matrix4::identity(),
+            dispatches: Vec::new(),
+            dynamic_vertices: ManuallyDrop::new(dynamic_vertices),
+            occlusion,
+            gpu_timer,
+            #[cfg(feature = "hot-reload")]
+            shader_watcher,
+            last_wait_end: None,
+            pacing: FramePacing::default(),
+            frame_times: FrameTimes::new(),
+            light,
+            passes: create_passes(),
             queue_families,
+            transfer_queue,
+            concurrent_present: settings.concurrent_present,
             resolution: settings.resolution,
-            vsync: settings.vsync,
-        })
+            present_mode,
+            recreate_swapchain: false,
+            max_texture_size: settings.max_texture_size,
+            coordinates: settings.coordinate_system,
+            strict_assets: settings.strict_assets,
+            settings: settings.clone(),
+            placeholder_mesh: None,
+            capture_requested: false,
+            screenshot: None,
+            pending_capture: None,
+            _single_thread: PhantomData,
+        };
+        upload::set_limit(settings.upload_budget);
+        telemetry::emit(TelemetryEvent::EngineInit {
+            gpu: engine.gpu_info().name,
+        });
+        Ok(engine)
     }
 }
 
@@ -179,8 +384,9 @@ impl Swapchain {
         surface: vk::SurfaceKHR,
         surface_loader: &ash::extensions::khr::Surface,
         queue_families: &[u32],
+        concurrent: bool,
         image_format: vk::Format,
-        vsync: bool,
+        present_mode: vk::PresentModeKHR,
         resolution: &[u32; 2],
         old: Option<&Swapchain>,
     ) -> Result<Self> {
@@ -204,7 +410,8 @@ impl Swapchain {
                 .min(capabilities.min_image_count + 1)
         };
 
-        let share_mode = if queue_families[0] == queue_families[1] {
+        // exclusive images have their ownership transferred to the presentation family every frame
+        let share_mode = if queue_families[0] == queue_families[1] || !concurrent {
             vk::SharingMode::EXCLUSIVE
         } else {
             vk::SharingMode::CONCURRENT
@@ -216,7 +423,7 @@ impl Swapchain {
             .image_format(image_format)
             .image_extent(extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
             .image_sharing_mode(share_mode)
             .queue_family_indices(queue_families)
             .pre_transform(capabilities.current_transform)
@@ -225,12 +432,7 @@ impl Swapchain {
                 old.map(|it| it.swapchain)
                     .unwrap_or(vk::SwapchainKHR::null()),
             )
-            .present_mode(get_present_mode(
-                physical_device,
-                surface,
-                surface_loader,
-                vsync,
-            )?);
+            .present_mode(present_mode);
         let swapchain = loader.create_swapchain(&create_info, None)?;
 
         let images = read_into_uninitialized_small_vector(|count, data| {
@@ -312,27 +514,155 @@ unsafe fn create_instance(
         .enabled_extension_names(&extensions);
 
     #[cfg(feature = "validation-layers")]
-    let mut debug = get_debug_info();
+    let mut debug = get_debug_info(debug_severity(log::max_level()));
 
+    // an empty severity mask is invalid, with logging off nothing is reported
     #[cfg(feature = "validation-layers")]
-    let create_info = create_info.push_next(&mut debug);
+    let create_info = if debug.message_severity.is_empty() {
+        create_info
+    } else {
+        create_info.push_next(&mut debug)
+    };
 
     Ok(Box::new(entry.create_instance(&create_info, None)?))
 }
 
-/// Finds the first gpu that is valid for our requirements and is not a integrated gpu.
+/// Extensions every device the engine runs on must support
+pub(super) fn device_extensions() -> [&'static CStr; 3] {
+    [
+        ash::extensions::khr::Swapchain::name(),
+        ash::extensions::khr::DynamicRendering::name(),
+        vk::ExtMemoryBudgetFn::name(),
+    ]
+}
+
+/// Finds the gpu whose name contains `name`, or if it is None the first gpu that is valid
+/// for our requirements and is not a integrated gpu.
 ///
-/// Will fall back to a integrated gpu if no discrete gpu was found
+/// Will fall back to a integrated gpu if no discrete gpu was found,
+/// and to the first valid gpu if the named gpu is not valid or doesn't exist
 unsafe fn get_physical_device(
     instance: &ash::Instance,
     surface: vk::SurfaceKHR,
     surface_loader: &ash::extensions::khr::Surface,
     extensions: &[&CStr],
+    name: Option<&str>,
 ) -> Result<vk::PhysicalDevice> {
+    let devices = suitable_devices(instance, surface, surface_loader, extensions)?;
+    let named = name.and_then(|name| {
+        let device = devices
+            .iter()
+            .copied()
+            .find(|device| gpu_name_matches(&device_name(instance, *device), name));
+        if device.is_none() {
+            warn!("No valid gpu named {name:?} found, falling back to the default gpu");
+        }
+        device
+    });
+    let device = named
+        .or_else(|| {
+            devices.iter().copied().find_or_first(|device| {
+                instance.get_physical_device_properties(*device).device_type
+                    == PhysicalDeviceType::DISCRETE_GPU
+            })
+        })
+        .ok_or(anyhow!("No valid gpu available"))?;
+    let props = instance.get_physical_device_properties(device);
+    if named.is_none() && props.device_type != PhysicalDeviceType::DISCRETE_GPU {
+        warn!("No discrete gpu found, falling back to integrated gpu");
+    }
+    info!("Using gpu {:?}", CStr::from_ptr(props.device_name.as_ptr()));
+    Ok(device)
+}
+
+/// Names of the gpus that support our required features and extensions
+/// and can present to `surface`, in the order the driver lists them
+pub(super) unsafe fn suitable_gpu_names(
+    instance: &ash::Instance,
+    surface: vk::SurfaceKHR,
+    surface_loader: &ash::extensions::khr::Surface,
+) -> Result<Vec<String>> {
+    let devices = suitable_devices(instance, surface, surface_loader, &device_extensions())?;
+    Ok(devices
+        .into_iter()
+        .map(|device| device_name(instance, device))
+        .collect())
+}
+
+/// Describes the suitable gpus of a throwaway instance and surface for `window`,
+/// which are destroyed before returning
+pub(crate) unsafe fn enumerate_gpus(window: &dyn HasRawWindowHandle) -> Result<Vec<GpuInfo>> {
+    let entry = load()?;
+    let instance = create_instance(&entry, window)?;
+    let surface_loader = ash::extensions::khr::Surface::new(&entry, &instance);
+    let gpus = ash_window::create_surface(&entry, &instance, window, None)
+        .map_err(Into::into)
+        .and_then(|surface| {
+            let devices =
+                suitable_devices(&instance, surface, &surface_loader, &device_extensions());
+            surface_loader.destroy_surface(surface, None);
+            devices
+        })
+        .map(|devices| {
+            devices
+                .into_iter()
+                .map(|device| describe_gpu(&instance, device))
+                .collect()
+        });
+    instance.destroy_instance(None);
+    gpus
+}
+
+/// Properties of a physical device as reported by [gpu_info](Engine::gpu_info)
+pub(super) unsafe fn describe_gpu(instance: &ash::Instance, device: vk::PhysicalDevice) -> GpuInfo {
+    let properties = instance.get_physical_device_properties(device);
+    let memory = instance.get_physical_device_memory_properties(device);
+    let video_memory = memory.memory_heaps[..memory.memory_heap_count as usize]
+        .iter()
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|heap| heap.size)
+        .sum();
+    let samples = properties.limits.framebuffer_color_sample_counts
+        & properties.limits.framebuffer_depth_sample_counts;
+    // sample count flags are equal to the number of samples
+    let max_samples = [64, 32, 16, 8, 4, 2]
+        .into_iter()
+        .find(|count| samples.contains(vk::SampleCountFlags::from_raw(*count)))
+        .unwrap_or(1);
+    GpuInfo {
+        name: device_name(instance, device),
+        discrete: properties.device_type == PhysicalDeviceType::DISCRETE_GPU,
+        video_memory,
+        max_samples,
+        max_texture_size: properties.limits.max_image_dimension2_d,
+    }
+}
+
+unsafe fn device_name(instance: &ash::Instance, device: vk::PhysicalDevice) -> String {
+    let props = instance.get_physical_device_properties(device);
+    CStr::from_ptr(props.device_name.as_ptr())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Whether the gpu named `device_name` is the one a [gpu](GraphicsSettings::gpu) setting
+/// of `name` asks for, names match if the device name contains it ignoring case
+fn gpu_name_matches(device_name: &str, name: &str) -> bool {
+    device_name.to_lowercase().contains(&name.to_lowercase())
+}
+
+/// Gpus with the required features and extensions and queue families for graphics and
+/// presenting to `surface`
+unsafe fn suitable_devices(
+    instance: &ash::Instance,
+    surface: vk::SurfaceKHR,
+    surface_loader: &ash::extensions::khr::Surface,
+    extensions: &[&CStr],
+) -> Result<SmallVec<[vk::PhysicalDevice; 8]>> {
     let devices = read_into_uninitialized_small_vector(|count, data| {
         (instance.fp_v1_0().enumerate_physical_devices)(instance.handle(), count, data)
     })?;
-    let device = devices
+    let devices = devices
         .into_iter()
         .filter(|device| is_valid_device(*device, instance, extensions))
         .filter(|device| {
@@ -348,7 +678,7 @@ unsafe fn get_physical_device(
             })
             .unwrap();
             for (index, prop) in props.into_iter().enumerate() {
-                if prop.queue_flags & vk::QueueFlags::GRAPHICS != vk::QueueFlags::empty() {
+                if prop.queue_flags.contains(GRAPHICS_QUEUE_FLAGS) {
                     has_graphics = true;
                 }
 
@@ -366,17 +696,8 @@ unsafe fn get_physical_device(
 
             has_present && has_graphics
         })
-        .find_or_first(|device| {
-            instance.get_physical_device_properties(*device).device_type
-                == PhysicalDeviceType::DISCRETE_GPU
-        })
-        .ok_or(anyhow!("No valid gpu available"))?;
-    let props = instance.get_physical_device_properties(device);
-    if props.device_type != PhysicalDeviceType::DISCRETE_GPU {
-        warn!("No discrete gpu found, falling back to integrated gpu");
-    }
-    info!("Using gpu {:?}", CStr::from_ptr(props.device_name.as_ptr()));
-    Ok(device)
+        .collect();
+    Ok(devices)
 }
 
 /// Tests if a physical device is valid for our required features and extensions
@@ -417,13 +738,14 @@ unsafe fn is_valid_device(
     }
 }
 
-/// Gets the graphics and presentation queue families
+/// Gets the graphics and presentation queue families,
+/// with a family for uploads other than the graphics family if there is one
 unsafe fn get_queue_families(
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
     surface: vk::SurfaceKHR,
     surface_loader: &ash::extensions::khr::Surface,
-) -> Result<[u32; 2]> {
+) -> Result<([u32; 2], Option<u32>)> {
     let mut graphics = None;
     let mut present = None;
     let props = read_into_uninitialized_small_vector(|count, data| {
@@ -433,8 +755,9 @@ unsafe fn get_queue_families(
         vk::Result::SUCCESS
     })
     .unwrap();
+    let flags = props.iter().map(|prop| prop.queue_flags).collect_vec();
     for (index, prop) in props.into_iter().enumerate() {
-        if prop.queue_flags & vk::QueueFlags::GRAPHICS != vk::QueueFlags::empty() {
+        if prop.queue_flags.contains(GRAPHICS_QUEUE_FLAGS) {
             graphics = Some(index as u32);
         }
 
@@ -449,10 +772,31 @@ unsafe fn get_queue_families(
             break;
         }
     }
-    Ok([
-        graphics.ok_or(anyhow!("Failed to find graphics queue"))?,
-        present.ok_or(anyhow!("Failed to find presentation queue"))?,
-    ])
+    let graphics = graphics.ok_or(anyhow!("Failed to find graphics queue"))?;
+    let present = present.ok_or(anyhow!("Failed to find presentation queue"))?;
+    Ok(([graphics, present], transfer_family(&flags, graphics, present)))
+}
+
+/// Index of a queue family other than `graphics` and `present` that can copy buffers and images,
+/// preferring dedicated transfer families over compute families.
+///
+/// Graphics and compute families can copy even if they don't report `TRANSFER`.
+/// The presentation family is excluded because its queue is submitted to by the presentation thread
+fn transfer_family(flags: &[vk::QueueFlags], graphics: u32, present: u32) -> Option<u32> {
+    let family = |accept: fn(vk::QueueFlags) -> bool| {
+        flags
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| ![graphics, present].contains(&(*index as u32)))
+            .find(|(_, flags)| !flags.contains(vk::QueueFlags::GRAPHICS) && accept(**flags))
+            .map(|(index, _)| index as u32)
+    };
+    family(|flags| {
+        flags.contains(vk::QueueFlags::TRANSFER) && !flags.contains(vk::QueueFlags::COMPUTE)
+    })
+    .or_else(|| {
+        family(|flags| flags.intersects(vk::QueueFlags::TRANSFER | vk::QueueFlags::COMPUTE))
+    })
 }
 
 /// Creates the logical device handle
@@ -497,48 +841,72 @@ unsafe fn create_device(
     )?))
 }
 
-/// Gets the surface format object for the given surface
+/// Gets the surface format object for the given surface, see [choose_surface_format]
 unsafe fn get_surface_format(
     physical_device: vk::PhysicalDevice,
     surface: vk::SurfaceKHR,
     surface_loader: &ash::extensions::khr::Surface,
 ) -> Result<vk::SurfaceFormatKHR> {
-    Ok(surface_loader
-        .get_physical_device_surface_formats(physical_device, surface)?
-        .into_iter()
-        .find_or_first(|fmt| {
-            fmt.format == vk::Format::B8G8R8_SRGB
-                && fmt.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+    let formats = surface_loader.get_physical_device_surface_formats(physical_device, surface)?;
+    let format =
+        choose_surface_format(&formats).ok_or(anyhow!("Failed to find valid surface format"))?;
+    if SURFACE_FORMATS.contains(&format.format) {
+        info!("Using surface format {:?}", format.format);
+    } else {
+        warn!(
+            "No 8 bit srgb surface format available, using {:?} in {:?}",
+            format.format, format.color_space
+        );
+    }
+    Ok(format)
+}
+
+/// Swapchain formats in order of preference, frames of either can be captured
+const SURFACE_FORMATS: [vk::Format; 2] = [vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB];
+
+/// Picks the first of [SURFACE_FORMATS] supported in the srgb color space,
+/// falling back to the first supported format
+fn choose_surface_format(formats: &[vk::SurfaceFormatKHR]) -> Option<vk::SurfaceFormatKHR> {
+    SURFACE_FORMATS
+        .iter()
+        .find_map(|&format| {
+            formats.iter().find(|fmt| {
+                fmt.format == format && fmt.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            })
         })
-        .ok_or(anyhow!("Failed to find valid surface format"))?)
+        .or_else(|| formats.first())
+        .copied()
 }
 
-/// Gets the presentation mode for the surface
-unsafe fn get_present_mode(
+/// Gets the presentation mode for the surface, see [choose_present_mode]
+pub(super) unsafe fn get_present_mode(
     physical_device: vk::PhysicalDevice,
     surface: vk::SurfaceKHR,
     surface_loader: &ash::extensions::khr::Surface,
     vsync: bool,
 ) -> VkResult<vk::PresentModeKHR> {
-    let target = if vsync {
-        vk::PresentModeKHR::MAILBOX
-    } else {
-        vk::PresentModeKHR::IMMEDIATE
-    };
+    let modes =
+        surface_loader.get_physical_device_surface_present_modes(physical_device, surface)?;
+    let mode = choose_present_mode(&modes, vsync);
+    if !vsync && mode == vk::PresentModeKHR::FIFO {
+        warn!("Presenting without vsync is not supported, falling back to FIFO");
+    }
+    info!("Using surface presentation mode: {mode:?}");
+    Ok(mode)
+}
 
-    Ok(
-        if let Some(mode) = surface_loader
-            .get_physical_device_surface_present_modes(physical_device, surface)?
+/// FIFO with vsync, otherwise MAILBOX or IMMEDIATE if either is supported.
+/// Falls back to FIFO, which every surface supports
+fn choose_present_mode(modes: &[vk::PresentModeKHR], vsync: bool) -> vk::PresentModeKHR {
+    let unsynchronized = [vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::IMMEDIATE];
+    if vsync {
+        vk::PresentModeKHR::FIFO
+    } else {
+        unsynchronized
             .into_iter()
-            .find(|mode| *mode == target)
-        {
-            info!("Using surface presentation mode: {mode:?}");
-            mode
-        } else {
-            warn!("Requested presentation mode {target:?} not supported, falling back to FIFO");
-            vk::PresentModeKHR::FIFO
-        },
-    )
+            .find(|mode| modes.contains(mode))
+            .unwrap_or(vk::PresentModeKHR::FIFO)
+    }
 }
 
 /// Creates the views for the swapchain images
@@ -569,9 +937,13 @@ unsafe fn create_swapchain_views(
 }
 
 /// Creates a per-frame data structure
+///
+/// `ownership_family` is the presentation queue family,
+/// if swapchain images have to be transferred to it before presenting
 unsafe fn create_frame(
     device: &ash::Device,
     graphics_index: u32,
+    ownership_family: Option<u32>,
     thread_count: usize,
     allocator: &Arc<Allocator>,
     global_descriptor_layout: vk::DescriptorSetLayout,
@@ -608,6 +980,9 @@ unsafe fn create_frame(
     let fence = device.create_fence(&fence_info, None)?;
     let graphics_semaphore = device.create_semaphore(&Default::default(), None)?;
     let present_semaphore = device.create_semaphore(&Default::default(), None)?;
+    let ownership_transfer = ownership_family
+        .map(|family| create_ownership_transfer(device, family))
+        .transpose()?;
 
     let ubo: GpuObject<Ubo> =
         GpuObject::new(allocator.clone(), vk::BufferUsageFlags::UNIFORM_BUFFER)?;
@@ -635,25 +1010,159 @@ unsafe fn create_frame(
         fence,
         graphics_semaphore,
         present_semaphore,
+        ownership_transfer,
         ubo: ManuallyDrop::new(ubo),
         global_descriptor,
         sync_data: Arc::new((Mutex::new(RenderResult::Ok), Default::default())),
     })
 }
 
+/// Creates the command buffer and semaphore used to acquire swapchain images on the presentation queue
+unsafe fn create_ownership_transfer(
+    device: &ash::Device,
+    present_family: u32,
+) -> VkResult<OwnershipTransfer> {
+    let create_info = vk::CommandPoolCreateInfo::builder()
+        .queue_family_index(present_family)
+        .flags(vk::CommandPoolCreateFlags::TRANSIENT);
+    let pool = device.create_command_pool(&create_info, None)?;
+    let alloc_info = vk::CommandBufferAllocateInfo::builder()
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1)
+        .command_pool(pool);
+    let cmd = device.allocate_command_buffers(&alloc_info)?[0];
+    let semaphore = device.create_semaphore(&Default::default(), None)?;
+    Ok(OwnershipTransfer {
+        pool,
+        cmd,
+        semaphore,
+    })
+}
+
+/// Points the shadow map binding of every frame's global descriptor set at the shadow map
+unsafe fn write_shadow_descriptors(device: &ash::Device, frames: &[Frame], shadow_map: &ShadowMap) {
+    let image_info = [vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(shadow_map.texture.view)
+        .sampler(shadow_map.texture.sampler)
+        .build()];
+    let writes = frames
+        .iter()
+        .map(|frame| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(frame.global_descriptor)
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_info)
+                .build()
+        })
+        .collect::<SmallVec<[_; MAX_FRAMES_IN_FLIGHT]>>();
+    device.update_descriptor_sets(&writes, &[]);
+}
+
+/// Number of render threads, half the number of cores unless `requested`.
+/// Clamped to `1..=MAX_RENDER_THREADS`
+fn render_thread_count(requested: Option<usize>) -> usize {
+    let count = requested.unwrap_or_else(|| {
+        available_parallelism()
+            .map(NonZeroUsize::get)
+            .unwrap_or_default()
+            / 2
+    });
+    let clamped = count.clamp(1, MAX_RENDER_THREADS);
+    if requested.is_some() && clamped != count {
+        warn!("{count} render threads are not supported, using {clamped} instead");
+    }
+    clamped
+}
+
+/// Clamps the requested frames in flight to `1..=MAX_FRAMES_IN_FLIGHT`
+fn frames_in_flight(requested: u32) -> usize {
+    let frames = (requested as usize).clamp(1, MAX_FRAMES_IN_FLIGHT);
+    if frames != requested as usize {
+        warn!("{requested} frames in flight are not supported, using {frames} instead");
+    }
+    frames
+}
+
+/// Clamps the requested samples per pixel to the most supported by the main pass' color,
+/// depth and object id attachments
+unsafe fn get_sample_count(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    requested: u32,
+) -> vk::SampleCountFlags {
+    let limits = instance
+        .get_physical_device_properties(physical_device)
+        .limits;
+    // integer formats may support fewer samples than the framebuffer limits
+    let object_id = instance
+        .get_physical_device_image_format_properties(
+            physical_device,
+            OBJECT_ID_FORMAT,
+            vk::ImageType::TYPE_2D,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            vk::ImageCreateFlags::empty(),
+        )
+        .map_or(vk::SampleCountFlags::TYPE_1, |properties| {
+            properties.sample_counts
+        });
+    let supported =
+        limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts & object_id;
+    let samples = sample_count(requested, supported);
+    if samples.as_raw() < requested {
+        warn!(
+            "{requested}x msaa is not supported, using {}x instead",
+            samples.as_raw()
+        );
+    } else if samples != vk::SampleCountFlags::TYPE_1 {
+        info!("Using {}x msaa", samples.as_raw());
+    }
+    samples
+}
+
 unsafe fn create_global_descriptor_layout(
     device: &ash::Device,
 ) -> VkResult<vk::DescriptorSetLayout> {
-    let bindings = [vk::DescriptorSetLayoutBinding::builder()
-        .binding(0)
-        .descriptor_count(1)
-        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-        .stage_flags(vk::ShaderStageFlags::VERTEX)
-        .build()];
+    let bindings = global_bindings();
     let layout_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
     device.create_descriptor_set_layout(&layout_info, None)
 }
 
+/// Bindings of the global set 0 shared by every pipeline,
+/// the frame's ubo, the shadow map and the environment
+pub(super) fn global_bindings() -> [vk::DescriptorSetLayoutBinding; 4] {
+    [
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+        // shadow map
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(1)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+        // environment cubemap and its uniform, see Environment
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(2)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(3)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+    ]
+}
+
 unsafe fn create_global_descriptor_set(
     device: &ash::Device,
     layout: vk::DescriptorSetLayout,
@@ -668,12 +1177,33 @@ unsafe fn create_global_descriptor_set(
 }
 
 unsafe fn create_descriptor_pool(device: &ash::Device) -> VkResult<vk::DescriptorPool> {
-    let sizes = [vk::DescriptorPoolSize::builder()
-        .descriptor_count(1)
-        .ty(vk::DescriptorType::UNIFORM_BUFFER)
-        .build()];
+    let sizes = [
+        vk::DescriptorPoolSize::builder()
+            .descriptor_count(16)
+            .ty(vk::DescriptorType::UNIFORM_BUFFER)
+            .build(),
+        // every material sampling a texture has its own, see TEXTURE_BINDING
+        vk::DescriptorPoolSize::builder()
+            .descriptor_count(256)
+            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .build(),
+        vk::DescriptorPoolSize::builder()
+            .descriptor_count(16)
+            .ty(vk::DescriptorType::STORAGE_BUFFER)
+            .build(),
+        vk::DescriptorPoolSize::builder()
+            .descriptor_count(16)
+            .ty(vk::DescriptorType::SAMPLER)
+            .build(),
+        vk::DescriptorPoolSize::builder()
+            .descriptor_count(64)
+            .ty(vk::DescriptorType::SAMPLED_IMAGE)
+            .build(),
+    ];
+    // materials free their own descriptor sets when they are destroyed
     let create_info = vk::DescriptorPoolCreateInfo::builder()
-        .max_sets(16)
+        .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+        .max_sets(512)
         .pool_sizes(&sizes);
     device.create_descriptor_pool(&create_info, None)
 }
@@ -710,6 +1240,7 @@ pub(super) unsafe fn create_depth_image(
     device: &ash::Device,
     format: vk::Format,
     extent: vk::Extent2D,
+    samples: vk::SampleCountFlags,
     allocator: Arc<Allocator>,
 ) -> Result<(Image, vk::ImageView)> {
     let create_info = vk::ImageCreateInfo::builder()
@@ -722,7 +1253,7 @@ pub(super) unsafe fn create_depth_image(
         .initial_layout(vk::ImageLayout::UNDEFINED)
         .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)
         .sharing_mode(vk::SharingMode::EXCLUSIVE)
-        .samples(vk::SampleCountFlags::TYPE_1);
+        .samples(samples);
     let alloc_info = vk_mem::AllocationCreateInfo {
         usage: vk_mem::MemoryUsage::GpuOnly,
         required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
@@ -747,33 +1278,86 @@ pub(super) unsafe fn create_depth_image(
     Ok((image, view))
 }
 
-/// loads the debug messenger functions and handle object.
+/// Creates the attachment the main pass writes the object ids of draws to,
+/// which can be copied back to the host for picking when it has a single sample
+pub(super) unsafe fn create_object_id_image(
+    device: &ash::Device,
+    extent: vk::Extent2D,
+    samples: vk::SampleCountFlags,
+    allocator: Arc<Allocator>,
+) -> Result<(Image, vk::ImageView)> {
+    let create_info = vk::ImageCreateInfo::builder()
+        .format(OBJECT_ID_FORMAT)
+        .image_type(vk::ImageType::TYPE_2D)
+        .extent(vk::Extent3D::from(extent))
+        .mip_levels(1)
+        .array_layers(1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .samples(samples);
+    let alloc_info = vk_mem::AllocationCreateInfo {
+        usage: vk_mem::MemoryUsage::GpuOnly,
+        required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ..Default::default()
+    };
+    let image = Image::new(&create_info, &alloc_info, allocator)?;
+    let sub_range = vk::ImageSubresourceRange::builder()
+        .base_mip_level(0)
+        .level_count(1)
+        .base_array_layer(0)
+        .layer_count(1)
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .build();
+
+    let view_info = vk::ImageViewCreateInfo::builder()
+        .image(*image)
+        .format(OBJECT_ID_FORMAT)
+        .subresource_range(sub_range)
+        .view_type(vk::ImageViewType::TYPE_2D);
+
+    let view = device.create_image_view(&view_info, None)?;
+    Ok((image, view))
+}
+
+/// loads the debug messenger functions and handle object,
+/// the messenger only reports messages of the given severities.
+/// No messenger is created if there are none, the functions are still used for labels
 #[cfg(feature = "validation-layers")]
 unsafe fn create_debug_messenger(
     entry: &ash::Entry,
     instance: &ash::Instance,
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
 ) -> Result<(
     Box<ash::extensions::ext::DebugUtils>,
-    vk::DebugUtilsMessengerEXT,
+    Option<vk::DebugUtilsMessengerEXT>,
 )> {
     let utils = ash::extensions::ext::DebugUtils::new(entry, instance);
-    let create_info = get_debug_info();
-    let messenger = utils.create_debug_utils_messenger(&create_info, None)?;
+    let messenger = if severity.is_empty() {
+        None
+    } else {
+        let create_info = get_debug_info(severity);
+        Some(utils.create_debug_utils_messenger(&create_info, None)?)
+    };
     Ok((Box::new(utils), messenger))
 }
 
 /// gets the create info struct for the debug messenger
 #[cfg(feature = "validation-layers")]
-fn get_debug_info() -> vk::DebugUtilsMessengerCreateInfoEXT {
+fn get_debug_info(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+) -> vk::DebugUtilsMessengerCreateInfoEXT {
+    // general messages are mostly the loader's info and verbose output
+    let general = if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+        vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+    } else {
+        vk::DebugUtilsMessageTypeFlagsEXT::empty()
+    };
     vk::DebugUtilsMessengerCreateInfoEXT::builder()
-        .message_severity(
-            vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
-        )
+        .message_severity(severity)
         .message_type(
-            vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+            general
                 | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
                 | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
         )
@@ -781,6 +1365,23 @@ fn get_debug_info() -> vk::DebugUtilsMessengerCreateInfoEXT {
         .build()
 }
 
+/// Validation message severities logged at `level` or below,
+/// following the levels [debug_callback] logs them at
+#[cfg(feature = "validation-layers")]
+fn debug_severity(level: log::LevelFilter) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+    use ash::vk::DebugUtilsMessageSeverityFlagsEXT as Flags;
+    use log::LevelFilter;
+    [
+        (LevelFilter::Error, Flags::ERROR),
+        (LevelFilter::Warn, Flags::WARNING),
+        (LevelFilter::Info, Flags::INFO),
+        (LevelFilter::Trace, Flags::VERBOSE),
+    ]
+    .into_iter()
+    .filter(|(min, _)| level >= *min)
+    .fold(Flags::empty(), |flags, (_, flag)| flags | flag)
+}
+
 /// copy of the internal ash function, but with a small vec instead of a regular vec
 unsafe fn read_into_uninitialized_small_vector<N: Copy + Default + TryInto<usize>, T>(
     f: impl Fn(&mut N, *mut T) -> vk::Result,
@@ -801,3 +1402,103 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+
+    #[cfg(feature = "validation-layers")]
+    use crate::vulkan::engine::init::debug_severity;
+    use crate::vulkan::engine::init::{
+        choose_present_mode, choose_surface_format, frames_in_flight, gpu_name_matches,
+        render_thread_count, transfer_family, MAX_RENDER_THREADS,
+    };
+
+    #[test]
+    fn srgb_formats_with_alpha_are_preferred() {
+        let format = |format, color_space| vk::SurfaceFormatKHR {
+            format,
+            color_space,
+        };
+        let nonlinear = vk::ColorSpaceKHR::SRGB_NONLINEAR;
+        let extended = vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT;
+        let mut formats = vec![
+            format(vk::Format::A2B10G10R10_UNORM_PACK32, nonlinear),
+            format(vk::Format::R8G8B8A8_SRGB, nonlinear),
+            format(vk::Format::B8G8R8A8_SRGB, extended),
+        ];
+        let chosen = choose_surface_format(&formats).map(|it| it.format);
+        assert_eq!(chosen, Some(vk::Format::R8G8B8A8_SRGB));
+        formats.push(format(vk::Format::B8G8R8A8_SRGB, nonlinear));
+        assert_eq!(choose_surface_format(&formats), formats.last().copied());
+        let chosen = choose_surface_format(&formats[..1]).map(|it| it.format);
+        assert_eq!(chosen, Some(vk::Format::A2B10G10R10_UNORM_PACK32));
+        assert_eq!(choose_surface_format(&[]), None);
+    }
+
+    #[test]
+    fn frames_in_flight_are_clamped() {
+        assert_eq!(frames_in_flight(0), 1);
+        assert_eq!(frames_in_flight(2), 2);
+        assert_eq!(frames_in_flight(8), 3);
+    }
+
+    #[test]
+    fn render_threads_are_clamped() {
+        assert_eq!(render_thread_count(Some(1)), 1);
+        assert_eq!(render_thread_count(Some(0)), 1);
+        assert_eq!(render_thread_count(Some(64)), MAX_RENDER_THREADS);
+        assert!((1..=MAX_RENDER_THREADS).contains(&render_thread_count(None)));
+    }
+
+    #[test]
+    fn vsync_selects_fifo() {
+        let fifo = vk::PresentModeKHR::FIFO;
+        let immediate = vk::PresentModeKHR::IMMEDIATE;
+        let mailbox = vk::PresentModeKHR::MAILBOX;
+        let modes = [fifo, immediate, mailbox];
+        assert_eq!(choose_present_mode(&modes, true), fifo);
+        assert_eq!(choose_present_mode(&modes, false), mailbox);
+        assert_eq!(choose_present_mode(&modes[..2], false), immediate);
+        assert_eq!(choose_present_mode(&modes[..1], false), fifo);
+    }
+
+    #[test]
+    fn dedicated_transfer_families_are_preferred() {
+        let graphics =
+            vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER;
+        let compute = vk::QueueFlags::COMPUTE;
+        let transfer = vk::QueueFlags::TRANSFER | vk::QueueFlags::SPARSE_BINDING;
+        assert_eq!(transfer_family(&[graphics, compute, transfer], 0, 0), Some(2));
+        assert_eq!(transfer_family(&[graphics, compute], 0, 0), Some(1));
+        assert_eq!(transfer_family(&[graphics, graphics], 0, 0), None);
+        assert_eq!(transfer_family(&[graphics], 0, 0), None);
+        // the presentation queue is used by another thread
+        assert_eq!(transfer_family(&[graphics, compute, transfer], 0, 2), Some(1));
+        assert_eq!(transfer_family(&[graphics, compute], 0, 1), None);
+    }
+
+    #[test]
+    fn gpus_are_matched_by_part_of_their_name() {
+        let name = "NVIDIA GeForce RTX 3060 Laptop GPU";
+        assert!(gpu_name_matches(name, "rtx 3060"));
+        assert!(gpu_name_matches(name, "NVIDIA"));
+        assert!(!gpu_name_matches(name, "Intel"));
+    }
+
+    #[test]
+    #[cfg(feature = "validation-layers")]
+    fn verbose_validation_messages_need_trace_logging() {
+        use ash::vk::DebugUtilsMessageSeverityFlagsEXT as Flags;
+        use log::LevelFilter;
+        let info = debug_severity(LevelFilter::Info);
+        assert_eq!(info, Flags::ERROR | Flags::WARNING | Flags::INFO);
+        assert_eq!(debug_severity(LevelFilter::Debug), info);
+        assert_eq!(debug_severity(LevelFilter::Trace), info | Flags::VERBOSE);
+        assert_eq!(
+            debug_severity(LevelFilter::Warn),
+            Flags::ERROR | Flags::WARNING
+        );
+        assert_eq!(debug_severity(LevelFilter::Off), Flags::empty());
+    }
+}
@@ -0,0 +1,52 @@
+//! Named regions of command buffers that show up in gpu debuggers and profilers
+//! like RenderDoc and Nsight.
+//!
+//! Labels are only recorded with the validation-layers feature,
+//! otherwise these functions do nothing
+
+use ash::vk;
+
+/// Records labels with the debug utils loader of one engine
+#[derive(Clone)]
+#[cfg_attr(not(feature = "validation-layers"), derive(Default))]
+pub(super) struct Labels {
+    #[cfg(feature = "validation-layers")]
+    utils: ash::extensions::ext::DebugUtils,
+}
+
+impl Labels {
+    #[cfg(feature = "validation-layers")]
+    pub(super) fn new(utils: &ash::extensions::ext::DebugUtils) -> Self {
+        Labels {
+            utils: utils.clone(),
+        }
+    }
+
+    /// Begins a labeled region of `cmd`, which has to be ended by [end](Labels::end)
+    /// in the same command buffer
+    ///
+    /// # Safety
+    /// `cmd` must be recording
+    #[inline]
+    #[allow(unused_variables)]
+    pub(super) unsafe fn begin(&self, cmd: vk::CommandBuffer, name: &str) {
+        #[cfg(feature = "validation-layers")]
+        {
+            let name = std::ffi::CString::new(name).unwrap_or_default();
+            let label = vk::DebugUtilsLabelEXT::builder().label_name(&name);
+            self.utils.cmd_begin_debug_utils_label(cmd, &label);
+        }
+    }
+
+    /// Ends the innermost labeled region of `cmd`
+    ///
+    /// # Safety
+    /// `cmd` must be recording and have a region begun by [begin](Labels::begin)
+    /// that wasn't ended yet
+    #[inline]
+    #[allow(unused_variables)]
+    pub(super) unsafe fn end(&self, cmd: vk::CommandBuffer) {
+        #[cfg(feature = "validation-layers")]
+        self.utils.cmd_end_debug_utils_label(cmd);
+    }
+}
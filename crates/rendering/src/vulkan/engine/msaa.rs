@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk;
+use vk_mem::Allocator;
+
+use crate::vulkan::engine::alloc::Image;
+use crate::vulkan::engine::init::create_object_id_image;
+
+/// Multisampled color and object id attachments of the main pass, resolved into the
+/// swapchain image and the single sampled object id image at the end of the pass.
+///
+/// Only exists when multisampling is enabled, the depth image is multisampled itself
+pub(super) struct MsaaTargets {
+    pub(super) color_view: vk::ImageView,
+    pub(super) object_id_view: vk::ImageView,
+    color: Image,
+    object_id: Image,
+    device: Arc<ash::Device>,
+}
+
+impl MsaaTargets {
+    pub(super) unsafe fn new(
+        device: Arc<ash::Device>,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        samples: vk::SampleCountFlags,
+        allocator: Arc<Allocator>,
+    ) -> Result<Self> {
+        let (color, color_view) =
+            create_color_image(&device, format, extent, samples, allocator.clone())?;
+        let (object_id, object_id_view) =
+            create_object_id_image(&device, extent, samples, allocator)?;
+        Ok(MsaaTargets {
+            color_view,
+            object_id_view,
+            color,
+            object_id,
+            device,
+        })
+    }
+
+    /// The color and object id image, which are transitioned like the images they resolve into
+    pub(super) fn images(&self) -> [vk::Image; 2] {
+        [*self.color, *self.object_id]
+    }
+}
+
+impl Drop for MsaaTargets {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_image_view(self.color_view, None);
+            self.device.destroy_image_view(self.object_id_view, None);
+        }
+    }
+}
+
+/// Highest sample count in `supported` that is at most `requested`,
+/// a single sample if `requested` is zero or one
+pub(super) fn sample_count(
+    requested: u32,
+    supported: vk::SampleCountFlags,
+) -> vk::SampleCountFlags {
+    // sample count flags are equal to the number of samples
+    [64, 32, 16, 8, 4, 2]
+        .into_iter()
+        .map(vk::SampleCountFlags::from_raw)
+        .find(|count| count.as_raw() <= requested && supported.contains(*count))
+        .unwrap_or(vk::SampleCountFlags::TYPE_1)
+}
+
+/// Multisampled color attachment that is never stored, only resolved
+unsafe fn create_color_image(
+    device: &ash::Device,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    samples: vk::SampleCountFlags,
+    allocator: Arc<Allocator>,
+) -> Result<(Image, vk::ImageView)> {
+    let create_info = vk::ImageCreateInfo::builder()
+        .format(format)
+        .image_type(vk::ImageType::TYPE_2D)
+        .extent(vk::Extent3D::from(extent))
+        .mip_levels(1)
+        .array_layers(1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .samples(samples);
+    let alloc_info = vk_mem::AllocationCreateInfo {
+        usage: vk_mem::MemoryUsage::GpuOnly,
+        required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        ..Default::default()
+    };
+    let image = Image::new(&create_info, &alloc_info, allocator)?;
+    let view_info = vk::ImageViewCreateInfo::builder()
+        .image(*image)
+        .format(format)
+        .subresource_range(vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .view_type(vk::ImageViewType::TYPE_2D);
+    let view = device.create_image_view(&view_info, None)?;
+    Ok((image, view))
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk::SampleCountFlags;
+
+    use crate::vulkan::engine::msaa::sample_count;
+
+    #[test]
+    fn sample_count_is_clamped_to_supported() {
+        let supported =
+            SampleCountFlags::TYPE_1 | SampleCountFlags::TYPE_2 | SampleCountFlags::TYPE_4;
+        assert_eq!(sample_count(4, supported), SampleCountFlags::TYPE_4);
+        assert_eq!(sample_count(8, supported), SampleCountFlags::TYPE_4);
+        assert_eq!(sample_count(3, supported), SampleCountFlags::TYPE_2);
+        assert_eq!(sample_count(1, supported), SampleCountFlags::TYPE_1);
+        assert_eq!(sample_count(0, supported), SampleCountFlags::TYPE_1);
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+use log::warn;
+use nalgebra::{Matrix4, Point3, Vector3};
+
+use engine::filesystem::DIRS;
+
+use crate::materials::{CullMode, MaterialDefinition};
+use crate::vulkan::engine::pipeline::{create_pipeline, set_viewport};
+use crate::Mesh;
+
+/// Most bounding boxes tested per frame, draws beyond this are never occlusion culled
+const MAX_QUERIES: u32 = 4096;
+/// Bounding boxes are grown by this fraction of their size, so that boxes coinciding with
+/// the surface of their mesh don't fail the depth test against it
+const BOX_MARGIN: f32 = 0.01;
+/// Distance around a bounding box within which the camera counts as inside of it,
+/// covers the near plane clipping the box's faces
+const CAMERA_MARGIN: f32 = 0.2;
+/// Vertices of the bounding box drawn by the bounds shader
+const BOX_VERTICES: u32 = 36;
+
+/// Hardware occlusion queries of the bounding boxes of every draw.
+///
+/// The boxes are tested against the depth buffer after the main pass.
+/// Their results are read once the frame's fence is signaled, so an object is hidden
+/// as many frames after its box became occluded as there are frames in flight,
+/// and drawn again just as late after it became visible. Draws are identified by their mesh and model matrix,
+/// so moving objects are always drawn
+pub(super) struct OcclusionQueries {
+    device: Arc<ash::Device>,
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    /// One pool per frame in flight
+    pools: Vec<vk::QueryPool>,
+    /// Keys and box matrices of the draws queried by each frame in flight, in query order
+    queried: Vec<Vec<(u64, Matrix4<f32>)>>,
+    /// Draws of the frame currently being recorded
+    current: Vec<(u64, Matrix4<f32>)>,
+    /// Draws whose bounding box produced no samples in the last frame with query results
+    hidden: HashSet<u64>,
+    camera: Point3<f32>,
+}
+
+impl OcclusionQueries {
+    pub(super) unsafe fn new(
+        device: Arc<ash::Device>,
+        depth_format: vk::Format,
+        samples: vk::SampleCountFlags,
+        global_descriptor_layout: vk::DescriptorSetLayout,
+        frames_in_flight: usize,
+    ) -> Result<Self> {
+        let data = vec![fs::read(
+            DIRS.asset.join("shaders").join("bounds.vert.spv"),
+        )?];
+        let definition = MaterialDefinition {
+            vertex_shader: "bounds.vert.spv".into(),
+            cull_mode: CullMode::None,
+            depth_write: false,
+            ..Default::default()
+        };
+        let (pipeline, layout, set_layouts) = create_pipeline(
+            &device,
+            None,
+            depth_format,
+            samples,
+            data,
+            global_descriptor_layout,
+            &definition,
+        )
+        .map_err(|e| anyhow!("Failed to create occlusion query pipeline: {e}"))?;
+        debug_assert!(
+            set_layouts.is_empty(),
+            "Bounds shaders only use the global set"
+        );
+
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::OCCLUSION)
+            .query_count(MAX_QUERIES);
+        let mut pools = Vec::with_capacity(frames_in_flight);
+        for _ in 0..frames_in_flight {
+            pools.push(device.create_query_pool(&create_info, None)?);
+        }
+
+        Ok(OcclusionQueries {
+            device,
+            pipeline,
+            layout,
+            pools,
+            queried: vec![Vec::new(); frames_in_flight],
+            current: Vec::new(),
+            hidden: HashSet::new(),
+            camera: Point3::origin(),
+        })
+    }
+
+    /// Reads the query results of the frame in flight `frame`,
+    /// must be called after waiting on the frame's fence
+    pub(super) unsafe fn collect(&mut self, frame: usize, view: &Matrix4<f32>) {
+        self.camera = view
+            .try_inverse()
+            .map_or_else(Point3::origin, |it| it.transform_point(&Point3::origin()));
+        self.hidden.clear();
+        let queried = &self.queried[frame];
+        if queried.is_empty() {
+            return;
+        }
+        let mut samples = vec![0u32; queried.len()];
+        if let Err(e) = self.device.get_query_pool_results(
+            self.pools[frame],
+            0,
+            queried.len() as u32,
+            &mut samples,
+            vk::QueryResultFlags::empty(),
+        ) {
+            warn!("Failed to read occlusion query results: {e}");
+            return;
+        }
+        self.hidden.extend(
+            queried
+                .iter()
+                .zip(samples)
+                .filter(|(_, samples)| *samples == 0)
+                .map(|((key, _), _)| *key),
+        );
+    }
+
+    /// Queues the bounding box of a draw to be tested this frame
+    ///
+    /// returns: false if the draw was occluded in the last frame with query results
+    pub(super) fn test(&mut self, mesh: &Mesh, transform: &Matrix4<f32>) -> bool {
+        let (min, max) = mesh.get_bounds();
+        if min.iter().zip(max.iter()).any(|(min, max)| min > max) {
+            return true;
+        }
+        let margin = (max - min) * BOX_MARGIN + Vector3::repeat(f32::EPSILON);
+        let (min, max) = (min - margin, max + margin);
+        // none of the box's faces are in front of a camera inside of it
+        let inside = transform.try_inverse().map_or(true, |inverse| {
+            let camera = inverse.transform_point(&self.camera);
+            (0..3).all(|axis| {
+                camera[axis] >= min[axis] - CAMERA_MARGIN
+                    && camera[axis] <= max[axis] + CAMERA_MARGIN
+            })
+        });
+        if inside {
+            return true;
+        }
+
+        let key = draw_key(mesh, transform);
+        if self.current.len() < MAX_QUERIES as usize {
+            let model = transform
+                * Matrix4::new_translation(&min)
+                * Matrix4::new_nonuniform_scaling(&(max - min));
+            self.current.push((key, model));
+        }
+        !self.hidden.contains(&key)
+    }
+
+    /// Moves the draws tested this frame to the frame in flight `frame`, before it is recorded
+    pub(super) fn finish_frame(&mut self, frame: usize) {
+        self.queried[frame] = std::mem::take(&mut self.current);
+    }
+
+    /// Draws the bounding boxes of the frame's draws against the depth buffer of the main pass
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd`: primary command buffer of the current frame, outside of any rendering instance
+    /// * `frame`: index of the frame in flight
+    /// * `global_descriptor`: descriptor set containing the frame's ubo
+    /// * `depth_view`: depth attachment written by the main pass
+    pub(super) unsafe fn record(
+        &self,
+        cmd: vk::CommandBuffer,
+        frame: usize,
+        global_descriptor: vk::DescriptorSet,
+        depth_view: vk::ImageView,
+        extent: vk::Extent2D,
+    ) {
+        let device = &self.device;
+        device.cmd_reset_query_pool(cmd, self.pools[frame], 0, MAX_QUERIES);
+        let draws = &self.queried[frame];
+        if draws.is_empty() {
+            return;
+        }
+
+        let barrier = [vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ)
+            .build()];
+        device.cmd_pipeline_barrier(
+            cmd,
+            vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            vk::DependencyFlags::empty(),
+            &barrier,
+            &[],
+            &[],
+        );
+
+        let depth_attachment = vk::RenderingAttachmentInfo::builder()
+            .image_view(depth_view)
+            .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE);
+        let rendering_info = vk::RenderingInfo::builder()
+            .layer_count(1)
+            .depth_attachment(&depth_attachment)
+            .render_area(vk::Rect2D {
+                offset: Default::default(),
+                extent,
+            });
+        device.cmd_begin_rendering(cmd, &rendering_info);
+        set_viewport(device, cmd, extent);
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_bind_descriptor_sets(
+            cmd,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.layout,
+            0,
+            &[global_descriptor],
+            &[],
+        );
+        for (index, (_, model)) in draws.iter().enumerate() {
+            device.cmd_push_constants(
+                cmd,
+                self.layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                std::slice::from_raw_parts(
+                    model.as_ptr() as *const u8,
+                    std::mem::size_of::<Matrix4<f32>>(),
+                ),
+            );
+            let index = index as u32;
+            device.cmd_begin_query(
+                cmd,
+                self.pools[frame],
+                index,
+                vk::QueryControlFlags::empty(),
+            );
+            device.cmd_draw(cmd, BOX_VERTICES, 1, 0, 0);
+            device.cmd_end_query(cmd, self.pools[frame], index);
+        }
+        device.cmd_end_rendering(cmd);
+    }
+}
+
+impl Drop for OcclusionQueries {
+    fn drop(&mut self) {
+        unsafe {
+            for pool in &self.pools {
+                self.device.destroy_query_pool(*pool, None);
+            }
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+/// Identifies a draw across frames by its mesh and model matrix
+fn draw_key(mesh: &Mesh, transform: &Matrix4<f32>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (mesh as *const Mesh as usize).hash(&mut hasher);
+    for value in transform.iter() {
+        value.to_bits().hash(&mut hasher);
+    }
+    hasher.finish()
+}
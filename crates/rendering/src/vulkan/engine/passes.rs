@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use ash::vk;
+use nalgebra::Matrix4;
+
+use crate::vulkan::engine::compute::{record_dispatches, ComputePipeline};
+use crate::vulkan::engine::msaa::MsaaTargets;
+use crate::vulkan::engine::occlusion::OcclusionQueries;
+use crate::vulkan::engine::pipeline::set_viewport;
+use crate::vulkan::engine::shadow::ShadowMap;
+use crate::vulkan::engine::{begin, InlineDraws, Recorder};
+use crate::Mesh;
+
+/// Everything a pass may need to record its commands for the current frame
+pub(super) struct FrameContext<'a> {
+    pub device: &'a ash::Device,
+    /// Primary command buffer of the frame, outside of any rendering instance
+    pub cmd: vk::CommandBuffer,
+    pub global_descriptor: vk::DescriptorSet,
+    pub color_view: vk::ImageView,
+    pub depth_view: vk::ImageView,
+    /// Attachment the main pass writes the object ids of tagged draws to
+    pub object_id_view: vk::ImageView,
+    /// Multisampled attachments the main pass renders into, None without multisampling
+    pub msaa: Option<&'a MsaaTargets>,
+    pub extent: vk::Extent2D,
+    /// Secondary command buffers recorded by the render threads
+    pub secondary_buffers: &'a [vk::CommandBuffer],
+    pub shadow_map: &'a ShadowMap,
+    pub shadow_casters: &'a [(Arc<Mesh>, Matrix4<f32>)],
+    /// None unless occlusion culling is enabled
+    pub occlusion: Option<&'a OcclusionQueries>,
+    /// Draws to record into the primary command buffer, None if render threads recorded
+    /// the secondary buffers
+    pub inline_draws: Option<&'a InlineDraws>,
+    /// Index of the frame in flight being recorded
+    pub frame_index: usize,
+    /// Compute pipelines and workgroup counts dispatched for the frame, in order
+    pub dispatches: &'a [(Arc<ComputePipeline>, [u32; 3])],
+    /// Color the main pass clears to, read from the settings every frame
+    pub clear_color: [f32; 4],
+}
+
+/// A step of the frame recorded into the primary command buffer.
+///
+/// Passes are executed in the order of the engine's pass list, each pass is responsible
+/// for the barriers its own targets need.
+/// The swapchain image is in `COLOR_ATTACHMENT_OPTIMAL` layout before the first pass
+/// and must be left in it after the last one
+pub(super) trait FramePass {
+    /// Logged while recording and used as the pass' debug label
+    fn name(&self) -> &'static str;
+
+    /// # Safety
+    /// Only called between beginning and ending the primary command buffer
+    unsafe fn record(&mut self, context: &FrameContext);
+}
+
+/// Runs the frame's compute work, whose results the later passes can draw
+pub(super) struct ComputePass;
+
+impl FramePass for ComputePass {
+    fn name(&self) -> &'static str {
+        "compute"
+    }
+
+    unsafe fn record(&mut self, context: &FrameContext) {
+        record_dispatches(context.device, context.cmd, context.dispatches);
+    }
+}
+
+/// Renders the shadow casters into the directional light's shadow map
+pub(super) struct ShadowPass;
+
+impl FramePass for ShadowPass {
+    fn name(&self) -> &'static str {
+        "shadow"
+    }
+
+    unsafe fn record(&mut self, context: &FrameContext) {
+        context.shadow_map.record(
+            context.cmd,
+            context.global_descriptor,
+            context.shadow_casters,
+        );
+    }
+}
+
+/// Renders the swapchain image by executing the render thread's secondary command buffers,
+/// or by recording the draws itself when rendering on a single thread
+pub(super) struct MainPass;
+
+impl FramePass for MainPass {
+    fn name(&self) -> &'static str {
+        "opaque"
+    }
+
+    unsafe fn record(&mut self, context: &FrameContext) {
+        let flags = if context.inline_draws.is_some() {
+            vk::RenderingFlags::empty()
+        } else {
+            vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS
+        };
+        begin(
+            context.color_view,
+            context.depth_view,
+            context.object_id_view,
+            context.msaa,
+            context.extent,
+            context.cmd,
+            context.device,
+            flags,
+            context.clear_color,
+        );
+        match context.inline_draws {
+            Some(inline) => {
+                set_viewport(context.device, context.cmd, context.extent);
+                let mut recorder = Recorder::new(
+                    context.cmd,
+                    inline.view_projection,
+                    context.global_descriptor,
+                );
+                for draw in &inline.draws {
+                    recorder.record(context.device, draw);
+                }
+            }
+            None => context
+                .device
+                .cmd_execute_commands(context.cmd, context.secondary_buffers),
+        }
+        context.device.cmd_end_rendering(context.cmd);
+    }
+}
+
+/// Tests the bounding boxes of this frame's draws against the main pass' depth,
+/// does nothing unless occlusion culling is enabled
+pub(super) struct OcclusionPass;
+
+impl FramePass for OcclusionPass {
+    fn name(&self) -> &'static str {
+        "occlusion"
+    }
+
+    unsafe fn record(&mut self, context: &FrameContext) {
+        if let Some(occlusion) = context.occlusion {
+            occlusion.record(
+                context.cmd,
+                context.frame_index,
+                context.global_descriptor,
+                context.depth_view,
+                context.extent,
+            );
+        }
+    }
+}
+
+/// The passes of a frame in execution order, new passes are added here
+pub(super) fn create_passes() -> Vec<Box<dyn FramePass>> {
+    vec![
+        Box::new(ComputePass),
+        Box::new(ShadowPass),
+        Box::new(MainPass),
+        Box::new(OcclusionPass),
+    ]
+}
@@ -1,30 +1,56 @@
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::ffi::CString;
 use std::fs;
 use std::io::Cursor;
+use std::path::PathBuf;
 
 use ash::prelude::VkResult;
 use ash::vk;
 use itertools::Itertools;
 use log::{error, info};
-use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
 use scopeguard::defer;
-use spirv_reflect::types::ReflectShaderStageFlags;
+use spirv_reflect::types::{ReflectDescriptorType, ReflectShaderStageFlags};
 
 use engine::filesystem::DIRS;
 
+use crate::materials::{
+    BlendMode, CullMode, DepthCompare, FrontFace, MaterialDefinition, SpecializationValue,
+};
+use crate::vulkan::engine::environment::{EnvironmentUniform, UNIFORM_BINDING};
+use crate::vulkan::engine::init::global_bindings;
+use crate::vulkan::engine::{Transforms, Ubo, OBJECT_ID_FORMAT, OBJECT_ID_OFFSET};
 use crate::vulkan::mesh::Vertex;
 
-static CACHE: OnceCell<vk::PipelineCache> = OnceCell::new();
+/// Shared by all pipelines of the current device, taken on cleanup so a new engine loads it again
+static CACHE: Mutex<Option<vk::PipelineCache>> = Mutex::new(None);
 
+/// Size in bytes of the uniform buffer bound to set 0 binding 0
+const UBO_SIZE: u32 = std::mem::size_of::<Ubo>() as u32;
+/// Size in bytes of the environment's uniform buffer bound to set 0 binding 3
+const ENVIRONMENT_UBO_SIZE: u32 = std::mem::size_of::<EnvironmentUniform>() as u32;
+
+/// A pipeline, its layout and the layouts of the descriptor sets after the global set
+pub type PipelineParts = (vk::Pipeline, vk::PipelineLayout, Vec<vk::DescriptorSetLayout>);
+
+/// Creates a graphics pipeline from the given shader modules.
+///
+/// A depth only pipeline without color attachments is created when `image_fmt` is `None`,
+/// otherwise the object id attachment used for picking follows the color attachment.
+/// Descriptor sets after the global set 0 are created from the shaders' reflection data,
+/// their layouts are returned along with the pipeline and must be destroyed with it.
+/// The viewport and scissor are dynamic, see [set_viewport], so pipelines outlive swapchain resizes.
+/// Fails if a shader's push constants or global set don't match what the engine binds
 pub fn create_pipeline(
     device: &ash::Device,
-    image_fmt: vk::Format,
+    image_fmt: Option<vk::Format>,
     depth_fmt: vk::Format,
-    extent: vk::Extent2D,
+    samples: vk::SampleCountFlags,
     module_data: Vec<Vec<u8>>,
     global_descriptor_layout: vk::DescriptorSetLayout,
-) -> Result<(vk::Pipeline, vk::PipelineLayout), Box<dyn Error>> {
+    definition: &MaterialDefinition,
+) -> Result<PipelineParts, Box<dyn Error>> {
     let module_data = module_data
         .into_iter()
         .map(|data| spirv_reflect::create_shader_module(&data).map(|it| (it, data)))
@@ -48,68 +74,74 @@ pub fn create_pipeline(
         }
     }
 
+    for (reflect, _) in &module_data {
+        check_interface(reflect)?;
+    }
+
     let name = CString::new("main").unwrap();
+    let constants = definition
+        .specialization_constants
+        .iter()
+        .copied()
+        .chain(definition.shading_constants())
+        .collect_vec();
+    let (spec_entries, spec_data) = specialization_data(&constants)?;
+    let spec_info = vk::SpecializationInfo::builder()
+        .map_entries(&spec_entries)
+        .data(&spec_data);
     let stages = module_data
         .iter()
         .map(|(info, module)| {
-            match info.get_shader_stage() {
-                ReflectShaderStageFlags::VERTEX => Ok(vk::ShaderStageFlags::VERTEX),
-                ReflectShaderStageFlags::FRAGMENT => Ok(vk::ShaderStageFlags::FRAGMENT),
-                ReflectShaderStageFlags::GEOMETRY => Ok(vk::ShaderStageFlags::GEOMETRY),
-                ReflectShaderStageFlags::TESSELLATION_CONTROL => {
-                    Ok(vk::ShaderStageFlags::TESSELLATION_CONTROL)
-                }
-                ReflectShaderStageFlags::TESSELLATION_EVALUATION => {
-                    Ok(vk::ShaderStageFlags::TESSELLATION_EVALUATION)
-                }
-                ReflectShaderStageFlags::COMPUTE => Ok(vk::ShaderStageFlags::COMPUTE),
-                _ => Err("Invalid stage flags"),
-            }
-                .map(|stage| {
-                    vk::PipelineShaderStageCreateInfo::builder()
-                        .stage(stage)
-                        .module(*module)
-                        .name(&name)
-                        .build()
-                })
+            get_stage(info).map(|stage| {
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(stage)
+                    .module(*module)
+                    .name(&name)
+                    .specialization_info(&spec_info)
+                    .build()
+            })
         })
         .collect::<Result<Vec<_>, _>>()?;
 
-    let fmts = [image_fmt];
+    let fmts = image_fmt
+        .into_iter()
+        .flat_map(|fmt| [fmt, OBJECT_ID_FORMAT])
+        .collect_vec();
     let mut render_info =
         vk::PipelineRenderingCreateInfo::builder().color_attachment_formats(&fmts).depth_attachment_format(depth_fmt);
 
-    let (bindings, attributes) = Vertex::get_vertex_description();
+    let (mut bindings, mut attributes) = Vertex::get_vertex_description();
+    if definition.instanced {
+        let (instance_binding, instance_attributes) = Vertex::get_instance_description();
+        bindings.push(instance_binding);
+        attributes.extend(instance_attributes);
+    }
+    if definition.skinned {
+        let (skin_binding, skin_attributes) = Vertex::get_skinning_description();
+        bindings.push(skin_binding);
+        attributes.extend(skin_attributes);
+    }
     let vert_input = vk::PipelineVertexInputStateCreateInfo::builder()
         .vertex_binding_descriptions(&bindings)
         .vertex_attribute_descriptions(&attributes);
 
-    let viewport = [vk::Viewport::builder()
-        .x(0.)
-        .y(0.)
-        .width(extent.width as f32)
-        .height(extent.height as f32)
-        .min_depth(0.)
-        .max_depth(1.)
-        .build()];
-
-    let scissor = [vk::Rect2D {
-        offset: Default::default(),
-        extent,
-    }];
-
     let depth = vk::PipelineDepthStencilStateCreateInfo::builder()
         .depth_test_enable(true)
-        .depth_write_enable(true)
-        .depth_compare_op(vk::CompareOp::LESS)
+        .depth_write_enable(definition.depth_write && definition.blend == BlendMode::Opaque)
+        .depth_compare_op(match definition.depth_compare {
+            DepthCompare::Less => vk::CompareOp::LESS,
+            DepthCompare::LessOrEqual => vk::CompareOp::LESS_OR_EQUAL,
+        })
         .depth_bounds_test_enable(false)
         .stencil_test_enable(false)
         .min_depth_bounds(0.)
         .max_depth_bounds(1.);
 
     let viewport = vk::PipelineViewportStateCreateInfo::builder()
-        .viewports(&viewport)
-        .scissors(&scissor);
+        .viewport_count(1)
+        .scissor_count(1);
+    let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+    let dynamic = vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(&dynamic_states);
 
     let input_asm = vk::PipelineInputAssemblyStateCreateInfo::builder()
         .primitive_restart_enable(false)
@@ -119,34 +151,53 @@ pub fn create_pipeline(
         .depth_clamp_enable(false)
         .polygon_mode(vk::PolygonMode::FILL)
         .line_width(1.)
-        .cull_mode(vk::CullModeFlags::BACK)
-        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-        .depth_bias_enable(false);
+        .cull_mode(match definition.cull_mode {
+            CullMode::Back => vk::CullModeFlags::BACK,
+            CullMode::Front => vk::CullModeFlags::FRONT,
+            CullMode::None => vk::CullModeFlags::NONE,
+        })
+        .front_face(match definition.front_face {
+            FrontFace::Ccw => vk::FrontFace::COUNTER_CLOCKWISE,
+            FrontFace::Cw => vk::FrontFace::CLOCKWISE,
+        })
+        .depth_bias_enable(definition.depth_bias.is_some());
+    let raster = if let Some((constant, slope)) = definition.depth_bias {
+        raster
+            .depth_bias_constant_factor(constant)
+            .depth_bias_slope_factor(slope)
+            .depth_bias_clamp(0.)
+    } else {
+        raster
+    };
 
     let multisample = vk::PipelineMultisampleStateCreateInfo::builder()
         .sample_shading_enable(false)
-        .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+        .rasterization_samples(samples)
         .min_sample_shading(1.)
         .alpha_to_coverage_enable(false)
         .alpha_to_one_enable(false);
 
-    let color_attachment = [vk::PipelineColorBlendAttachmentState::builder()
-        .color_write_mask(vk::ColorComponentFlags::RGBA)
-        .blend_enable(false)
-        .build()]; // todo alpha blend
+    let color_attachment = fmts
+        .iter()
+        .map(|&fmt| color_blend_attachment(definition.blend, fmt))
+        .collect_vec();
 
     let color = vk::PipelineColorBlendStateCreateInfo::builder()
         .logic_op_enable(false)
         .attachments(&color_attachment);
 
-    let desc = [global_descriptor_layout];
-    let layout = create_layout(module_data.iter().map(|it| &it.0), device, &desc)?;
+    let set_layouts = create_set_layouts(module_data.iter().map(|it| &it.0), device, 1)?;
+    let desc = std::iter::once(global_descriptor_layout)
+        .chain(set_layouts.iter().copied())
+        .collect_vec();
+    let layout = create_layout(device, &desc)?;
 
     let create_info = [vk::GraphicsPipelineCreateInfo::builder()
         .push_next(&mut render_info)
         .stages(&stages)
         .vertex_input_state(&vert_input)
         .viewport_state(&viewport)
+        .dynamic_state(&dynamic)
         .input_assembly_state(&input_asm)
         .rasterization_state(&raster)
         .render_pass(vk::RenderPass::null())
@@ -156,31 +207,329 @@ pub fn create_pipeline(
         .depth_stencil_state(&depth)
         .build()];
 
-    let cache = CACHE.get_or_try_init(|| load_cache(device))?;
-    match unsafe { device.create_graphics_pipelines(*cache, &create_info, None) } {
-        Ok(pipelines) => Ok((pipelines[0], layout)),
+    let cache = get_cache(device)?;
+    match unsafe { device.create_graphics_pipelines(cache, &create_info, None) } {
+        Ok(pipelines) => Ok((pipelines[0], layout, set_layouts)),
         Err((_, e)) => Err(e.into()),
     }
 }
 
-fn create_layout<'a, I>(iter: I, device: &ash::Device, set_layouts: &[vk::DescriptorSetLayout]) -> VkResult<vk::PipelineLayout>
-    where
-        I: Iterator<Item=&'a spirv_reflect::ShaderModule>,
-{
-    let ranges = [vk::PushConstantRange::builder()
-        .size(std::mem::size_of::<nalgebra::Matrix4<f32>>() as u32)
-        .offset(0)
-        .stage_flags(vk::ShaderStageFlags::VERTEX)
+/// Whether a shader declares the binding `binding` of set `set`,
+/// fails if it is declared as anything but a combined image sampler
+pub fn declares_sampler(
+    module_data: &[u8],
+    (set, binding): (u32, u32),
+) -> Result<bool, Box<dyn Error>> {
+    let module = spirv_reflect::create_shader_module(module_data)?;
+    for declared in module.enumerate_descriptor_bindings(None)? {
+        if (declared.set, declared.binding) != (set, binding) {
+            continue;
+        }
+        return match get_descriptor_type(declared.descriptor_type)? {
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER => Ok(true),
+            ty => Err(format!(
+                "Binding {binding} of set {set} is a {ty:?} instead of a combined image sampler"
+            )
+            .into()),
+        };
+    }
+    Ok(false)
+}
+
+/// Sets the dynamic viewport and scissor of the graphics pipelines to cover `extent`.
+///
+/// Dynamic state isn't inherited by secondary command buffers,
+/// so every command buffer drawing with a pipeline has to set it
+pub unsafe fn set_viewport(device: &ash::Device, cmd: vk::CommandBuffer, extent: vk::Extent2D) {
+    let viewport = [vk::Viewport::builder()
+        .x(0.)
+        .y(0.)
+        .width(extent.width as f32)
+        .height(extent.height as f32)
+        .min_depth(0.)
+        .max_depth(1.)
+        .build()];
+    let scissor = [vk::Rect2D {
+        offset: Default::default(),
+        extent,
+    }];
+    device.cmd_set_viewport(cmd, 0, &viewport);
+    device.cmd_set_scissor(cmd, 0, &scissor);
+}
+
+/// Creates a compute pipeline from a single compute shader.
+///
+/// Compute shaders don't see the global set, every set they declare is created from
+/// the reflection data and returned along with the pipeline.
+/// Fails if the shader isn't a compute shader or reads push constants
+pub fn create_compute_pipeline(
+    device: &ash::Device,
+    module_data: Vec<u8>,
+) -> Result<PipelineParts, Box<dyn Error>> {
+    let reflect = spirv_reflect::create_shader_module(&module_data)?;
+    if get_stage(&reflect)? != vk::ShaderStageFlags::COMPUTE {
+        return Err("Compute pipelines need a compute shader".into());
+    }
+    if !reflect.enumerate_push_constant_blocks(None)?.is_empty() {
+        return Err("Compute shaders can't read push constants".into());
+    }
+    let code = ash::util::read_spv(&mut Cursor::new(module_data))?;
+    let create_info = vk::ShaderModuleCreateInfo::builder().code(&code);
+    let module = unsafe { device.create_shader_module(&create_info, None)? };
+    defer! {
+        unsafe { device.destroy_shader_module(module, None) };
+    }
+
+    let set_layouts = create_set_layouts(std::iter::once(&reflect), device, 0)?;
+    let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(&set_layouts);
+    let layout = unsafe { device.create_pipeline_layout(&layout_info, None)? };
+    let name = CString::new("main").unwrap();
+    let stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(module)
+        .name(&name)
+        .build();
+    let create_info = [vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(layout)
         .build()];
 
-    let create_info = vk::PipelineLayoutCreateInfo::builder().push_constant_ranges(&ranges).set_layouts(set_layouts);
-    //todo descriptor sets from reflection data
+    let cache = get_cache(device)?;
+    match unsafe { device.create_compute_pipelines(cache, &create_info, None) } {
+        Ok(pipelines) => Ok((pipelines[0], layout, set_layouts)),
+        Err((_, e)) => Err(e.into()),
+    }
+}
+
+/// The pipeline cache shared by all pipelines, loaded by the first pipeline created
+fn get_cache(device: &ash::Device) -> VkResult<vk::PipelineCache> {
+    let mut cache = CACHE.lock();
+    match *cache {
+        Some(cache) => Ok(cache),
+        None => Ok(*cache.insert(load_cache(device)?)),
+    }
+}
+
+fn get_stage(module: &spirv_reflect::ShaderModule) -> Result<vk::ShaderStageFlags, &'static str> {
+    match module.get_shader_stage() {
+        ReflectShaderStageFlags::VERTEX => Ok(vk::ShaderStageFlags::VERTEX),
+        ReflectShaderStageFlags::FRAGMENT => Ok(vk::ShaderStageFlags::FRAGMENT),
+        ReflectShaderStageFlags::GEOMETRY => Ok(vk::ShaderStageFlags::GEOMETRY),
+        ReflectShaderStageFlags::TESSELLATION_CONTROL => {
+            Ok(vk::ShaderStageFlags::TESSELLATION_CONTROL)
+        }
+        ReflectShaderStageFlags::TESSELLATION_EVALUATION => {
+            Ok(vk::ShaderStageFlags::TESSELLATION_EVALUATION)
+        }
+        ReflectShaderStageFlags::COMPUTE => Ok(vk::ShaderStageFlags::COMPUTE),
+        _ => Err("Invalid stage flags"),
+    }
+}
+
+/// Checks that a shader only reads push constants and global set bindings the engine provides,
+/// so shaders written against a different layout fail to load instead of reading garbage
+fn check_interface(module: &spirv_reflect::ShaderModule) -> Result<(), Box<dyn Error>> {
+    let stage = get_stage(module)?;
+    let ranges = push_constant_ranges();
+    for block in module.enumerate_push_constant_blocks(None)? {
+        for member in &block.members {
+            let (start, end) = (member.offset, member.offset + member.size);
+            if !in_push_constant_range(stage, start, end, &ranges) {
+                return Err(format!(
+                    "Push constant {} of the {stage:?} shader uses bytes {start}..{end}, \
+                     which the engine does not push to that stage",
+                    member.name
+                )
+                .into());
+            }
+        }
+    }
+
+    let globals = global_bindings();
+    for binding in module.enumerate_descriptor_bindings(None)? {
+        if binding.set != 0 {
+            continue;
+        }
+        let descriptor_type = get_descriptor_type(binding.descriptor_type)?;
+        let matches = globals.iter().any(|global| {
+            global.binding == binding.binding
+                && global.descriptor_type == descriptor_type
+                && global.stage_flags.contains(stage)
+        });
+        if !matches {
+            return Err(format!(
+                "Global binding {} of the {stage:?} shader is a {descriptor_type:?}, \
+                 which the engine does not bind there for that stage",
+                binding.binding
+            )
+            .into());
+        }
+        let size = if binding.binding == UNIFORM_BINDING {
+            ENVIRONMENT_UBO_SIZE
+        } else {
+            UBO_SIZE
+        };
+        if descriptor_type == vk::DescriptorType::UNIFORM_BUFFER && binding.block.size > size {
+            return Err(format!(
+                "Global uniform buffer {} of the {stage:?} shader is {} bytes, \
+                 larger than the engine's {size} bytes",
+                binding.binding, binding.block.size
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Whether the push constant bytes `start..end` are pushed to `stage`
+fn in_push_constant_range(
+    stage: vk::ShaderStageFlags,
+    start: u32,
+    end: u32,
+    ranges: &[vk::PushConstantRange],
+) -> bool {
+    ranges.iter().any(|range| {
+        range.stage_flags.contains(stage)
+            && start >= range.offset
+            && end <= range.offset + range.size
+    })
+}
+
+/// Descriptor bindings by set and binding number
+type SetBindings = BTreeMap<u32, BTreeMap<u32, vk::DescriptorSetLayoutBinding>>;
+
+/// Creates the layouts of the descriptor sets from `first_set` on,
+/// materials start after the global set 0.
+///
+/// Sets are bound contiguously so unused set numbers get empty layouts
+fn create_set_layouts<'a, I>(
+    modules: I,
+    device: &ash::Device,
+    first_set: u32,
+) -> Result<Vec<vk::DescriptorSetLayout>, Box<dyn Error>>
+where
+    I: Iterator<Item = &'a spirv_reflect::ShaderModule>,
+{
+    let sets = reflect_set_bindings(modules, first_set)?;
+    let last_set = match sets.keys().next_back() {
+        Some(set) => *set,
+        None => return Ok(Vec::new()),
+    };
+    (first_set..=last_set)
+        .map(|set| -> Result<_, Box<dyn Error>> {
+            let bindings = sets
+                .get(&set)
+                .map(|bindings| bindings.values().copied().collect_vec())
+                .unwrap_or_default();
+            let create_info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
+            Ok(unsafe { device.create_descriptor_set_layout(&create_info, None)? })
+        })
+        .collect()
+}
+
+/// Reflects the bindings the shaders declare in every set from `first_set` on,
+/// combining the stage flags of bindings used by several stages
+fn reflect_set_bindings<'a, I>(modules: I, first_set: u32) -> Result<SetBindings, Box<dyn Error>>
+where
+    I: Iterator<Item = &'a spirv_reflect::ShaderModule>,
+{
+    let mut sets = SetBindings::new();
+    for module in modules {
+        let stage = get_stage(module)?;
+        for binding in module.enumerate_descriptor_bindings(None)? {
+            if binding.set < first_set {
+                continue;
+            }
+            let descriptor_type = get_descriptor_type(binding.descriptor_type)?;
+            sets.entry(binding.set)
+                .or_default()
+                .entry(binding.binding)
+                .and_modify(|it| it.stage_flags |= stage)
+                .or_insert_with(|| {
+                    vk::DescriptorSetLayoutBinding::builder()
+                        .binding(binding.binding)
+                        .descriptor_type(descriptor_type)
+                        .descriptor_count(binding.count.max(1))
+                        .stage_flags(stage)
+                        .build()
+                });
+        }
+    }
+    Ok(sets)
+}
+
+fn get_descriptor_type(
+    descriptor_type: ReflectDescriptorType,
+) -> Result<vk::DescriptorType, &'static str> {
+    match descriptor_type {
+        ReflectDescriptorType::Sampler => Ok(vk::DescriptorType::SAMPLER),
+        ReflectDescriptorType::CombinedImageSampler => {
+            Ok(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        }
+        ReflectDescriptorType::SampledImage => Ok(vk::DescriptorType::SAMPLED_IMAGE),
+        ReflectDescriptorType::StorageImage => Ok(vk::DescriptorType::STORAGE_IMAGE),
+        ReflectDescriptorType::UniformBuffer => Ok(vk::DescriptorType::UNIFORM_BUFFER),
+        ReflectDescriptorType::StorageBuffer => Ok(vk::DescriptorType::STORAGE_BUFFER),
+        _ => Err("Unsupported descriptor type"),
+    }
+}
+
+/// Packs specialization constants into map entries and the data they point into.
+///
+/// Every stage shares the same constants, vulkan ignores entries a shader does not declare
+fn specialization_data(
+    constants: &[(u32, SpecializationValue)],
+) -> Result<(Vec<vk::SpecializationMapEntry>, Vec<u8>), Box<dyn Error>> {
+    if !constants.iter().map(|(id, _)| id).all_unique() {
+        return Err("Duplicate specialization constant id".into());
+    }
+    let entries = (0u32..)
+        .zip(constants)
+        .map(|(index, (id, _))| vk::SpecializationMapEntry {
+            constant_id: *id,
+            offset: index * 4,
+            size: 4,
+        })
+        .collect();
+    let data = constants
+        .iter()
+        .flat_map(|(_, value)| value.to_ne_bytes())
+        .collect();
+    Ok((entries, data))
+}
+
+/// Creates the pipeline layout of the global set followed by the material's own `set_layouts`,
+/// with the push constants of every draw
+fn create_layout(
+    device: &ash::Device,
+    set_layouts: &[vk::DescriptorSetLayout],
+) -> VkResult<vk::PipelineLayout> {
+    let ranges = push_constant_ranges();
+    let create_info = vk::PipelineLayoutCreateInfo::builder()
+        .push_constant_ranges(&ranges)
+        .set_layouts(set_layouts);
     unsafe { device.create_pipeline_layout(&create_info, None) }
 }
 
+/// Push constants of every draw, the model and normal matrix followed by the object id
+fn push_constant_ranges() -> [vk::PushConstantRange; 2] {
+    [
+        vk::PushConstantRange::builder()
+            .size(std::mem::size_of::<Transforms>() as u32)
+            .offset(0)
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .build(),
+        vk::PushConstantRange::builder()
+            .size(std::mem::size_of::<[u32; 2]>() as u32)
+            .offset(OBJECT_ID_OFFSET)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build(),
+    ]
+}
+
 /// Loads the pipeline cache from a file or creates a new empty cache if the file could not be read
 fn load_cache(device: &ash::Device) -> VkResult<vk::PipelineCache> {
-    let path = DIRS.project.cache_dir().join("pipeline_cache");
+    let path = cache_path();
     if let Ok(data) = fs::read(&path) {
         let create_info = vk::PipelineCacheCreateInfo::builder().initial_data(&data);
         info!("Loading pipeline cache from {}", path.to_string_lossy());
@@ -191,21 +540,196 @@ fn load_cache(device: &ash::Device) -> VkResult<vk::PipelineCache> {
     }
 }
 
+fn cache_path() -> PathBuf {
+    DIRS.project.cache_dir().join("pipeline_cache")
+}
+
+/// Path of the pipeline cache file, the size in bytes of the file and of the cache's current data.
+///
+/// The file size is None if there is no file, the data size if no pipeline was created yet
+pub fn cache_info(device: &ash::Device) -> (PathBuf, Option<u64>, Option<usize>) {
+    let path = cache_path();
+    let file_size = fs::metadata(&path).ok().map(|metadata| metadata.len());
+    let cache = *CACHE.lock();
+    let data_size = cache
+        .and_then(|cache| unsafe { device.get_pipeline_cache_data(cache) }.ok())
+        .map(|data| data.len());
+    (path, file_size, data_size)
+}
+
 /// Saves the pipeline cache to disk and then destroys it.
 ///
 /// Does nothing if the cache was never initialized
 pub fn cleanup_cache(device: &ash::Device) {
-    if let Some(cache) = CACHE.get() {
+    if let Some(cache) = CACHE.lock().take() {
         unsafe {
-            if let Ok(data) = device.get_pipeline_cache_data(*cache) {
-                let path = DIRS.project.cache_dir().join("pipeline_cache");
+            if let Ok(data) = device.get_pipeline_cache_data(cache) {
+                let path = cache_path();
                 if let Err(e) = fs::write(&path, &data) {
                     error!("Failed to write pipeline cache to {path:?}, Error: {e}");
                 } else {
                     info!("Saved pipeline cache to {path:?}");
                 }
             }
-            device.destroy_pipeline_cache(*cache, None);
+            device.destroy_pipeline_cache(cache, None);
+        }
+    }
+}
+
+/// Blend state of an attachment, integer attachments like the object ids can't be blended
+fn color_blend_attachment(
+    blend: BlendMode,
+    fmt: vk::Format,
+) -> vk::PipelineColorBlendAttachmentState {
+    let state = vk::PipelineColorBlendAttachmentState::builder()
+        .color_write_mask(vk::ColorComponentFlags::RGBA);
+    if blend == BlendMode::Opaque || fmt == OBJECT_ID_FORMAT {
+        return state.blend_enable(false).build();
+    }
+    state
+        .blend_enable(true)
+        .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+        .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ONE)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+        .alpha_blend_op(vk::BlendOp::ADD)
+        .build()
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+    use itertools::Itertools;
+
+    use crate::materials::{BlendMode, SpecializationValue};
+    use crate::vulkan::engine::pipeline::{
+        color_blend_attachment, in_push_constant_range, push_constant_ranges, reflect_set_bindings,
+        specialization_data,
+    };
+    use crate::vulkan::engine::{Transforms, OBJECT_ID_FORMAT, OBJECT_ID_OFFSET};
+
+    #[test]
+    fn packs_specialization_constants() {
+        let constants = [
+            (3, SpecializationValue::Bool(true)),
+            (1, SpecializationValue::Float(0.5)),
+        ];
+        let (entries, data) = specialization_data(&constants).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[1].constant_id, entries[1].offset), (1, 4));
+        assert_eq!(&data[..4], &1u32.to_ne_bytes());
+        assert_eq!(&data[4..], &0.5f32.to_ne_bytes());
+        assert!(specialization_data(&[constants[0], constants[0]]).is_err());
+    }
+
+    #[test]
+    fn push_constants_outside_of_stage_range() {
+        let ranges = push_constant_ranges();
+        let vertex = vk::ShaderStageFlags::VERTEX;
+        let fragment = vk::ShaderStageFlags::FRAGMENT;
+        let geometry = vk::ShaderStageFlags::GEOMETRY;
+        assert!(in_push_constant_range(vertex, 0, 64, &ranges));
+        assert!(in_push_constant_range(vertex, 64, 112, &ranges));
+        assert!(!in_push_constant_range(vertex, 0, 128, &ranges));
+        assert!(!in_push_constant_range(vertex, 112, 120, &ranges));
+        assert!(in_push_constant_range(fragment, 112, 120, &ranges));
+        assert!(!in_push_constant_range(fragment, 0, 112, &ranges));
+        assert!(!in_push_constant_range(geometry, 0, 4, &ranges));
+    }
+
+    #[test]
+    fn push_constant_ranges_match_the_pushed_data() {
+        let [vertex, fragment] = push_constant_ranges();
+        // the model matrix and the std430 mat3 of the vertex shaders' push constant block
+        assert_eq!(vertex.size, 64 + 48);
+        assert_eq!(vertex.size as usize, std::mem::size_of::<Transforms>());
+        assert_eq!(fragment.offset, vertex.offset + vertex.size);
+        assert_eq!(fragment.offset, OBJECT_ID_OFFSET);
+        assert_eq!(fragment.size as usize, std::mem::size_of::<[u32; 2]>());
+        // the least every device supports
+        assert!(fragment.offset + fragment.size <= 128);
+    }
+
+    #[test]
+    fn only_color_attachments_are_blended() {
+        let color = vk::Format::B8G8R8A8_SRGB;
+        let blended = color_blend_attachment(BlendMode::Alpha, color);
+        assert_eq!(blended.blend_enable, vk::TRUE);
+        assert_eq!(blended.src_color_blend_factor, vk::BlendFactor::SRC_ALPHA);
+        assert_eq!(
+            blended.dst_color_blend_factor,
+            vk::BlendFactor::ONE_MINUS_SRC_ALPHA
+        );
+        let ids = color_blend_attachment(BlendMode::Alpha, OBJECT_ID_FORMAT);
+        assert_eq!(ids.blend_enable, vk::FALSE);
+        let opaque = color_blend_attachment(BlendMode::Opaque, color);
+        assert_eq!(opaque.blend_enable, vk::FALSE);
+    }
+
+    const VERTEX: u32 = 0;
+    const FRAGMENT: u32 = 4;
+
+    /// Assembles a shader of the spir-v execution model `stage` that loads
+    /// a `sampler2D` at `set` binding 0
+    fn sampler_shader(stage: u32, set: u32) -> spirv_reflect::ShaderModule {
+        let op = |opcode: u32, operands: &[u32]| {
+            std::iter::once(((operands.len() as u32 + 1) << 16) | opcode)
+                .chain(operands.iter().copied())
+                .collect_vec()
+        };
+        // "main" and its null terminator
+        let main = [u32::from_le_bytes(*b"main"), 0];
+        let mut words = vec![0x07230203, 0x00010000, 0, 11, 0];
+        words.extend(op(17, &[1])); // OpCapability Shader
+        words.extend(op(14, &[0, 1])); // OpMemoryModel Logical GLSL450
+        words.extend(op(15, &[stage, 1, main[0], main[1]])); // OpEntryPoint %1 "main"
+        if stage == FRAGMENT {
+            words.extend(op(16, &[1, 7])); // OpExecutionMode %1 OriginUpperLeft
         }
+        words.extend(op(71, &[7, 34, set])); // OpDecorate %7 DescriptorSet
+        words.extend(op(71, &[7, 33, 0])); // OpDecorate %7 Binding
+        words.extend(op(19, &[2])); // %2 = OpTypeVoid
+        words.extend(op(33, &[3, 2])); // %3 = OpTypeFunction %2
+        words.extend(op(22, &[4, 32])); // %4 = OpTypeFloat 32
+        words.extend(op(25, &[5, 4, 1, 0, 0, 0, 1, 0])); // %5 = OpTypeImage %4 2D sampled
+        words.extend(op(27, &[6, 5])); // %6 = OpTypeSampledImage %5
+        words.extend(op(32, &[8, 0, 6])); // %8 = OpTypePointer UniformConstant %6
+        words.extend(op(59, &[8, 7, 0])); // %7 = OpVariable %8 UniformConstant
+        words.extend(op(54, &[2, 1, 0, 3])); // %1 = OpFunction %2 None %3
+        words.extend(op(248, &[9])); // OpLabel
+        words.extend(op(61, &[6, 10, 7])); // %10 = OpLoad %6 %7
+        words.extend(op(253, &[])); // OpReturn
+        words.extend(op(56, &[])); // OpFunctionEnd
+        let bytes = words.iter().flat_map(|it| it.to_le_bytes()).collect_vec();
+        spirv_reflect::create_shader_module(&bytes).unwrap()
+    }
+
+    #[test]
+    fn material_sets_are_reflected() {
+        let fragment = sampler_shader(FRAGMENT, 1);
+        let sets = reflect_set_bindings([&fragment].into_iter(), 1).unwrap();
+        assert_eq!(sets.keys().copied().collect_vec(), [1]);
+        let binding = sets[&1][&0];
+        assert_eq!(binding.binding, 0);
+        assert_eq!(
+            binding.descriptor_type,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+        );
+        assert_eq!(binding.descriptor_count, 1);
+        assert_eq!(binding.stage_flags, vk::ShaderStageFlags::FRAGMENT);
+
+        let vertex = sampler_shader(VERTEX, 1);
+        let sets = reflect_set_bindings([&vertex, &fragment].into_iter(), 1).unwrap();
+        assert_eq!(
+            sets[&1][&0].stage_flags,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT
+        );
+        // the global set is created by the engine
+        let global = sampler_shader(FRAGMENT, 0);
+        let sets = reflect_set_bindings([&global].into_iter(), 1).unwrap();
+        assert!(sets.is_empty());
+        let sets = reflect_set_bindings([&global].into_iter(), 0).unwrap();
+        assert_eq!(sets.keys().copied().collect_vec(), [0]);
     }
 }
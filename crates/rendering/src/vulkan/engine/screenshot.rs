@@ -0,0 +1,64 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+
+use crate::FrameCapture;
+
+/// Swapchain formats whose frames can be read back as 8 bit rgba
+pub(super) const CAPTURE_FORMATS: [vk::Format; 4] = [
+    vk::Format::B8G8R8A8_SRGB,
+    vk::Format::B8G8R8A8_UNORM,
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::R8G8B8A8_UNORM,
+];
+
+/// Swaps the red and blue channel of tightly packed 8 bit pixels,
+/// turning the bgra pixels of `B8G8R8A8` swapchain images into rgba
+pub(super) fn bgra_to_rgba(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+}
+
+/// Writes a captured frame to `path` as an 8 bit rgb png,
+/// alpha is dropped since presented frames are opaque
+pub(super) fn write_png(path: &Path, capture: &FrameCapture) -> Result<()> {
+    let expected = capture.width as usize * capture.height as usize * 4;
+    if capture.pixels.len() != expected {
+        return Err(anyhow!(
+            "Capture of {}x{} pixels has {} bytes instead of {expected}",
+            capture.width,
+            capture.height,
+            capture.pixels.len()
+        ));
+    }
+    let rgb = capture
+        .pixels
+        .chunks_exact(4)
+        .flat_map(|pixel| &pixel[..3])
+        .copied()
+        .collect::<Vec<_>>();
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, capture.width, capture.height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&rgb)?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vulkan::engine::screenshot::bgra_to_rgba;
+
+    #[test]
+    fn bgra_is_swizzled_to_rgba() {
+        let mut pixels = [10, 20, 30, 255, 1, 2, 3, 4];
+        bgra_to_rgba(&mut pixels);
+        assert_eq!(pixels, [30, 20, 10, 255, 3, 2, 1, 4]);
+    }
+}
@@ -0,0 +1,294 @@
+use std::fs;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+use nalgebra::{Matrix4, Orthographic3, Point3, Vector3};
+use vk_mem::Allocator;
+
+use engine::filesystem::DIRS;
+
+use crate::materials::MaterialDefinition;
+use crate::vulkan::engine::alloc::Image;
+use crate::vulkan::engine::pipeline::{create_pipeline, set_viewport};
+use crate::vulkan::engine::COORDINATE_CORRECTION;
+use crate::vulkan::texture::Texture;
+use crate::{CoordinateSystem, Mesh};
+
+/// Half the width of the area around the origin that is covered by the shadow map
+const SHADOW_EXTENT: f32 = 20.;
+/// Distance from the origin the light's view is placed at
+const LIGHT_DISTANCE: f32 = 50.;
+/// Depth bias applied to shadow casters to avoid shadow acne
+const SHADOW_DEPTH_BIAS: (f32, f32) = (1.25, 1.75);
+
+/// Depth only render target rendered from the directional light's point of view
+pub(super) struct ShadowMap {
+    pub(super) texture: Texture,
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    extent: vk::Extent2D,
+    light_space: Matrix4<f32>,
+    coordinates: CoordinateSystem,
+    device: Arc<ash::Device>,
+}
+
+impl ShadowMap {
+    #[allow(clippy::too_many_arguments)]
+    pub(super) unsafe fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: Arc<ash::Device>,
+        allocator: Arc<Allocator>,
+        resolution: u32,
+        global_descriptor_layout: vk::DescriptorSetLayout,
+        coordinates: CoordinateSystem,
+        light_direction: Vector3<f32>,
+    ) -> Result<Self> {
+        let format = get_shadow_format(instance, physical_device)?;
+        let extent = vk::Extent2D {
+            width: resolution.max(1),
+            height: resolution.max(1),
+        };
+        let create_info = vk::ImageCreateInfo::builder()
+            .format(format)
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(vk::Extent3D::from(extent))
+            .mip_levels(1)
+            .array_layers(1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .samples(vk::SampleCountFlags::TYPE_1);
+        let alloc_info = vk_mem::AllocationCreateInfo {
+            usage: vk_mem::MemoryUsage::GpuOnly,
+            required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ..Default::default()
+        };
+        let image = Image::new(&create_info, &alloc_info, allocator)?;
+        let texture = Texture::from_depth_image(image, format, device.clone())?;
+
+        let data = vec![fs::read(DIRS.asset.join("shaders").join("shadow.vert.spv"))?];
+        let definition = MaterialDefinition {
+            vertex_shader: "shadow.vert.spv".into(),
+            depth_bias: Some(SHADOW_DEPTH_BIAS),
+            ..Default::default()
+        };
+        let (pipeline, layout, set_layouts) = create_pipeline(
+            &device,
+            None,
+            format,
+            vk::SampleCountFlags::TYPE_1,
+            data,
+            global_descriptor_layout,
+            &definition,
+        )
+        .map_err(|e| anyhow!("Failed to create shadow pipeline: {e}"))?;
+        debug_assert!(set_layouts.is_empty(), "Shadow shaders only use the global set");
+
+        Ok(ShadowMap {
+            texture,
+            pipeline,
+            layout,
+            extent,
+            light_space: light_space_matrix(coordinates, light_direction),
+            coordinates,
+            device,
+        })
+    }
+
+    /// View projection matrix of the light, used both to render and to sample the shadow map
+    #[inline]
+    pub(super) fn light_space(&self) -> Matrix4<f32> {
+        self.light_space
+    }
+
+    /// Points the light's view along `direction`, which must not be zero
+    pub(super) fn set_direction(&mut self, direction: Vector3<f32>) {
+        self.light_space = light_space_matrix(self.coordinates, direction);
+    }
+
+    /// Records the depth only pass for all shadow casters,
+    /// leaving the shadow map ready to be sampled by the fragment shaders.
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd`: primary command buffer of the current frame, outside of any rendering instance
+    /// * `global_descriptor`: descriptor set containing the frame's ubo
+    /// * `draws`: meshes and model matrices of the shadow casters
+    pub(super) unsafe fn record(
+        &self,
+        cmd: vk::CommandBuffer,
+        global_descriptor: vk::DescriptorSet,
+        draws: &[(Arc<Mesh>, Matrix4<f32>)],
+    ) {
+        let device = &self.device;
+        // the previous frame may still be sampling the shadow map
+        let barrier = [vk::ImageMemoryBarrier::builder()
+            .dst_access_mask(
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+            )
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .image(**self.texture.image)
+            .subresource_range(subresource_range())
+            .build()];
+        device.cmd_pipeline_barrier(
+            cmd,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &barrier,
+        );
+
+        let depth_attachment = vk::RenderingAttachmentInfo::builder()
+            .image_view(self.texture.view)
+            .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.,
+                    stencil: 0,
+                },
+            });
+        let rendering_info = vk::RenderingInfo::builder()
+            .layer_count(1)
+            .depth_attachment(&depth_attachment)
+            .render_area(vk::Rect2D {
+                offset: Default::default(),
+                extent: self.extent,
+            });
+        device.cmd_begin_rendering(cmd, &rendering_info);
+        set_viewport(device, cmd, self.extent);
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_bind_descriptor_sets(
+            cmd,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.layout,
+            0,
+            &[global_descriptor],
+            &[],
+        );
+        let mut last_mesh = std::ptr::null();
+        for (mesh, transform) in draws {
+            if !std::ptr::eq(mesh.as_ref(), last_mesh) {
+                last_mesh = mesh.as_ref();
+                mesh.bind(device, cmd);
+            }
+            device.cmd_push_constants(
+                cmd,
+                self.layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                std::slice::from_raw_parts(
+                    transform.as_ptr() as *const u8,
+                    std::mem::size_of::<Matrix4<f32>>(),
+                ),
+            );
+            device.cmd_draw_indexed(cmd, mesh.get_index_count(), 1, 0, 0, 0);
+        }
+        device.cmd_end_rendering(cmd);
+
+        let barrier = [vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .old_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image(**self.texture.image)
+            .subresource_range(subresource_range())
+            .build()];
+        device.cmd_pipeline_barrier(
+            cmd,
+            vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &barrier,
+        );
+    }
+}
+
+impl Drop for ShadowMap {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+/// Orthographic view projection of the directional light looking at the origin along `direction`,
+/// following the world's conventions so shadow casters keep their winding
+fn light_space_matrix(coordinates: CoordinateSystem, direction: Vector3<f32>) -> Matrix4<f32> {
+    let mut direction = direction.normalize();
+    // the view has no defined roll when looking straight along the up axis
+    if direction.cross(&coordinates.up()).norm_squared() < 1e-6 {
+        direction = (direction + Vector3::x() * 1e-2).normalize();
+    }
+    let eye = Point3::from(-direction * LIGHT_DISTANCE);
+    let view = coordinates.look_at(&eye, &Point3::origin());
+    let projection = Orthographic3::new(
+        -SHADOW_EXTENT,
+        SHADOW_EXTENT,
+        -SHADOW_EXTENT,
+        SHADOW_EXTENT,
+        0.1,
+        LIGHT_DISTANCE * 2.,
+    );
+    *COORDINATE_CORRECTION
+        * projection.to_homogeneous()
+        * coordinates.view_correction()
+        * view.to_homogeneous()
+}
+
+/// Finds a depth only format that can be both rendered to and sampled
+unsafe fn get_shadow_format(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> Result<vk::Format> {
+    let required =
+        vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE;
+    [vk::Format::D32_SFLOAT, vk::Format::D16_UNORM]
+        .into_iter()
+        .find(|fmt| {
+            instance
+                .get_physical_device_format_properties(physical_device, *fmt)
+                .optimal_tiling_features
+                .contains(required)
+        })
+        .ok_or_else(|| anyhow!("Failed to find a valid shadow map format"))
+}
+
+fn subresource_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::DEPTH,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nalgebra::Vector3;
+
+    use crate::vulkan::engine::shadow::light_space_matrix;
+    use crate::CoordinateSystem;
+
+    #[test]
+    fn light_shining_straight_down_has_a_view() {
+        let coordinates = CoordinateSystem::default();
+        let matrix = light_space_matrix(coordinates, -coordinates.up());
+        assert!(matrix.iter().all(|it| it.is_finite()));
+        let matrix = light_space_matrix(coordinates, Vector3::new(0.3, -1., 0.2));
+        assert!(matrix.iter().all(|it| it.is_finite()));
+    }
+}
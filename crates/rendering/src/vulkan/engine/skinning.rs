@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use ash::prelude::VkResult;
+use ash::vk;
+use nalgebra::Matrix4;
+use parking_lot::Mutex;
+use vk_mem::Allocator;
+
+use crate::vulkan::engine::alloc::StorageBuffer;
+use crate::vulkan::engine::deletion::{self, Resource};
+use crate::Material;
+
+/// Skeletons whose bone sets fit into one descriptor pool, per frame in flight
+const SKELETONS_PER_POOL: u32 = 64;
+
+/// Bone matrices of one skinned instance.
+///
+/// Every frame in flight has its own storage buffer and descriptor set,
+/// so the bones can be updated each frame without waiting on the gpu.
+/// Skinned meshes are not drawn into the shadow map yet
+pub struct Skeleton {
+    device: Arc<ash::Device>,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    buffers: Mutex<Vec<StorageBuffer>>,
+    joint_count: usize,
+}
+
+impl Skeleton {
+    /// Creates a skeleton whose bone buffers are bound to set 1 of the skinned `material`,
+    /// all bones start out as identity matrices
+    pub(super) fn new(
+        material: &Material,
+        joint_count: usize,
+        frames_in_flight: usize,
+        allocator: Arc<Allocator>,
+        pools: &BonePools,
+    ) -> Result<Self> {
+        if !material.skinned {
+            return Err(anyhow!("Skeletons require a skinned material"));
+        }
+        if joint_count == 0 {
+            return Err(anyhow!("Skeletons need at least one joint"));
+        }
+        let layout = *material
+            .descriptor_layouts
+            .first()
+            .ok_or_else(|| anyhow!("Skinned material has no bone descriptor set"))?;
+
+        let size = (joint_count * std::mem::size_of::<Matrix4<f32>>()) as vk::DeviceSize;
+        let identity = vec![Matrix4::<f32>::identity(); joint_count];
+        let mut buffers = Vec::with_capacity(frames_in_flight);
+        for _ in 0..frames_in_flight {
+            let mut buffer = StorageBuffer::new(material.device.clone(), allocator.clone(), size)?;
+            buffer.write(0, &identity);
+            buffers.push(buffer);
+        }
+
+        let (descriptor_pool, descriptor_sets) = pools.allocate(&vec![layout; frames_in_flight])?;
+        for (set, buffer) in descriptor_sets.iter().zip(&buffers) {
+            let buffer_info = [vk::DescriptorBufferInfo::builder()
+                .buffer(buffer.get_buffer())
+                .offset(0)
+                .range(buffer.size())
+                .build()];
+            let write = [vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(&buffer_info)
+                .build()];
+            unsafe { material.device.update_descriptor_sets(&write, &[]) };
+        }
+
+        Ok(Skeleton {
+            device: material.device.clone(),
+            descriptor_pool,
+            descriptor_sets,
+            buffers: Mutex::new(buffers),
+            joint_count,
+        })
+    }
+
+    #[inline]
+    pub fn joint_count(&self) -> usize {
+        self.joint_count
+    }
+
+    /// Writes the bone matrices used by the frame in flight `frame`
+    pub(super) fn write(&self, frame: usize, bones: &[Matrix4<f32>]) -> Result<()> {
+        if bones.len() > self.joint_count {
+            return Err(anyhow!(
+                "Got {} bone matrices for a skeleton with {} joints",
+                bones.len(),
+                self.joint_count
+            ));
+        }
+        self.buffers.lock()[frame].write(0, bones);
+        Ok(())
+    }
+
+    pub(super) fn get_descriptor_set(&self, frame: usize) -> vk::DescriptorSet {
+        self.descriptor_sets[frame]
+    }
+}
+
+impl Drop for Skeleton {
+    fn drop(&mut self) {
+        let sets = std::mem::take(&mut self.descriptor_sets);
+        deletion::queue(
+            self.device.clone(),
+            Resource::DescriptorSets(self.descriptor_pool, sets),
+        );
+    }
+}
+
+/// Descriptor pools the bone sets of skeletons are allocated from,
+/// another pool is created whenever all of them are exhausted
+pub(super) struct BonePools {
+    device: Arc<ash::Device>,
+    frames_in_flight: u32,
+    pools: Mutex<Vec<vk::DescriptorPool>>,
+}
+
+impl BonePools {
+    pub(super) fn new(device: Arc<ash::Device>, frames_in_flight: usize) -> Self {
+        BonePools {
+            device,
+            frames_in_flight: frames_in_flight as u32,
+            pools: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Allocates one set per layout from the newest pool with enough space left
+    fn allocate(
+        &self,
+        layouts: &[vk::DescriptorSetLayout],
+    ) -> Result<(vk::DescriptorPool, Vec<vk::DescriptorSet>)> {
+        let mut pools = self.pools.lock();
+        for &pool in pools.iter().rev() {
+            match self.allocate_from(pool, layouts) {
+                Ok(sets) => return Ok((pool, sets)),
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let pool = self.create_pool()?;
+        pools.push(pool);
+        Ok((pool, self.allocate_from(pool, layouts)?))
+    }
+
+    fn allocate_from(
+        &self,
+        pool: vk::DescriptorPool,
+        layouts: &[vk::DescriptorSetLayout],
+    ) -> VkResult<Vec<vk::DescriptorSet>> {
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(layouts);
+        unsafe { self.device.allocate_descriptor_sets(&alloc_info) }
+    }
+
+    fn create_pool(&self) -> VkResult<vk::DescriptorPool> {
+        let max_sets = SKELETONS_PER_POOL * self.frames_in_flight;
+        let sizes = [vk::DescriptorPoolSize::builder()
+            .descriptor_count(max_sets)
+            .ty(vk::DescriptorType::STORAGE_BUFFER)
+            .build()];
+        // skeletons free their own sets when they are destroyed
+        let create_info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+            .max_sets(max_sets)
+            .pool_sizes(&sizes);
+        unsafe { self.device.create_descriptor_pool(&create_info, None) }
+    }
+
+    /// Destroys every pool.
+    ///
+    /// # Safety
+    /// The deletion queue must be flushed first, since it frees sets of these pools
+    pub(super) unsafe fn destroy(&mut self) {
+        for pool in self.pools.get_mut().drain(..) {
+            self.device.destroy_descriptor_pool(pool, None);
+        }
+    }
+}
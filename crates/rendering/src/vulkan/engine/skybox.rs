@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use ash::vk;
+
+use crate::materials::{CullMode, DepthCompare, MaterialDefinition};
+use crate::vulkan::engine::deletion::{self, Resource};
+use crate::vulkan::engine::pipeline::PipelineParts;
+use crate::vulkan::texture::Texture;
+
+/// Cubemap drawn behind all geometry, sampled in the direction from the camera through every pixel
+pub(super) struct Skybox {
+    texture: Texture,
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    descriptor_layouts: Vec<vk::DescriptorSetLayout>,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    descriptor_pool: vk::DescriptorPool,
+    device: Arc<ash::Device>,
+}
+
+/// Definition of the skybox pipeline, drawn at the far plane before any other geometry.
+///
+/// It doesn't write depth, so everything drawn after it is in front of it
+pub(super) fn definition() -> MaterialDefinition {
+    MaterialDefinition {
+        vertex_shader: "skybox.vert.spv".into(),
+        fragment_shader: "skybox.frag.spv".into(),
+        texture: None,
+        cull_mode: CullMode::None,
+        depth_write: false,
+        depth_compare: DepthCompare::LessOrEqual,
+        ..Default::default()
+    }
+}
+
+impl Skybox {
+    /// Takes ownership of the cubemap and the pipeline created from [definition],
+    /// pointing the pipeline's set 1 at the cubemap
+    pub(super) fn new(
+        texture: Texture,
+        (pipeline, layout, descriptor_layouts): PipelineParts,
+        descriptor_pool: vk::DescriptorPool,
+        device: Arc<ash::Device>,
+    ) -> Result<Self> {
+        let mut skybox = Skybox {
+            texture,
+            pipeline,
+            layout,
+            descriptor_layouts,
+            descriptor_sets: Vec::new(),
+            descriptor_pool,
+            device,
+        };
+        if skybox.descriptor_layouts.len() != 1 {
+            return Err(anyhow!("Skybox shaders must sample the cubemap from set 1"));
+        }
+        let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&skybox.descriptor_layouts);
+        skybox.descriptor_sets = unsafe { skybox.device.allocate_descriptor_sets(&alloc_info)? };
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(skybox.texture.view)
+            .sampler(skybox.texture.sampler)
+            .build()];
+        let write = [vk::WriteDescriptorSet::builder()
+            .dst_set(skybox.descriptor_sets[0])
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info)
+            .build()];
+        unsafe { skybox.device.update_descriptor_sets(&write, &[]) };
+        Ok(skybox)
+    }
+
+    /// Draws the sky over the whole viewport, binding its own pipeline
+    /// so the next draw has to bind its material again
+    pub(super) unsafe fn record(
+        &self,
+        device: &ash::Device,
+        cmd: vk::CommandBuffer,
+        global_descriptor: vk::DescriptorSet,
+    ) {
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        let sets = [global_descriptor, self.descriptor_sets[0]];
+        device.cmd_bind_descriptor_sets(
+            cmd,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.layout,
+            0,
+            &sets,
+            &[],
+        );
+        device.cmd_draw(cmd, 3, 1, 0, 0);
+    }
+}
+
+impl Drop for Skybox {
+    fn drop(&mut self) {
+        deletion::queue(self.device.clone(), Resource::Pipeline(self.pipeline));
+        deletion::queue(self.device.clone(), Resource::PipelineLayout(self.layout));
+        if !self.descriptor_sets.is_empty() {
+            let sets = std::mem::take(&mut self.descriptor_sets);
+            deletion::queue(
+                self.device.clone(),
+                Resource::DescriptorSets(self.descriptor_pool, sets),
+            );
+        }
+        for layout in self.descriptor_layouts.drain(..) {
+            deletion::queue(self.device.clone(), Resource::DescriptorSetLayout(layout));
+        }
+    }
+}
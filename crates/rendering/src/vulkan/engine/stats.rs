@@ -0,0 +1,55 @@
+use crate::FrameStats;
+
+/// Frames the average of [FrameStats] is taken over
+const WINDOW: usize = 120;
+
+/// Ring buffer of the cpu times of the last frames, as measured for [FramePacing](crate::FramePacing)
+pub(super) struct FrameTimes {
+    times: [f32; WINDOW],
+    /// Frames measured so far, the next time is written at `count % WINDOW`
+    count: usize,
+}
+
+impl FrameTimes {
+    pub(super) fn new() -> Self {
+        FrameTimes {
+            times: [0.; WINDOW],
+            count: 0,
+        }
+    }
+
+    pub(super) fn push(&mut self, ms: f32) {
+        self.times[self.count % WINDOW] = ms;
+        self.count += 1;
+    }
+
+    pub(super) fn stats(&self) -> FrameStats {
+        if self.count == 0 {
+            return FrameStats::default();
+        }
+        let measured = &self.times[..self.count.min(WINDOW)];
+        FrameStats {
+            cpu_ms: self.times[(self.count - 1) % WINDOW],
+            average_cpu_ms: measured.iter().sum::<f32>() / measured.len() as f32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::vulkan::engine::stats::{FrameTimes, WINDOW};
+
+    #[test]
+    fn average_covers_the_last_frames() {
+        let mut times = FrameTimes::new();
+        assert_eq!(times.stats().average_cpu_ms, 0.);
+        times.push(4.);
+        times.push(2.);
+        assert_eq!(times.stats().cpu_ms, 2.);
+        assert_eq!(times.stats().average_cpu_ms, 3.);
+        for _ in 0..WINDOW {
+            times.push(1.);
+        }
+        assert_eq!(times.stats().average_cpu_ms, 1.);
+    }
+}
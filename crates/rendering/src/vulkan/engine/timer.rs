@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk;
+use log::warn;
+
+/// Timestamps at the start and end of every frame's primary command buffer,
+/// measuring how long the gpu spent executing the frame.
+///
+/// Like occlusion queries the results are read once the frame's fence is signaled,
+/// so they are as many frames old as there are frames in flight
+pub(super) struct GpuTimer {
+    device: Arc<ash::Device>,
+    /// Two queries per frame in flight
+    pool: vk::QueryPool,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    /// Mask of the bits of a timestamp that hold its value
+    mask: u64,
+    /// Whether the frame in flight wrote its timestamps since they were last read
+    written: Vec<bool>,
+}
+
+impl GpuTimer {
+    /// Returns None if the queue family can't write timestamps
+    pub(super) unsafe fn new(
+        device: Arc<ash::Device>,
+        properties: &vk::PhysicalDeviceProperties,
+        family: &vk::QueueFamilyProperties,
+        frames_in_flight: usize,
+    ) -> Result<Option<Self>> {
+        let bits = family.timestamp_valid_bits;
+        if bits == 0 {
+            return Ok(None);
+        }
+        let create_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(2 * frames_in_flight as u32);
+        let pool = device.create_query_pool(&create_info, None)?;
+        Ok(Some(GpuTimer {
+            device,
+            pool,
+            period: properties.limits.timestamp_period,
+            mask: u64::MAX >> (64 - bits.min(64)),
+            written: vec![false; frames_in_flight],
+        }))
+    }
+
+    /// Writes the timestamp of the start of the frame in flight `frame`,
+    /// `cmd` must be the frame's primary command buffer right after it began
+    pub(super) unsafe fn begin(&self, cmd: vk::CommandBuffer, frame: usize) {
+        let first = 2 * frame as u32;
+        self.device.cmd_reset_query_pool(cmd, self.pool, first, 2);
+        self.device
+            .cmd_write_timestamp(cmd, vk::PipelineStageFlags::TOP_OF_PIPE, self.pool, first);
+    }
+
+    /// Writes the timestamp of the end of the frame, right before `cmd` is ended
+    pub(super) unsafe fn end(&mut self, cmd: vk::CommandBuffer, frame: usize) {
+        self.device.cmd_write_timestamp(
+            cmd,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            self.pool,
+            2 * frame as u32 + 1,
+        );
+        self.written[frame] = true;
+    }
+
+    /// Milliseconds the gpu spent on the frame in flight `frame`,
+    /// must be called after waiting on the frame's fence
+    pub(super) unsafe fn collect(&mut self, frame: usize) -> Option<f32> {
+        if !std::mem::take(&mut self.written[frame]) {
+            return None;
+        }
+        let mut timestamps = [0u64; 2];
+        if let Err(e) = self.device.get_query_pool_results(
+            self.pool,
+            2 * frame as u32,
+            2,
+            &mut timestamps,
+            vk::QueryResultFlags::TYPE_64,
+        ) {
+            warn!("Failed to read frame timestamps: {e}");
+            return None;
+        }
+        let ticks = timestamps[1].wrapping_sub(timestamps[0]) & self.mask;
+        Some(ticks as f32 * self.period / 1_000_000.)
+    }
+}
+
+impl Drop for GpuTimer {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_query_pool(self.pool, None) };
+    }
+}
@@ -0,0 +1,451 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use ash::prelude::VkResult;
+use ash::vk;
+use ash::vk::DeviceSize;
+use log::{error, trace};
+use parking_lot::{Condvar, Mutex};
+
+use crate::vulkan::engine::alloc::StagingBuffer;
+
+/// How often an upload waiting for budget checks the fences of the submitted uploads again,
+/// signaled fences don't wake it up
+const FENCE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Bytes of staging memory held by uploads that haven't finished yet and the most allowed
+struct Budget {
+    /// Bytes of uploads that are still being recorded
+    reserved: DeviceSize,
+    /// Uploads on the gpu, their bytes are reclaimed once their fence is signaled
+    submitted: Vec<Submitted>,
+    limit: Option<DeviceSize>,
+}
+
+struct Submitted {
+    fence: vk::Fence,
+    size: DeviceSize,
+    device: Arc<ash::Device>,
+}
+
+static BUDGET: Mutex<Budget> = Mutex::new(Budget {
+    reserved: 0,
+    submitted: Vec::new(),
+    limit: None,
+});
+static RELEASED: Condvar = Condvar::new();
+
+/// Staging memory reserved by an upload that isn't submitted yet,
+/// returned to the budget when dropped
+pub(crate) struct UploadPermit {
+    size: DeviceSize,
+}
+
+/// A mesh, texture or material whose data is still being copied to the gpu.
+///
+/// The value can't be used until the copy finished, which is polled with [is_ready](Upload::is_ready)
+/// or waited for with [wait](Upload::wait). Uploads must be finished or dropped before the engine
+pub struct Upload<T> {
+    value: T,
+    transfer: Option<Transfer>,
+    /// Runs once the transfer finished, before the value is handed out
+    on_ready: Option<Box<dyn FnOnce(&T) + Send>>,
+}
+
+/// Command buffers an upload is recorded in and the queues they are submitted to
+#[derive(Copy, Clone)]
+pub struct UploadCommands {
+    /// Records the copies
+    pub(crate) copy: vk::CommandBuffer,
+    /// Queue the copies are submitted to, the transfer queue if there is one
+    pub(crate) copy_queue: vk::Queue,
+    /// Set when the copies run on a dedicated transfer queue
+    pub(crate) ownership: Option<Ownership>,
+}
+
+/// Hands uploaded resources from the transfer queue family to the graphics queue family
+#[derive(Copy, Clone)]
+pub(crate) struct Ownership {
+    pub(crate) transfer_family: u32,
+    pub(crate) graphics_family: u32,
+    /// Acquires the resources on the graphics queue once the copies finished,
+    /// commands that need a graphics queue like mip blits are recorded after the acquire
+    pub(crate) acquire: vk::CommandBuffer,
+    pub(crate) graphics_queue: vk::Queue,
+}
+
+/// Copy commands submitted with a fence,
+/// keeping the staging memory they read alive until the fence is signaled
+pub(crate) struct Transfer {
+    fence: vk::Fence,
+    /// Orders the graphics queue's acquire after the copies on the transfer queue
+    semaphore: Option<vk::Semaphore>,
+    /// Pools only used by this transfer, destroyed with their command buffers once it finished
+    command_pools: Vec<vk::CommandPool>,
+    _staging: StagingBuffer,
+    device: Arc<ash::Device>,
+}
+
+impl<T> Upload<T> {
+    /// Value that didn't need to be uploaded, it is ready right away
+    pub(crate) fn ready(value: T) -> Self {
+        Self::new(value, None)
+    }
+
+    /// Value that becomes usable once `transfer` finished, if there is one
+    pub(crate) fn new(value: T, transfer: Option<Transfer>) -> Self {
+        Upload {
+            value,
+            transfer,
+            on_ready: None,
+        }
+    }
+
+    /// Whether the copy finished without blocking,
+    /// the staging memory is released as soon as it did
+    pub fn is_ready(&mut self) -> Result<bool> {
+        if let Some(transfer) = &self.transfer {
+            if !transfer.is_finished()? {
+                return Ok(false);
+            }
+            self.transfer = None;
+        }
+        if let Some(on_ready) = self.on_ready.take() {
+            on_ready(&self.value);
+        }
+        Ok(true)
+    }
+
+    /// Blocks until the copy finished and returns the uploaded value
+    pub fn wait(mut self) -> Result<T> {
+        if let Some(transfer) = self.transfer.take() {
+            transfer.wait()?;
+        }
+        if let Some(on_ready) = self.on_ready.take() {
+            on_ready(&self.value);
+        }
+        Ok(self.value)
+    }
+
+    /// Replaces the value that becomes usable once the copy finished
+    pub(crate) fn map<U>(self, f: impl FnOnce(T) -> U) -> Upload<U> {
+        let (value, transfer) = self.into_parts();
+        Upload::new(f(value), transfer)
+    }
+
+    /// The value and the transfer it waits for, so it can be moved into another upload
+    pub(crate) fn into_parts(self) -> (T, Option<Transfer>) {
+        debug_assert!(self.on_ready.is_none(), "Upload callback would be dropped");
+        (self.value, self.transfer)
+    }
+
+    /// Runs `on_ready` once the copy finished, immediately if it already did.
+    /// Not run if the upload is dropped before that
+    pub(crate) fn on_ready(mut self, on_ready: impl FnOnce(&T) + Send + 'static) -> Self {
+        if self.transfer.is_some() {
+            self.on_ready = Some(Box::new(on_ready));
+        } else {
+            on_ready(&self.value);
+        }
+        self
+    }
+
+    /// Destroys `pools` once the copy finished, for pools created just to record the upload
+    pub(crate) fn with_command_pools(mut self, pools: Vec<vk::CommandPool>) -> Self {
+        match &mut self.transfer {
+            Some(transfer) => transfer.command_pools = pools,
+            None => unreachable!("Command pools given to an upload without transfer"),
+        }
+        self
+    }
+}
+
+impl UploadCommands {
+    /// Copies recorded in `cmd` and submitted to the graphics queue `queue`
+    pub fn graphics(cmd: vk::CommandBuffer, queue: vk::Queue) -> Self {
+        UploadCommands {
+            copy: cmd,
+            copy_queue: queue,
+            ownership: None,
+        }
+    }
+
+    /// Command buffer submitted to the graphics queue,
+    /// which must be begun by the caller if it isn't the one recording the copies
+    pub(crate) fn graphics_cmd(&self) -> vk::CommandBuffer {
+        self.ownership
+            .map_or(self.copy, |ownership| ownership.acquire)
+    }
+
+    /// Queue family indices of a barrier releasing a resource on the transfer queue
+    /// and acquiring it on the graphics queue, ignored if there is no transfer queue
+    pub(crate) fn families(&self) -> (u32, u32) {
+        match self.ownership {
+            Some(ownership) => (ownership.transfer_family, ownership.graphics_family),
+            None => (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED),
+        }
+    }
+}
+
+impl Transfer {
+    /// Submits the recorded commands with a new fence, signaled once the uploaded resources
+    /// can be used on the graphics queue.
+    ///
+    /// With a transfer queue the copies are submitted to it and the graphics queue's commands
+    /// wait for them. `staging` is held until the gpu is done with it and the budget of `permit`
+    /// is returned once the fence is signaled, even if the upload isn't polled
+    pub(crate) unsafe fn submit(
+        device: Arc<ash::Device>,
+        commands: &UploadCommands,
+        staging: StagingBuffer,
+        permit: UploadPermit,
+    ) -> VkResult<Self> {
+        let fence = device.create_fence(&vk::FenceCreateInfo::default(), None)?;
+        let semaphore = match commands.ownership {
+            Some(ownership) => match submit_with_ownership(&device, commands, ownership, fence) {
+                Ok(semaphore) => Some(semaphore),
+                Err(e) => {
+                    device.destroy_fence(fence, None);
+                    return Err(e);
+                }
+            },
+            None => {
+                let copy = [commands.copy];
+                let submit_info = [vk::SubmitInfo::builder().command_buffers(&copy).build()];
+                if let Err(e) = device.queue_submit(commands.copy_queue, &submit_info, fence) {
+                    device.destroy_fence(fence, None);
+                    return Err(e);
+                }
+                None
+            }
+        };
+        permit.submit(fence, device.clone());
+        Ok(Transfer {
+            fence,
+            semaphore,
+            command_pools: Vec::new(),
+            _staging: staging,
+            device,
+        })
+    }
+
+    fn is_finished(&self) -> VkResult<bool> {
+        unsafe { self.device.get_fence_status(self.fence) }
+    }
+
+    fn wait(&self) -> VkResult<()> {
+        unsafe { self.device.wait_for_fences(&[self.fence], true, u64::MAX) }
+    }
+}
+
+/// Records the release of `buffers` after they were written by the copies
+/// and their acquire on the graphics queue, readable with `dst_access` in `dst_stage` after it.
+///
+/// Must be recorded before the copy command buffer ends,
+/// the acquire command buffer is begun and ended. Does nothing without a transfer queue
+pub(crate) unsafe fn hand_over_buffers(
+    device: &ash::Device,
+    commands: &UploadCommands,
+    buffers: &[vk::Buffer],
+    (dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+) -> VkResult<()> {
+    let ownership = match commands.ownership {
+        Some(ownership) => ownership,
+        None => return Ok(()),
+    };
+    let barriers = |src_access, dst_access| {
+        buffers
+            .iter()
+            .map(|buffer| {
+                vk::BufferMemoryBarrier::builder()
+                    .src_queue_family_index(ownership.transfer_family)
+                    .dst_queue_family_index(ownership.graphics_family)
+                    .buffer(*buffer)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)
+                    .src_access_mask(src_access)
+                    .dst_access_mask(dst_access)
+                    .build()
+            })
+            .collect::<Vec<_>>()
+    };
+    device.cmd_pipeline_barrier(
+        commands.copy,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        vk::DependencyFlags::empty(),
+        &[],
+        &barriers(vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::empty()),
+        &[],
+    );
+    let begin_info =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(ownership.acquire, &begin_info)?;
+    device.cmd_pipeline_barrier(
+        ownership.acquire,
+        vk::PipelineStageFlags::TOP_OF_PIPE,
+        dst_stage,
+        vk::DependencyFlags::empty(),
+        &[],
+        &barriers(vk::AccessFlags::empty(), dst_access),
+        &[],
+    );
+    device.end_command_buffer(ownership.acquire)
+}
+
+/// Submits the copies to the transfer queue and the acquire to the graphics queue,
+/// returning the semaphore ordering them
+unsafe fn submit_with_ownership(
+    device: &ash::Device,
+    commands: &UploadCommands,
+    ownership: Ownership,
+    fence: vk::Fence,
+) -> VkResult<vk::Semaphore> {
+    let semaphore = [device.create_semaphore(&Default::default(), None)?];
+    let copy = [commands.copy];
+    let submit_info = [vk::SubmitInfo::builder()
+        .command_buffers(&copy)
+        .signal_semaphores(&semaphore)
+        .build()];
+    if let Err(e) = device.queue_submit(commands.copy_queue, &submit_info, vk::Fence::null()) {
+        device.destroy_semaphore(semaphore[0], None);
+        return Err(e);
+    }
+    let acquire = [ownership.acquire];
+    let submit_info = [vk::SubmitInfo::builder()
+        .command_buffers(&acquire)
+        .wait_semaphores(&semaphore)
+        .wait_dst_stage_mask(&[vk::PipelineStageFlags::ALL_COMMANDS])
+        .build()];
+    if let Err(e) = device.queue_submit(ownership.graphics_queue, &submit_info, fence) {
+        // the copies still signal the semaphore and read the staging buffer
+        let _ = device.queue_wait_idle(commands.copy_queue);
+        device.destroy_semaphore(semaphore[0], None);
+        return Err(e);
+    }
+    Ok(semaphore[0])
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        // the staging buffer and command buffers can't be freed while the gpu still uses them
+        if let Err(e) = self.wait() {
+            error!("Failed to wait for upload: {e}");
+        }
+        // the fence can't be polled by waiting uploads once it is destroyed
+        release(self.fence);
+        unsafe {
+            self.device.destroy_fence(self.fence, None);
+            if let Some(semaphore) = self.semaphore {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+            for pool in self.command_pools.drain(..) {
+                self.device.destroy_command_pool(pool, None);
+            }
+        }
+    }
+}
+
+/// Limits the staging memory of all uploads in flight to `limit` bytes, unlimited if None
+pub(super) fn set_limit(limit: Option<DeviceSize>) {
+    BUDGET.lock().limit = limit;
+    RELEASED.notify_all();
+}
+
+/// Reserves `size` bytes of staging memory,
+/// blocking until enough of the budget is released by uploads finishing on the gpu.
+///
+/// The permit must be held until the upload is submitted with [Transfer::submit]
+pub(crate) fn reserve(size: DeviceSize) -> UploadPermit {
+    let mut budget = BUDGET.lock();
+    loop {
+        budget.reclaim();
+        if fits(budget.in_flight(), size, budget.limit) {
+            break;
+        }
+        trace!("Waiting for {size} bytes of upload budget");
+        RELEASED.wait_for(&mut budget, FENCE_POLL_INTERVAL);
+    }
+    budget.reserved += size;
+    UploadPermit { size }
+}
+
+/// Returns the budget of the upload submitted with `fence` if it wasn't reclaimed already
+fn release(fence: vk::Fence) {
+    let mut budget = BUDGET.lock();
+    budget.submitted.retain(|upload| upload.fence != fence);
+    drop(budget);
+    RELEASED.notify_all();
+}
+
+impl Budget {
+    fn in_flight(&self) -> DeviceSize {
+        let submitted: DeviceSize = self.submitted.iter().map(|upload| upload.size).sum();
+        self.reserved + submitted
+    }
+
+    /// Drops the uploads whose fence was signaled, a lost device releases all of them
+    fn reclaim(&mut self) {
+        self.submitted.retain(|upload| unsafe {
+            matches!(upload.device.get_fence_status(upload.fence), Ok(false))
+        });
+    }
+}
+
+impl UploadPermit {
+    /// Keeps the budget until `fence` is signaled instead of until the permit is dropped
+    fn submit(self, fence: vk::Fence, device: Arc<ash::Device>) {
+        let mut budget = BUDGET.lock();
+        budget.reserved -= self.size;
+        budget.submitted.push(Submitted {
+            fence,
+            size: self.size,
+            device,
+        });
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for UploadPermit {
+    fn drop(&mut self) {
+        BUDGET.lock().reserved -= self.size;
+        RELEASED.notify_all();
+    }
+}
+
+/// Whether an upload of `size` bytes may start, uploads larger than the whole budget
+/// are started once nothing else is in flight so they can't wait forever
+fn fits(in_flight: DeviceSize, size: DeviceSize, limit: Option<DeviceSize>) -> bool {
+    match limit {
+        Some(limit) => in_flight == 0 || in_flight + size <= limit,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use crate::vulkan::engine::upload::{fits, Upload};
+
+    #[test]
+    fn budget_blocks_uploads_over_limit() {
+        assert!(fits(0, 64, Some(128)));
+        assert!(fits(64, 64, Some(128)));
+        assert!(!fits(65, 64, Some(128)));
+        assert!(fits(0, 256, Some(128)));
+        assert!(fits(1 << 40, 1 << 40, None));
+    }
+
+    #[test]
+    fn ready_uploads_run_their_callback_immediately() {
+        let called = Arc::new(AtomicBool::new(false));
+        let flag = called.clone();
+        let mut upload = Upload::ready(7).on_ready(move |_| flag.store(true, Ordering::Relaxed));
+        assert!(called.load(Ordering::Relaxed));
+        assert!(upload.is_ready().unwrap());
+        assert_eq!(upload.map(|value| value * 2).wait().unwrap(), 14);
+    }
+}
@@ -1,61 +1,294 @@
 use std::collections::HashMap;
 use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Weak};
 
+use ash::vk::Handle;
 use ash::{Device, vk};
 use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 
-use crate::vulkan::material::creation::load_material;
+use crate::materials::{MaterialDefinition, PbrFactors, PBR_FACTORS_BINDING, TEXTURE_BINDING};
+use crate::vulkan::engine::alloc::{GpuObject, StorageBuffer};
+use crate::vulkan::engine::deletion::{self, Resource};
+use crate::vulkan::engine::upload::Upload;
+use crate::vulkan::material::creation::load_definition;
+use crate::vulkan::sampler::Sampler;
 use crate::vulkan::texture::Texture;
 
-mod creation;
+pub(super) mod creation;
 
 pub struct Material {
-    pub pipeline: vk::Pipeline,
+    /// Raw pipeline handle, replaced when the material's shaders are reloaded
+    pub(crate) pipeline: AtomicU64,
     pub layout: vk::PipelineLayout,
     pub device: Arc<Device>,
-    pub texture: Option<Texture>,
+    pub texture: Option<Arc<Texture>>,
+    /// Model matrices are read per instance instead of from push constants, see [DrawBatch](crate::DrawBatch)
+    pub instanced: bool,
+    /// Vertices are transformed by the bone matrices of a [Skeleton](crate::Skeleton)
+    pub skinned: bool,
+    /// Blended with the framebuffer, so its draws are recorded after the opaque ones,
+    /// sorted back to front
+    pub transparent: bool,
+    /// Layouts of the material's own descriptor sets, bound after the global set
+    pub descriptor_layouts: Vec<vk::DescriptorSetLayout>,
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    pub descriptor_pool: vk::DescriptorPool,
+    /// Uniform buffer of the material's pbr factors, written to its descriptor set once
+    pub factors: Option<GpuObject<PbrUniform>>,
+}
+
+/// [PbrFactors] and the emissive color in the std140 layout the pbr shaders declare
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PbrUniform {
+    base_color: [f32; 4],
+    /// Only rgb is used
+    emissive: [f32; 4],
+    metallic: f32,
+    roughness: f32,
+    _padding: [f32; 2],
+}
+
+impl PbrUniform {
+    pub fn new(factors: &PbrFactors, [r, g, b]: [f32; 3]) -> Self {
+        PbrUniform {
+            base_color: factors.base_color,
+            emissive: [r, g, b, 0.],
+            metallic: factors.metallic,
+            roughness: factors.roughness,
+            _padding: [0.; 2],
+        }
+    }
 }
 
 static CACHE: Lazy<Mutex<HashMap<String, Weak<Material>>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
 impl Material {
-    pub fn new(
-        name: impl Into<String>,
-        device: &ash::Device,
-        image_fmt: vk::Format,
-        extent: vk::Extent2D,
-    ) -> Result<Arc<Self>, Box<dyn Error>> {
-        let name = name.into();
+    /// Returns the material `name` if it is still alive, otherwise reads its definition
+    /// from the asset database and builds it with `create`.
+    ///
+    /// Only materials whose upload finished are cached, so cached ones are ready right away
+    pub(crate) fn new_async(
+        name: &str,
+        create: impl FnOnce(&MaterialDefinition) -> Result<Upload<Arc<Self>>, Box<dyn Error>>,
+    ) -> Result<Upload<Arc<Self>>, Box<dyn Error>> {
         let cache = CACHE.lock();
-        if let Some(Some(mat)) = cache.get(name.as_str()).map(Weak::upgrade) {
-            return Ok(mat);
+        if let Some(Some(mat)) = cache.get(name).map(Weak::upgrade) {
+            return Ok(Upload::ready(mat));
         }
         drop(cache);
 
-        let material = load_material(&name, device, image_fmt, extent, )?;
-        let mut cache = CACHE.lock();
-        cache.insert(name, Arc::downgrade(&material));
-        Ok(material)
+        let upload = create(&load_definition(name)?)?;
+        let name = name.to_owned();
+        Ok(upload.on_ready(move |material| {
+            CACHE.lock().insert(name, Arc::downgrade(material));
+        }))
+    }
+
+    /// Names of the materials created with [new_async](Material::new_async), with the ones still alive
+    pub(crate) fn cached() -> Vec<(String, Option<Arc<Material>>)> {
+        CACHE
+            .lock()
+            .iter()
+            .map(|(name, material)| (name.clone(), material.upgrade()))
+            .collect()
+    }
+
+    /// Swaps in a pipeline rebuilt from the material's definition,
+    /// destroying the old one once no frame in flight uses it.
+    ///
+    /// The new pipeline must be compatible with the material's layout
+    #[cfg(feature = "hot-reload")]
+    pub(super) fn replace_pipeline(&self, pipeline: vk::Pipeline) {
+        let old = self.pipeline.swap(pipeline.as_raw(), Ordering::AcqRel);
+        deletion::queue(self.device.clone(), Resource::Pipeline(vk::Pipeline::from_raw(old)));
+    }
+
+    pub(super) fn get_pipeline(&self) -> vk::Pipeline {
+        vk::Pipeline::from_raw(self.pipeline.load(Ordering::Acquire))
     }
 
     pub(super) fn get_pipeline_layout(&self) -> vk::PipelineLayout {
         self.layout
     }
 
+    pub(super) fn get_descriptor_sets(&self) -> &[vk::DescriptorSet] {
+        &self.descriptor_sets
+    }
+
+    /// Points a storage buffer binding of one of the material's descriptor sets at `buffer`.
+    ///
+    /// Descriptors can't be changed while a frame using the material is in flight,
+    /// so storage buffers should be written before the material is first rendered
+    ///
+    /// # Arguments
+    ///
+    /// * `set`: set number as declared in the shader, the global set 0 can't be written
+    /// * `binding`: binding number of the storage buffer inside the set
+    pub fn write_storage_buffer(
+        &self,
+        set: u32,
+        binding: u32,
+        buffer: &StorageBuffer,
+    ) -> Result<(), Box<dyn Error>> {
+        self.write_buffer_descriptor(
+            set,
+            binding,
+            vk::DescriptorType::STORAGE_BUFFER,
+            buffer.get_buffer(),
+            buffer.size(),
+        )
+    }
+
+    /// Points the pbr factor binding at the material's own uniform buffer,
+    /// does nothing for materials without pbr factors
+    pub(crate) fn write_factors(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(factors) = &self.factors {
+            let (set, binding) = PBR_FACTORS_BINDING;
+            self.write_buffer_descriptor(
+                set,
+                binding,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                factors.get_buffer(),
+                std::mem::size_of::<PbrUniform>() as vk::DeviceSize,
+            )
+            .map_err(|_| {
+                format!("Material has pbr factors, but its shaders don't read them from set {set}")
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Points the texture binding at the material's texture, a white one if it has none.
+    /// Only called for materials whose shaders sample it at [TEXTURE_BINDING]
+    pub(crate) fn write_texture(&self) -> Result<(), Box<dyn Error>> {
+        let texture = self
+            .texture
+            .as_ref()
+            .ok_or("Material's shaders sample a texture, but it has none")?;
+        let (set, binding) = TEXTURE_BINDING;
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .sampler(texture.sampler)
+            .image_view(texture.view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()];
+        self.write_image_descriptor(
+            set,
+            binding,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            &image_info,
+        )
+    }
+
+    fn write_buffer_descriptor(
+        &self,
+        set: u32,
+        binding: u32,
+        ty: vk::DescriptorType,
+        buffer: vk::Buffer,
+        range: vk::DeviceSize,
+    ) -> Result<(), Box<dyn Error>> {
+        let buffer_info = [vk::DescriptorBufferInfo::builder()
+            .buffer(buffer)
+            .offset(0)
+            .range(range)
+            .build()];
+        let write = [vk::WriteDescriptorSet::builder()
+            .dst_set(self.get_descriptor_set(set)?)
+            .dst_binding(binding)
+            .descriptor_type(ty)
+            .buffer_info(&buffer_info)
+            .build()];
+        unsafe { self.device.update_descriptor_sets(&write, &[]) };
+        Ok(())
+    }
+
+    /// Points a separate `SAMPLER` binding of one of the material's descriptor sets at `sampler`,
+    /// the same restrictions as for [write_storage_buffer](Material::write_storage_buffer) apply
+    pub fn write_sampler(
+        &self,
+        set: u32,
+        binding: u32,
+        sampler: &Sampler,
+    ) -> Result<(), Box<dyn Error>> {
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .sampler(sampler.sampler)
+            .build()];
+        self.write_image_descriptor(set, binding, vk::DescriptorType::SAMPLER, &image_info)
+    }
+
+    /// Points a `SAMPLED_IMAGE` binding of one of the material's descriptor sets at `texture`.
+    ///
+    /// The texture is sampled with whatever sampler the shader combines it with,
+    /// its own sampler is ignored
+    pub fn write_sampled_image(
+        &self,
+        set: u32,
+        binding: u32,
+        texture: &Texture,
+    ) -> Result<(), Box<dyn Error>> {
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_view(texture.view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .build()];
+        self.write_image_descriptor(
+            set,
+            binding,
+            vk::DescriptorType::SAMPLED_IMAGE,
+            &image_info,
+        )
+    }
+
+    fn write_image_descriptor(
+        &self,
+        set: u32,
+        binding: u32,
+        ty: vk::DescriptorType,
+        image_info: &[vk::DescriptorImageInfo],
+    ) -> Result<(), Box<dyn Error>> {
+        let write = [vk::WriteDescriptorSet::builder()
+            .dst_set(self.get_descriptor_set(set)?)
+            .dst_binding(binding)
+            .descriptor_type(ty)
+            .image_info(image_info)
+            .build()];
+        unsafe { self.device.update_descriptor_sets(&write, &[]) };
+        Ok(())
+    }
+
+    /// Looks up one of the material's own sets by its set number
+    fn get_descriptor_set(&self, set: u32) -> Result<vk::DescriptorSet, Box<dyn Error>> {
+        set.checked_sub(1)
+            .and_then(|index| self.descriptor_sets.get(index as usize))
+            .copied()
+            .ok_or_else(|| "Material has no descriptor set with this number".into())
+    }
+
     pub(super) unsafe fn bind(&self, device: &ash::Device, cmd: vk::CommandBuffer) {
-        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.get_pipeline());
     }
 }
 
 impl Drop for Material {
     fn drop(&mut self) {
-        unsafe {
-            self.device.device_wait_idle().unwrap();
-            self.device.destroy_pipeline_layout(self.layout, None);
-            self.device.destroy_pipeline(self.pipeline, None);
+        deletion::queue(self.device.clone(), Resource::PipelineLayout(self.layout));
+        let pipeline = vk::Pipeline::from_raw(*self.pipeline.get_mut());
+        deletion::queue(self.device.clone(), Resource::Pipeline(pipeline));
+        if !self.descriptor_sets.is_empty() {
+            let sets = std::mem::take(&mut self.descriptor_sets);
+            deletion::queue(
+                self.device.clone(),
+                Resource::DescriptorSets(self.descriptor_pool, sets),
+            );
+        }
+        for layout in self.descriptor_layouts.drain(..) {
+            deletion::queue(self.device.clone(), Resource::DescriptorSetLayout(layout));
+        }
+        if let Some(factors) = self.factors.take() {
+            deletion::queue(self.device.clone(), Resource::Buffer(factors.into_buffer()));
         }
     }
 }
\ No newline at end of file
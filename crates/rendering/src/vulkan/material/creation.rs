@@ -1,15 +1,17 @@
 use std::error::Error;
-use std::sync::Arc;
 
-use ash::vk;
+use engine::database::CONN;
 
-use crate::vulkan::material::Material;
+use crate::materials::database;
+use crate::materials::MaterialDefinition;
 
-pub fn load_material(
-    name: &String,
-    device: &ash::Device,
-    image_fmt: vk::Format,
-    extent: vk::Extent2D,
-) -> Result<Arc<Material>, Box<dyn Error>> {
-    todo!()
+/// Reads the definition of the material `name` from the asset database
+pub fn load_definition(name: &str) -> Result<MaterialDefinition, Box<dyn Error>> {
+    CONN.with(|conn| {
+        let conn = conn
+            .as_ref()
+            .map_err(|e| format!("Failed to open the asset database: {e}"))?;
+        database::query(conn, name)?
+            .ok_or_else(|| format!("Material {name} is not in the asset database").into())
+    })
 }
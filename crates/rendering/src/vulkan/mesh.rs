@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::ptr::copy_nonoverlapping;
 use std::sync::Arc;
@@ -6,17 +7,31 @@ use ash::vk;
 use ash::vk::DeviceSize;
 use log::trace;
 use memoffset::offset_of;
+use obj::raw::object::Polygon;
+use obj::raw::{self, RawObj};
+use obj::{load_obj, Obj, ObjError, ObjResult, Position, TexturedVertex};
 use smallvec::{smallvec, SmallVec};
 use vk_mem::Allocator;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 
-use crate::vulkan::engine::alloc::Buffer;
+use crate::geometry::Aabb;
+use crate::vulkan::engine::alloc::{Buffer, StagingPool};
+use crate::vulkan::engine::upload::{self, Transfer, Upload, UploadCommands};
+
+/// Vertex buffer binding of the [skin vertices](SkinVertex), after the instance binding
+pub(crate) const SKIN_BINDING: u32 = 2;
 
 pub struct Mesh {
-    indices: Vec<u32>,
+    index_count: u32,
+    /// UINT16 for meshes whose vertices can all be indexed with 16 bits, see [index_type]
+    index_type: vk::IndexType,
     _vertices: Vec<Vertex>,
     vertex_buffer: Buffer,
+    /// Joints and weights of skinned meshes, bound separately so that only skinned draws read them
+    skin_buffer: Option<Buffer>,
     index_buffer: Buffer,
+    /// Minimum and maximum corner of the model space bounding box
+    bounds: (nalgebra::Vector3<f32>, nalgebra::Vector3<f32>),
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -27,54 +42,106 @@ pub struct Vertex {
     pub uv: nalgebra::Vector2<f32>,
 }
 
+/// Bones influencing a vertex of a skinned mesh, only read by skinned materials
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct SkinVertex {
+    /// Indices of the bones influencing the vertex, from glTF's `JOINTS_0`
+    pub joints: [u32; 4],
+    /// Influence of each of the vertex's [joints](SkinVertex::joints), from glTF's `WEIGHTS_0`
+    pub weights: nalgebra::Vector4<f32>,
+}
+
 impl Mesh {
     /// Creates a new mesh representing a 3d model.
     ///
     /// vertices and indices are immediately copied to the gpu,
     /// blocking until the queue submission is finished.
+    /// Indices are narrowed to 16 bits when there are few enough vertices.
     ///
     /// # Arguments
     ///
     /// * `vertices`: vertices of the model
     /// * `indices`: model indices
     /// * `device`: device handle
-    /// * `cmd`: command buffer to run the copy commands
-    /// * `queue`: queue to submit the copy commands to
+    /// * `commands`: command buffers to run the copy commands and the queues to submit them to
+    /// * `staging`: pool of the staging buffer the vertices and indices are copied from
     /// * `allocator`: allocator to use when allocating the gpu buffers
     ///
     /// returns: Result<Mesh, Box<dyn Error, Global>>
     pub fn new(
         vertices: Vec<Vertex>,
         indices: Vec<u32>,
-        device: &ash::Device,
-        cmd: vk::CommandBuffer,
-        queue: vk::Queue,
+        device: Arc<ash::Device>,
+        commands: UploadCommands,
+        staging: &Arc<StagingPool>,
         allocator: Arc<Allocator>,
     ) -> Result<Self> {
+        Self::new_async(vertices, indices, device, commands, staging, allocator)?.wait()
+    }
+
+    /// Like [new](Mesh::new), but returns as soon as the copy is submitted.
+    ///
+    /// The command buffers must not be reset or freed until the upload finished.
+    /// With a transfer queue the buffers are handed to the graphics queue family after the copy
+    pub fn new_async(
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+        device: Arc<ash::Device>,
+        commands: UploadCommands,
+        staging: &Arc<StagingPool>,
+        allocator: Arc<Allocator>,
+    ) -> Result<Upload<Self>> {
+        Self::new_skinned_async(
+            vertices,
+            Vec::new(),
+            indices,
+            device,
+            commands,
+            staging,
+            allocator,
+        )
+    }
+
+    /// Like [new_async](Mesh::new_async), with the joints and weights of each vertex
+    /// uploaded to a separate buffer read by skinned materials.
+    ///
+    /// Meshes without `skin` vertices can't be drawn with skinned materials
+    pub fn new_skinned_async(
+        vertices: Vec<Vertex>,
+        skin: Vec<SkinVertex>,
+        indices: Vec<u32>,
+        device: Arc<ash::Device>,
+        commands: UploadCommands,
+        staging: &Arc<StagingPool>,
+        allocator: Arc<Allocator>,
+    ) -> Result<Upload<Self>> {
+        if !skin.is_empty() && skin.len() != vertices.len() {
+            bail!(
+                "Mesh has {} skin vertices for {} vertices",
+                skin.len(),
+                vertices.len()
+            );
+        }
         let vertex_size = std::mem::size_of::<Vertex>() * vertices.len();
-        let index_size = std::mem::size_of::<u32>() * indices.len();
-
-        let create_info = vk::BufferCreateInfo::builder()
-            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
-            .size((vertex_size + index_size) as DeviceSize)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
-        let alloc_info = vk_mem::AllocationCreateInfo {
-            usage: vk_mem::MemoryUsage::CpuToGpu,
-            flags: vk_mem::AllocationCreateFlags::MAPPED,
-            required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE
-                | vk::MemoryPropertyFlags::HOST_COHERENT,
-            ..Default::default()
-        };
+        let skin_size = std::mem::size_of::<SkinVertex>() * skin.len();
+        let index_type = index_type(vertices.len());
+        let index_data = index_bytes(&indices, index_type);
+        let index_size = index_data.len();
+        let total_size = (vertex_size + skin_size + index_size) as DeviceSize;
+
         unsafe {
-            let staging_buf = Buffer::new(&create_info, &alloc_info, allocator.clone())?;
+            let permit = upload::reserve(total_size);
+            let staging_buf = staging.take(total_size)?;
             let ptr = staging_buf.get_info().get_mapped_data();
 
             // copy vertices and indices into the staging buffer
-            // Vertices are stored first, indices are stored immediately after in them buffer
+            // Vertices are stored first, followed by the skin vertices and then the indices
             copy_nonoverlapping(vertices.as_ptr() as *const u8, ptr, vertex_size);
+            copy_nonoverlapping(skin.as_ptr() as *const u8, ptr.add(vertex_size), skin_size);
             copy_nonoverlapping(
-                indices.as_ptr() as *const u8,
-                ptr.add(vertex_size),
+                index_data.as_ptr(),
+                ptr.add(vertex_size + skin_size),
                 index_size,
             );
 
@@ -90,12 +157,23 @@ impl Mesh {
                 .sharing_mode(vk::SharingMode::EXCLUSIVE);
             let vertex_buffer = Buffer::new(&create_info, &alloc_info, allocator.clone())?;
 
+            let skin_buffer = if skin.is_empty() {
+                None
+            } else {
+                let create_info = vk::BufferCreateInfo::builder()
+                    .usage(vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER)
+                    .size(skin_size as DeviceSize)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE);
+                Some(Buffer::new(&create_info, &alloc_info, allocator.clone())?)
+            };
+
             let create_info = vk::BufferCreateInfo::builder()
                 .usage(vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER)
                 .size(index_size as DeviceSize)
                 .sharing_mode(vk::SharingMode::EXCLUSIVE);
             let index_buffer = Buffer::new(&create_info, &alloc_info, allocator)?;
 
+            let cmd = commands.copy;
             let begin_info = vk::CommandBufferBeginInfo::builder()
                 .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
             device.begin_command_buffer(cmd, &begin_info)?;
@@ -105,46 +183,419 @@ impl Mesh {
                 dst_offset: 0,
                 size: vertex_size as DeviceSize,
             }];
-            device.cmd_copy_buffer(cmd, *staging_buf, *vertex_buffer, &cpy);
+            device.cmd_copy_buffer(cmd, **staging_buf, *vertex_buffer, &cpy);
+            // skin vertices copy
+            if let Some(skin_buffer) = &skin_buffer {
+                let cpy = [vk::BufferCopy {
+                    src_offset: vertex_size as DeviceSize,
+                    dst_offset: 0,
+                    size: skin_size as DeviceSize,
+                }];
+                device.cmd_copy_buffer(cmd, **staging_buf, **skin_buffer, &cpy);
+            }
             // indices copy
             let cpy = [vk::BufferCopy {
-                src_offset: vertex_size as DeviceSize,
+                src_offset: (vertex_size + skin_size) as DeviceSize,
                 dst_offset: 0,
                 size: index_size as DeviceSize,
             }];
-            device.cmd_copy_buffer(cmd, *staging_buf, *index_buffer, &cpy);
+            device.cmd_copy_buffer(cmd, **staging_buf, *index_buffer, &cpy);
+            let read = (
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::INDEX_READ,
+            );
+            let mut buffers: SmallVec<[vk::Buffer; 3]> = smallvec![*vertex_buffer, *index_buffer];
+            buffers.extend(skin_buffer.as_ref().map(|buffer| **buffer));
+            upload::hand_over_buffers(&device, &commands, &buffers, read)?;
             device.end_command_buffer(cmd)?;
-
-            let submit_info = [vk::SubmitInfo::builder().command_buffers(&[cmd]).build()];
-            device.queue_submit(queue, &submit_info, vk::Fence::null())?;
-            device.queue_wait_idle(queue)?;
+            let transfer = Transfer::submit(device, &commands, staging_buf, permit)?;
 
             trace!(
-                "Loaded model with {} vertices, {} indices",
+                "Uploading model with {} vertices, {} indices",
                 vertices.len(),
                 indices.len()
             );
-            Ok(Mesh {
-                indices,
+            let bounds = vertices.iter().fold(
+                (
+                    nalgebra::Vector3::repeat(f32::INFINITY),
+                    nalgebra::Vector3::repeat(f32::NEG_INFINITY),
+                ),
+                |(min, max), vertex| (min.inf(&vertex.position), max.sup(&vertex.position)),
+            );
+            let mesh = Mesh {
+                index_count: indices.len() as u32,
+                index_type,
                 _vertices: vertices,
                 vertex_buffer,
+                skin_buffer,
                 index_buffer,
-            })
+                bounds,
+            };
+            Ok(Upload::new(mesh, Some(transfer)))
         }
     }
 
     pub(super) unsafe fn bind(&self, device: &ash::Device, cmd: vk::CommandBuffer) {
-        device.cmd_bind_index_buffer(cmd, *self.index_buffer, 0, vk::IndexType::UINT32);
+        device.cmd_bind_index_buffer(cmd, *self.index_buffer, 0, self.index_type);
         let bufs = [*self.vertex_buffer];
         device.cmd_bind_vertex_buffers(cmd, 0, &bufs, &[0]);
     }
 
+    /// Binds the skin vertices read by skinned materials, see [SKIN_BINDING]
+    pub(super) unsafe fn bind_skin(&self, device: &ash::Device, cmd: vk::CommandBuffer) {
+        if let Some(skin_buffer) = &self.skin_buffer {
+            let bufs = [**skin_buffer];
+            device.cmd_bind_vertex_buffers(cmd, SKIN_BINDING, &bufs, &[0]);
+        }
+    }
+
+    /// Whether the mesh has joints and weights and can be drawn with skinned materials
+    #[inline]
+    pub fn is_skinned(&self) -> bool {
+        self.skin_buffer.is_some()
+    }
+
     #[inline]
     pub(super) fn get_index_count(&self) -> u32 {
-        self.indices.len() as u32
+        self.index_count
+    }
+
+    /// Width of the indices in the mesh's index buffer
+    #[inline]
+    pub fn get_index_type(&self) -> vk::IndexType {
+        self.index_type
+    }
+
+    /// Minimum and maximum corner of the axis aligned bounding box around the model's vertices
+    #[inline]
+    pub fn get_bounds(&self) -> (nalgebra::Vector3<f32>, nalgebra::Vector3<f32>) {
+        self.bounds
+    }
+
+    /// Model space bounding box around the model's vertices, empty if there are none
+    #[inline]
+    pub fn get_aabb(&self) -> Aabb {
+        let (min, max) = self.bounds;
+        Aabb {
+            min: min.into(),
+            max: max.into(),
+        }
+    }
+}
+
+/// Combines several meshes into a single one by baking their transforms into the vertices.
+///
+/// Indices of each mesh are offset by the number of vertices before it,
+/// the combined mesh keeps 32-bit indices.
+///
+/// # Arguments
+///
+/// * `meshes`: vertices, indices and model matrix of each mesh
+///
+/// returns: the combined vertices and indices
+pub fn merge_meshes(
+    meshes: &[(Vec<Vertex>, Vec<u32>, nalgebra::Matrix4<f32>)],
+) -> (Vec<Vertex>, Vec<u32>) {
+    let vertex_count = meshes.iter().map(|(vertices, _, _)| vertices.len()).sum();
+    let index_count = meshes.iter().map(|(_, indices, _)| indices.len()).sum();
+    let mut merged_vertices = Vec::with_capacity(vertex_count);
+    let mut merged_indices = Vec::with_capacity(index_count);
+    for (vertices, indices, transform) in meshes {
+        let offset = merged_vertices.len() as u32;
+        // normals are transformed by the inverse transpose to stay correct under non uniform scale
+        let normal_matrix = transform
+            .fixed_slice::<3, 3>(0, 0)
+            .into_owned()
+            .try_inverse()
+            .unwrap_or_else(nalgebra::Matrix3::identity)
+            .transpose();
+        merged_vertices.extend(vertices.iter().map(|vertex| Vertex {
+            position: transform.transform_point(&vertex.position.into()).coords,
+            normal: nalgebra::UnitVector3::new_normalize(
+                normal_matrix * vertex.normal.into_inner(),
+            ),
+            uv: vertex.uv,
+        }));
+        merged_indices.extend(indices.iter().map(|index| index + offset));
+    }
+    (merged_vertices, merged_indices)
+}
+
+/// Reads the vertices and indices of a Wavefront obj model.
+///
+/// Texture coordinates are only read if every face has them, otherwise they are all zero.
+/// Their v coordinate is flipped, obj's origin is the bottom left corner of a texture
+/// while vulkan's is the top left.
+/// Missing or invalid normals are replaced with an up facing normal.
+///
+/// returns: the vertices, the indices and whether all of the model's normals were valid
+pub(crate) fn parse_obj(data: &[u8]) -> Result<(Vec<Vertex>, Vec<u32>, bool), ObjError> {
+    let textured: ObjResult<Obj<TexturedVertex>> = load_obj(data);
+    let (vertices, indices) = match textured {
+        Ok(obj) => (
+            obj.vertices
+                .into_iter()
+                .map(|vertex| {
+                    let [u, v, _] = vertex.texture;
+                    (vertex.position, vertex.normal, [u, 1. - v])
+                })
+                .collect::<Vec<_>>(),
+            wide_indices(obj.indices),
+        ),
+        // faces without texture coordinates
+        Err(_) => match load_obj::<obj::Vertex, _, u16>(data) {
+            Ok(obj) => (
+                obj.vertices
+                    .into_iter()
+                    .map(|vertex| (vertex.position, vertex.normal, [0.; 2]))
+                    .collect(),
+                wide_indices(obj.indices),
+            ),
+            // faces without normals, the zero normals are invalid so they get recomputed
+            Err(_) => match raw::parse_obj(data).ok().and_then(textured_without_normals) {
+                Some(textured) => textured,
+                None => {
+                    let obj: Obj<Position> = load_obj(data)?;
+                    (
+                        obj.vertices
+                            .into_iter()
+                            .map(|vertex| (vertex.position, [0.; 3], [0.; 2]))
+                            .collect(),
+                        wide_indices(obj.indices),
+                    )
+                }
+            },
+        },
+    };
+    let mut valid_normals = true;
+    let vertices = vertices
+        .into_iter()
+        .map(|(position, normal, uv)| {
+            let normal = unit_normal(normal);
+            valid_normals &= normal.is_some();
+            Vertex {
+                position: nalgebra::Vector3::from(position),
+                normal: normal.unwrap_or_else(nalgebra::Vector3::y_axis),
+                uv: nalgebra::Vector2::from(uv),
+            }
+        })
+        .collect();
+    Ok((vertices, indices, valid_normals))
+}
+
+fn wide_indices(indices: Vec<u16>) -> Vec<u32> {
+    indices.into_iter().map(u32::from).collect()
+}
+
+/// Position, zero normal and flipped texture coordinate of every vertex of an obj model
+/// whose faces all have texture coordinates but no normals, which obj-rs has no vertex type for.
+/// Faces share the vertices with the same position and texture coordinate
+/// and polygons are split into triangle fans.
+///
+/// returns: the vertices and indices, None if any face isn't made of positions
+/// and texture coordinates or refers to one that doesn't exist
+#[allow(clippy::type_complexity)]
+fn textured_without_normals(
+    obj: RawObj,
+) -> Option<(Vec<([f32; 3], [f32; 3], [f32; 2])>, Vec<u32>)> {
+    let mut vertices = Vec::new();
+    let mut shared = HashMap::new();
+    let mut indices = Vec::new();
+    for polygon in &obj.polygons {
+        let corners = match polygon {
+            Polygon::PT(corners) if corners.len() >= 3 => corners,
+            _ => return None,
+        };
+        let mut fan = Vec::with_capacity(corners.len());
+        for &(position, texture) in corners {
+            let index = match shared.get(&(position, texture)) {
+                Some(&index) => index,
+                None => {
+                    let &(x, y, z, _) = obj.positions.get(position)?;
+                    let &(u, v, _) = obj.tex_coords.get(texture)?;
+                    let index = vertices.len() as u32;
+                    vertices.push(([x, y, z], [0.; 3], [u, 1. - v]));
+                    shared.insert((position, texture), index);
+                    index
+                }
+            };
+            fan.push(index);
+        }
+        for pair in fan[1..].windows(2) {
+            indices.extend([fan[0], pair[0], pair[1]]);
+        }
+    }
+    Some((vertices, indices))
+}
+
+/// Reads the vertices and indices of the first primitive of the first mesh of a glTF file,
+/// along with the joints and weights of skinned vertices.
+///
+/// Missing or invalid normals are replaced with an up facing normal,
+/// missing texture coordinates are all zero.
+/// Fails if the primitive isn't made of indexed triangles
+///
+/// returns: the vertices, the skin vertices, empty if the primitive has no joints,
+/// the indices and whether all of the model's normals were valid
+#[allow(clippy::type_complexity)]
+pub(crate) fn parse_gltf(
+    document: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+) -> Result<(Vec<Vertex>, Vec<SkinVertex>, Vec<u32>, bool)> {
+    let primitive = document
+        .meshes()
+        .next()
+        .and_then(|mesh| mesh.primitives().next())
+        .ok_or_else(|| anyhow!("glTF file contains no meshes"))?;
+    if primitive.mode() != gltf::mesh::Mode::Triangles {
+        bail!(
+            "glTF primitive is made of {:?} instead of triangles",
+            primitive.mode()
+        );
+    }
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let indices: Vec<u32> = reader
+        .read_indices()
+        .ok_or_else(|| anyhow!("glTF primitive has no indices"))?
+        .into_u32()
+        .collect();
+    let positions: Vec<[f32; 3]> = reader
+        .read_positions()
+        .ok_or_else(|| anyhow!("glTF primitive has no positions"))?
+        .collect();
+    if let Some(index) = indices.iter().find(|&&it| it as usize >= positions.len()) {
+        bail!(
+            "glTF index {index} is out of bounds of {} vertices",
+            positions.len()
+        );
+    }
+    let mut normals = reader.read_normals().into_iter().flatten();
+    let mut uvs = reader
+        .read_tex_coords(0)
+        .map(|uvs| uvs.into_f32())
+        .into_iter()
+        .flatten();
+    let skin = match reader.read_joints(0) {
+        Some(joints) => {
+            let mut weights = reader
+                .read_weights(0)
+                .map(|weights| weights.into_f32())
+                .into_iter()
+                .flatten();
+            let mut joints = joints.into_u16();
+            (0..positions.len())
+                .map(|_| SkinVertex {
+                    joints: joints.next().unwrap_or_default().map(u32::from),
+                    weights: nalgebra::Vector4::from(weights.next().unwrap_or_default()),
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    let mut valid_normals = true;
+    let vertices = positions
+        .into_iter()
+        .map(|position| {
+            let normal = normals.next().and_then(unit_normal);
+            valid_normals &= normal.is_some();
+            Vertex {
+                position: nalgebra::Vector3::from(position),
+                normal: normal.unwrap_or_else(nalgebra::Vector3::y_axis),
+                uv: nalgebra::Vector2::from(uvs.next().unwrap_or_default()),
+            }
+        })
+        .collect();
+    Ok((vertices, skin, indices, valid_normals))
+}
+
+/// Normalized `normal`, None if it has no direction
+fn unit_normal(normal: [f32; 3]) -> Option<nalgebra::UnitVector3<f32>> {
+    nalgebra::UnitVector3::try_new(nalgebra::Vector3::from(normal), f32::EPSILON)
+        .filter(|normal| normal.iter().all(|it| it.is_finite()))
+}
+
+/// 16 bit indices if every one of `vertex_count` vertices can be indexed with them,
+/// otherwise 32 bit
+fn index_type(vertex_count: usize) -> vk::IndexType {
+    if vertex_count <= u16::MAX as usize {
+        vk::IndexType::UINT16
+    } else {
+        vk::IndexType::UINT32
+    }
+}
+
+/// Native endian bytes of the indices narrowed to the width of `index_type`,
+/// as they are copied to the index buffer
+fn index_bytes(indices: &[u32], index_type: vk::IndexType) -> Vec<u8> {
+    if index_type == vk::IndexType::UINT16 {
+        indices
+            .iter()
+            .flat_map(|&index| (index as u16).to_ne_bytes())
+            .collect()
+    } else {
+        indices
+            .iter()
+            .flat_map(|index| index.to_ne_bytes())
+            .collect()
+    }
+}
+
+/// Replaces the normals of all vertices with smooth normals,
+/// averaged from the normals of the triangles they are part of weighted by the triangle's area.
+///
+/// Vertices not referenced by any non degenerate triangle get an up facing normal
+pub(crate) fn recompute_normals(vertices: &mut [Vertex], indices: &[u32]) {
+    let mut normals = vec![nalgebra::Vector3::<f32>::zeros(); vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|it| it as usize);
+        let (pa, pb, pc) = (
+            vertices[a].position,
+            vertices[b].position,
+            vertices[c].position,
+        );
+        // the cross product's length is twice the triangle's area
+        let normal = (pb - pa).cross(&(pc - pa));
+        normals[a] += normal;
+        normals[b] += normal;
+        normals[c] += normal;
+    }
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        vertex.normal = nalgebra::UnitVector3::try_new(normal, f32::EPSILON)
+            .unwrap_or_else(nalgebra::Vector3::y_axis);
     }
 }
 
+/// Cube with sides of length one centered on the origin, with flat normals
+/// and counter clockwise front faces
+///
+/// returns: the cube's vertices and indices
+pub(crate) fn unit_cube() -> (Vec<Vertex>, Vec<u32>) {
+    use nalgebra::Vector3;
+    // each face's normal and two edge directions whose cross product is the normal
+    let faces = [
+        (Vector3::x_axis(), Vector3::y(), Vector3::z()),
+        (-Vector3::x_axis(), Vector3::z(), Vector3::y()),
+        (Vector3::y_axis(), Vector3::z(), Vector3::x()),
+        (-Vector3::y_axis(), Vector3::x(), Vector3::z()),
+        (Vector3::z_axis(), Vector3::x(), Vector3::y()),
+        (-Vector3::z_axis(), Vector3::y(), Vector3::x()),
+    ];
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (normal, u, v) in faces {
+        let offset = vertices.len() as u32;
+        for (s, t) in [(0., 0.), (1., 0.), (1., 1.), (0., 1.)] {
+            vertices.push(Vertex {
+                position: normal.into_inner() * 0.5 + u * (s - 0.5) + v * (t - 0.5),
+                normal,
+                uv: nalgebra::Vector2::new(s, t),
+            });
+        }
+        indices.extend([0, 1, 2, 0, 2, 3].map(|index| index + offset));
+    }
+    (vertices, indices)
+}
+
 impl Vertex {
     /// Gets the vertex input and attribute descriptions
     pub(crate) fn get_vertex_description() -> (
@@ -180,4 +631,222 @@ impl Vertex {
 
         (input, attributes)
     }
+
+    /// Binding and attributes of the per instance model matrices used by instanced materials,
+    /// each column of the matrix takes up one attribute location starting at 3
+    pub(crate) fn get_instance_description() -> (
+        vk::VertexInputBindingDescription,
+        [vk::VertexInputAttributeDescription; 4],
+    ) {
+        let stride = std::mem::size_of::<nalgebra::Matrix4<f32>>() as u32;
+        let input = vk::VertexInputBindingDescription::builder()
+            .binding(1)
+            .stride(stride)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build();
+        let attributes = [0, 1, 2, 3].map(|column| {
+            vk::VertexInputAttributeDescription::builder()
+                .binding(1)
+                .location(3 + column)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(column * stride / 4)
+                .build()
+        });
+        (input, attributes)
+    }
+
+    /// Binding and attributes of the [skin vertices](SkinVertex) read by skinned materials,
+    /// the bone indices and weights are at locations 7 and 8 after the instance attributes
+    pub(crate) fn get_skinning_description() -> (
+        vk::VertexInputBindingDescription,
+        [vk::VertexInputAttributeDescription; 2],
+    ) {
+        let input = vk::VertexInputBindingDescription::builder()
+            .binding(SKIN_BINDING)
+            .stride(std::mem::size_of::<SkinVertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build();
+        let attributes = [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(SKIN_BINDING)
+                .location(7)
+                .format(vk::Format::R32G32B32A32_UINT)
+                .offset(offset_of!(SkinVertex, joints) as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(SKIN_BINDING)
+                .location(8)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(offset_of!(SkinVertex, weights) as u32)
+                .build(),
+        ];
+        (input, attributes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ash::vk;
+    use nalgebra::{Matrix4, UnitVector3, Vector2, Vector3};
+
+    use crate::vulkan::mesh::{
+        index_bytes, index_type, merge_meshes, parse_gltf, parse_obj, recompute_normals, unit_cube,
+        SkinVertex, Vertex,
+    };
+
+    /// Binary glTF file of a single triangle with the given primitive attributes and indices
+    fn triangle_glb(primitive: &str) -> Vec<u8> {
+        let json = format!(
+            r#"{{"asset":{{"version":"2.0"}},"buffers":[{{"byteLength":42}}],
+            "bufferViews":[{{"buffer":0,"byteLength":36}},{{"buffer":0,"byteOffset":36,"byteLength":6}}],
+            "accessors":[
+            {{"bufferView":0,"componentType":5126,"count":3,"type":"VEC3","min":[0,0,0],"max":[1,1,0]}},
+            {{"bufferView":1,"componentType":5123,"count":3,"type":"SCALAR"}}],
+            "meshes":[{{"primitives":[{primitive}]}}]}}"#
+        );
+        let mut json = json.into_bytes();
+        json.resize((json.len() + 3) / 4 * 4, b' ');
+        let mut bin = [[0f32, 0., 0.], [1., 0., 0.], [0., 1., 0.]]
+            .iter()
+            .flatten()
+            .flat_map(|it| it.to_le_bytes())
+            .chain([0u16, 1, 2].iter().flat_map(|it| it.to_le_bytes()))
+            .collect::<Vec<_>>();
+        bin.resize(44, 0);
+        let mut glb = b"glTF".to_vec();
+        glb.extend(2u32.to_le_bytes());
+        glb.extend((12 + 8 + json.len() as u32 + 8 + bin.len() as u32).to_le_bytes());
+        glb.extend((json.len() as u32).to_le_bytes());
+        glb.extend(b"JSON");
+        glb.extend(json);
+        glb.extend((bin.len() as u32).to_le_bytes());
+        glb.extend(b"BIN\0");
+        glb.extend(bin);
+        glb
+    }
+
+    #[test]
+    fn small_meshes_use_16_bit_indices() {
+        assert_eq!(index_type(3), vk::IndexType::UINT16);
+        assert_eq!(index_type(u16::MAX as usize), vk::IndexType::UINT16);
+        assert_eq!(index_type(u16::MAX as usize + 1), vk::IndexType::UINT32);
+        let indices = [0, 1, u16::MAX as u32];
+        let narrow = index_bytes(&indices, vk::IndexType::UINT16);
+        assert_eq!(narrow.len(), 6);
+        assert_eq!(&narrow[4..], u16::MAX.to_ne_bytes());
+        assert_eq!(index_bytes(&indices, vk::IndexType::UINT32).len(), 12);
+    }
+
+    #[test]
+    fn skin_is_not_part_of_vertices() {
+        assert_eq!(std::mem::size_of::<Vertex>(), 32);
+        assert_eq!(std::mem::size_of::<SkinVertex>(), 32);
+    }
+
+    #[test]
+    fn recomputed_normals() {
+        let vertex = |x, y| Vertex {
+            position: Vector3::new(x, y, 0.),
+            normal: UnitVector3::new_unchecked(Vector3::zeros()),
+            uv: Vector2::zeros(),
+        };
+        let mut vertices = [vertex(0., 0.), vertex(1., 0.), vertex(0., 1.), vertex(5., 5.)];
+        recompute_normals(&mut vertices, &[0, 1, 2]);
+        for vertex in &vertices[..3] {
+            assert!((vertex.normal.into_inner() - Vector3::z()).norm() < 1e-6);
+        }
+        // not part of any triangle
+        assert_eq!(vertices[3].normal, Vector3::y_axis());
+    }
+
+    #[test]
+    fn obj_texture_coordinates() {
+        let textured = b"v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 0.25\nvn 0 0 1\n\
+            f 1/1/1 2/2/1 3/3/1\n";
+        let (vertices, indices, valid_normals) = parse_obj(textured).unwrap();
+        assert!(valid_normals);
+        assert_eq!(indices, vec![0, 1, 2]);
+        let uvs = vertices.iter().map(|vertex| vertex.uv).collect::<Vec<_>>();
+        assert_eq!(
+            uvs,
+            vec![
+                Vector2::new(0., 1.),
+                Vector2::new(1., 1.),
+                Vector2::new(0., 0.75)
+            ]
+        );
+        assert_eq!(vertices[1].position, Vector3::x());
+
+        let untextured = b"v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\n";
+        let (vertices, _, _) = parse_obj(untextured).unwrap();
+        assert!(vertices.iter().all(|vertex| vertex.uv == Vector2::zeros()));
+
+        // texture coordinates without normals, the quad is split into two triangles
+        let without_normals = b"v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\n\
+            vt 0 1\nf 1/1 2/2 3/3 4/4\n";
+        let (vertices, indices, valid_normals) = parse_obj(without_normals).unwrap();
+        assert!(!valid_normals);
+        assert_eq!(indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(vertices[2].position, Vector3::new(1., 1., 0.));
+        assert_eq!(vertices[2].uv, Vector2::new(1., 0.));
+        assert_eq!(vertices[3].uv, Vector2::new(0., 0.));
+    }
+
+    #[test]
+    fn obj_without_normals() {
+        let data = b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+        let (vertices, indices, valid_normals) = parse_obj(data).unwrap();
+        assert!(!valid_normals);
+        assert_eq!(indices, vec![0, 1, 2]);
+        assert_eq!(vertices[2].position, Vector3::y());
+        assert_eq!(vertices[2].normal, Vector3::y_axis());
+        assert_eq!(vertices[2].uv, Vector2::zeros());
+    }
+
+    #[test]
+    fn gltf_without_normals() {
+        let glb = triangle_glb(r#"{"attributes":{"POSITION":0},"indices":1}"#);
+        let (document, buffers, _) = gltf::import_slice(&glb).unwrap();
+        let (vertices, skin, indices, valid_normals) = parse_gltf(&document, &buffers).unwrap();
+        assert!(!valid_normals);
+        assert!(skin.is_empty());
+        assert_eq!(indices, vec![0, 1, 2]);
+        assert_eq!(vertices[2].position, Vector3::y());
+        assert_eq!(vertices[2].uv, Vector2::zeros());
+
+        let glb = triangle_glb(r#"{"attributes":{"POSITION":0}}"#);
+        let (document, buffers, _) = gltf::import_slice(&glb).unwrap();
+        assert!(parse_gltf(&document, &buffers).is_err());
+    }
+
+    #[test]
+    fn merged_meshes() {
+        let vertex = Vertex {
+            position: Vector3::new(1., 0., 0.),
+            normal: Vector3::x_axis(),
+            uv: Vector2::zeros(),
+        };
+        let translation = Matrix4::new_translation(&Vector3::new(0., 2., 0.));
+        let (vertices, indices) = merge_meshes(&[
+            (vec![vertex; 3], vec![0, 1, 2], Matrix4::identity()),
+            (vec![vertex; 3], vec![2, 1, 0], translation),
+        ]);
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(indices, vec![0, 1, 2, 5, 4, 3]);
+        assert_eq!(vertices[3].position, Vector3::new(1., 2., 0.));
+        assert_eq!(vertices[3].normal, Vector3::x_axis());
+    }
+
+    #[test]
+    fn unit_cube_faces_outwards() {
+        let (vertices, indices) = unit_cube();
+        assert_eq!(indices.len(), 36);
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|it| vertices[it as usize]);
+            let normal = (b.position - a.position).cross(&(c.position - a.position));
+            assert!(normal.dot(&a.normal) > 0.);
+            assert!(normal.dot(&a.position) > 0.);
+            assert!(a.position.iter().all(|it| it.abs() == 0.5));
+        }
+    }
 }
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+
+use anyhow::Result;
+use ash::vk;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::materials::{AddressMode, Filter, SamplerDefinition};
+use crate::vulkan::engine::deletion::{self, Resource};
+
+/// Sampler object that can be bound to separate `SAMPLER` descriptors
+/// and shared between any number of sampled images
+pub struct Sampler {
+    pub(super) sampler: vk::Sampler,
+    definition: SamplerDefinition,
+    device: Arc<ash::Device>,
+}
+
+static CACHE: Lazy<Mutex<HashMap<SamplerDefinition, Weak<Sampler>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+impl Sampler {
+    /// Returns the sampler with the given definition, creating it if no equal sampler is alive
+    ///
+    /// # Arguments
+    ///
+    /// * `definition`: sampling parameters
+    /// * `device`: device handle
+    /// * `max_anisotropy`: anisotropy used if the definition is anisotropic
+    pub(crate) fn get(
+        definition: &SamplerDefinition,
+        device: Arc<ash::Device>,
+        max_anisotropy: f32,
+    ) -> Result<Arc<Self>> {
+        let mut cache = CACHE.lock();
+        if let Some(sampler) = cache.get(definition).and_then(Weak::upgrade) {
+            return Ok(sampler);
+        }
+        let filter = match definition.filter {
+            Filter::Nearest => vk::Filter::NEAREST,
+            Filter::Linear => vk::Filter::LINEAR,
+        };
+        let address_mode = match definition.address_mode {
+            AddressMode::Repeat => vk::SamplerAddressMode::REPEAT,
+            AddressMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+            AddressMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        };
+        let anisotropic = definition.anisotropic && definition.filter == Filter::Linear;
+        let create_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(filter)
+            .min_filter(filter)
+            .address_mode_u(address_mode)
+            .address_mode_v(address_mode)
+            .address_mode_w(address_mode)
+            .anisotropy_enable(anisotropic)
+            .max_anisotropy(if anisotropic { max_anisotropy } else { 1. })
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .mip_lod_bias(0.)
+            .min_lod(0.)
+            .max_lod(vk::LOD_CLAMP_NONE);
+        let sampler = unsafe { device.create_sampler(&create_info, None)? };
+        let sampler = Arc::new(Sampler {
+            sampler,
+            definition: *definition,
+            device,
+        });
+        cache.insert(*definition, Arc::downgrade(&sampler));
+        Ok(sampler)
+    }
+
+    #[inline]
+    pub fn definition(&self) -> &SamplerDefinition {
+        &self.definition
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        let mut cache = CACHE.lock();
+        // a new sampler may have replaced the expired entry already
+        if cache
+            .get(&self.definition)
+            .map_or(false, |it| it.strong_count() == 0)
+        {
+            cache.remove(&self.definition);
+        }
+        deletion::queue(self.device.clone(), Resource::Sampler(self.sampler));
+    }
+}
@@ -1,9 +1,48 @@
 #version 450
 
 layout(location = 0) out vec4 outColor;
+layout(location = 1) out uvec2 objectId;
 
-layout(location = 0) in vec4 fragColor;
+layout(location = 0) in vec3 worldNormal;
+layout(location = 1) in vec4 shadowCoord;
+layout(location = 2) in vec2 uv;
+
+layout(set = 0, binding = 0) uniform ubo {
+    mat4 view;
+    mat4 projection;
+    mat4 orthographic;
+    mat4 lightSpace;
+    // direction the directional light travels in
+    vec4 lightDirection;
+    vec4 lightColor;
+} uboData;
+layout(set = 0, binding = 1) uniform sampler2DShadow shadowMap;
+// the material's texture, white for materials without one, see TEXTURE_BINDING
+layout(set = 2, binding = 1) uniform sampler2D tex;
+
+// shading model and emissive color of the material, see MaterialDefinition::shading_constants
+layout(constant_id = 100) const bool UNLIT = false;
+layout(constant_id = 101) const float EMISSIVE_R = 0.0;
+layout(constant_id = 102) const float EMISSIVE_G = 0.0;
+layout(constant_id = 103) const float EMISSIVE_B = 0.0;
+
+// id of the drawn object plus one split into its low and high half, zero for untagged draws
+layout(push_constant) uniform constants {
+    layout(offset = 112) uvec2 id;
+} pushConstants;
+
+const vec3 AMBIENT = vec3(0.75);
 
 void main() {
-    outColor = fragColor;
+    vec4 emissive = vec4(EMISSIVE_R, EMISSIVE_G, EMISSIVE_B, 0.0);
+    vec4 albedo = texture(tex, uv);
+    if (UNLIT) {
+        outColor = albedo + emissive;
+    } else {
+        vec3 projected = shadowCoord.xyz / shadowCoord.w;
+        float shadow = texture(shadowMap, vec3(projected.xy * 0.5 + 0.5, projected.z));
+        float diffuse = max(dot(normalize(worldNormal), -uboData.lightDirection.xyz), 0.0);
+        outColor = vec4(AMBIENT + uboData.lightColor.rgb * diffuse * shadow, 1.0) * albedo + emissive;
+    }
+    objectId = pushConstants.id;
 }
\ No newline at end of file
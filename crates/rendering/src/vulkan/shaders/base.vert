@@ -7,20 +7,27 @@ layout (location=2) in vec2 uv;
 layout (set=0, binding=0) uniform ubo {
     mat4 view;
     mat4 projection;
+    mat4 orthographic;
+    mat4 light_space;
+    vec4 light_direction;
+    vec4 light_color;
 } ubo_data;
 
 layout (push_constant) uniform constants {
     mat4 model;
+    // inverse transpose of the model matrix, keeps normals perpendicular under non-uniform scale
+    mat3 normal_matrix;
 } push_constants;
 
-layout(location = 0) out vec4 frag_color;
+layout(location = 0) out vec3 world_normal;
+layout(location = 1) out vec4 shadow_coord;
+layout(location = 2) out vec2 frag_uv;
 
 
 void main() {
-    mat4 transform = ubo_data.projection * ubo_data.view * push_constants.model;
-    gl_Position = transform * vec4(position, 1.0);
-
-    vec4 amient = vec4(0.75, 0.75, 0.75, 1.0);
-    vec4 diffuse = vec4(max(dot(vec3(0.24525, -0.919709, -0.30656966), -normal), 0) * vec3(1.0, 1.0, 1.0), 1);
-    frag_color = amient + diffuse;
-}
\ No newline at end of file
+    vec4 world_position = push_constants.model * vec4(position, 1.0);
+    gl_Position = ubo_data.projection * ubo_data.view * world_position;
+    shadow_coord = ubo_data.light_space * world_position;
+    frag_uv = uv;
+    world_normal = push_constants.normal_matrix * normal;
+}
@@ -0,0 +1,33 @@
+#version 450
+
+layout (set=0, binding=0) uniform ubo {
+    mat4 view;
+    mat4 projection;
+    mat4 orthographic;
+    mat4 light_space;
+} ubo_data;
+
+// maps the unit cube onto the bounding box in world space
+layout (push_constant) uniform constants {
+    mat4 model;
+} push_constants;
+
+const vec3 corners[8] = vec3[](
+    vec3(0, 0, 0), vec3(1, 0, 0), vec3(0, 1, 0), vec3(1, 1, 0),
+    vec3(0, 0, 1), vec3(1, 0, 1), vec3(0, 1, 1), vec3(1, 1, 1)
+);
+
+// the box is drawn without culling, so the winding of the faces does not matter
+const int indices[36] = int[](
+    0, 1, 2, 2, 1, 3,
+    4, 6, 5, 5, 6, 7,
+    0, 2, 4, 4, 2, 6,
+    1, 5, 3, 3, 5, 7,
+    0, 4, 1, 1, 4, 5,
+    2, 3, 6, 6, 3, 7
+);
+
+void main() {
+    vec3 corner = corners[indices[gl_VertexIndex]];
+    gl_Position = ubo_data.projection * ubo_data.view * push_constants.model * vec4(corner, 1.0);
+}
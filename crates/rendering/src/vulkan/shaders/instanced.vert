@@ -0,0 +1,29 @@
+#version 450
+
+layout (location=0) in vec3 position;
+layout (location=1) in vec3 normal;
+layout (location=2) in vec2 uv;
+// per instance model matrix, takes up locations 3 to 6
+layout (location=3) in mat4 model;
+
+layout (set=0, binding=0) uniform ubo {
+    mat4 view;
+    mat4 projection;
+    mat4 orthographic;
+    mat4 light_space;
+    vec4 light_direction;
+    vec4 light_color;
+} ubo_data;
+
+layout(location = 0) out vec3 world_normal;
+layout(location = 1) out vec4 shadow_coord;
+layout(location = 2) out vec2 frag_uv;
+
+
+void main() {
+    vec4 world_position = model * vec4(position, 1.0);
+    gl_Position = ubo_data.projection * ubo_data.view * world_position;
+    shadow_coord = ubo_data.light_space * world_position;
+    frag_uv = uv;
+    world_normal = mat3(transpose(inverse(model))) * normal;
+}
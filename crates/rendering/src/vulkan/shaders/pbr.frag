@@ -0,0 +1,109 @@
+#version 450
+
+layout(location = 0) out vec4 outColor;
+layout(location = 1) out uvec2 objectId;
+
+layout(location = 0) in vec3 worldPosition;
+layout(location = 1) in vec3 worldNormal;
+layout(location = 2) in vec4 shadowCoord;
+layout(location = 3) in vec3 cameraPosition;
+
+layout(set = 0, binding = 0) uniform ubo {
+    mat4 view;
+    mat4 projection;
+    mat4 orthographic;
+    mat4 lightSpace;
+    // direction the directional light travels in
+    vec4 lightDirection;
+    vec4 lightColor;
+} uboData;
+layout(set = 0, binding = 1) uniform sampler2DShadow shadowMap;
+// mip levels of the environment are blurred over wider cones, see Environment
+layout(set = 0, binding = 2) uniform samplerCube environmentMap;
+layout(set = 0, binding = 3) uniform environment {
+    // spherical harmonics of the environment's irradiance divided by pi
+    vec4 sh[9];
+    float maxLod;
+} env;
+
+// the material's factors, see PbrUniform
+layout(set = 2, binding = 0) uniform factors {
+    vec4 baseColor;
+    vec4 emissive;
+    float metallic;
+    float roughness;
+} material;
+
+layout(push_constant) uniform constants {
+    layout(offset = 112) uvec2 id;
+} pushConstants;
+
+const float PI = 3.14159265359;
+// the brdf's diffuse term is divided by pi, so the light is scaled to look as bright as in the base shaders
+const float LIGHT_INTENSITY = 3.0;
+
+// GGX normal distribution
+float distribution(float nDotH, float alpha) {
+    float alpha2 = alpha * alpha;
+    float d = nDotH * nDotH * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * d * d);
+}
+
+// Smith geometry term with the Schlick-GGX approximation for direct light
+float geometry(float nDotV, float nDotL, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    return nDotV / (nDotV * (1.0 - k) + k) * nDotL / (nDotL * (1.0 - k) + k);
+}
+
+vec3 fresnel(float cosTheta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+vec3 fresnelRoughness(float cosTheta, vec3 f0, float roughness) {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+// diffuse light reflected by a white surface facing n
+vec3 irradiance(vec3 n) {
+    return 0.282095 * env.sh[0].rgb
+        + 0.488603 * (env.sh[1].rgb * n.y + env.sh[2].rgb * n.z + env.sh[3].rgb * n.x)
+        + 1.092548 * (env.sh[4].rgb * n.x * n.y + env.sh[5].rgb * n.y * n.z + env.sh[7].rgb * n.x * n.z)
+        + 0.315392 * env.sh[6].rgb * (3.0 * n.z * n.z - 1.0)
+        + 0.546274 * env.sh[8].rgb * (n.x * n.x - n.y * n.y);
+}
+
+// scale and bias of f0 of the specular environment brdf, Karis' analytic approximation
+vec2 environmentBrdf(float nDotV, float roughness) {
+    const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+    const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+    vec4 r = roughness * c0 + c1;
+    float a004 = min(r.x * r.x, exp2(-9.28 * nDotV)) * r.x + r.y;
+    return vec2(-1.04, 1.04) * a004 + r.zw;
+}
+
+void main() {
+    vec3 albedo = material.baseColor.rgb;
+    float roughness = clamp(material.roughness, 0.04, 1.0);
+    vec3 n = normalize(worldNormal);
+    vec3 v = normalize(cameraPosition - worldPosition);
+    vec3 l = -uboData.lightDirection.xyz;
+    vec3 h = normalize(v + l);
+    float nDotV = max(dot(n, v), 1e-4);
+    float nDotL = max(dot(n, l), 0.0);
+
+    vec3 f0 = mix(vec3(0.04), albedo, material.metallic);
+    vec3 f = fresnel(max(dot(h, v), 0.0), f0);
+    vec3 specular = distribution(max(dot(n, h), 0.0), roughness * roughness)
+        * geometry(nDotV, nDotL, roughness) * f / (4.0 * nDotV * max(nDotL, 1e-4));
+    vec3 diffuse = (1.0 - f) * (1.0 - material.metallic) * albedo / PI;
+
+    vec3 projected = shadowCoord.xyz / shadowCoord.w;
+    float shadow = texture(shadowMap, vec3(projected.xy * 0.5 + 0.5, projected.z));
+    vec3 kd = (1.0 - fresnelRoughness(nDotV, f0, roughness)) * (1.0 - material.metallic);
+    vec2 brdf = environmentBrdf(nDotV, roughness);
+    vec3 reflected = textureLod(environmentMap, reflect(-v, n), roughness * env.maxLod).rgb;
+    vec3 ambient = kd * albedo * irradiance(n) + reflected * (f0 * brdf.x + brdf.y);
+    vec3 color = ambient + (diffuse + specular) * uboData.lightColor.rgb * LIGHT_INTENSITY * nDotL * shadow;
+    outColor = vec4(color + material.emissive.rgb, material.baseColor.a);
+    objectId = pushConstants.id;
+}
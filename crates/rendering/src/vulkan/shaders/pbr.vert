@@ -0,0 +1,33 @@
+#version 450
+
+layout (location=0) in vec3 position;
+layout (location=1) in vec3 normal;
+layout (location=2) in vec2 uv;
+
+layout (set=0, binding=0) uniform ubo {
+    mat4 view;
+    mat4 projection;
+    mat4 orthographic;
+    mat4 light_space;
+} ubo_data;
+
+layout (push_constant) uniform constants {
+    mat4 model;
+    // inverse transpose of the model matrix, keeps normals perpendicular under non-uniform scale
+    mat3 normal_matrix;
+} push_constants;
+
+layout(location = 0) out vec3 world_position;
+layout(location = 1) out vec3 world_normal;
+layout(location = 2) out vec4 shadow_coord;
+// inverting the view matrix once per vertex is cheaper than once per fragment
+layout(location = 3) out vec3 camera_position;
+
+void main() {
+    vec4 world = push_constants.model * vec4(position, 1.0);
+    gl_Position = ubo_data.projection * ubo_data.view * world;
+    world_position = world.xyz;
+    world_normal = push_constants.normal_matrix * normal;
+    shadow_coord = ubo_data.light_space * world;
+    camera_position = inverse(ubo_data.view)[3].xyz;
+}
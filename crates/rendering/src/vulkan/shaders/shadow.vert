@@ -0,0 +1,18 @@
+#version 450
+
+layout (location=0) in vec3 position;
+
+layout (set=0, binding=0) uniform ubo {
+    mat4 view;
+    mat4 projection;
+    mat4 orthographic;
+    mat4 light_space;
+} ubo_data;
+
+layout (push_constant) uniform constants {
+    mat4 model;
+} push_constants;
+
+void main() {
+    gl_Position = ubo_data.light_space * push_constants.model * vec4(position, 1.0);
+}
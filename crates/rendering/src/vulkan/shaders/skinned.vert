@@ -0,0 +1,47 @@
+#version 450
+
+layout (location=0) in vec3 position;
+layout (location=1) in vec3 normal;
+layout (location=2) in vec2 uv;
+// locations 3 to 6 are used by the instance matrices of instanced materials,
+// joints and weights are read from the mesh's separate skin vertex buffer
+layout (location=7) in uvec4 joints;
+layout (location=8) in vec4 weights;
+
+layout (set=0, binding=0) uniform ubo {
+    mat4 view;
+    mat4 projection;
+    mat4 orthographic;
+    mat4 light_space;
+    vec4 light_direction;
+    vec4 light_color;
+} ubo_data;
+
+// model space bone matrices of the skeleton being drawn
+layout (set=1, binding=0) readonly buffer bone_data {
+    mat4 bones[];
+} skeleton;
+
+layout (push_constant) uniform constants {
+    mat4 model;
+    // inverse transpose of the model matrix, keeps normals perpendicular under non-uniform scale
+    mat3 normal_matrix;
+} push_constants;
+
+layout(location = 0) out vec3 world_normal;
+layout(location = 1) out vec4 shadow_coord;
+layout(location = 2) out vec2 frag_uv;
+
+
+void main() {
+    mat4 skin = weights.x * skeleton.bones[joints.x]
+        + weights.y * skeleton.bones[joints.y]
+        + weights.z * skeleton.bones[joints.z]
+        + weights.w * skeleton.bones[joints.w];
+    vec4 world_position = push_constants.model * skin * vec4(position, 1.0);
+    gl_Position = ubo_data.projection * ubo_data.view * world_position;
+    shadow_coord = ubo_data.light_space * world_position;
+    frag_uv = uv;
+    // bones are rigid transforms, so they rotate normals like the vertices
+    world_normal = push_constants.normal_matrix * mat3(skin) * normal;
+}
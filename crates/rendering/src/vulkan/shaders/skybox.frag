@@ -0,0 +1,14 @@
+#version 450
+
+layout(location = 0) out vec4 outColor;
+layout(location = 1) out uvec2 objectId;
+
+layout(location = 0) in vec3 direction;
+
+layout(set = 1, binding = 0) uniform samplerCube skybox;
+
+void main() {
+    outColor = vec4(texture(skybox, direction).rgb, 1.0);
+    // the sky is never tagged
+    objectId = uvec2(0);
+}
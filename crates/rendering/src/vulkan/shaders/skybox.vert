@@ -0,0 +1,17 @@
+#version 450
+
+layout(location = 0) out vec3 direction;
+
+layout(set = 0, binding = 0) uniform ubo {
+    mat4 view;
+    mat4 projection;
+} uboData;
+
+void main() {
+    // a triangle covering the screen at the far plane, drawn without vertex buffers
+    vec2 position = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+    gl_Position = vec4(position, 1.0, 1.0);
+    // only the rotation of the view applies, the sky is infinitely far away
+    vec4 viewDirection = inverse(uboData.projection) * vec4(position, 1.0, 1.0);
+    direction = transpose(mat3(uboData.view)) * (viewDirection.xyz / viewDirection.w);
+}
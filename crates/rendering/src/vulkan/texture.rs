@@ -1,62 +1,375 @@
-use crate::vulkan::engine::alloc::{Buffer, Image};
+use crate::vulkan::dds::{self, CompressedTexture};
+use crate::vulkan::engine::alloc::{Image, StagingPool};
+use crate::vulkan::engine::deletion::{self, Resource};
+use crate::vulkan::engine::upload::{self, Ownership, Transfer, Upload, UploadCommands};
 use ash::vk;
 use ash::vk::DeviceSize;
-use png::Decoder;
-use std::fs::File;
+use std::mem::ManuallyDrop;
 use std::path::Path;
 use std::sync::Arc;
 use ash::prelude::VkResult;
 use vk_mem::Allocator;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use image::imageops::{self, FilterType};
+use image::{ImageFormat, Rgba, RgbaImage};
+use log::{info, warn};
+
+/// Side length in pixels of the squares of the placeholder checkerboard
+const CHECKER_SIZE: u32 = 4;
+/// Format of textures decoded from png, jpeg, bmp and tga images
+const RGBA_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+/// Image formats textures can be decoded from besides DDS
+const DECODED_FORMATS: [ImageFormat; 4] = [
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::Bmp,
+    ImageFormat::Tga,
+];
+/// Features [RGBA_FORMAT] needs to blit its mip levels
+const MIP_BLIT_FEATURES: vk::FormatFeatureFlags = vk::FormatFeatureFlags::from_raw(
+    vk::FormatFeatureFlags::BLIT_SRC.as_raw()
+        | vk::FormatFeatureFlags::BLIT_DST.as_raw()
+        | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR.as_raw(),
+);
 
 pub struct Texture {
-    pub(super) image: Image,
+    pub(super) image: ManuallyDrop<Image>,
     pub(super) view: vk::ImageView,
     pub(super) sampler: vk::Sampler,
     device: Arc<ash::Device>,
 }
 
+/// Texel data of every mip level of an image, ready to be copied into it
+struct Levels<'a> {
+    format: vk::Format,
+    width: u32,
+    height: u32,
+    data: &'a [u8],
+    /// Byte range of every mip level in `data`, largest first
+    ranges: &'a [(usize, usize)],
+    /// Whether every level holds the six faces of a cubemap one after another
+    cube: bool,
+    /// Blit the full mip chain from the only level in `data` after it is copied
+    generate_mips: bool,
+}
+
 impl Texture {
+    /// Loads a png, jpeg, bmp or tga texture with a generated mip chain,
+    /// or a block compressed DDS texture with its own mip levels.
+    ///
+    /// `format_features` returns the optimal tiling features of a format.
+    /// DDS textures in a format that can't be sampled are replaced with
+    /// the png texture of the same name
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         path: impl AsRef<Path>,
+        device: Arc<ash::Device>,
+        commands: UploadCommands,
+        staging: &Arc<StagingPool>,
+        anisotropy: f32,
+        max_size: u32,
+        allocator: Arc<Allocator>,
+        format_features: impl Fn(vk::Format) -> vk::FormatFeatureFlags,
+    ) -> Result<Self> {
+        Self::new_async(
+            path,
+            device,
+            commands,
+            staging,
+            anisotropy,
+            max_size,
+            allocator,
+            format_features,
+        )?
+        .wait()
+    }
+
+    /// Like [new](Texture::new), but returns as soon as the copy is submitted.
+    ///
+    /// The texture is decoded before anything is recorded, so the command buffers are unused
+    /// if it fails to load. Otherwise they must not be reset or freed until the upload finished.
+    /// With a transfer queue the image is handed to the graphics queue family after the copy
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_async(
+        path: impl AsRef<Path>,
+        device: Arc<ash::Device>,
+        commands: UploadCommands,
+        staging: &Arc<StagingPool>,
+        anisotropy: f32,
+        max_size: u32,
+        allocator: Arc<Allocator>,
+        format_features: impl Fn(vk::Format) -> vk::FormatFeatureFlags,
+    ) -> Result<Upload<Self>> {
+        let path = path.as_ref();
+        let is_dds = path
+            .extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("dds"));
+        if !is_dds {
+            return Self::from_image(
+                path,
+                device,
+                commands,
+                staging,
+                anisotropy,
+                max_size,
+                allocator,
+                &format_features,
+            );
+        }
+        let texture = dds::parse(std::fs::read(path)?)?;
+        if format_features(texture.format).contains(vk::FormatFeatureFlags::SAMPLED_IMAGE) {
+            return Self::from_compressed(
+                texture, path, device, commands, staging, anisotropy, max_size, allocator,
+            );
+        }
+        let fallback = path.with_extension("png");
+        warn!(
+            "Texture format {:?} of {path:?} is not supported, loading {fallback:?} instead",
+            texture.format
+        );
+        Self::from_image(
+            &fallback,
+            device,
+            commands,
+            staging,
+            anisotropy,
+            max_size,
+            allocator,
+            &format_features,
+        )
+    }
+
+    /// Uploads a decoded image and blits its mip levels,
+    /// DDS textures ship their own levels and don't need the blit support
+    #[allow(clippy::too_many_arguments)]
+    fn from_image(
+        path: &Path,
+        device: Arc<ash::Device>,
+        commands: UploadCommands,
+        staging: &Arc<StagingPool>,
+        anisotropy: f32,
+        max_size: u32,
+        allocator: Arc<Allocator>,
+        format_features: &impl Fn(vk::Format) -> vk::FormatFeatureFlags,
+    ) -> Result<Upload<Self>> {
+        // required of every device, but blitting without it is undefined
+        if !format_features(RGBA_FORMAT).contains(MIP_BLIT_FEATURES) {
+            bail!("Texture format {RGBA_FORMAT:?} doesn't support the linear blits of mip levels");
+        }
+        let mut pixels = decode_image(path)?;
+        if pixels.width().max(pixels.height()) > max_size {
+            let (width, height) = downscaled_size(pixels.width(), pixels.height(), max_size);
+            info!(
+                "Downscaling texture {path:?} from {}x{} to {width}x{height}",
+                pixels.width(),
+                pixels.height()
+            );
+            pixels = imageops::resize(&pixels, width, height, FilterType::Triangle);
+        }
+        let levels = Levels {
+            format: RGBA_FORMAT,
+            width: pixels.width(),
+            height: pixels.height(),
+            data: pixels.as_raw(),
+            ranges: &[(0, pixels.as_raw().len())],
+            cube: false,
+            generate_mips: true,
+        };
+        Self::submit(levels, device, commands, staging, anisotropy, allocator)
+    }
+
+    /// Uploads the mip levels of a compressed texture as they are,
+    /// levels larger than `max_size` are skipped instead of downscaled
+    #[allow(clippy::too_many_arguments)]
+    fn from_compressed(
+        texture: CompressedTexture,
+        path: &Path,
+        device: Arc<ash::Device>,
+        commands: UploadCommands,
+        staging: &Arc<StagingPool>,
+        anisotropy: f32,
+        max_size: u32,
+        allocator: Arc<Allocator>,
+    ) -> Result<Upload<Self>> {
+        let skipped = skipped_levels(
+            texture.width,
+            texture.height,
+            texture.levels.len(),
+            max_size,
+        );
+        if skipped > 0 {
+            info!(
+                "Skipping {skipped} mip levels of texture {path:?} larger than {max_size} pixels"
+            );
+        }
+        let ranges = &texture.levels[skipped..];
+        let start = ranges[0].0;
+        let end = ranges.last().map_or(start, |(offset, size)| offset + size);
+        let ranges = ranges
+            .iter()
+            .map(|(offset, size)| (offset - start, *size))
+            .collect::<Vec<_>>();
+        let levels = Levels {
+            format: texture.format,
+            width: (texture.width >> skipped).max(1),
+            height: (texture.height >> skipped).max(1),
+            data: &texture.data[start..end],
+            ranges: &ranges,
+            cube: false,
+            generate_mips: false,
+        };
+        Self::submit(levels, device, commands, staging, anisotropy, allocator)
+    }
+
+    /// Magenta and black checkerboard standing in for textures that failed to load,
+    /// without mip levels so it stays sharp at a distance
+    pub fn placeholder(
         device: Arc<ash::Device>,
         cmd: vk::CommandBuffer,
         queue: vk::Queue,
         anisotropy: f32,
         allocator: Arc<Allocator>,
     ) -> Result<Self> {
-        let decoder = Decoder::new(File::open(path)?);
-        let mut reader = decoder.read_info()?;
-        let size = reader.output_buffer_size();
-        let staging_info = vk::BufferCreateInfo::builder()
-            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
-            .size(size as DeviceSize)
-            .sharing_mode(vk::SharingMode::EXCLUSIVE);
-        let staging_alloc_info = vk_mem::AllocationCreateInfo {
-            usage: vk_mem::MemoryUsage::CpuToGpu,
-            flags: vk_mem::AllocationCreateFlags::MAPPED,
-            required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE
-                | vk::MemoryPropertyFlags::HOST_COHERENT,
-            ..Default::default()
+        Self::from_pixels(&checkerboard(), device, cmd, queue, anisotropy, allocator)
+    }
+
+    /// A single white texel, bound to materials whose shaders sample a texture they don't have
+    /// so their color is left unchanged
+    pub fn white(
+        device: Arc<ash::Device>,
+        cmd: vk::CommandBuffer,
+        queue: vk::Queue,
+        anisotropy: f32,
+        allocator: Arc<Allocator>,
+    ) -> Result<Self> {
+        let pixels = RgbaImage::from_pixel(1, 1, Rgba([255; 4]));
+        Self::from_pixels(&pixels, device, cmd, queue, anisotropy, allocator)
+    }
+
+    /// Uploads decoded pixels without mip levels
+    fn from_pixels(
+        pixels: &RgbaImage,
+        device: Arc<ash::Device>,
+        cmd: vk::CommandBuffer,
+        queue: vk::Queue,
+        anisotropy: f32,
+        allocator: Arc<Allocator>,
+    ) -> Result<Self> {
+        let levels = Levels {
+            format: RGBA_FORMAT,
+            width: pixels.width(),
+            height: pixels.height(),
+            data: pixels.as_raw(),
+            ranges: &[(0, pixels.as_raw().len())],
+            cube: false,
+            generate_mips: false,
+        };
+        let commands = UploadCommands::graphics(cmd, queue);
+        // too rare to keep their staging memory around
+        let staging = StagingPool::mapped(allocator.clone(), 0);
+        Self::submit(levels, device, commands, &staging, anisotropy, allocator)?.wait()
+    }
+
+    /// Uploads the faces of a cubemap in the order +x, -x, +y, -y, +z, -z,
+    /// which must all be squares of the same size.
+    ///
+    /// Every mip level is the faces downscaled to half the size of the level before it,
+    /// so sampling a higher level returns the environment blurred over a wider cone
+    pub fn cubemap(
+        faces: &[RgbaImage; 6],
+        device: Arc<ash::Device>,
+        cmd: vk::CommandBuffer,
+        queue: vk::Queue,
+        anisotropy: f32,
+        allocator: Arc<Allocator>,
+    ) -> Result<Self> {
+        let size = faces[0].width();
+        if faces
+            .iter()
+            .any(|face| face.width() != size || face.height() != size)
+        {
+            bail!("Cubemap faces must be squares of the same size");
+        }
+        let mut data = Vec::new();
+        let mut ranges = Vec::new();
+        let mut level = faces.clone();
+        loop {
+            let start = data.len();
+            for face in &level {
+                data.extend_from_slice(face.as_raw());
+            }
+            ranges.push((start, data.len() - start));
+            let half = level[0].width() / 2;
+            if half == 0 {
+                break;
+            }
+            level = level.map(|face| imageops::resize(&face, half, half, FilterType::Triangle));
+        }
+        let levels = Levels {
+            format: RGBA_FORMAT,
+            width: size,
+            height: size,
+            data: &data,
+            ranges: &ranges,
+            cube: true,
+            generate_mips: false,
         };
-        let staging_buffer =
-            unsafe { Buffer::new(&staging_info, &staging_alloc_info, allocator.clone())? };
+        let commands = UploadCommands::graphics(cmd, queue);
+        let staging = StagingPool::mapped(allocator.clone(), 0);
+        Self::submit(levels, device, commands, &staging, anisotropy, allocator)?.wait()
+    }
+
+    /// Copies texel data into a new image, the image can be sampled once the upload finished.
+    ///
+    /// The copy command buffer moves every level from `UNDEFINED` to `TRANSFER_DST_OPTIMAL`
+    /// before copying into it, and only after the copy are the levels transitioned
+    /// to `SHADER_READ_ONLY_OPTIMAL`: by the queue family ownership transfer, one level at a time
+    /// by the mip blits, or by a final barrier waiting on the copy's writes
+    fn submit(
+        levels: Levels,
+        device: Arc<ash::Device>,
+        commands: UploadCommands,
+        staging: &Arc<StagingPool>,
+        anisotropy: f32,
+        allocator: Arc<Allocator>,
+    ) -> Result<Upload<Self>> {
+        let size = levels.data.len();
+        let permit = upload::reserve(size as DeviceSize);
+        let staging_buffer = staging.take(size as DeviceSize)?;
         let ptr = staging_buffer.get_info().get_mapped_data();
-        let info = reader.next_frame(unsafe { std::slice::from_raw_parts_mut(ptr, size) })?;
+        unsafe { std::ptr::copy_nonoverlapping(levels.data.as_ptr(), ptr, size) };
 
+        let (level_count, usage) = if levels.generate_mips {
+            (
+                mip_level_count(levels.width, levels.height),
+                vk::ImageUsageFlags::TRANSFER_SRC,
+            )
+        } else {
+            (levels.ranges.len() as u32, vk::ImageUsageFlags::empty())
+        };
+        let (layer_count, flags, view_type) = if levels.cube {
+            (
+                6,
+                vk::ImageCreateFlags::CUBE_COMPATIBLE,
+                vk::ImageViewType::CUBE,
+            )
+        } else {
+            (1, vk::ImageCreateFlags::empty(), vk::ImageViewType::TYPE_2D)
+        };
         let ext = vk::Extent3D {
-            width: info.width,
-            height: info.height,
+            width: levels.width,
+            height: levels.height,
             depth: 1,
         };
         let create_info = vk::ImageCreateInfo::builder()
+            .flags(flags)
             .extent(ext)
             .image_type(vk::ImageType::TYPE_2D)
-            .format(vk::Format::R8G8B8A8_SRGB)
+            .format(levels.format)
             .tiling(vk::ImageTiling::OPTIMAL)
-            .mip_levels(1)
-            .array_layers(1)
-            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .mip_levels(level_count)
+            .array_layers(layer_count)
+            .usage(usage | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
             .initial_layout(vk::ImageLayout::UNDEFINED)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
             .samples(vk::SampleCountFlags::TYPE_1);
@@ -68,12 +381,13 @@ impl Texture {
         let sub_range = vk::ImageSubresourceRange {
             aspect_mask: vk::ImageAspectFlags::COLOR,
             base_mip_level: 0,
-            level_count: 1,
+            level_count,
             base_array_layer: 0,
-            layer_count: 1,
+            layer_count,
         };
         unsafe {
             let image = Image::new(&create_info, &alloc_info, allocator)?;
+            let cmd = commands.copy;
             let begin_info = vk::CommandBufferBeginInfo::builder()
                 .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
             device.begin_command_buffer(cmd, &begin_info)?;
@@ -95,59 +409,128 @@ impl Texture {
                 &[],
                 &barrier,
             );
-            let cpy = [vk::BufferImageCopy::builder()
-                .buffer_image_height(0)
-                .buffer_offset(0)
-                .buffer_row_length(0)
-                .image_subresource(vk::ImageSubresourceLayers {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    mip_level: 0,
-                    base_array_layer: 0,
-                    layer_count: 1,
+            // rows are tightly packed, for block compressed formats in whole blocks,
+            // and the faces of a cubemap level follow each other
+            let cpy = levels
+                .ranges
+                .iter()
+                .enumerate()
+                .map(|(level, (offset, _))| {
+                    vk::BufferImageCopy::builder()
+                        .buffer_image_height(0)
+                        .buffer_offset(*offset as DeviceSize)
+                        .buffer_row_length(0)
+                        .image_subresource(vk::ImageSubresourceLayers {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: level as u32,
+                            base_array_layer: 0,
+                            layer_count,
+                        })
+                        .image_offset(vk::Offset3D::default())
+                        .image_extent(vk::Extent3D {
+                            width: (ext.width >> level).max(1),
+                            height: (ext.height >> level).max(1),
+                            depth: 1,
+                        })
+                        .build()
                 })
-                .image_offset(vk::Offset3D::default())
-                .image_extent(ext)
-                .build()];
-            let barrier = [vk::ImageMemoryBarrier::builder()
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                .image(*image)
-                .subresource_range(sub_range)
-                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                .dst_access_mask(vk::AccessFlags::SHADER_READ)
-                .build()];
-            device.cmd_pipeline_barrier(
-                cmd,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::FRAGMENT_SHADER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &barrier,
-            );
+                .collect::<Vec<_>>();
             device.cmd_copy_buffer_to_image(
                 cmd,
-                *staging_buffer,
+                **staging_buffer,
                 *image,
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                 &cpy,
             );
-
-            device.end_command_buffer(cmd)?;
-            let submit_info = [vk::SubmitInfo::builder().command_buffers(&[cmd]).build()];
-            device.queue_submit(queue, &submit_info, vk::Fence::null())?;
-            device.queue_wait_idle(queue)?;
+            match commands.ownership {
+                // mip levels are blitted on the graphics queue, once it owns the image
+                Some(ownership) => {
+                    hand_over_image(
+                        &device,
+                        cmd,
+                        ownership,
+                        *image,
+                        sub_range,
+                        levels.generate_mips,
+                    )?;
+                    device.end_command_buffer(cmd)?;
+                    if levels.generate_mips {
+                        generate_mips(&device, ownership.acquire, *image, ext, level_count);
+                    }
+                    device.end_command_buffer(ownership.acquire)?;
+                }
+                None => {
+                    if levels.generate_mips {
+                        generate_mips(&device, cmd, *image, ext, level_count);
+                    } else {
+                        let barrier = [vk::ImageMemoryBarrier::builder()
+                            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                            .image(*image)
+                            .subresource_range(sub_range)
+                            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                            .build()];
+                        device.cmd_pipeline_barrier(
+                            cmd,
+                            vk::PipelineStageFlags::TRANSFER,
+                            vk::PipelineStageFlags::FRAGMENT_SHADER,
+                            vk::DependencyFlags::empty(),
+                            &[],
+                            &[],
+                            &barrier,
+                        );
+                    }
+                    device.end_command_buffer(cmd)?;
+                }
+            }
+            let transfer = Transfer::submit(device.clone(), &commands, staging_buffer, permit)?;
             let view_info = vk::ImageViewCreateInfo::builder()
                 .image(*image)
-                .format(vk::Format::R8G8B8A8_SRGB)
-                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(levels.format)
+                .view_type(view_type)
                 .subresource_range(sub_range);
             let view = device.create_image_view(&view_info, None)?;
-            let sampler = create_sampler(&device, anisotropy)?;
+            let sampler = create_sampler(&device, anisotropy, level_count)?;
+            let texture = Texture {
+                image: ManuallyDrop::new(image),
+                view,
+                sampler,
+                device,
+            };
+            Ok(Upload::new(texture, Some(transfer)))
+        }
+    }
+}
+
+impl Texture {
+    /// Wraps a depth image so it can be sampled as a shadow map.
+    ///
+    /// The image must have been created with `SAMPLED` usage and a depth only format
+    pub fn from_depth_image(
+        image: Image,
+        format: vk::Format,
+        device: Arc<ash::Device>,
+    ) -> Result<Self> {
+        let sub_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(*image)
+            .format(format)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .subresource_range(sub_range);
+        unsafe {
+            let view = device.create_image_view(&view_info, None)?;
+            let sampler = create_comparison_sampler(&device)?;
             Ok(Texture {
-                image,
+                image: ManuallyDrop::new(image),
                 view,
                 sampler,
                 device,
@@ -156,7 +539,187 @@ impl Texture {
     }
 }
 
-unsafe fn create_sampler(device: &ash::Device, anisotropy: f32) -> VkResult<vk::Sampler> {
+/// Records the release of the copied image on the transfer queue in `cmd`,
+/// then begins the graphics queue's acquire command buffer and records the acquire in it.
+///
+/// Images with generated mips stay in the transfer layout for the blits recorded after the acquire,
+/// others are transitioned to be sampled
+unsafe fn hand_over_image(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    ownership: Ownership,
+    image: vk::Image,
+    sub_range: vk::ImageSubresourceRange,
+    generate_mips: bool,
+) -> VkResult<()> {
+    let (new_layout, dst_stage, dst_access) = if generate_mips {
+        (
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE,
+        )
+    } else {
+        (
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        )
+    };
+    // both halves of the transfer must describe the same layout transition
+    let barrier = |src_access, dst_access| {
+        [vk::ImageMemoryBarrier::builder()
+            .src_queue_family_index(ownership.transfer_family)
+            .dst_queue_family_index(ownership.graphics_family)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(new_layout)
+            .image(image)
+            .subresource_range(sub_range)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .build()]
+    };
+    device.cmd_pipeline_barrier(
+        cmd,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &barrier(vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::empty()),
+    );
+    let begin_info =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(ownership.acquire, &begin_info)?;
+    device.cmd_pipeline_barrier(
+        ownership.acquire,
+        vk::PipelineStageFlags::TOP_OF_PIPE,
+        dst_stage,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &barrier(vk::AccessFlags::empty(), dst_access),
+    );
+    Ok(())
+}
+
+/// Fills every level after the first of a 2D image by blitting the level before it,
+/// leaving the whole image ready to be sampled.
+///
+/// Every level has to be in the transfer destination layout and the first one filled
+unsafe fn generate_mips(
+    device: &ash::Device,
+    cmd: vk::CommandBuffer,
+    image: vk::Image,
+    extent: vk::Extent3D,
+    level_count: u32,
+) {
+    let barrier = |level, old_layout, new_layout, src_access_mask, dst_access_mask| {
+        vk::ImageMemoryBarrier::builder()
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .image(image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: level,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask)
+            .build()
+    };
+    let subresource = |level| vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        mip_level: level,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    // odd sizes round down, the blit's filter covers the dropped texels
+    let corner = |level: u32| vk::Offset3D {
+        x: (extent.width >> level).max(1) as i32,
+        y: (extent.height >> level).max(1) as i32,
+        z: 1,
+    };
+    for level in 1..level_count {
+        let src = level - 1;
+        device.cmd_pipeline_barrier(
+            cmd,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier(
+                src,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+            )],
+        );
+        let blit = vk::ImageBlit::builder()
+            .src_subresource(subresource(src))
+            .src_offsets([vk::Offset3D::default(), corner(src)])
+            .dst_subresource(subresource(level))
+            .dst_offsets([vk::Offset3D::default(), corner(level)])
+            .build();
+        device.cmd_blit_image(
+            cmd,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[blit],
+            vk::Filter::LINEAR,
+        );
+        device.cmd_pipeline_barrier(
+            cmd,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier(
+                src,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::AccessFlags::SHADER_READ,
+            )],
+        );
+    }
+    // the last level is only ever written
+    device.cmd_pipeline_barrier(
+        cmd,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[barrier(
+            level_count - 1,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+        )],
+    );
+}
+
+/// Levels of a full mip chain down to a single texel,
+/// image sizes don't have to be powers of two
+fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+unsafe fn create_sampler(
+    device: &ash::Device,
+    anisotropy: f32,
+    level_count: u32,
+) -> VkResult<vk::Sampler> {
     let create_info = vk::SamplerCreateInfo::builder()
         .mag_filter(vk::Filter::LINEAR)
         .min_filter(vk::Filter::LINEAR)
@@ -167,20 +730,173 @@ unsafe fn create_sampler(device: &ash::Device, anisotropy: f32) -> VkResult<vk::
         .max_anisotropy(anisotropy)
         .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
         .unnormalized_coordinates(false)
-        .compare_enable(false) // todo
+        .compare_enable(false)
         .compare_op(vk::CompareOp::ALWAYS)
         .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
         .mip_lod_bias(0.)
         .min_lod(0.)
-        .max_lod(0.); // todo mip mapping
+        .max_lod((level_count - 1) as f32);
+    device.create_sampler(&create_info, None)
+}
+
+/// Creates a sampler that compares against the sampled depth instead of returning it.
+///
+/// Uses linear filtering so the hardware performs percentage closer filtering,
+/// samples outside the texture are treated as unoccluded
+pub(crate) unsafe fn create_comparison_sampler(device: &ash::Device) -> VkResult<vk::Sampler> {
+    let create_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+        .anisotropy_enable(false)
+        .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+        .unnormalized_coordinates(false)
+        .compare_enable(true)
+        .compare_op(vk::CompareOp::LESS_OR_EQUAL)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .min_lod(0.)
+        .max_lod(0.);
     device.create_sampler(&create_info, None)
 }
 
+/// Reads a png, jpeg, bmp or tga image and converts it to 8 bit rgba.
+///
+/// The format is picked by the file extension,
+/// or by the first bytes of the file if the extension is unknown
+pub(crate) fn decode_image(path: &Path) -> Result<RgbaImage> {
+    let data = std::fs::read(path)?;
+    let format = ImageFormat::from_path(path).or_else(|_| image::guess_format(&data));
+    let format = match format {
+        Ok(format) if DECODED_FORMATS.contains(&format) => format,
+        Ok(format) => bail!("Texture {path:?} has unsupported image format {format:?}"),
+        Err(_) => bail!("Texture {path:?} is not a png, jpeg, bmp or tga image"),
+    };
+    let image = image::load_from_memory_with_format(&data, format)
+        .map_err(|e| anyhow!("Failed to decode texture {path:?}: {e}"))?;
+    Ok(image.into_rgba8())
+}
+
+/// Decodes the six faces of a cubemap with [decode_image], in the order +x, -x, +y, -y, +z, -z
+pub(crate) fn decode_faces(faces: &[impl AsRef<Path>; 6]) -> Result<[RgbaImage; 6]> {
+    let faces = faces
+        .iter()
+        .map(|path| decode_image(path.as_ref()))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(faces.try_into().unwrap())
+}
+
+/// Pixels of the placeholder texture, squares of [CHECKER_SIZE] pixels
+fn checkerboard() -> RgbaImage {
+    const MAGENTA: Rgba<u8> = Rgba([255, 0, 255, 255]);
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    RgbaImage::from_fn(CHECKER_SIZE * 8, CHECKER_SIZE * 8, |x, y| {
+        if (x / CHECKER_SIZE + y / CHECKER_SIZE) % 2 == 0 {
+            MAGENTA
+        } else {
+            BLACK
+        }
+    })
+}
+
+/// Number of leading mip levels larger than `max_size`, the smallest level is always kept
+fn skipped_levels(width: u32, height: u32, level_count: usize, max_size: u32) -> usize {
+    (0..level_count)
+        .find(|&level| (width >> level).max(height >> level) <= max_size)
+        .unwrap_or(level_count - 1)
+}
+
+/// Largest size with the same aspect ratio that fits into `max_size` in both dimensions
+fn downscaled_size(width: u32, height: u32, max_size: u32) -> (u32, u32) {
+    let scale = max_size as f64 / width.max(height) as f64;
+    let scale = |size: u32| ((size as f64 * scale).round() as u32).clamp(1, max_size);
+    (scale(width), scale(height))
+}
+
 impl Drop for Texture {
     fn drop(&mut self) {
-        unsafe {
-            self.device.destroy_sampler(self.sampler, None);
-            self.device.destroy_image_view(self.view, None);
-        }
+        deletion::queue(self.device.clone(), Resource::Sampler(self.sampler));
+        deletion::queue(self.device.clone(), Resource::ImageView(self.view));
+        let image = unsafe { ManuallyDrop::take(&mut self.image) };
+        deletion::queue(self.device.clone(), Resource::Image(image));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+
+    use crate::vulkan::texture::{
+        checkerboard, decode_image, downscaled_size, mip_level_count, skipped_levels, CHECKER_SIZE,
+    };
+
+    #[test]
+    fn downscaled_keeps_aspect_ratio() {
+        assert_eq!(downscaled_size(4096, 2048, 1024), (1024, 512));
+        assert_eq!(downscaled_size(1000, 4000, 2000), (500, 2000));
+        assert_eq!(downscaled_size(8192, 1, 1024), (1024, 1));
+    }
+
+    #[test]
+    fn mip_levels_over_max_size_are_skipped() {
+        assert_eq!(skipped_levels(4096, 2048, 13, 4096), 0);
+        assert_eq!(skipped_levels(4096, 2048, 13, 1024), 2);
+        assert_eq!(skipped_levels(4096, 2048, 1, 1024), 0);
+        assert_eq!(skipped_levels(4096, 4096, 3, 512), 2);
+    }
+
+    #[test]
+    fn mip_chain_ends_at_one_texel() {
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(256, 256), 9);
+        assert_eq!(mip_level_count(300, 17), 9);
+        assert_eq!(mip_level_count(1, 1024), 11);
+    }
+
+    #[test]
+    fn jpeg_and_png_decode_to_the_same_size() {
+        let dir = std::env::temp_dir();
+        let pixels = RgbaImage::from_fn(48, 20, |x, y| Rgba([x as u8 * 5, y as u8 * 12, 0, 255]));
+        let png = dir.join("dragonfire_decode_test.png");
+        let jpeg = dir.join("dragonfire_decode_test.jpg");
+        pixels.save_with_format(&png, ImageFormat::Png).unwrap();
+        // jpeg has no alpha channel
+        DynamicImage::ImageRgba8(pixels.clone())
+            .to_rgb8()
+            .save_with_format(&jpeg, ImageFormat::Jpeg)
+            .unwrap();
+        let decoded = (decode_image(&png), decode_image(&jpeg));
+        std::fs::remove_file(&png).unwrap();
+        std::fs::remove_file(&jpeg).unwrap();
+        let (png, jpeg) = (decoded.0.unwrap(), decoded.1.unwrap());
+        assert_eq!(png.dimensions(), (48, 20));
+        assert_eq!(jpeg.dimensions(), png.dimensions());
+        assert_eq!(png, pixels);
+        assert_eq!(jpeg.get_pixel(0, 0)[3], 255);
+    }
+
+    #[test]
+    fn unknown_image_formats_are_rejected() {
+        let path = std::env::temp_dir().join("dragonfire_decode_test.txt");
+        std::fs::write(&path, b"not an image").unwrap();
+        let decoded = decode_image(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(decoded.is_err());
+    }
+
+    #[test]
+    fn checkerboard_alternates() {
+        let pixels = checkerboard();
+        assert_eq!(
+            pixels.get_pixel(0, 0),
+            pixels.get_pixel(CHECKER_SIZE - 1, CHECKER_SIZE - 1)
+        );
+        assert_ne!(pixels.get_pixel(0, 0), pixels.get_pixel(CHECKER_SIZE, 0));
+        assert_ne!(pixels.get_pixel(0, 0), pixels.get_pixel(0, CHECKER_SIZE));
+        assert_eq!(
+            pixels.get_pixel(0, 0),
+            pixels.get_pixel(CHECKER_SIZE, CHECKER_SIZE)
+        );
     }
 }
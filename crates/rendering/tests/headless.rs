@@ -0,0 +1,347 @@
+//! Renders a single triangle into a hidden window and compares the frame against a reference image.
+//!
+//! The test is skipped when there is no display or no vulkan capable gpu.
+//! A missing reference image fails the test, set `BLESS=1` to write or replace it.
+//!
+//! `rapid_resize` stresses swapchain recreation and needs a visible window on a compositor,
+//! run it manually with `cargo test -p rendering --test headless -- --ignored rapid_resize`
+//! and check that it neither panics nor hangs and that validation layers stay quiet
+#![cfg(all(feature = "vulkan", target_os = "linux"))]
+
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use engine::database::DATABASE_PATH_VAR;
+use nalgebra::{Isometry3, Matrix4, Point3, Vector2, Vector3};
+use uom::si::angle::degree;
+use uom::si::f32::Angle;
+use winit::dpi::PhysicalSize;
+use winit::event_loop::EventLoop;
+use winit::platform::run_return::EventLoopExtRunReturn;
+use winit::platform::unix::EventLoopExtUnix;
+use winit::window::{Window, WindowBuilder};
+
+use rendering::{
+    enumerate_gpus, try_create_rendering_engine, Camera, Engine, FrameCapture, GraphicsSettings,
+    RenderingEngine, Vertex,
+};
+
+const SIZE: u32 = 128;
+/// Largest difference of a single color channel that is still considered equal
+const TOLERANCE: u8 = 8;
+/// Fraction of channels allowed to differ more than [TOLERANCE], covers rasterization differences
+/// along the triangle's edges between gpus
+const MAX_MISMATCHED: f32 = 0.01;
+/// Frames rendered before the captured one, so that every frame in flight was used once
+const WARMUP_FRAMES: usize = 3;
+/// Window sizes of the resize stress test, changed every frame
+const RESIZE_STEPS: u32 = 300;
+
+#[test]
+fn renders_triangle() {
+    let (_event_loop, _window, mut engine) = match headless_engine() {
+        Some(parts) => parts,
+        None => return,
+    };
+
+    let (vertices, indices) = triangle();
+    let mesh = engine
+        .create_mesh(vertices, indices)
+        .expect("Failed to create mesh");
+    let material = engine
+        .load_material("base")
+        .expect("Failed to load material");
+    let cached = engine
+        .load_material("base")
+        .expect("Failed to load material");
+    assert!(Arc::ptr_eq(&material, &cached), "Material was loaded twice");
+    drop(cached);
+    let mut camera = Camera::new(SIZE, SIZE, Angle::new::<degree>(45.));
+    camera.view = Isometry3::look_at_rh(&Point3::new(0., 0., 2.), &Point3::origin(), &Vector3::y());
+
+    for frame in 0..=WARMUP_FRAMES {
+        if frame == WARMUP_FRAMES {
+            engine.capture_next_frame();
+        }
+        engine.begin_rendering(&camera);
+        engine.render(&mesh, &material, Matrix4::identity());
+        engine.end_rendering();
+    }
+    let capture = engine.take_capture().expect("Frame was not captured");
+    drop(mesh);
+    drop(material);
+    drop(engine);
+
+    let center = pixel(&capture, capture.width / 2, capture.height / 2);
+    assert_ne!(center, pixel(&capture, 0, 0), "Triangle was not rendered");
+
+    let reference = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/reference/triangle.png");
+    if std::env::var_os("BLESS").is_some() {
+        write_png(&reference, &capture).expect("Failed to write reference image");
+        eprintln!("Wrote reference image to {reference:?}");
+        return;
+    }
+    assert!(
+        reference.exists(),
+        "Reference image {reference:?} is missing, run with BLESS=1 to write it"
+    );
+    let expected = read_png(&reference).expect("Failed to read reference image");
+    assert_eq!(
+        (capture.width, capture.height),
+        (expected.width, expected.height),
+        "Frame size does not match the reference image"
+    );
+    let mismatched = capture
+        .pixels
+        .iter()
+        .zip(&expected.pixels)
+        .filter(|(actual, expected)| actual.abs_diff(**expected) > TOLERANCE)
+        .count();
+    let allowed = (capture.pixels.len() as f32 * MAX_MISMATCHED) as usize;
+    assert!(
+        mismatched <= allowed,
+        "{mismatched} channels differ from the reference image, at most {allowed} are allowed"
+    );
+}
+
+#[test]
+fn renders_at_the_new_size_after_resize() {
+    let (mut event_loop, window, mut engine) = match headless_engine() {
+        Some(parts) => parts,
+        None => return,
+    };
+    let (vertices, indices) = triangle();
+    let mesh = engine
+        .create_mesh(vertices, indices)
+        .expect("Failed to create mesh");
+    // loaded before the resize, so the pipeline was created for the old extent
+    let material = engine
+        .load_material("base")
+        .expect("Failed to load material");
+    let mut camera = Camera::new(SIZE, SIZE, Angle::new::<degree>(45.));
+    camera.view = Isometry3::look_at_rh(&Point3::new(0., 0., 2.), &Point3::origin(), &Vector3::y());
+    engine.begin_rendering(&camera);
+    engine.render(&mesh, &material, Matrix4::identity());
+    engine.end_rendering();
+
+    let size = SIZE * 2;
+    resize(&mut event_loop, &window, size);
+    engine.resize(size, size);
+    for frame in 0..=WARMUP_FRAMES {
+        if frame == WARMUP_FRAMES {
+            engine.capture_next_frame();
+        }
+        engine.begin_rendering(&camera);
+        engine.render(&mesh, &material, Matrix4::identity());
+        engine.end_rendering();
+    }
+    let capture = engine.take_capture().expect("Frame was not captured");
+    drop(mesh);
+    drop(material);
+    drop(engine);
+
+    if (capture.width, capture.height) != (size, size) {
+        eprintln!("Skipping resize check, the window was not resized");
+        return;
+    }
+    // a viewport and scissor left at the old extent would only cover the top left quarter
+    let center = pixel(&capture, size / 2, size / 2);
+    assert_ne!(
+        center,
+        pixel(&capture, 0, 0),
+        "Triangle was not rendered at the new size"
+    );
+}
+
+#[test]
+fn skybox_is_drawn_after_resize() {
+    let (mut event_loop, window, mut engine) = match headless_engine() {
+        Some(parts) => parts,
+        None => return,
+    };
+    let sky = [255, 0, 0, 255];
+    let face = FrameCapture {
+        width: 4,
+        height: 4,
+        pixels: sky.repeat(16),
+    };
+    let path = std::env::temp_dir().join("dragonfire-skybox-face.png");
+    write_png(&path, &face).expect("Failed to write skybox face");
+    engine
+        .set_skybox([path.as_path(); 6])
+        .expect("Failed to set skybox");
+    let camera = Camera::new(SIZE, SIZE, Angle::new::<degree>(45.));
+
+    let size = SIZE * 2;
+    resize(&mut event_loop, &window, size);
+    engine.resize(size, size);
+    for frame in 0..=WARMUP_FRAMES {
+        if frame == WARMUP_FRAMES {
+            engine.capture_next_frame();
+        }
+        engine.begin_rendering(&camera);
+        engine.end_rendering();
+    }
+    let capture = engine.take_capture().expect("Frame was not captured");
+    drop(engine);
+
+    // every pixel looks at the sky, black would be the clear color
+    for (x, y) in [(0, 0), (capture.width / 2, capture.height / 2)] {
+        let color = pixel(&capture, x, y);
+        assert!(
+            color[0] > 255 - TOLERANCE && color[1] < TOLERANCE && color[2] < TOLERANCE,
+            "Pixel {x}, {y} is {color:?} instead of the skybox color"
+        );
+    }
+}
+
+#[test]
+fn async_mesh_upload_becomes_ready() {
+    let (_event_loop, _window, mut engine) = match headless_engine() {
+        Some(parts) => parts,
+        None => return,
+    };
+
+    let (vertices, indices) = triangle();
+    let mut upload = engine
+        .create_mesh_async(vertices, indices)
+        .expect("Failed to submit mesh");
+    let start = Instant::now();
+    while !upload.is_ready().expect("Failed to poll upload") {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "Upload never finished"
+        );
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let mesh = upload.wait().expect("Failed to wait for upload");
+    let (min, max) = mesh.get_bounds();
+    assert!(min.x < max.x && min.y < max.y, "Mesh has no extent");
+    drop(mesh);
+    drop(engine);
+}
+
+#[test]
+fn enumerated_gpus_include_the_engine_gpu() {
+    let (_event_loop, window, engine) = match headless_engine() {
+        Some(parts) => parts,
+        None => return,
+    };
+    let gpus = enumerate_gpus(&window);
+    assert!(gpus.contains(&engine.gpu_info()));
+}
+
+#[test]
+#[ignore]
+fn rapid_resize() {
+    let mut event_loop = EventLoop::<()>::new_any_thread();
+    let window = WindowBuilder::new()
+        .with_inner_size(PhysicalSize::new(SIZE, SIZE))
+        .build(&event_loop)
+        .expect("Failed to create window");
+    let settings = GraphicsSettings {
+        resolution: [SIZE, SIZE],
+        vsync: false,
+        ..Default::default()
+    };
+    let mut engine =
+        try_create_rendering_engine(&window, &settings).expect("Failed to create engine");
+    let camera = Camera::new(SIZE, SIZE, Angle::new::<degree>(45.));
+    for step in 0..RESIZE_STEPS {
+        // sizes change faster than the compositor follows, so acquires and presents
+        // go out of date at every point of the frame
+        let size = SIZE + step % 7 * 37;
+        resize(&mut event_loop, &window, size);
+        if step % 3 != 0 {
+            engine.resize(size, size);
+        }
+        engine.begin_rendering(&camera);
+        engine.end_rendering();
+    }
+    engine.wait();
+}
+
+/// Hidden window of [SIZE] and an engine rendering to it without vsync,
+/// None if there is no display or no usable vulkan device to run the test on.
+///
+/// Materials are loaded from the test materials instead of the asset directory's database
+fn headless_engine() -> Option<(EventLoop<()>, Window, Box<Engine>)> {
+    if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+        eprintln!("Skipping rendering test, no display available");
+        return None;
+    }
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/materials.db");
+    std::env::set_var(DATABASE_PATH_VAR, fixture);
+    let event_loop = EventLoop::<()>::new_any_thread();
+    let window = WindowBuilder::new()
+        .with_visible(false)
+        .with_inner_size(PhysicalSize::new(SIZE, SIZE))
+        .build(&event_loop)
+        .expect("Failed to create window");
+    let settings = GraphicsSettings {
+        resolution: [SIZE, SIZE],
+        vsync: false,
+        ..Default::default()
+    };
+    match try_create_rendering_engine(&window, &settings) {
+        Ok(engine) => Some((event_loop, window, engine)),
+        Err(e) => {
+            eprintln!("Skipping rendering test, no usable vulkan device: {e}");
+            None
+        }
+    }
+}
+
+/// Requests a new window size and handles the events it caused
+fn resize(event_loop: &mut EventLoop<()>, window: &Window, size: u32) {
+    window.set_inner_size(PhysicalSize::new(size, size));
+    event_loop.run_return(|event, _, control_flow| {
+        if let winit::event::Event::MainEventsCleared = event {
+            *control_flow = winit::event_loop::ControlFlow::Exit;
+        }
+    });
+}
+
+/// Vertices and indices of a triangle around the origin facing the camera,
+/// with both windings so that it is visible regardless of the culling settings
+fn triangle() -> (Vec<Vertex>, Vec<u32>) {
+    let vertex = |x: f32, y: f32| Vertex {
+        position: Vector3::new(x, y, 0.),
+        normal: Vector3::z_axis(),
+        uv: Vector2::zeros(),
+    };
+    (
+        vec![vertex(-0.5, -0.5), vertex(0.5, -0.5), vertex(0., 0.5)],
+        vec![0, 1, 2, 0, 2, 1],
+    )
+}
+
+fn pixel(capture: &FrameCapture, x: u32, y: u32) -> &[u8] {
+    let start = (y * capture.width + x) as usize * 4;
+    &capture.pixels[start..start + 4]
+}
+
+fn write_png(path: &Path, capture: &FrameCapture) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let mut encoder = png::Encoder::new(File::create(path)?, capture.width, capture.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&capture.pixels)?;
+    Ok(())
+}
+
+fn read_png(path: &Path) -> Result<FrameCapture, Box<dyn Error>> {
+    let decoder = png::Decoder::new(File::open(path)?);
+    let mut reader = decoder.read_info()?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels)?;
+    pixels.truncate(info.buffer_size());
+    Ok(FrameCapture {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
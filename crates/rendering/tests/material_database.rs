@@ -0,0 +1,50 @@
+//! Populates an in memory asset database and reads materials back the way the engine loads them
+
+use rusqlite::Connection;
+
+use rendering::materials::database::{init, query, MaterialRow};
+use rendering::materials::{BlendMode, CullMode};
+
+#[test]
+fn inserted_materials_are_read_back() {
+    let conn = Connection::open_in_memory().unwrap();
+    init(&conn).unwrap();
+    MaterialRow::new("glass", "pbr.vert.spv", "pbr.frag.spv")
+        .with_blend(BlendMode::Alpha)
+        .with_cull_mode(CullMode::None)
+        .with_texture("glass.png")
+        .with_texture("glass_normal.png")
+        .insert(&conn)
+        .unwrap();
+    // creating the tables again keeps their rows
+    init(&conn).unwrap();
+
+    let glass = query(&conn, "glass")
+        .unwrap()
+        .expect("Material was not inserted");
+    assert_eq!(glass.vertex_shader, "pbr.vert.spv");
+    assert_eq!(glass.fragment_shader, "pbr.frag.spv");
+    assert_eq!(glass.blend, BlendMode::Alpha);
+    assert_eq!(glass.cull_mode, CullMode::None);
+    assert_eq!(glass.texture.as_deref(), Some("glass.png"));
+    assert_eq!(query(&conn, "missing").unwrap(), None);
+}
+
+#[test]
+fn inserting_replaces_materials() {
+    let conn = Connection::open_in_memory().unwrap();
+    init(&conn).unwrap();
+    MaterialRow::new("base", "base.vert.spv", "base.frag.spv")
+        .with_texture("texture.png")
+        .insert(&conn)
+        .unwrap();
+    MaterialRow::new("base", "base.vert.spv", "unlit.frag.spv")
+        .insert(&conn)
+        .unwrap();
+
+    let base = query(&conn, "base").unwrap().unwrap();
+    assert_eq!(base.fragment_shader, "unlit.frag.spv");
+    assert_eq!(base.blend, BlendMode::Opaque);
+    assert_eq!(base.cull_mode, CullMode::Back);
+    assert_eq!(base.texture, None);
+}
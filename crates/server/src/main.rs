@@ -1,15 +1,57 @@
 use std::time::Duration;
 
-use log::info;
+use log::{error, info, LevelFilter};
+
+use engine::ecs::{Schedule, Stage};
+use engine::transform::rotate;
+
+use crate::server::Server;
+
+mod server;
 
 const TICK_INTERVAL: Duration = Duration::from_millis(50);
+/// Ticks between logging how far the simulation got
+const TICKS_PER_LOG: u64 = 1200;
 
 #[tokio::main]
 async fn main() {
+    init_logging().expect("Failed to initialize logging");
     info!("Server starting");
+    let mut schedule = Schedule::new();
+    schedule.add_system(Stage::Update, |world| world.run(rotate));
+    let mut server = Server::new(schedule);
+    info!(
+        "Ticking {} times per second",
+        1. / TICK_INTERVAL.as_secs_f64()
+    );
     let mut interval = tokio::time::interval(TICK_INTERVAL);
     loop {
         interval.tick().await;
-        //todo
+        // ticks missed while a slow one ran are caught up, so each one steps the same time
+        if let Err(e) = server.tick(TICK_INTERVAL) {
+            error!("Tick {} failed: {e}", server.ticks());
+        } else if server.ticks() % TICKS_PER_LOG == 0 {
+            info!(
+                "Ran {} ticks, {:.0} s simulated",
+                server.ticks(),
+                server.elapsed().as_secs_f64()
+            );
+        }
     }
 }
+
+fn init_logging() -> Result<(), fern::InitError> {
+    fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "[{}][{}] {}",
+                record.level(),
+                record.target(),
+                message
+            ))
+        })
+        .level(LevelFilter::Info)
+        .chain(std::io::stdout())
+        .apply()?;
+    Ok(())
+}
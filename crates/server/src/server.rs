@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use uom::si::f64::Time;
+use uom::si::time::second;
+
+use engine::ecs::{Schedule, World};
+
+/// World simulated by the server and the systems stepping it every tick
+pub struct Server {
+    world: World,
+    schedule: Schedule,
+    ticks: u64,
+    elapsed: Duration,
+}
+
+impl Server {
+    pub fn new(schedule: Schedule) -> Self {
+        Server {
+            world: World::new(),
+            schedule,
+            ticks: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Runs every system once, each seeing `delta` as the step's [Time]
+    pub fn tick(&mut self, delta: Duration) -> Result<()> {
+        self.schedule
+            .run(&self.world, Time::new::<second>(delta.as_secs_f64()))?;
+        self.ticks += 1;
+        self.elapsed += delta;
+        Ok(())
+    }
+
+    /// Number of ticks run so far
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Simulated time of all ticks so far
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use uom::si::f64::Time;
+    use uom::si::time::millisecond;
+
+    use engine::ecs::{Schedule, Stage, UniqueView};
+
+    use crate::server::Server;
+
+    #[test]
+    fn ticks_run_the_schedule() {
+        let total_ms = Arc::new(AtomicU64::new(0));
+        let mut schedule = Schedule::new();
+        let total = total_ms.clone();
+        schedule.add_system(Stage::Update, move |world| {
+            world.run(|time: UniqueView<Time>| {
+                let ms = time.get::<millisecond>().round() as u64;
+                total.fetch_add(ms, Ordering::Relaxed);
+            })
+        });
+        let mut server = Server::new(schedule);
+        server.tick(Duration::from_millis(50)).unwrap();
+        server.tick(Duration::from_millis(30)).unwrap();
+        assert_eq!(server.ticks(), 2);
+        assert_eq!(server.elapsed(), Duration::from_millis(80));
+        assert_eq!(total_ms.load(Ordering::Relaxed), 80);
+    }
+}